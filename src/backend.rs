@@ -0,0 +1,310 @@
+//Representations of an annovate file on disk. The line format with its leaders (`>`, `=`, `<`, ...) is the default,
+//files ending in `.toml` are read and written as TOML instead. Both go through `AnnovateData`.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::rc::Rc;
+
+use {Annovate, Annotation, AnnoError, Fragment, Timestamp, ValueType, is_valid_tag};
+use data::{AnnovateData, FileData};
+use ids::is_valid_id;
+use reader::{AnnovateReader, Event};
+use toml::{self, Item, TomlValue};
+
+/// Parser and serializer of annovate files
+pub trait Backend {
+    /// Name as used by `--format`
+    fn name( &self ) -> &'static str;
+
+    fn parse( &self, text: &str ) -> Result<AnnovateData, AnnoError>;
+
+    /// Text of the whole file. `previous` is the current content of the file, a backend may keep unchanged parts of it.
+    fn serialize( &self, anno: &Annovate, previous: Option<&str> ) -> String;
+}
+
+/// The line format of `.annovate` files
+pub struct LineBackend;
+
+impl Backend for LineBackend {
+    fn name( &self ) -> &'static str {
+        "annovate"
+    }
+
+    fn parse( &self, text: &str ) -> Result<AnnovateData, AnnoError> {
+        let mut data = AnnovateData::default();
+        let mut positions: HashMap<String, usize> = HashMap::new();
+        let mut current: Option<usize> = None;
+        for event in AnnovateReader::new( text.as_bytes() ) {
+            match try!( event ) {
+                Event::Delta( None ) => current = None,
+                Event::FileStart( name ) | Event::Delta( Some( name ) ) => {
+                    //duplicate sections and delta records are merged
+                    let files = &mut data.files;
+                    current = Some( *positions.entry( name.clone() ).or_insert_with( || {
+                        files.push( FileData { name: name, tags: vec![], annotations: vec![] } );
+                        files.len() - 1
+                    } ) );
+                },
+                Event::Tag( tag ) => match current {
+                    None => data.directory_tags.push( tag ),
+                    Some( pos ) => data.files[ pos ].tags.push( tag )
+                },
+                Event::Annotation( anno ) => match current {
+                    None => data.directory.push( anno ),
+                    Some( pos ) => data.files[ pos ].annotations.push( anno )
+                }
+            }
+        }
+        Ok( data )
+    }
+
+    /// A file with CRLF line endings keeps them
+    fn serialize( &self, anno: &Annovate, previous: Option<&str> ) -> String {
+        let crlf = previous.map( |p| p.contains( "\r\n" ) ).unwrap_or( false );
+        let previous = previous.map( |p| p.replace( "\r\n", "\n" ) );
+        let patched = match previous {
+            Some( ref previous ) if anno.save_options.preserve_layout => anno.patch( previous ),
+            _ => None
+        };
+        let text = patched.unwrap_or_else( || anno.render() );
+        if crlf { text.replace( '\n', "\r\n" ) } else { text }
+    }
+}
+
+/// TOML with the fields of `AnnovateData`: `directory_tags`, `[[directory]]`, `[[files]]` and `[[files.annotations]]`
+pub struct TomlBackend;
+
+impl Backend for TomlBackend {
+    fn name( &self ) -> &'static str {
+        "toml"
+    }
+
+    fn parse( &self, text: &str ) -> Result<AnnovateData, AnnoError> {
+        parse_toml( text )
+    }
+
+    fn serialize( &self, anno: &Annovate, _previous: Option<&str> ) -> String {
+        let data = anno.to_data();
+        let mut out = String::new();
+        if !data.directory_tags.is_empty() {
+            out.push_str( &format!( "directory_tags = {}\n", toml_array( &data.directory_tags ) ) );
+        }
+        for annotation in &data.directory {
+            write_toml_annotation( &mut out, "directory", annotation );
+        }
+        for file in &data.files {
+            out.push_str( &format!( "\n[[files]]\nname = {}\n", toml_string( &file.name ) ) );
+            if !file.tags.is_empty() {
+                out.push_str( &format!( "tags = {}\n", toml_array( &file.tags ) ) );
+            }
+            for annotation in &file.annotations {
+                write_toml_annotation( &mut out, "files.annotations", annotation );
+            }
+        }
+        if out.starts_with( '\n' ) {
+            out.remove( 0 );
+        }
+        out
+    }
+}
+
+/// Backend for a name of `--format`
+pub fn backend_by_name( name: &str ) -> Option<Rc<dyn Backend>> {
+    match name {
+        "annovate" => Some( Rc::new( LineBackend ) ),
+        "toml" => Some( Rc::new( TomlBackend ) ),
+        _ => None
+    }
+}
+
+/// Backend for a meta file: TOML if the name ends in `.toml`, otherwise the line format
+pub fn backend_for_path( path: &Path ) -> Rc<dyn Backend> {
+    match path.extension().and_then( |e| e.to_str() ) {
+        Some( "toml" ) => Rc::new( TomlBackend ),
+        _ => Rc::new( LineBackend )
+    }
+}
+
+/// Basic string with escapes, always on one line
+fn toml_string( text: &str ) -> String {
+    let mut result = String::from( "\"" );
+    for c in text.chars() {
+        match c {
+            '"' => result.push_str( "\\\"" ),
+            '\\' => result.push_str( "\\\\" ),
+            '\n' => result.push_str( "\\n" ),
+            '\r' => result.push_str( "\\r" ),
+            '\t' => result.push_str( "\\t" ),
+            c if c.is_control() => result.push_str( &format!( "\\u{:04X}", c as u32 ) ),
+            c => result.push( c )
+        }
+    }
+    result.push( '"' );
+    result
+}
+
+fn toml_array( items: &[String] ) -> String {
+    format!( "[{}]", items.iter().map( |i| toml_string( i ) ).collect::<Vec<_>>().join( ", " ) )
+}
+
+fn write_toml_annotation( out: &mut String, table: &str, annotation: &Annotation ) {
+    out.push_str( &format!( "\n[[{}]]\nkey = {}\nvalue = {}\ncontext = {}\n",
+                            table, toml_string( &annotation.key ), toml_string( &annotation.value ), toml_string( &annotation.context ) ) );
+    if let Some( created ) = annotation.created {
+        out.push_str( &format!( "created = {}\n", created ) ); //a TOML date-time
+    }
+    if let Some( expires ) = annotation.expires {
+        out.push_str( &format!( "expires = {}\n", expires ) );
+    }
+    if let Some( ref region ) = annotation.region {
+        out.push_str( &format!( "region = {}\n", toml_string( &region.to_string() ) ) );
+    }
+    if annotation.protected {
+        out.push_str( "protected = true\n" );
+    }
+    if let Some( ref id ) = annotation.id {
+        out.push_str( &format!( "id = {}\n", toml_string( id ) ) );
+    }
+    if let Some( value_type ) = annotation.value_type {
+        out.push_str( &format!( "type = {}\n", toml_string( value_type.name() ) ) );
+    }
+}
+
+/// The table the following keys belong to
+#[derive(Clone, Copy, PartialEq)]
+enum Table {
+    Root,
+    Directory,
+    File,
+    FileAnnotation
+}
+
+/// Fields of an annotation table, checked when the table ends
+#[derive(Default)]
+struct AnnotationFields {
+    key: Option<String>,
+    value: Option<String>,
+    context: Option<String>,
+    created: Option<String>,
+    expires: Option<String>,
+    region: Option<String>,
+    id: Option<String>,
+    protected: bool,
+    value_type: Option<String>
+}
+
+/// Annotations of a TOML meta file
+fn parse_toml( text: &str ) -> Result<AnnovateData, AnnoError> {
+    let mut data = AnnovateData::default();
+    let mut table = Table::Root;
+    let mut fields = AnnotationFields::default();
+    let mut file_name: Option<String> = None;
+    for ( line, item ) in try!( toml::parse( text ) ) {
+        match item {
+            Item::Table { name, array } => {
+                //the previous table ends
+                try!( finish_table( line, &mut data, table, &mut fields, &mut file_name ) );
+                if !array {
+                    return toml::error( line, "only arrays of tables ([[...]]) are supported" );
+                }
+                table = match name.join( "." ).as_str() {
+                    "directory" => Table::Directory,
+                    "files" => {
+                        data.files.push( FileData { name: String::new(), tags: vec![], annotations: vec![] } );
+                        Table::File
+                    },
+                    "files.annotations" if !data.files.is_empty() => Table::FileAnnotation,
+                    "files.annotations" => return toml::error( line, "[[files.annotations]] before the first [[files]]" ),
+                    name => return toml::error( line, &format!( "unknown table `{}`", name ) )
+                };
+            },
+            Item::Pair( key, value ) => match ( table, key.as_str(), value ) {
+                ( Table::Root, "directory_tags", TomlValue::List( tags ) ) => data.directory_tags = try!( check_tags( line, tags ) ),
+                ( Table::File, "name", TomlValue::Text( name ) ) => file_name = Some( name ),
+                ( Table::File, "tags", TomlValue::List( tags ) ) => data.files.last_mut().unwrap().tags = try!( check_tags( line, tags ) ),
+                ( Table::Directory, "protected", TomlValue::Bool( protected ) ) | ( Table::FileAnnotation, "protected", TomlValue::Bool( protected ) ) => fields.protected = protected,
+                ( Table::Directory, field, TomlValue::Text( text ) ) | ( Table::FileAnnotation, field, TomlValue::Text( text ) ) => {
+                    let slot = match field {
+                        "key" => &mut fields.key,
+                        "value" => &mut fields.value,
+                        "context" => &mut fields.context,
+                        "created" => &mut fields.created,
+                        "expires" => &mut fields.expires,
+                        "region" => &mut fields.region,
+                        "id" => &mut fields.id,
+                        "type" => &mut fields.value_type,
+                        _ => return toml::error( line, &format!( "unknown key `{}`", field ) )
+                    };
+                    *slot = Some( text );
+                },
+                ( _, key, _ ) => return toml::error( line, &format!( "unknown key `{}` or wrong kind of value", key ) )
+            }
+        }
+    }
+    try!( finish_table( toml::last_line( text ), &mut data, table, &mut fields, &mut file_name ) );
+    Ok( data )
+}
+
+fn check_tags( line: u64, tags: Vec<String> ) -> Result<Vec<String>, AnnoError> {
+    match tags.iter().find( |t| !is_valid_tag( t ) ) {
+        Some( tag ) => toml::error( line, &format!( "invalid tag `{}`", tag ) ),
+        None => Ok( tags )
+    }
+}
+
+fn finish_table( line: u64, data: &mut AnnovateData, table: Table, fields: &mut AnnotationFields, file_name: &mut Option<String> ) -> Result<(), AnnoError> {
+    let fields = ::std::mem::replace( fields, AnnotationFields::default() );
+    match table {
+        Table::Root => Ok( () ),
+        Table::File => match file_name.take() {
+            Some( name ) => {
+                data.files.last_mut().unwrap().name = name; //the table was pushed with its header
+                Ok( () )
+            },
+            None => toml::error( line, "[[files]] without `name`" )
+        },
+        Table::Directory | Table::FileAnnotation => {
+            let mut annotation = match ( fields.key, fields.value ) {
+                ( Some( key ), Some( value ) ) => Annotation::new( key, value, fields.context.unwrap_or_default() ),
+                _ => return toml::error( line, "annotation without `key` or `value`" )
+            };
+            if let Some( created ) = fields.created {
+                match Timestamp::parse( &created ) {
+                    Some( created ) => annotation.created = Some( created ),
+                    None => return toml::error( line, &format!( "invalid timestamp `{}`", created ) )
+                }
+            }
+            if let Some( expires ) = fields.expires {
+                match Timestamp::parse( &expires ) {
+                    Some( expires ) => annotation.expires = Some( expires ),
+                    None => return toml::error( line, &format!( "invalid timestamp `{}`", expires ) )
+                }
+            }
+            if let Some( region ) = fields.region {
+                match Fragment::parse_region( &region ) {
+                    Some( region ) => annotation.region = Some( region ),
+                    None => return toml::error( line, &format!( "invalid region `{}`", region ) )
+                }
+            }
+            if let Some( id ) = fields.id {
+                if !is_valid_id( &id ) {
+                    return toml::error( line, &format!( "invalid ID `{}`", id ) );
+                }
+                annotation.id = Some( id );
+            }
+            annotation.protected = fields.protected;
+            if let Some( name ) = fields.value_type {
+                match ValueType::from_name( &name ) {
+                    Some( value_type ) => annotation = annotation.with_type( value_type ),
+                    None => return toml::error( line, &format!( "unknown type `{}`", name ) )
+                }
+            }
+            if table == Table::Directory {
+                data.directory.push( annotation );
+            } else {
+                data.files.last_mut().unwrap().annotations.push( annotation );
+            }
+            Ok( () )
+        }
+    }
+}
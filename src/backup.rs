@@ -0,0 +1,97 @@
+//Backups of a meta file. Before a save overwrites the file, its old content is copied to `<name>.bak.1`; the
+//previous `.bak.1` becomes `.bak.2` and so on, and backups beyond the number to keep are removed:
+//
+//    .annovate          current content
+//    .annovate.bak.1    before the last save
+//    .annovate.bak.2    before the save before
+//
+//Unlike the journal, backups are complete copies that can be read without annovate.
+
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use {Annovate, AnnoError, write_atomically};
+use data::AnnovateData;
+
+/// How many backups `save` keeps of the file it overwrites
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct BackupPolicy {
+    /// Number of backups, none if 0
+    pub keep: usize
+}
+
+impl BackupPolicy {
+    pub fn keep( count: usize ) -> BackupPolicy {
+        BackupPolicy { keep: count }
+    }
+
+    pub fn is_enabled( &self ) -> bool {
+        self.keep > 0
+    }
+}
+
+/// Backup `n` of `path`: `<name>.bak.<n>` next to it, 1 is the most recent
+pub fn backup_path( path: &Path, n: usize ) -> PathBuf {
+    let mut name = path.file_name().map( |f| f.to_os_string() ).unwrap_or_default();
+    name.push( format!( ".bak.{}", n ) );
+    path.with_file_name( name )
+}
+
+fn remove_if_exists( path: &Path ) -> Result<(), AnnoError> {
+    match fs::remove_file( path ) {
+        Err( ref e ) if e.kind() == io::ErrorKind::NotFound => Ok( () ),
+        result => result.map_err( |e| AnnoError::io( path, e ) )
+    }
+}
+
+/// Store `content`, the old content of `path`, as backup 1 and shift the other backups, keeping `policy.keep`
+pub fn rotate_backups( path: &Path, content: &str, policy: BackupPolicy ) -> Result<(), AnnoError> {
+    if !policy.is_enabled() {
+        return Ok( () );
+    }
+    //backups of a larger number to keep, and the oldest that is shifted out
+    let mut n = policy.keep;
+    while backup_path( path, n ).exists() {
+        try!( remove_if_exists( &backup_path( path, n ) ) );
+        n += 1;
+    }
+    for n in ( 1..policy.keep ).rev() {
+        let from = backup_path( path, n );
+        if from.exists() {
+            try!( fs::rename( &from, backup_path( path, n + 1 ) ).map_err( |e| AnnoError::io( &from, e ) ) );
+        }
+    }
+    write_atomically( &backup_path( path, 1 ), content.as_bytes() )
+}
+
+/// Numbers of the existing backups of `path`, most recent first
+pub fn list_backups( path: &Path ) -> Vec<usize> {
+    ( 1.. ).take_while( |&n| backup_path( path, n ).exists() ).collect()
+}
+
+impl Annovate {
+    /// Go back to the content of backup `n` (see `backup_path`). Returns false if there is no such backup.
+    /// When the result is saved, the current content becomes the most recent backup.
+    pub fn restore_backup( &mut self, n: usize ) -> Result<bool, AnnoError> {
+        try!( self.check_writable() );
+        let path = backup_path( &self.filename, n );
+        let mut text = String::new();
+        match File::open( &path ) {
+            Ok( mut file ) => { try!( file.read_to_string( &mut text ).map_err( |e| AnnoError::io( &path, e ) ) ); },
+            Err( ref e ) if e.kind() == io::ErrorKind::NotFound => return Ok( false ),
+            Err( e ) => return Err( AnnoError::io( &path, e ) )
+        }
+        let restored = if text.trim().is_empty() {
+            Annovate::from_data( AnnovateData::default(), &self.filename )
+        } else {
+            Annovate::from_data( try!( self.backend.parse( &text ) ), &self.filename )
+        };
+        self.dir = restored.dir;
+        self.files = restored.files;
+        self.file_order = restored.file_order;
+        self.dir_tags = restored.dir_tags;
+        self.tags = restored.tags;
+        Ok( self.mark_modified( true, true ) )
+    }
+}
@@ -0,0 +1,93 @@
+//A change log of the meta file, like commit messages: every recorded change is a protected annotation of the
+//directory with the key `changelog`, whose value is a one-line summary, optionally followed by an empty line and
+//details. `anno --message "..."` records the summary of a command together with what it changed:
+//
+//    >changelog
+//    =Mark the raw data as reviewed
+//    =
+//    =a.csv: added `reviewed`
+//    <annovate program, user alice
+//    !2026-10-16T09:05:00Z
+//    ^protected
+//    %3f9a1c2e07b4d815
+//
+//Being protected, entries are only changed or removed with `--force`.
+
+use {Annovate, Annotation, AnnoError, Timestamp};
+use changes::{Change, ChangeSet};
+use context;
+use ids;
+
+/// Key of the change-log entries of the directory
+pub const CHANGELOG_KEY: &'static str = "changelog";
+
+/// An entry of the change log
+#[derive(Clone, PartialEq, Debug)]
+pub struct ChangeRecord {
+    pub summary: String,
+    /// Empty if the change has no details
+    pub details: String,
+    pub context: String,
+    pub created: Option<Timestamp>
+}
+
+impl ChangeRecord {
+    fn from_annotation( annotation: &Annotation ) -> ChangeRecord {
+        let mut parts = annotation.value.splitn( 2, '\n' );
+        let summary = parts.next().unwrap_or( "" ).to_string(); //splitn yields at least one part
+        let details = parts.next().map( |rest| rest.trim_start_matches( '\n' ).to_string() ).unwrap_or( String::new() );
+        ChangeRecord { summary: summary, details: details, context: annotation.context.clone(), created: annotation.created }
+    }
+}
+
+/// One line per change of `changes`, like `a.csv: added `reviewed``, as details of a change-log entry
+pub fn describe_changes( changes: &ChangeSet ) -> String {
+    let mut lines = Vec::new();
+    for target in &changes.targets {
+        let name = target.target.as_ref().map( |t| t.as_str() ).unwrap_or( "directory" );
+        for change in &target.changes {
+            lines.push( match *change {
+                Change::Added( ref a ) => format!( "{}: added `{}`", name, a.key ),
+                Change::Removed( ref a ) => format!( "{}: removed `{}`", name, a.key ),
+                Change::TagAdded( ref tag ) => format!( "{}: tagged +{}", name, tag ),
+                Change::TagRemoved( ref tag ) => format!( "{}: untagged +{}", name, tag )
+            } );
+        }
+    }
+    lines.join( "\n" )
+}
+
+/// The directory annotation of a change-log entry, e.g. to append it as delta record
+pub fn change_entry( summary: &str, details: &str, context: &str ) -> Result<Annotation, AnnoError> {
+    if summary.trim().is_empty() || summary.contains( '\n' ) {
+        return Err( AnnoError::FormatError( "the summary of a change must be a single line that is not empty".to_string() ) );
+    }
+    let value = if details.is_empty() { summary.to_string() } else { format!( "{}\n\n{}", summary, details ) };
+    Ok( Annotation::new( CHANGELOG_KEY.to_string(), value, context.to_string() ).with_created( Timestamp::now() )
+                                                                               .with_id( ids::generate_id() )
+                                                                               .with_protected( true ) )
+}
+
+impl Annovate {
+    /// Add an entry to the change log with the current user in its context. `summary` is a single line,
+    /// `details` may be empty.
+    pub fn record_change( &mut self, summary: &str, details: &str ) -> Result<(), AnnoError> {
+        self.record_change_in_context( summary, details, &context::library_context() )
+    }
+
+    /// Like `record_change` with the given context
+    pub fn record_change_in_context( &mut self, summary: &str, details: &str, context: &str ) -> Result<(), AnnoError> {
+        let entry = try!( change_entry( summary, details, context ) );
+        //the key is protected against changes, but adding entries is what the change log is for
+        let override_protection = self.override_protection;
+        self.override_protection = true;
+        let result = self.add_directory_annotation( entry );
+        self.override_protection = override_protection;
+        result
+    }
+
+    /// The entries of the change log, oldest first
+    pub fn change_log( &self ) -> Vec<ChangeRecord> {
+        self.get_directory_annotations().iter().filter( |a| a.key == CHANGELOG_KEY ).map( ChangeRecord::from_annotation ).collect()
+    }
+}
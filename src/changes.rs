@@ -0,0 +1,93 @@
+use {Annovate, Annotation, AnnoContainer, OpenMode, TagSet};
+
+/// A single difference of the annotations or tags of a target
+#[derive(Clone, PartialEq, Debug)]
+pub enum Change {
+    Added( Annotation ),
+    Removed( Annotation ),
+    TagAdded( String ),
+    TagRemoved( String )
+}
+
+/// Changes of one target, `None` for the directory
+#[derive(Clone, PartialEq, Debug)]
+pub struct TargetChanges {
+    pub target: Option<String>,
+    pub changes: Vec<Change>
+}
+
+/// Everything that differs between two states of the metadata, e.g. before and after a command
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct ChangeSet {
+    pub targets: Vec<TargetChanges>
+}
+
+/// Annotations of `after` that `before` does not have (duplicates are counted), followed by the opposite
+fn diff_containers( before: &AnnoContainer, after: &AnnoContainer ) -> Vec<Change> {
+    let mut unmatched: Vec<&Annotation> = before.iter().collect();
+    let mut changes = Vec::new();
+    for annotation in after {
+        match unmatched.iter().position( |a| *a == annotation ) {
+            Some( pos ) => { unmatched.remove( pos ); },
+            None => changes.push( Change::Added( annotation.clone() ) )
+        }
+    }
+    changes.extend( unmatched.into_iter().map( |a| Change::Removed( a.clone() ) ) );
+    changes
+}
+
+fn diff_tags( before: Option<&TagSet>, after: Option<&TagSet> ) -> Vec<Change> {
+    let empty = TagSet::new();
+    let ( before, after ) = ( before.unwrap_or( &empty ), after.unwrap_or( &empty ) );
+    let mut changes: Vec<Change> = after.difference( before ).map( |t| Change::TagAdded( t.clone() ) ).collect();
+    changes.extend( before.difference( after ).map( |t| Change::TagRemoved( t.clone() ) ) );
+    changes
+}
+
+impl ChangeSet {
+    /// Differences from `before` to `after`: the directory first, then the targets in the order of `after`
+    /// followed by those that only `before` has
+    pub fn between( before: &Annovate, after: &Annovate ) -> ChangeSet {
+        let mut targets = Vec::new();
+        let mut dir_changes = diff_containers( &before.dir, &after.dir );
+        dir_changes.extend( diff_tags( Some( &before.dir_tags ), Some( &after.dir_tags ) ) );
+        if !dir_changes.is_empty() {
+            targets.push( TargetChanges { target: None, changes: dir_changes } );
+        }
+        let mut names = after.get_files();
+        names.extend( before.get_files().into_iter().filter( |f| !after.files.contains_key( f ) ) );
+        let empty = AnnoContainer::new();
+        for name in names {
+            let mut changes = diff_containers( before.files.get( &name ).unwrap_or( &empty ), after.files.get( &name ).unwrap_or( &empty ) );
+            changes.extend( diff_tags( before.tags.get( &name ), after.tags.get( &name ) ) );
+            if !changes.is_empty() {
+                targets.push( TargetChanges { target: Some( name ), changes: changes } );
+            }
+        }
+        ChangeSet { targets: targets }
+    }
+
+    pub fn is_empty( &self ) -> bool {
+        self.targets.is_empty()
+    }
+
+    /// Number of changes of all targets
+    pub fn len( &self ) -> usize {
+        self.targets.iter().map( |t| t.changes.len() ).sum()
+    }
+}
+
+impl Annovate {
+    /// Copy of the annotations and tags that does not hold the lock, e.g. to compare it with the state after a change
+    pub fn snapshot( &self ) -> Annovate {
+        let mut snapshot = Annovate::from_content( self.dir.clone(), self.files.clone(), self.file_order.clone(), self.dir_tags.clone(), self.tags.clone(), &self.filename );
+        snapshot.mode = OpenMode::ReadOnly;
+        snapshot.save_options = self.save_options.clone();
+        snapshot.backend = self.backend.clone();
+        snapshot.lock_timeout = self.lock_timeout;
+        snapshot.undone.set( self.undone.get() );
+        snapshot.override_protection = self.override_protection;
+        snapshot.quota = self.quota;
+        snapshot
+    }
+}
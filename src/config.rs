@@ -0,0 +1,306 @@
+//User configuration in `~/.config/annovate/config.toml`, shared by the command line program and other programs
+//that embed annovate. A missing configuration file is an empty configuration.
+//
+//    meta_name = ".annovate"
+//    context_fields = "user,time"
+//    color = "auto"                # auto, always or never
+//    list_key = "description"
+//    dotfiles = false
+//    backups = 3                   # copies of the meta file kept before saving, 0 for none
+//    journal = 50                  # saves that undo can revert, 0 for no journal
+//    integrity = "warn"            # hash footer of the meta file: off, warn or error if it does not match
+//    identity = "hash"             # also find annotated files by their content: name or hash
+//    encrypt_keys = ["secret.*"]   # keys whose values put encrypts, see `crypt`
+//    max_per_file = 1000           # annotations of a file or the directory before a quota warning, see `quota`
+//    max_per_key = 100             # entries of one key of a file or the directory
+//    quota = "dedup"               # warn, or remove exact duplicates first, when a limit is exceeded
+//    normalization = "nfc"         # Unicode form of filenames: none, nfc or nfd (default nfc on macOS, else none)
+//    language = "de"               # of messages and help: en or de, before LC_ALL, LC_MESSAGES and LANG
+//
+//    [directories."/home/me/projects"]
+//    context_fields = "user,git,time"
+
+use std::env;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use AnnoError;
+use identity::IdentityMode;
+use integrity::IntegrityPolicy;
+use journal;
+use migrate;
+use normalization::Normalization;
+use quota::{Quota, QuotaAction};
+use toml::{self, Item, TomlValue};
+
+/// Environment variable with the path of the configuration file
+pub const CONFIG_VAR: &'static str = "ANNOVATE_CONFIG";
+
+/// When output is colored
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ColorMode {
+    /// If stdout is a terminal
+    Auto,
+    Always,
+    Never
+}
+
+impl ColorMode {
+    pub fn from_name( name: &str ) -> Option<ColorMode> {
+        match name {
+            "auto" => Some( ColorMode::Auto ),
+            "always" => Some( ColorMode::Always ),
+            "never" => Some( ColorMode::Never ),
+            _ => None
+        }
+    }
+}
+
+/// Settings of the configuration, `None` for those that are not set
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Settings {
+    /// Name of meta files
+    pub meta_name: Option<String>,
+    /// Parts of the context of new annotations, comma separated like `--context-fields`
+    pub context_fields: Option<String>,
+    pub color: Option<ColorMode>,
+    /// Key that `list` shows without a key
+    pub list_key: Option<String>,
+    /// Whether dotfiles are considered like with `-d`
+    pub dotfiles: Option<bool>,
+    /// Number of backups of the meta file that saving keeps
+    pub backups: Option<usize>,
+    /// Number of saves kept in the journal, none if 0
+    pub journal: Option<usize>,
+    /// Whether meta files get an integrity footer and what happens if they do not match it
+    pub integrity: Option<IntegrityPolicy>,
+    /// Whether annotated files are found by their content as well
+    pub identity: Option<IdentityMode>,
+    /// Keys or namespace patterns like `secret.*` whose values are encrypted, comma separated
+    pub encrypt_keys: Option<String>,
+    /// Annotations of a file or the directory before the quota is exceeded
+    pub max_per_file: Option<usize>,
+    /// Entries of one key before the quota is exceeded
+    pub max_per_key: Option<usize>,
+    /// What adding an annotation that exceeds the quota does
+    pub quota_action: Option<QuotaAction>,
+    /// Unicode form that filenames are stored and looked up in
+    pub normalization: Option<Normalization>,
+    /// Language of messages like `de`, which the locale of the environment does not override
+    pub language: Option<String>
+}
+
+impl Settings {
+    /// Settings of `other` that are set replace those of `self`
+    pub fn overridden_by( mut self, other: &Settings ) -> Settings {
+        if other.meta_name.is_some() { self.meta_name = other.meta_name.clone(); }
+        if other.context_fields.is_some() { self.context_fields = other.context_fields.clone(); }
+        if other.color.is_some() { self.color = other.color; }
+        if other.list_key.is_some() { self.list_key = other.list_key.clone(); }
+        if other.dotfiles.is_some() { self.dotfiles = other.dotfiles; }
+        if other.backups.is_some() { self.backups = other.backups; }
+        if other.journal.is_some() { self.journal = other.journal; }
+        if other.integrity.is_some() { self.integrity = other.integrity; }
+        if other.identity.is_some() { self.identity = other.identity; }
+        if other.encrypt_keys.is_some() { self.encrypt_keys = other.encrypt_keys.clone(); }
+        if other.max_per_file.is_some() { self.max_per_file = other.max_per_file; }
+        if other.max_per_key.is_some() { self.max_per_key = other.max_per_key; }
+        if other.quota_action.is_some() { self.quota_action = other.quota_action; }
+        if other.normalization.is_some() { self.normalization = other.normalization; }
+        if other.language.is_some() { self.language = other.language.clone(); }
+        self
+    }
+
+    /// The name of meta files: `ANNOVATE_META_NAME` if it is set, then the configured name, then `.annovate`
+    pub fn meta_name( &self ) -> String {
+        let from_env = env::var( migrate::META_NAME_VAR ).map( |n| !n.trim().is_empty() ).unwrap_or( false );
+        match self.meta_name {
+            Some( ref name ) if !from_env => name.clone(),
+            _ => migrate::meta_name()
+        }
+    }
+
+    pub fn context_fields( &self ) -> &str {
+        self.context_fields.as_ref().map( |f| f.as_str() ).unwrap_or( "time" )
+    }
+
+    pub fn color( &self ) -> ColorMode {
+        self.color.unwrap_or( ColorMode::Auto )
+    }
+
+    pub fn list_key( &self ) -> &str {
+        self.list_key.as_ref().map( |k| k.as_str() ).unwrap_or( "description" )
+    }
+
+    pub fn dotfiles( &self ) -> bool {
+        self.dotfiles.unwrap_or( false )
+    }
+
+    pub fn backups( &self ) -> usize {
+        self.backups.unwrap_or( 0 )
+    }
+
+    pub fn journal( &self ) -> usize {
+        self.journal.unwrap_or( journal::DEFAULT_JOURNAL_ENTRIES )
+    }
+
+    pub fn integrity( &self ) -> IntegrityPolicy {
+        self.integrity.unwrap_or_default()
+    }
+
+    pub fn identity( &self ) -> IdentityMode {
+        self.identity.unwrap_or_default()
+    }
+
+    pub fn encrypt_keys( &self ) -> Vec<&str> {
+        self.encrypt_keys.as_ref().map( |keys| keys.split( ',' ).map( |k| k.trim() ).filter( |k| !k.is_empty() ).collect() ).unwrap_or_default()
+    }
+
+    pub fn quota( &self ) -> Quota {
+        Quota { max_per_target: self.max_per_file, max_per_key: self.max_per_key, action: self.quota_action.unwrap_or_default() }
+    }
+
+    pub fn normalization( &self ) -> Normalization {
+        self.normalization.unwrap_or_default()
+    }
+
+    pub fn language( &self ) -> Option<&str> {
+        self.language.as_ref().map( |l| l.as_str() )
+    }
+}
+
+/// The configuration: settings for all directories and overrides for some directories and their subdirectories
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Config {
+    pub defaults: Settings,
+    pub directories: Vec<( PathBuf, Settings )>
+}
+
+/// `~` at the start of a configured path is the home directory
+fn expand_home( path: &str ) -> PathBuf {
+    match ( path.starts_with( "~/" ) || path == "~", env::var_os( "HOME" ) ) {
+        ( true, Some( home ) ) => PathBuf::from( home ).join( path[ 1.. ].trim_start_matches( '/' ) ),
+        _ => PathBuf::from( path )
+    }
+}
+
+fn set_setting( settings: &mut Settings, line: u64, key: &str, value: TomlValue ) -> Result<(), AnnoError> {
+    match ( key, value ) {
+        ( "meta_name", TomlValue::Text( name ) ) => settings.meta_name = Some( name ),
+        ( "context_fields", TomlValue::Text( fields ) ) => settings.context_fields = Some( fields ),
+        ( "context_fields", TomlValue::List( fields ) ) => settings.context_fields = Some( fields.join( "," ) ),
+        ( "color", TomlValue::Text( name ) ) => match ColorMode::from_name( &name ) {
+            Some( mode ) => settings.color = Some( mode ),
+            None => return toml::error( line, &format!( "unknown color mode `{}` (auto, always or never)", name ) )
+        },
+        ( "color", TomlValue::Bool( color ) ) => settings.color = Some( if color { ColorMode::Auto } else { ColorMode::Never } ),
+        ( "list_key", TomlValue::Text( key ) ) => settings.list_key = Some( key ),
+        ( "dotfiles", TomlValue::Bool( dotfiles ) ) => settings.dotfiles = Some( dotfiles ),
+        ( "backups", TomlValue::Text( count ) ) => match count.parse::<usize>() {
+            Ok( count ) => settings.backups = Some( count ),
+            Err( _ ) => return toml::error( line, &format!( "invalid number of backups `{}`", count ) )
+        },
+        ( "journal", TomlValue::Text( count ) ) => match count.parse::<usize>() {
+            Ok( count ) => settings.journal = Some( count ),
+            Err( _ ) => return toml::error( line, &format!( "invalid number of journal entries `{}`", count ) )
+        },
+        ( "journal", TomlValue::Bool( false ) ) => settings.journal = Some( 0 ),
+        ( "integrity", TomlValue::Text( name ) ) => match IntegrityPolicy::from_name( &name ) {
+            Some( policy ) => settings.integrity = Some( policy ),
+            None => return toml::error( line, &format!( "unknown integrity mode `{}` (off, warn or error)", name ) )
+        },
+        ( "identity", TomlValue::Text( name ) ) => match IdentityMode::from_name( &name ) {
+            Some( mode ) => settings.identity = Some( mode ),
+            None => return toml::error( line, &format!( "unknown identity mode `{}` (name or hash)", name ) )
+        },
+        ( "encrypt_keys", TomlValue::Text( keys ) ) => settings.encrypt_keys = Some( keys ),
+        ( "encrypt_keys", TomlValue::List( keys ) ) => settings.encrypt_keys = Some( keys.join( "," ) ),
+        ( "max_per_file", TomlValue::Text( count ) ) | ( "max_per_key", TomlValue::Text( count ) ) => match count.parse::<usize>() {
+            Ok( count ) if key == "max_per_file" => settings.max_per_file = Some( count ),
+            Ok( count ) => settings.max_per_key = Some( count ),
+            Err( _ ) => return toml::error( line, &format!( "invalid limit `{}` of `{}`", count, key ) )
+        },
+        ( "quota", TomlValue::Text( name ) ) => match QuotaAction::from_name( &name ) {
+            Some( action ) => settings.quota_action = Some( action ),
+            None => return toml::error( line, &format!( "unknown quota action `{}` (warn or dedup)", name ) )
+        },
+        ( "normalization", TomlValue::Text( name ) ) => match Normalization::from_name( &name ) {
+            Some( form ) => settings.normalization = Some( form ),
+            None => return toml::error( line, &format!( "unknown normalization `{}` (none, nfc or nfd)", name ) )
+        },
+        ( "language", TomlValue::Text( name ) ) => settings.language = Some( name ),
+        ( key, _ ) => return toml::error( line, &format!( "unknown setting `{}` or wrong kind of value", key ) )
+    }
+    Ok( () )
+}
+
+impl Config {
+    /// Read a configuration from TOML
+    pub fn parse( text: &str ) -> Result<Config, AnnoError> {
+        let mut config = Config::default();
+        //None for the top level, otherwise the index in `directories`
+        let mut table: Option<usize> = None;
+        for ( line, item ) in try!( toml::parse( text ) ) {
+            match item {
+                Item::Table { ref name, array: false } if name.len() == 2 && name[ 0 ] == "directories" => {
+                    config.directories.push( ( expand_home( &name[ 1 ] ), Settings::default() ) );
+                    table = Some( config.directories.len() - 1 );
+                },
+                Item::Table { .. } => return toml::error( line, "only tables [directories.\"<path>\"] are supported" ),
+                Item::Pair( key, value ) => {
+                    let settings = match table {
+                        Some( index ) => &mut config.directories[ index ].1,
+                        None => &mut config.defaults
+                    };
+                    try!( set_setting( settings, line, &key, value ) );
+                }
+            }
+        }
+        Ok( config )
+    }
+
+    /// Read the configuration file at `path`; a missing file is an empty configuration
+    pub fn load( path: &Path ) -> Result<Config, AnnoError> {
+        let mut text = String::new();
+        match File::open( path ) {
+            Ok( mut file ) => try!( file.read_to_string( &mut text ).map_err( |e| AnnoError::io( path, e ) ) ),
+            Err( ref e ) if e.kind() == io::ErrorKind::NotFound => return Ok( Config::default() ),
+            Err( e ) => return Err( AnnoError::io( path, e ) )
+        };
+        Config::parse( &text ).map_err( |e| e.at( path ) )
+    }
+
+    /// `ANNOVATE_CONFIG`, otherwise `annovate/config.toml` in `XDG_CONFIG_HOME` or `~/.config` (`%APPDATA%` on Windows)
+    pub fn default_path() -> Option<PathBuf> {
+        if let Some( path ) = env::var_os( CONFIG_VAR ).filter( |p| !p.is_empty() ) {
+            return Some( PathBuf::from( path ) );
+        }
+        let config_dir = if cfg!( windows ) {
+            env::var_os( "APPDATA" ).map( PathBuf::from )
+        } else {
+            env::var_os( "XDG_CONFIG_HOME" ).filter( |p| !p.is_empty() ).map( PathBuf::from )
+                .or_else( || env::var_os( "HOME" ).map( |home| PathBuf::from( home ).join( ".config" ) ) )
+        };
+        config_dir.map( |dir| dir.join( "annovate" ).join( "config.toml" ) )
+    }
+
+    /// The configuration of the user, empty if there is none
+    pub fn load_default() -> Result<Config, AnnoError> {
+        match Config::default_path() {
+            Some( path ) => Config::load( &path ),
+            None => Ok( Config::default() )
+        }
+    }
+
+    /// Settings for `dir`: the top-level settings, overridden by those of the directories that contain `dir`,
+    /// the innermost last
+    pub fn settings_for( &self, dir: &Path ) -> Settings {
+        let dir = dir.canonicalize().unwrap_or( dir.to_path_buf() );
+        let mut matching: Vec<&( PathBuf, Settings )> = self.directories.iter()
+            .filter( |&&( ref path, _ )| dir.starts_with( path.canonicalize().unwrap_or( path.clone() ) ) )
+            .collect();
+        matching.sort_by_key( |&&( ref path, _ )| path.components().count() );
+        matching.into_iter().fold( self.defaults.clone(), |settings, &( _, ref overrides )| settings.overridden_by( overrides ) )
+    }
+}
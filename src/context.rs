@@ -0,0 +1,145 @@
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use time;
+
+use AnnoError;
+use git::{current_branch, run_git};
+
+/// Source of one part of the context of new annotations
+pub trait ContextProvider {
+    /// Text of this part, `None` if it is not available (e.g. outside of a git repository)
+    fn describe( &self ) -> Option<String>;
+}
+
+/// Name of the current user from `USER` or `USERNAME`
+pub struct UserProvider;
+
+impl ContextProvider for UserProvider {
+    fn describe( &self ) -> Option<String> {
+        env::var( "USER" ).or_else( |_| env::var( "USERNAME" ) ).ok()
+                          .filter( |user| !user.is_empty() )
+                          .map( |user| format!( "user {}", user ) )
+    }
+}
+
+/// Name of this computer
+pub struct HostProvider;
+
+impl ContextProvider for HostProvider {
+    fn describe( &self ) -> Option<String> {
+        let from_file = || {
+            let mut name = String::new();
+            File::open( "/etc/hostname" ).and_then( |mut f| f.read_to_string( &mut name ) ).ok().map( |_| name )
+        };
+        let from_command = || {
+            Command::new( "hostname" ).output().ok()
+                                      .filter( |output| output.status.success() )
+                                      .map( |output| String::from_utf8_lossy( &output.stdout ).into_owned() )
+        };
+        env::var( "HOSTNAME" ).ok().or_else( from_file ).or_else( from_command )
+                              .map( |host| host.trim().to_string() )
+                              .filter( |host| !host.is_empty() )
+                              .map( |host| format!( "host {}", host ) )
+    }
+}
+
+/// Abbreviated hash of the git HEAD of a directory
+pub struct GitProvider {
+    pub dir: PathBuf
+}
+
+impl ContextProvider for GitProvider {
+    fn describe( &self ) -> Option<String> {
+        run_git( &self.dir, &[ "rev-parse", "--short", "HEAD" ] ).ok()
+                                                                 .map( |hash| hash.trim().to_string() )
+                                                                 .filter( |hash| !hash.is_empty() )
+                                                                 .map( |hash| format!( "git {}", hash ) )
+    }
+}
+
+/// Checked out git branch of a directory
+pub struct BranchProvider {
+    pub dir: PathBuf
+}
+
+impl ContextProvider for BranchProvider {
+    fn describe( &self ) -> Option<String> {
+        current_branch( &self.dir ).ok().and_then( |branch| branch ).map( |branch| format!( "branch {}", branch ) )
+    }
+}
+
+/// Local date and time like `16.10.2026 09:05:00`
+pub struct TimeProvider;
+
+impl ContextProvider for TimeProvider {
+    fn describe( &self ) -> Option<String> {
+        let now = time::now();
+        Some( format!( "{}.{}.{} {:02}:{:02}:{:02}",
+                       now.tm_mday,
+                       now.tm_mon + 1,
+                       now.tm_year + 1900,
+                       now.tm_hour,
+                       now.tm_min,
+                       now.tm_sec ) )
+    }
+}
+
+/// Provider for a field name of `--context-fields`: user, host, branch, git or time.
+/// `dir` is the directory whose git HEAD is recorded.
+pub fn provider_by_name( name: &str, dir: &Path ) -> Option<Box<dyn ContextProvider>> {
+    match name {
+        "user" => Some( Box::new( UserProvider ) ),
+        "host" => Some( Box::new( HostProvider ) ),
+        "branch" => Some( Box::new( BranchProvider { dir: dir.to_path_buf() } ) ),
+        "git" => Some( Box::new( GitProvider { dir: dir.to_path_buf() } ) ),
+        "time" => Some( Box::new( TimeProvider ) ),
+        _ => None
+    }
+}
+
+/// Assembles the context of new annotations from a prefix and the parts of several providers,
+/// e.g. `annovate program, user alice, git 1a2b3c4, 16.10.2026 09:05:00`
+pub struct ContextBuilder {
+    prefix: String,
+    providers: Vec<Box<dyn ContextProvider>>
+}
+
+impl ContextBuilder {
+    pub fn new( prefix: &str ) -> ContextBuilder {
+        ContextBuilder { prefix: prefix.to_string(), providers: Vec::new() }
+    }
+
+    /// Builder with the providers of a comma separated list of field names like `user,host,git,time`
+    pub fn from_fields( prefix: &str, fields: &str, dir: &Path ) -> Result<ContextBuilder, AnnoError> {
+        let mut builder = ContextBuilder::new( prefix );
+        for name in fields.split( ',' ).map( |f| f.trim() ).filter( |f| !f.is_empty() ) {
+            match provider_by_name( name, dir ) {
+                Some( provider ) => builder = builder.with( provider ),
+                None => return Err( AnnoError::FormatError( format!( "unknown context field `{}`", name ) ) )
+            }
+        }
+        Ok( builder )
+    }
+
+    pub fn with( mut self, provider: Box<dyn ContextProvider> ) -> ContextBuilder {
+        self.providers.push( provider );
+        self
+    }
+
+    /// The prefix followed by the available parts, separated by commas
+    pub fn build( &self ) -> String {
+        let mut parts = vec![ self.prefix.clone() ];
+        parts.extend( self.providers.iter().filter_map( |p| p.describe() ) );
+        parts.retain( |p| !p.is_empty() );
+        parts.join( ", " )
+    }
+}
+
+/// Context of annotations that the library adds without one from the caller, like `annovate library, user alice`
+pub fn library_context() -> String {
+    ContextBuilder::new( "annovate library" ).with( Box::new( UserProvider ) ).build()
+}
@@ -0,0 +1,99 @@
+//Copies of annotations from one file to others. A `CopySpec` names the source, the destinations, the keys to copy
+//and how the keys are renamed on the way:
+//
+//    owner=original_owner      the key `owner` is copied as `original_owner`
+//    *=upstream.*              every other key is copied into the namespace `upstream`
+//
+//Only the most recent entry of each key is copied, with a new creation time and `copied from <src>` in its context.
+
+use {Annovate, Annotation, AnnoContainer, AnnoError, Timestamp};
+
+/// Stands for every key that has no mapping of its own, and for the original key in the new name
+pub const ANY_KEY: &'static str = "*";
+
+/// What `Annovate::copy` copies from where to where
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct CopySpec {
+    pub src: String,
+    pub destinations: Vec<String>,
+    /// Keys or namespace patterns like `quality.*` to copy, all keys if empty
+    pub keys: Vec<String>,
+    /// Renamings `( from, to )` of keys, see above
+    pub map: Vec<( String, String )>,
+    /// Context of the copies, followed by `copied from <src>`
+    pub context: String
+}
+
+impl CopySpec {
+    pub fn new( src: &str, destinations: &[String] ) -> CopySpec {
+        CopySpec { src: src.to_string(), destinations: destinations.to_vec(), ..CopySpec::default() }
+    }
+
+    pub fn with_keys( mut self, keys: &[String] ) -> CopySpec {
+        self.keys = keys.to_vec();
+        self
+    }
+
+    pub fn with_map( mut self, map: Vec<( String, String )> ) -> CopySpec {
+        self.map = map;
+        self
+    }
+
+    pub fn with_context( mut self, context: &str ) -> CopySpec {
+        self.context = context.to_string();
+        self
+    }
+
+    /// Parse renamings like `owner=original_owner,*=upstream.*`. Returns `None` if a part has no `=` or an empty side.
+    pub fn parse_map( text: &str ) -> Option<Vec<( String, String )>> {
+        text.split( ',' ).map( |part| {
+            let mut sides = part.splitn( 2, '=' ).map( |side| side.trim() );
+            match ( sides.next(), sides.next() ) {
+                ( Some( from ), Some( to ) ) if !from.is_empty() && !to.is_empty() => Some( ( from.to_string(), to.to_string() ) ),
+                _ => None
+            }
+        } ).collect()
+    }
+
+    /// Whether the annotation has one of the keys to copy
+    pub fn selects( &self, annotation: &Annotation ) -> bool {
+        self.keys.is_empty() || self.keys.iter().any( |k| annotation.key_matches( k ) )
+    }
+
+    /// The key under which `key` is copied
+    pub fn target_key( &self, key: &str ) -> String {
+        let mapping = self.map.iter().find( |m| m.0 == key ).or_else( || self.map.iter().find( |m| m.0 == ANY_KEY ) );
+        match mapping {
+            Some( &( _, ref to ) ) => to.replace( ANY_KEY, key ),
+            None => key.to_string()
+        }
+    }
+}
+
+impl Annovate {
+    /// Copy the most recent entry of each selected key of `spec.src` to every destination.
+    /// Returns the number of copied annotations, counted for every destination.
+    pub fn copy( &mut self, spec: &CopySpec ) -> Result<usize, AnnoError> {
+        try!( self.check_writable() );
+        let context = if spec.context.is_empty() { format!( "copied from {}", spec.src ) } else { format!( "{}, copied from {}", spec.context, spec.src ) };
+        let mut copies: AnnoContainer = Vec::new();
+        let mut copied_keys: Vec<&str> = Vec::new();
+        if let Some( annotations ) = self.files.get( &spec.src ) {
+            for annotation in annotations.iter().rev() {
+                if spec.selects( annotation ) && !copied_keys.contains( &annotation.key.as_str() ) {
+                    copied_keys.push( &annotation.key );
+                    copies.push( Annotation::new( spec.target_key( &annotation.key ), annotation.value.clone(), context.clone() ).with_created( Timestamp::now() ) );
+                }
+            }
+        }
+        copies.reverse();
+        let mut count = 0;
+        for dst in &spec.destinations {
+            for copy in &copies {
+                try!( self.add_file_annotation( dst, copy.clone() ) );
+                count += 1;
+            }
+        }
+        Ok( count )
+    }
+}
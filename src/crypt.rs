@@ -0,0 +1,223 @@
+//Encrypted values for confidential annotations. `anno put --encrypt`, and every put of a key that matches
+//`encrypt_keys` in the configuration, stores the value encrypted with the passphrase in `ANNOVATE_PASSPHRASE`:
+//
+//    >secret.note
+//    =enc:v1:q1b3...
+//    <annovate program, 16.10.2026 12:30:00
+//
+//`query` and `get` decrypt such values when the passphrase is set and show `<encrypted>` otherwise. Keys, contexts
+//and times stay readable. The value is encrypted with ChaCha20 (RFC 8439) and authenticated with HMAC-SHA-256;
+//both keys are derived from the passphrase and a random salt with PBKDF2-HMAC-SHA-256. The encoded value holds
+//the salt, the nonce, the ciphertext and the authentication tag.
+//
+//The ciphers are those of the RustCrypto crates and salts and nonces come from the random number generator of the
+//operating system, both behind the default feature `crypt`. Without it, or without a random number generator,
+//encrypting and decrypting fail instead of weakening the encryption.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::env;
+#[cfg(feature = "crypt")]
+use std::io;
+
+#[cfg(feature = "crypt")]
+use chacha20::ChaCha20;
+#[cfg(feature = "crypt")]
+use chacha20::cipher::{KeyIvInit, StreamCipher, StreamCipherSeek};
+#[cfg(feature = "crypt")]
+use hmac::{Hmac, Mac};
+#[cfg(feature = "crypt")]
+use rustc_serialize::base64::{FromBase64, ToBase64, STANDARD};
+#[cfg(feature = "crypt")]
+use sha2::Sha256;
+
+use {Annotation, AnnoError};
+
+/// Start of an encrypted value
+pub const ENCRYPTED_PREFIX: &'static str = "enc:v1:";
+
+/// Environment variable with the passphrase
+pub const PASSPHRASE_VAR: &'static str = "ANNOVATE_PASSPHRASE";
+
+/// Shown instead of an encrypted value that cannot be decrypted
+pub const ENCRYPTED_PLACEHOLDER: &'static str = "<encrypted>";
+
+/// PBKDF2 rounds, which make guessing the passphrase slow
+pub const KDF_ITERATIONS: u32 = 100000;
+
+const SALT_LEN: usize = 16;
+#[cfg(feature = "crypt")]
+const NONCE_LEN: usize = 12;
+#[cfg(feature = "crypt")]
+const TAG_LEN: usize = 32;
+
+#[cfg(feature = "crypt")]
+type HmacSha256 = Hmac<Sha256>;
+
+#[cfg(feature = "crypt")]
+fn new_mac( key: &[u8] ) -> HmacSha256 {
+    HmacSha256::new_from_slice( key ).expect( "HMAC takes keys of any length" )
+}
+
+/// HMAC-SHA-256 of `data`
+#[cfg(feature = "crypt")]
+pub fn hmac_sha256( key: &[u8], data: &[u8] ) -> [u8; 32] {
+    let mut mac = new_mac( key );
+    mac.update( data );
+    mac.finalize().into_bytes().into()
+}
+
+/// PBKDF2 (RFC 8018) with HMAC-SHA-256, `out.len()` bytes
+#[cfg(feature = "crypt")]
+pub fn pbkdf2_sha256( passphrase: &[u8], salt: &[u8], iterations: u32, out: &mut [u8] ) {
+    ::pbkdf2::pbkdf2_hmac::<Sha256>( passphrase, salt, iterations, out );
+}
+
+/// XOR `data` with the ChaCha20 key stream (RFC 8439) starting at block `counter`; encrypts and decrypts
+#[cfg(feature = "crypt")]
+pub fn chacha20( key: &[u8; 32], nonce: &[u8; NONCE_LEN], counter: u32, data: &mut [u8] ) {
+    let mut cipher = ChaCha20::new( key.into(), nonce.into() );
+    cipher.seek( counter as u64 * 64 );
+    cipher.apply_keystream( data );
+}
+
+/// Unpredictable bytes for salts and nonces from the operating system
+#[cfg(feature = "crypt")]
+fn random_bytes( out: &mut [u8] ) -> Result<(), AnnoError> {
+    ::getrandom::getrandom( out ).map_err( |e| AnnoError::from( io::Error::new( io::ErrorKind::Other, format!( "no random numbers from the system: {}", e ) ) ) )
+}
+
+/// Whether `value` was encrypted by a `Keyring`
+pub fn is_encrypted( value: &str ) -> bool {
+    value.starts_with( ENCRYPTED_PREFIX )
+}
+
+/// A passphrase with the keys derived from it. New values are encrypted with one random salt per keyring, so that
+/// the slow key derivation runs once for all of them; the keys of other salts are derived once when they are needed.
+#[cfg_attr(not(feature = "crypt"), allow(dead_code))]
+pub struct Keyring {
+    passphrase: String,
+    /// Chosen when the first value is encrypted
+    salt: RefCell<Option<[u8; SALT_LEN]>>,
+    /// The cipher key and the MAC key of each salt
+    keys: RefCell<HashMap<[u8; SALT_LEN], ( [u8; 32], [u8; 32] )>>
+}
+
+impl Keyring {
+    pub fn new( passphrase: &str ) -> Keyring {
+        Keyring { passphrase: passphrase.to_string(), salt: RefCell::new( None ), keys: RefCell::new( HashMap::new() ) }
+    }
+
+    /// The keyring of the passphrase in `ANNOVATE_PASSPHRASE`, `None` if it is not set or empty
+    pub fn from_env() -> Option<Keyring> {
+        match env::var( PASSPHRASE_VAR ) {
+            Ok( ref passphrase ) if !passphrase.is_empty() => Some( Keyring::new( passphrase ) ),
+            _ => None
+        }
+    }
+
+    /// The cipher key and the MAC key of `salt`
+    #[cfg(feature = "crypt")]
+    fn keys( &self, salt: &[u8; SALT_LEN] ) -> ( [u8; 32], [u8; 32] ) {
+        *self.keys.borrow_mut().entry( *salt ).or_insert_with( || {
+            let mut derived = [ 0u8; 64 ];
+            pbkdf2_sha256( self.passphrase.as_bytes(), salt, KDF_ITERATIONS, &mut derived );
+            let ( mut cipher_key, mut mac_key ) = ( [ 0u8; 32 ], [ 0u8; 32 ] );
+            cipher_key.copy_from_slice( &derived[ ..32 ] );
+            mac_key.copy_from_slice( &derived[ 32.. ] );
+            ( cipher_key, mac_key )
+        } )
+    }
+
+    /// The salt of new values
+    #[cfg(feature = "crypt")]
+    fn salt( &self ) -> Result<[u8; SALT_LEN], AnnoError> {
+        if let Some( salt ) = *self.salt.borrow() {
+            return Ok( salt );
+        }
+        let mut salt = [ 0u8; SALT_LEN ];
+        try!( random_bytes( &mut salt ) );
+        *self.salt.borrow_mut() = Some( salt );
+        Ok( salt )
+    }
+
+    /// `plaintext` encrypted, starting with `ENCRYPTED_PREFIX`. Fails if the system has no random numbers.
+    #[cfg(feature = "crypt")]
+    pub fn encrypt( &self, plaintext: &str ) -> Result<String, AnnoError> {
+        let salt = try!( self.salt() );
+        let mut nonce = [ 0u8; NONCE_LEN ];
+        try!( random_bytes( &mut nonce ) );
+        let ( cipher_key, mac_key ) = self.keys( &salt );
+        let mut data = plaintext.as_bytes().to_vec();
+        chacha20( &cipher_key, &nonce, 1, &mut data );
+        let mut mac = new_mac( &mac_key );
+        mac.update( &salt );
+        mac.update( &nonce );
+        mac.update( &data );
+        let mut encoded = Vec::with_capacity( SALT_LEN + NONCE_LEN + data.len() + TAG_LEN );
+        encoded.extend_from_slice( &salt );
+        encoded.extend_from_slice( &nonce );
+        encoded.extend_from_slice( &data );
+        encoded.extend_from_slice( &mac.finalize().into_bytes() );
+        Ok( format!( "{}{}", ENCRYPTED_PREFIX, encoded.to_base64( STANDARD ) ) )
+    }
+
+    #[cfg(not(feature = "crypt"))]
+    pub fn encrypt( &self, _plaintext: &str ) -> Result<String, AnnoError> {
+        Err( AnnoError::Unsupported( "crypt" ) )
+    }
+
+    /// The plaintext of a value from `encrypt`. Fails with `AnnoError::Decryption` for a wrong passphrase
+    /// or a changed value.
+    #[cfg(feature = "crypt")]
+    pub fn decrypt( &self, value: &str ) -> Result<String, AnnoError> {
+        if !is_encrypted( value ) {
+            return Err( AnnoError::Decryption );
+        }
+        let encoded = try!( value[ ENCRYPTED_PREFIX.len().. ].from_base64().map_err( |_| AnnoError::Decryption ) );
+        if encoded.len() < SALT_LEN + NONCE_LEN + TAG_LEN {
+            return Err( AnnoError::Decryption );
+        }
+        let ( mut salt, mut nonce ) = ( [ 0u8; SALT_LEN ], [ 0u8; NONCE_LEN ] );
+        salt.copy_from_slice( &encoded[ ..SALT_LEN ] );
+        nonce.copy_from_slice( &encoded[ SALT_LEN..SALT_LEN + NONCE_LEN ] );
+        let ( data, tag ) = encoded[ SALT_LEN + NONCE_LEN.. ].split_at( encoded.len() - SALT_LEN - NONCE_LEN - TAG_LEN );
+        let ( cipher_key, mac_key ) = self.keys( &salt );
+        let mut mac = new_mac( &mac_key );
+        mac.update( &salt );
+        mac.update( &nonce );
+        mac.update( data );
+        //compared in constant time, so that the time does not tell how much of the tag is right
+        try!( mac.verify_slice( tag ).map_err( |_| AnnoError::Decryption ) );
+        let mut data = data.to_vec();
+        chacha20( &cipher_key, &nonce, 1, &mut data );
+        String::from_utf8( data ).map_err( |_| AnnoError::Decryption )
+    }
+
+    #[cfg(not(feature = "crypt"))]
+    pub fn decrypt( &self, _value: &str ) -> Result<String, AnnoError> {
+        Err( AnnoError::Unsupported( "crypt" ) )
+    }
+}
+
+impl Annotation {
+    pub fn is_encrypted( &self ) -> bool {
+        is_encrypted( &self.value )
+    }
+
+    /// The same annotation with its value encrypted. Encrypted values are text, the type is dropped.
+    pub fn encrypted( mut self, keyring: &Keyring ) -> Result<Annotation, AnnoError> {
+        self.value = try!( keyring.encrypt( &self.value ) );
+        self.value_type = None;
+        Ok( self )
+    }
+
+    /// The same annotation with its value decrypted if it is encrypted
+    pub fn decrypted( &self, keyring: &Keyring ) -> Result<Annotation, AnnoError> {
+        let mut annotation = self.clone();
+        if self.is_encrypted() {
+            annotation.value = try!( keyring.decrypt( &self.value ) );
+        }
+        Ok( annotation )
+    }
+}
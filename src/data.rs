@@ -0,0 +1,58 @@
+//Plain representation of an annovate file for serializers. With the cargo feature `serde` it implements
+//`Serialize` and `Deserialize`, so that annotations can be embedded in other formats without converters.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use {Annovate, Annotation, TagSet};
+
+/// Tags and annotations of one target
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+pub struct FileData {
+    pub name: String,
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Vec::is_empty"))]
+    pub tags: Vec<String>,
+    pub annotations: Vec<Annotation>
+}
+
+/// All annotations and tags; files keep their order
+#[derive(Clone, PartialEq, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+pub struct AnnovateData {
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub directory: Vec<Annotation>,
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Vec::is_empty"))]
+    pub directory_tags: Vec<String>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub files: Vec<FileData>
+}
+
+impl Annovate {
+    pub fn to_data( &self ) -> AnnovateData {
+        let files = self.iter_files().map( |name| FileData {
+            name: name.to_string(),
+            tags: self.tags.get( name ).map( |t| t.iter().cloned().collect() ).unwrap_or_default(),
+            annotations: self.files[ name ].clone()
+        } ).collect();
+        AnnovateData { directory: self.dir.clone(), directory_tags: self.dir_tags.iter().cloned().collect(), files: files }
+    }
+
+    /// In-memory annovate file with the content of `data`; `filename` is used by `save`.
+    /// Entries of the same file are merged.
+    pub fn from_data( data: AnnovateData, filename: &Path ) -> Annovate {
+        let mut files: HashMap<String, Vec<Annotation>> = HashMap::new();
+        let mut file_order = Vec::new();
+        let mut tags: HashMap<String, TagSet> = HashMap::new();
+        for file in data.files {
+            if !files.contains_key( &file.name ) {
+                file_order.push( file.name.clone() );
+            }
+            if !file.tags.is_empty() {
+                tags.entry( file.name.clone() ).or_insert( TagSet::new() ).extend( file.tags );
+            }
+            files.entry( file.name ).or_insert( vec![] ).extend( file.annotations );
+        }
+        Annovate::from_content( data.directory, files, file_order, data.directory_tags.into_iter().collect(), tags, filename )
+    }
+}
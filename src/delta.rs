@@ -0,0 +1,75 @@
+//Write-ahead append mode for pipelines that annotate many files one after another. Instead of reading and
+//rewriting the whole meta file, new annotations are appended to its end as a delta record:
+//
+//    &results/run-17.csv
+//    >rows
+//    =1000
+//    <pipeline, 16.10.2026 2:11:56
+//    !2026-10-16T02:11:56Z
+//
+//The `&` line names the target that the following annotations are added to, an empty name the directory.
+//Delta records follow the sections of the file. Loading replays them in order, and the next save (e.g. by
+//`anno compact`) folds them into the sections. Appending does not write a journal entry.
+
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use {AnnoContainer, AnnoError, create_new_annovate_file, format_annotation_block};
+use backend::{Backend, LineBackend, backend_for_path};
+use lock::FileLock;
+use validate;
+
+/// Leader of the line that starts a delta record
+pub const DELTA_LEADER: char = '&';
+
+/// Number of delta records in the raw content of a meta file
+pub fn count_records( content: &[u8] ) -> usize {
+    //value lines start with `=` or `\`, so a leading `&` always starts a record
+    content.split( |b| *b == b'\n' ).filter( |line| line.first() == Some( &( DELTA_LEADER as u8 ) ) ).count()
+}
+
+/// The delta record that adds `annotations` to `target`, `None` for the directory
+pub fn format_record( target: Option<&str>, annotations: &AnnoContainer ) -> String {
+    format!( "{}{}\n{}", DELTA_LEADER, target.unwrap_or( "" ), format_annotation_block( annotations ) )
+}
+
+/// Whether the last byte of `file` is a line break; an empty file counts as ending with one
+fn ends_with_newline( file: &mut File ) -> Result<bool, AnnoError> {
+    if try!( file.seek( SeekFrom::End( 0 ) ) ) == 0 {
+        return Ok( true );
+    }
+    let mut last = [ 0u8 ];
+    try!( file.seek( SeekFrom::End( -1 ) ) );
+    try!( file.read_exact( &mut last ) );
+    Ok( last[ 0 ] == b'\n' )
+}
+
+/// Append `annotations` of `target` (`None` for the directory) to the meta file at `path` as one delta record
+/// without reading the rest of the file. A missing meta file is created. The file is locked while the record
+/// is written, waiting at most `lock_timeout` for another process. Only the line format supports delta records.
+pub fn append_annotations( path: &Path, target: Option<&str>, annotations: &AnnoContainer, lock_timeout: Duration ) -> Result<(), AnnoError> {
+    if backend_for_path( path ).name() != LineBackend.name() {
+        return Err( AnnoError::FormatError( format!( "{} is not in the line format, annotations cannot be appended", path.display() ) ) );
+    }
+    if let Some( target ) = target {
+        try!( validate::check_filename( target ) );
+    }
+    for annotation in annotations {
+        try!( validate::check_annotation( annotation ) );
+    }
+    if annotations.is_empty() {
+        return Ok( () );
+    }
+    let _lock = try!( FileLock::acquire( path, lock_timeout ) );
+    if !path.exists() {
+        try!( create_new_annovate_file( path, &LineBackend, "new annovate file" ) );
+    }
+    let mut file = try!( OpenOptions::new().read( true ).append( true ).open( path ).map_err( |e| AnnoError::io( path, e ) ) );
+    let mut record = format_record( target, annotations );
+    if !try!( ends_with_newline( &mut file ) ) {
+        record.insert( 0, '\n' );
+    }
+    file.write_all( record.as_bytes() ).and_then( |_| file.flush() ).map_err( |e| AnnoError::io( path, e ) )
+}
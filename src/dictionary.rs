@@ -0,0 +1,197 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use {Annovate, AnnoContainer, AnnoError, Fragment, Target};
+
+/// Annotation target of a single column of a tabular file
+pub fn column_target( filename: &str, column: &str ) -> String {
+    Target::column( filename, column ).to_string()
+}
+
+/// Field delimiter guessed from the file extension (tab for .tsv/.tab, comma otherwise)
+pub fn delimiter_for( filename: &str ) -> char {
+    let lower = filename.to_lowercase();
+    if lower.ends_with( ".tsv" ) || lower.ends_with( ".tab" ) {
+        '\t'
+    } else {
+        ','
+    }
+}
+
+/// Split a single CSV line into fields, honoring double quotes
+pub fn split_fields( line: &str, delimiter: char ) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut current = String::new();
+    let mut in_quotes = false;
+    let mut chars = line.chars().peekable();
+    while let Some( c ) = chars.next() {
+        if in_quotes {
+            if c == '"' {
+                if chars.peek() == Some( &'"' ) {
+                    current.push( '"' );
+                    chars.next();
+                } else {
+                    in_quotes = false;
+                }
+            } else {
+                current.push( c );
+            }
+        } else if c == '"' {
+            in_quotes = true;
+        } else if c == delimiter {
+            fields.push( current );
+            current = String::new();
+        } else {
+            current.push( c );
+        }
+    }
+    fields.push( current );
+    fields
+}
+
+fn escape_csv( field: &str ) -> String {
+    if field.contains( ',' ) || field.contains( '"' ) || field.contains( '\n' ) {
+        format!( "\"{}\"", field.replace( "\"", "\"\"" ) )
+    } else {
+        field.to_string()
+    }
+}
+
+fn escape_markdown( field: &str ) -> String {
+    field.replace( "|", "\\|" ).replace( "\n", "<br>" )
+}
+
+/// Most recent value for each key, keys in order of first appearance
+fn latest_values( annotations: &AnnoContainer ) -> ( Vec<String>, HashMap<String, String> ) {
+    let mut keys = Vec::new();
+    let mut values = HashMap::new();
+    for anno in annotations {
+        if !values.contains_key( &anno.key ) {
+            keys.push( anno.key.clone() );
+        }
+        values.insert( anno.key.clone(), anno.value.clone() );
+    }
+    ( keys, values )
+}
+
+/// Documentation of a tabular file assembled from its file-level and column-level annotations
+pub struct DataDictionary {
+    pub filename: String,
+    pub file_entries: Vec<( String, String )>,
+    pub keys: Vec<String>,
+    pub columns: Vec<( String, HashMap<String, String> )>
+}
+
+impl DataDictionary {
+    /// Build the dictionary for `filename` in `dir`. The columns are read from the header line of the file;
+    /// annotated columns that are missing in the header are appended at the end.
+    pub fn new( anno: &Annovate, dir: &Path, filename: &str ) -> Result<DataDictionary, AnnoError> {
+        let mut header = String::new();
+        let path = dir.join( Target::parse( filename ).file );
+        let fd = try!( File::open( &path ).map_err( |e| AnnoError::io( &path, e ) ) );
+        try!( BufReader::new( fd ).read_line( &mut header ) );
+        let mut column_names = split_fields( header.trim_right_matches( |c| c == '\n' || c == '\r' ), delimiter_for( filename ) );
+
+        let mut annotated: Vec<String> = anno.get_files().iter()
+                                             .map( |f| Target::parse( f ) )
+                                             .filter( |t| t.file == filename )
+                                             .filter_map( |t| match t.fragment {
+                                                 Some( Fragment::Column( name ) ) => Some( name ),
+                                                 _ => None
+                                             } )
+                                             .collect();
+        annotated.sort();
+        for column in annotated {
+            if !column_names.contains( &column ) {
+                column_names.push( column );
+            }
+        }
+
+        let mut keys = Vec::new();
+        let mut columns = Vec::new();
+        for column in column_names {
+            let ( column_keys, values ) = match anno.get_file_annotations( &column_target( filename, &column ) ) {
+                Some( annotations ) => latest_values( annotations ),
+                None => ( vec![], HashMap::new() )
+            };
+            for key in column_keys {
+                if !keys.contains( &key ) {
+                    keys.push( key );
+                }
+            }
+            columns.push( ( column, values ) );
+        }
+
+        let file_entries = match anno.get_file_annotations( filename ) {
+            Some( annotations ) => {
+                let ( file_keys, mut values ) = latest_values( annotations );
+                file_keys.into_iter().map( |k| { let v = values.remove( &k ).unwrap(); ( k, v ) } ).collect()
+            },
+            None => vec![]
+        };
+
+        Ok( DataDictionary { filename: filename.to_string(), file_entries: file_entries, keys: keys, columns: columns } )
+    }
+
+    /// Render as a Markdown document with a bullet list for the file and a table for the columns
+    pub fn to_markdown( &self ) -> String {
+        let mut out = format!( "# {}\n\n", self.filename );
+        for &( ref key, ref value ) in &self.file_entries {
+            out.push_str( &format!( "- **{}**: {}\n", escape_markdown( key ), escape_markdown( value ) ) );
+        }
+        if !self.file_entries.is_empty() {
+            out.push_str( "\n" );
+        }
+        out.push_str( "| Column |" );
+        for key in &self.keys {
+            out.push_str( &format!( " {} |", escape_markdown( key ) ) );
+        }
+        out.push_str( "\n|---|" );
+        for _ in &self.keys {
+            out.push_str( "---|" );
+        }
+        out.push_str( "\n" );
+        for &( ref column, ref values ) in &self.columns {
+            out.push_str( &format!( "| {} |", escape_markdown( column ) ) );
+            for key in &self.keys {
+                out.push_str( &format!( " {} |", escape_markdown( values.get( key ).map( |v| v.as_str() ).unwrap_or( "" ) ) ) );
+            }
+            out.push_str( "\n" );
+        }
+        out
+    }
+
+    /// Render as CSV with one row per column. File-level annotations are written as rows with an empty column name.
+    pub fn to_csv( &self ) -> String {
+        let mut keys = self.keys.clone();
+        for &( ref key, _ ) in &self.file_entries {
+            if !keys.contains( key ) {
+                keys.push( key.clone() );
+            }
+        }
+
+        let mut out = String::from( "column" );
+        for key in &keys {
+            out.push_str( &format!( ",{}", escape_csv( key ) ) );
+        }
+        out.push_str( "\n" );
+
+        if !self.file_entries.is_empty() {
+            for key in &keys {
+                let value = self.file_entries.iter().find( |e| e.0 == *key ).map( |e| e.1.as_str() ).unwrap_or( "" );
+                out.push_str( &format!( ",{}", escape_csv( value ) ) );
+            }
+            out.push_str( "\n" );
+        }
+        for &( ref column, ref values ) in &self.columns {
+            out.push_str( &escape_csv( column ) );
+            for key in &keys {
+                out.push_str( &format!( ",{}", escape_csv( values.get( key ).map( |v| v.as_str() ).unwrap_or( "" ) ) ) );
+            }
+            out.push_str( "\n" );
+        }
+        out
+    }
+}
@@ -0,0 +1,97 @@
+//Comparison of the current values of two annovate files, e.g. before merging the file of a colleague.
+//Older entries of a key are history and do not take part.
+
+use {Annovate, Annotation, AnnoContainer};
+
+/// A difference of one key of a target
+#[derive(Clone, PartialEq, Debug)]
+pub enum DiffEntry {
+    /// Only the other file has the key
+    Added( Annotation ),
+    /// Only this file has the key
+    Removed( Annotation ),
+    /// Both files have the key with different values: ours, then theirs
+    Changed( Annotation, Annotation )
+}
+
+/// Differences of one target, `None` for the directory
+#[derive(Clone, PartialEq, Debug)]
+pub struct TargetDiff {
+    pub target: Option<String>,
+    pub entries: Vec<DiffEntry>
+}
+
+/// Result of `Annovate::diff`
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct DiffReport {
+    pub targets: Vec<TargetDiff>
+}
+
+impl DiffReport {
+    pub fn is_empty( &self ) -> bool {
+        self.targets.is_empty()
+    }
+
+    /// Number of added, removed and changed keys
+    pub fn counts( &self ) -> ( usize, usize, usize ) {
+        let mut counts = ( 0, 0, 0 );
+        for entry in self.targets.iter().flat_map( |t| t.entries.iter() ) {
+            match *entry {
+                DiffEntry::Added( _ ) => counts.0 += 1,
+                DiffEntry::Removed( _ ) => counts.1 += 1,
+                DiffEntry::Changed( _, _ ) => counts.2 += 1
+            }
+        }
+        counts
+    }
+}
+
+/// Most recent annotation of every key, in the order the keys first appear
+fn current_values( annotations: &AnnoContainer ) -> Vec<&Annotation> {
+    let mut current: Vec<&Annotation> = Vec::new();
+    for annotation in annotations {
+        match current.iter().position( |a| a.key == annotation.key ) {
+            Some( pos ) => current[ pos ] = annotation,
+            None => current.push( annotation )
+        }
+    }
+    current
+}
+
+fn diff_containers( ours: &AnnoContainer, theirs: &AnnoContainer ) -> Vec<DiffEntry> {
+    let ( ours, theirs ) = ( current_values( ours ), current_values( theirs ) );
+    let mut entries = Vec::new();
+    for our in &ours {
+        match theirs.iter().find( |a| a.key == our.key ) {
+            Some( their ) if their.value != our.value || their.value_type != our.value_type => {
+                entries.push( DiffEntry::Changed( ( *our ).clone(), ( *their ).clone() ) )
+            },
+            Some( _ ) => {},
+            None => entries.push( DiffEntry::Removed( ( *our ).clone() ) )
+        }
+    }
+    entries.extend( theirs.iter().filter( |a| !ours.iter().any( |o| o.key == a.key ) ).map( |a| DiffEntry::Added( ( *a ).clone() ) ) );
+    entries
+}
+
+impl Annovate {
+    /// Keys that `other` added, removed or changed compared to this file: the directory first, then the files
+    /// in the order of this file followed by those that only `other` has
+    pub fn diff( &self, other: &Annovate ) -> DiffReport {
+        let mut targets = Vec::new();
+        let dir_entries = diff_containers( &self.dir, &other.dir );
+        if !dir_entries.is_empty() {
+            targets.push( TargetDiff { target: None, entries: dir_entries } );
+        }
+        let mut names = self.get_files();
+        names.extend( other.get_files().into_iter().filter( |f| !self.files.contains_key( f ) ) );
+        let empty = AnnoContainer::new();
+        for name in names {
+            let entries = diff_containers( self.files.get( &name ).unwrap_or( &empty ), other.files.get( &name ).unwrap_or( &empty ) );
+            if !entries.is_empty() {
+                targets.push( TargetDiff { target: Some( name ), entries: entries } );
+            }
+        }
+        DiffReport { targets: targets }
+    }
+}
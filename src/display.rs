@@ -0,0 +1,89 @@
+//ANSI colors of the terminal output. All escape codes are produced here, the output formats only choose styles.
+
+use std::env;
+use std::io::{stdout, IsTerminal};
+
+use annovate::config::ColorMode;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Style {
+    /// Header rows and headings between tables
+    Heading,
+    Key,
+    /// Report: file has metadata (`=`)
+    Annotated,
+    /// Report: metadata of a file that does not exist (`+`)
+    Orphaned,
+    /// Report: file without metadata (`-`)
+    Missing,
+    /// Report: file that changed since its checksum was stored (`~`)
+    Changed
+}
+
+impl Style {
+    fn code( &self ) -> &'static str {
+        match *self {
+            Style::Heading => "1;4",
+            Style::Key => "36",
+            Style::Annotated => "32",
+            Style::Orphaned => "33",
+            Style::Missing => "31",
+            Style::Changed => "35"
+        }
+    }
+
+    /// Style of a status symbol of `report`
+    pub fn of_marker( marker: &str ) -> Option<Style> {
+        match marker {
+            "=" => Some( Style::Annotated ),
+            "+" => Some( Style::Orphaned ),
+            "-" => Some( Style::Missing ),
+            "~" => Some( Style::Changed ),
+            _ => None
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Colors {
+    enabled: bool
+}
+
+impl Colors {
+    pub fn disabled() -> Colors {
+        Colors { enabled: false }
+    }
+
+    /// Colors are used if stdout is a terminal, unless `--no-color` was given or `NO_COLOR` is set (see no-color.org)
+    pub fn detect( no_color: bool ) -> Colors {
+        let no_color_env = env::var_os( "NO_COLOR" ).map( |v| !v.is_empty() ).unwrap_or( false );
+        Colors { enabled: !no_color && !no_color_env && stdout().is_terminal() }
+    }
+
+    /// Like `detect`, but the configured mode may switch colors on or off whatever stdout is; `--no-color` still wins
+    pub fn with_mode( mode: ColorMode, no_color: bool ) -> Colors {
+        match mode {
+            ColorMode::Always if !no_color => Colors { enabled: true },
+            ColorMode::Never => Colors::disabled(),
+            _ => Colors::detect( no_color )
+        }
+    }
+
+    pub fn paint( &self, text: &str, style: Style ) -> String {
+        if self.enabled && !text.is_empty() {
+            format!( "\x1b[{}m{}\x1b[0m", style.code(), text )
+        } else {
+            text.to_string()
+        }
+    }
+
+    /// Paint `text` and pad it with spaces to `width` characters. Padding before painting would count the escape codes.
+    pub fn pad( &self, text: &str, width: usize, style: Option<Style> ) -> String {
+        let padding = width.saturating_sub( text.chars().count() );
+        let text = match style {
+            Some( style ) => self.paint( text, style ),
+            None => text.to_string()
+        };
+        format!( "{}{}", text, " ".repeat( padding ) )
+    }
+}
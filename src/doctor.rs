@@ -0,0 +1,460 @@
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use {AnnoError, Fragment, Target, Timestamp, is_valid_tag};
+use ids::is_valid_id;
+use integrity::{self, IntegrityStatus};
+use normalization::{self, Normalization};
+use protect::PROTECTED_FLAG;
+use subdir;
+
+/// Context that is recorded for entries whose context line was missing
+pub const REPAIR_CONTEXT: &'static str = "repaired by anno doctor";
+
+/// Kind of structural problem found in a metadata file
+#[derive(Clone, PartialEq, Debug)]
+pub enum ProblemKind {
+    /// The line is not valid UTF-8
+    InvalidEncoding,
+    /// An empty line
+    BlankLine,
+    /// A line that starts with an unknown character
+    InvalidLeader( char ),
+    /// A value line without leader inside an entry
+    MissingValueLeader,
+    /// A `#` line that does not describe a valid file part, or that follows a subdirectory
+    InvalidFragment( String ),
+    /// A `#` line that does not follow a `@file` section
+    FragmentWithoutFile,
+    /// A `=` line that does not belong to any key
+    OrphanedValue,
+    /// A `<` line that does not belong to any key
+    OrphanedContext,
+    /// An entry that has no context line
+    TruncatedEntry,
+    /// A `!` line that does not follow a context line
+    OrphanedTimestamp,
+    /// A `!` line that is not a valid timestamp
+    InvalidTimestamp( String ),
+    /// A `~` line that does not follow a context or creation time line
+    OrphanedExpiry,
+    /// A `~` line that is not a valid timestamp
+    InvalidExpiry( String ),
+    /// A `*` line that does not follow a context, creation time or expiry time line
+    OrphanedRegion,
+    /// A `*` line that is not a range of lines or bytes
+    InvalidRegion( String ),
+    /// A `^` line that does not follow a context, creation time, expiry time or region line
+    OrphanedFlag,
+    /// A `^` line with an unknown flag
+    InvalidFlag( String ),
+    /// A `%` line that does not follow a context, creation time, expiry time, region or flag line
+    OrphanedId,
+    /// A `%` line that is not a valid ID
+    InvalidId( String ),
+    /// A `+` line that is not a valid tag
+    InvalidTag( String ),
+    /// A section for a file that already had a section at the given line
+    DuplicateSection( String, u64 ),
+    /// A file section without any annotations
+    EmptySection( String ),
+    /// A file section whose name differs only in its Unicode normalization from that of the section at the given
+    /// line, e.g. `café` written on macOS and on Linux
+    NormalizationDuplicate( String, String, u64 ),
+    /// A `@` or `#` line after the first delta record
+    SectionAfterDelta,
+    /// The `$` line with the hash of the file does not match its content
+    IntegrityMismatch
+}
+
+/// A problem together with the line where it occurs
+#[derive(Clone, PartialEq, Debug)]
+pub struct Problem {
+    pub line: u64,
+    pub kind: ProblemKind
+}
+
+impl fmt::Display for Problem {
+    fn fmt( &self, f: &mut fmt::Formatter ) -> fmt::Result {
+        try!( write!( f, "line {}: ", self.line ) );
+        match self.kind {
+            ProblemKind::InvalidEncoding =>
+                write!( f, "invalid UTF-8; the broken bytes will be replaced" ),
+            ProblemKind::BlankLine =>
+                write!( f, "blank line; it will be removed" ),
+            ProblemKind::InvalidLeader( c ) =>
+                write!( f, "unknown leader `{}`; the line will be removed", c ),
+            ProblemKind::MissingValueLeader =>
+                write!( f, "value line without `=`; it will be added to the value" ),
+            ProblemKind::InvalidFragment( ref fragment ) =>
+                write!( f, "invalid file part `{}`; its annotations will be removed", fragment ),
+            ProblemKind::FragmentWithoutFile =>
+                write!( f, "file part outside of a file section; its annotations will be removed" ),
+            ProblemKind::OrphanedValue =>
+                write!( f, "value without a key; the line will be removed" ),
+            ProblemKind::OrphanedContext =>
+                write!( f, "context without a key; the line will be removed" ),
+            ProblemKind::OrphanedTimestamp =>
+                write!( f, "creation time without an entry; the line will be removed" ),
+            ProblemKind::InvalidTimestamp( ref text ) =>
+                write!( f, "invalid creation time `{}`; the line will be removed", text ),
+            ProblemKind::OrphanedExpiry =>
+                write!( f, "expiry time without an entry; the line will be removed" ),
+            ProblemKind::InvalidExpiry( ref text ) =>
+                write!( f, "invalid expiry time `{}`; the line will be removed", text ),
+            ProblemKind::OrphanedRegion =>
+                write!( f, "region without an entry; the line will be removed" ),
+            ProblemKind::InvalidRegion( ref text ) =>
+                write!( f, "invalid region `{}`; the line will be removed", text ),
+            ProblemKind::OrphanedFlag =>
+                write!( f, "flag without an entry; the line will be removed" ),
+            ProblemKind::InvalidFlag( ref text ) =>
+                write!( f, "unknown flag `{}`; the line will be removed", text ),
+            ProblemKind::OrphanedId =>
+                write!( f, "ID without an entry; the line will be removed" ),
+            ProblemKind::InvalidId( ref text ) =>
+                write!( f, "invalid ID `{}`; the line will be removed", text ),
+            ProblemKind::InvalidTag( ref tag ) =>
+                write!( f, "invalid tag `{}`; the line will be removed", tag ),
+            ProblemKind::TruncatedEntry =>
+                write!( f, "entry has no context line (truncated?); the context `{}` will be added", REPAIR_CONTEXT ),
+            ProblemKind::DuplicateSection( ref name, first ) =>
+                write!( f, "`{}` already has a section at line {}; both sections will be merged", name, first ),
+            ProblemKind::EmptySection( ref name ) =>
+                write!( f, "`{}` has no annotations; the section will be removed", name ),
+            ProblemKind::NormalizationDuplicate( ref name, ref other, first ) =>
+                write!( f, "`{}` differs from `{}` at line {} only in its Unicode normalization; both will be merged as `{}`",
+                        name, other, first, Normalization::Nfc.apply( name ) ),
+            ProblemKind::SectionAfterDelta =>
+                write!( f, "section after a delta record; delta records will be folded into the sections" ),
+            ProblemKind::IntegrityMismatch =>
+                write!( f, "the hash does not match the content (edited outside of annovate or truncated); the footer will be removed" ),
+        }
+    }
+}
+
+struct Section {
+    base: String,
+    fragment: Option<String>,
+    first_line: u64,
+    lines: Vec<String>
+}
+
+/// Result of examining a metadata file: the problems and the repaired text
+pub struct Checkup {
+    pub problems: Vec<Problem>,
+    pub repaired: String
+}
+
+/// Examine the raw content of a metadata file
+pub fn examine( content: &[u8] ) -> Checkup {
+    let mut problems = Vec::new();
+    let mut dir_lines: Vec<String> = Vec::new();
+    let mut sections: Vec<Section> = Vec::new();
+    let mut current: Option<usize> = None; //index into sections, None for directory annotations
+    let mut current_base: Option<String> = None;
+    let mut skipping = false; //inside a section that will be dropped
+    let mut in_deltas = false; //an `&` line was read
+
+    let mut entry: Vec<String> = Vec::new(); //key and value lines of the open entry
+    let mut entry_line = 0u64;
+    let mut closed: Option<bool> = None; //the previous line closed an entry; false if that entry was dropped
+    let mut after_entry: Option<bool> = None; //like `closed`, but also after the `!`, `~` and `^` lines of the entry
+    let mut footer_line: Option<u64> = None;
+
+    fn close_entry( entry: &mut Vec<String>, context: Option<&str>, target: &mut Vec<String> ) {
+        if entry.is_empty() {
+            return;
+        }
+        target.extend( entry.drain( .. ) );
+        target.push( format!( "<{}", context.unwrap_or( REPAIR_CONTEXT ) ) );
+    }
+
+    macro_rules! target_lines {
+        () => ( match current { Some( i ) => &mut sections[ i ].lines, None => &mut dir_lines } )
+    }
+
+    let mut raw_lines: Vec<&[u8]> = content.split( |b| *b == b'\n' ).collect();
+    if raw_lines.last().map( |l| l.is_empty() ).unwrap_or( false ) {
+        raw_lines.pop(); //trailing newline
+    }
+
+    for ( index, raw ) in raw_lines.iter().enumerate() {
+        let line_no = index as u64 + 1;
+        //CRLF line endings, e.g. from Windows editors
+        let raw = if raw.ends_with( b"\r" ) { &raw[ ..raw.len() - 1 ] } else { raw };
+        let line = match String::from_utf8( raw.to_vec() ) {
+            Ok( line ) => line,
+            Err( _ ) => {
+                problems.push( Problem { line: line_no, kind: ProblemKind::InvalidEncoding } );
+                String::from_utf8_lossy( raw ).into_owned()
+            }
+        };
+        let leader = match line.chars().next() {
+            Some( c ) => c,
+            None => {
+                problems.push( Problem { line: line_no, kind: ProblemKind::BlankLine } );
+                continue;
+            }
+        };
+        let rest = line[ leader.len_utf8().. ].to_string();
+        //the integrity footer is checked below and not copied to the repaired text
+        if leader == '$' && integrity::footer_hash( &line ).is_some() {
+            footer_line = Some( line_no );
+            continue;
+        }
+        let follows_context = closed.take();
+        let follows_entry = after_entry.take();
+
+        if leader == '@' || leader == '#' || leader == '&' {
+            if leader == '&' {
+                in_deltas = true;
+            } else if in_deltas {
+                problems.push( Problem { line: line_no, kind: ProblemKind::SectionAfterDelta } );
+            }
+            if !entry.is_empty() {
+                problems.push( Problem { line: entry_line, kind: ProblemKind::TruncatedEntry } );
+                if !skipping {
+                    close_entry( &mut entry, None, target_lines!() );
+                }
+                entry.clear();
+            }
+            let ( base, fragment ) = if leader == '@' {
+                ( rest.clone(), None )
+            } else if leader == '&' {
+                //delta records are folded into the sections of their targets
+                skipping = false;
+                current_base = None;
+                if rest.is_empty() {
+                    current = None;
+                    continue;
+                }
+                let target = Target::parse( &rest );
+                ( target.file.clone(), target.fragment.map( |f| f.to_string() ) )
+            } else {
+                match current_base {
+                    None => {
+                        problems.push( Problem { line: line_no, kind: ProblemKind::FragmentWithoutFile } );
+                        skipping = true;
+                        continue;
+                    },
+                    Some( ref base ) if Fragment::parse( &rest ).is_none() || subdir::is_subdirectory_entry( base ) => {
+                        problems.push( Problem { line: line_no, kind: ProblemKind::InvalidFragment( rest.clone() ) } );
+                        skipping = true;
+                        continue;
+                    },
+                    Some( ref base ) => ( base.clone(), Some( rest.clone() ) )
+                }
+            };
+            skipping = false;
+            if leader == '@' {
+                current_base = Some( base.clone() );
+            }
+            match sections.iter().position( |s| s.base == base && s.fragment == fragment ) {
+                Some( i ) => {
+                    if leader != '&' {
+                        let name = match fragment { Some( ref f ) => format!( "{}#{}", base, f ), None => base.clone() };
+                        problems.push( Problem { line: line_no, kind: ProblemKind::DuplicateSection( name, sections[ i ].first_line ) } );
+                    }
+                    current = Some( i );
+                },
+                None => {
+                    sections.push( Section { base: base, fragment: fragment, first_line: line_no, lines: vec![] } );
+                    current = Some( sections.len() - 1 );
+                }
+            }
+        } else if leader == '>' || leader == '+' {
+            if !entry.is_empty() {
+                problems.push( Problem { line: entry_line, kind: ProblemKind::TruncatedEntry } );
+                if !skipping {
+                    close_entry( &mut entry, None, target_lines!() );
+                }
+                entry.clear();
+            }
+            if leader == '+' {
+                if !is_valid_tag( rest.trim_right() ) {
+                    problems.push( Problem { line: line_no, kind: ProblemKind::InvalidTag( rest.clone() ) } );
+                } else if !skipping {
+                    target_lines!().push( line.clone() ); //tags are kept as they are
+                }
+            } else {
+                entry.push( line.clone() );
+                entry_line = line_no;
+            }
+        } else if leader == '=' || leader == '\\' {
+            if entry.is_empty() {
+                problems.push( Problem { line: line_no, kind: ProblemKind::OrphanedValue } );
+            } else {
+                entry.push( line.clone() );
+            }
+        } else if leader == '<' {
+            if entry.is_empty() {
+                problems.push( Problem { line: line_no, kind: ProblemKind::OrphanedContext } );
+            } else if skipping {
+                entry.clear();
+                closed = Some( false );
+            } else {
+                close_entry( &mut entry, Some( &rest ), target_lines!() );
+                closed = Some( true );
+            }
+            after_entry = closed;
+        } else if leader == '!' {
+            match follows_context {
+                None => problems.push( Problem { line: line_no, kind: ProblemKind::OrphanedTimestamp } ),
+                Some( _ ) if Timestamp::parse( &rest ).is_none() => problems.push( Problem { line: line_no, kind: ProblemKind::InvalidTimestamp( rest.clone() ) } ),
+                Some( true ) => target_lines!().push( line.clone() ),
+                Some( false ) => {} //the entry is dropped together with its section
+            }
+            if follows_context.is_some() {
+                after_entry = follows_context;
+            }
+        } else if leader == '~' {
+            match follows_entry {
+                None => problems.push( Problem { line: line_no, kind: ProblemKind::OrphanedExpiry } ),
+                Some( _ ) if Timestamp::parse( &rest ).is_none() => problems.push( Problem { line: line_no, kind: ProblemKind::InvalidExpiry( rest.clone() ) } ),
+                Some( true ) => target_lines!().push( line.clone() ),
+                Some( false ) => {}
+            }
+            after_entry = follows_entry;
+        } else if leader == '*' {
+            match follows_entry {
+                None => problems.push( Problem { line: line_no, kind: ProblemKind::OrphanedRegion } ),
+                Some( _ ) if Fragment::parse_region( rest.trim_right() ).is_none() => problems.push( Problem { line: line_no, kind: ProblemKind::InvalidRegion( rest.clone() ) } ),
+                Some( true ) => target_lines!().push( line.clone() ),
+                Some( false ) => {}
+            }
+            after_entry = follows_entry;
+        } else if leader == '^' {
+            match follows_entry {
+                None => problems.push( Problem { line: line_no, kind: ProblemKind::OrphanedFlag } ),
+                Some( _ ) if rest.trim_right() != PROTECTED_FLAG => problems.push( Problem { line: line_no, kind: ProblemKind::InvalidFlag( rest.clone() ) } ),
+                Some( true ) => target_lines!().push( line.clone() ),
+                Some( false ) => {}
+            }
+            after_entry = follows_entry;
+        } else if leader == '%' {
+            match follows_entry {
+                None => problems.push( Problem { line: line_no, kind: ProblemKind::OrphanedId } ),
+                Some( _ ) if !is_valid_id( rest.trim_right() ) => problems.push( Problem { line: line_no, kind: ProblemKind::InvalidId( rest.clone() ) } ),
+                Some( true ) => target_lines!().push( line.clone() ),
+                Some( false ) => {}
+            }
+        } else if entry.len() > 1 {
+            //continuation of a multi-line value that lost its leader
+            problems.push( Problem { line: line_no, kind: ProblemKind::MissingValueLeader } );
+            entry.push( format!( "={}", line ) );
+        } else {
+            problems.push( Problem { line: line_no, kind: ProblemKind::InvalidLeader( leader ) } );
+        }
+    }
+    if !entry.is_empty() {
+        problems.push( Problem { line: entry_line, kind: ProblemKind::TruncatedEntry } );
+        if !skipping {
+            close_entry( &mut entry, None, target_lines!() );
+        }
+    }
+    if let Some( line ) = footer_line {
+        if integrity::verify( &String::from_utf8_lossy( content ) ) == IntegrityStatus::Mismatch {
+            problems.push( Problem { line: line, kind: ProblemKind::IntegrityMismatch } );
+        }
+    }
+
+    //files whose names differ only in their normalization are merged under the composed name
+    let mut first_of: Vec<( String, u64 )> = Vec::new(); //the first name of each file and its line
+    for section in sections.iter().filter( |s| s.fragment.is_none() ) {
+        match first_of.iter().find( |&&( ref name, _ )| normalization::differ_only_in_normalization( name, &section.base ) ) {
+            Some( &( ref name, line ) ) => problems.push( Problem { line: section.first_line, kind: ProblemKind::NormalizationDuplicate( section.base.clone(), name.clone(), line ) } ),
+            None => if !first_of.iter().any( |&( ref name, _ )| *name == section.base ) { first_of.push( ( section.base.clone(), section.first_line ) ) }
+        }
+    }
+    let merged: Vec<String> = problems.iter().filter_map( |p| match p.kind {
+        ProblemKind::NormalizationDuplicate( ref name, ref other, _ ) => Some( vec![ name.clone(), other.clone() ] ),
+        _ => None
+    } ).flat_map( |names| names ).collect();
+    let mut normalized_sections: Vec<Section> = Vec::new();
+    for mut section in sections {
+        if merged.contains( &section.base ) {
+            section.base = Normalization::Nfc.apply( &section.base );
+        }
+        match normalized_sections.iter().position( |s| s.base == section.base && s.fragment == section.fragment ) {
+            Some( i ) => normalized_sections[ i ].lines.extend( section.lines ),
+            None => normalized_sections.push( section )
+        }
+    }
+    let sections = normalized_sections;
+
+    //assemble the repaired text, fragments are written below their file
+    let mut repaired = String::new();
+    for line in &dir_lines {
+        repaired.push_str( line );
+        repaired.push( '\n' );
+    }
+    let mut bases: Vec<&str> = Vec::new();
+    for section in &sections {
+        if !bases.contains( &section.base.as_str() ) {
+            bases.push( &section.base );
+        }
+    }
+    for base in bases {
+        let parts: Vec<&Section> = sections.iter().filter( |s| s.base == base && !s.lines.is_empty() ).collect();
+        let whole_file = sections.iter().find( |s| s.base == base && s.fragment.is_none() );
+        if parts.is_empty() {
+            let line = whole_file.map( |s| s.first_line ).unwrap_or( 0 );
+            problems.push( Problem { line: line, kind: ProblemKind::EmptySection( base.to_string() ) } );
+            continue;
+        }
+        repaired.push_str( &format!( "@{}\n", base ) );
+        if let Some( section ) = whole_file {
+            for line in &section.lines {
+                repaired.push_str( line );
+                repaired.push( '\n' );
+            }
+        }
+        for section in parts.iter().filter( |s| s.fragment.is_some() ) {
+            repaired.push_str( &format!( "#{}\n", section.fragment.as_ref().unwrap() ) );
+            for line in &section.lines {
+                repaired.push_str( line );
+                repaired.push( '\n' );
+            }
+        }
+    }
+    problems.sort_by_key( |p| p.line );
+    Checkup { problems: problems, repaired: repaired }
+}
+
+fn read_raw( path: &Path ) -> Result<Vec<u8>, AnnoError> {
+    let mut content = Vec::new();
+    let mut file = try!( File::open( path ).map_err( |e| AnnoError::io( path, e ) ) );
+    try!( file.read_to_end( &mut content ) );
+    Ok( content )
+}
+
+/// List the structural problems of a metadata file
+pub fn diagnose( path: &Path ) -> Result<Vec<Problem>, AnnoError> {
+    let content = try!( read_raw( path ) );
+    Ok( examine( &content ).problems )
+}
+
+/// Path of the backup that `repair` writes before touching the metadata file
+pub fn backup_path( path: &Path ) -> PathBuf {
+    let mut name = path.file_name().map( |n| n.to_os_string() ).unwrap_or_default();
+    name.push( ".bak" );
+    path.with_file_name( name )
+}
+
+/// Repair a metadata file after copying the original to `backup_path`.
+/// Returns the problems that were fixed; nothing is written if there are none.
+pub fn repair( path: &Path ) -> Result<Vec<Problem>, AnnoError> {
+    let content = try!( read_raw( path ) );
+    let checkup = examine( &content );
+    if checkup.problems.is_empty() {
+        return Ok( checkup.problems );
+    }
+    try!( fs::copy( path, backup_path( path ) ) );
+    let mut file = try!( File::create( path ).map_err( |e| AnnoError::io( path, e ) ) );
+    try!( file.write_all( checkup.repaired.as_bytes() ) );
+    try!( file.flush() );
+    Ok( checkup.problems )
+}
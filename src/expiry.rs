@@ -0,0 +1,78 @@
+//Annotations that only hold for a while, like a `status` of `wip`, can expire:
+//
+//    >status
+//    =wip
+//    <annovate program, 16.10.2026 2:11:56
+//    !2026-10-16T02:11:56Z
+//    ~2026-10-23T02:11:56Z
+//
+//The `~` line after the creation time says when. Expired annotations stay in the meta file until they are
+//pruned, but they are hidden when annotations are shown unless all entries are asked for (`-a`).
+
+use {Annovate, AnnoContainer, AnnoError, Annotation, Timestamp};
+
+/// Units of an expiry duration with their length in seconds
+const UNITS: &'static [( char, i64 )] = &[ ( 's', 1 ), ( 'm', 60 ), ( 'h', 60 * 60 ), ( 'd', 24 * 60 * 60 ), ( 'w', 7 * 24 * 60 * 60 ) ];
+
+/// Length in seconds of a duration like `90s`, `30m`, `12h`, `7d` or `2w`
+pub fn parse_duration( text: &str ) -> Option<i64> {
+    let text = text.trim();
+    let unit = match text.chars().last() {
+        Some( unit ) => unit,
+        None => return None
+    };
+    let seconds = match UNITS.iter().find( |u| u.0 == unit ) {
+        Some( &( _, seconds ) ) => seconds,
+        None => return None
+    };
+    let count = &text[ ..text.len() - 1 ];
+    if count.is_empty() || !count.chars().all( |c| c.is_ascii_digit() ) {
+        return None;
+    }
+    count.parse::<i64>().ok().and_then( |count| count.checked_mul( seconds ) )
+}
+
+/// When an annotation made at `now` expires: `text` is a duration for `parse_duration` or a timestamp
+/// like `2026-12-31T00:00:00Z`
+pub fn expiry_time( text: &str, now: Timestamp ) -> Option<Timestamp> {
+    match parse_duration( text ) {
+        Some( seconds ) => now.seconds().checked_add( seconds ).map( Timestamp::from_seconds ),
+        None => Timestamp::parse( text )
+    }
+}
+
+impl Annotation {
+    /// Whether the annotation has expired at `now`; annotations without expiry time never do
+    pub fn is_expired( &self, now: Timestamp ) -> bool {
+        self.expires.map( |expires| expires <= now ).unwrap_or( false )
+    }
+}
+
+/// Drop the annotations that have expired at `now`. Returns how many were dropped.
+pub fn retain_unexpired( annotations: &mut AnnoContainer, now: Timestamp ) -> usize {
+    let old_length = annotations.len();
+    annotations.retain( |a| !a.is_expired( now ) );
+    old_length - annotations.len()
+}
+
+impl Annovate {
+    /// Like `get_values`, but the most recent value of `key` that has not expired at `now`
+    pub fn get_unexpired_values( &self, key: &str, now: Timestamp ) -> Vec<( String, &str )> {
+        self.iter_files().filter_map( |f| {
+            self.files[ f ].iter().rev().find( |a| a.key == key && !a.is_expired( now ) ).map( |a| ( f.to_string(), a.value.as_str() ) )
+        } ).collect()
+    }
+
+    /// Remove the annotations of the directory and the files that have expired at `now`. A file without
+    /// annotations and tags is dropped afterwards. Returns the number of removed annotations.
+    pub fn prune_expired( &mut self, now: Timestamp ) -> Result<usize, AnnoError> {
+        try!( self.check_writable() );
+        let mut removed = retain_unexpired( &mut self.dir, now );
+        let expired: Vec<String> = self.files.iter().filter( |&( _, annos )| annos.iter().any( |a| a.is_expired( now ) ) )
+                                                    .map( |( name, _ )| name.clone() ).collect();
+        for filename in &expired {
+            removed += try!( self.retain_file_annotations( filename, |_, a| !a.is_expired( now ) ) );
+        }
+        Ok( self.mark_modified( removed > 0, removed ) )
+    }
+}
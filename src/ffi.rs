@@ -0,0 +1,201 @@
+//C interface for bindings in other languages, e.g. Python with ctypes or R with .C, behind the feature `ffi`.
+//Build a shared library with
+//
+//    cargo rustc --lib --release --features ffi --crate-type cdylib
+//
+//and declare the functions like this:
+//
+//    typedef struct Annovate Annovate;
+//
+//    int annovate_open( const char *path, int read_only, Annovate **anno );
+//    int annovate_get( const Annovate *anno, const char *filename, const char *key, char **value );
+//    int annovate_put( Annovate *anno, const char *filename, const char *key, const char *value, const char *context );
+//    int annovate_save( Annovate *anno );
+//    void annovate_close( Annovate *anno );
+//    void annovate_free_string( char *text );
+//    const char *annovate_last_error( void );
+//
+//Every `int` function returns one of the `ANNOVATE_*` codes below; on failure `annovate_last_error` describes the
+//error until another call of the same thread fails. A `NULL` filename stands for the directory, a `NULL` context for
+//`annovate library, user <name>`. Strings are UTF-8; values from `annovate_get` are freed with
+//`annovate_free_string`. A handle must only be used by one thread at a time.
+
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::ptr;
+
+use {Annovate, Annotation, AnnoError, OpenMode, Timestamp};
+use context;
+
+pub const ANNOVATE_OK: c_int = 0;
+/// Any error without a code of its own, e.g. reading or writing a file
+pub const ANNOVATE_ERROR: c_int = 1;
+/// A `NULL` pointer, a string that is not UTF-8 or a key, value, context or filename that cannot be stored
+pub const ANNOVATE_INVALID_ARGUMENT: c_int = 2;
+/// The file or directory has no annotation with the key
+pub const ANNOVATE_NOT_FOUND: c_int = 3;
+/// The meta file is malformed or does not match its integrity footer
+pub const ANNOVATE_INVALID_FILE: c_int = 4;
+/// Another process holds the lock of the meta file
+pub const ANNOVATE_LOCKED: c_int = 5;
+/// Change of a meta file that was opened read-only
+pub const ANNOVATE_READ_ONLY: c_int = 6;
+/// Change of a protected annotation
+pub const ANNOVATE_PROTECTED: c_int = 7;
+/// A panic was caught before it could cross into the caller
+pub const ANNOVATE_PANIC: c_int = 8;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = RefCell::new( None );
+}
+
+fn set_last_error( message: &str ) {
+    //a filename in the message could contain NUL
+    let message = CString::new( message.replace( '\0', "\\0" ) ).unwrap();
+    LAST_ERROR.with( |last| *last.borrow_mut() = Some( message ) );
+}
+
+/// Code of `error` for C
+fn error_code( error: &AnnoError ) -> c_int {
+    match *error {
+        AnnoError::ParseError { .. } | AnnoError::IntegrityMismatch( _ ) => ANNOVATE_INVALID_FILE,
+        AnnoError::Locked( _ ) => ANNOVATE_LOCKED,
+        AnnoError::ReadOnly => ANNOVATE_READ_ONLY,
+        AnnoError::Protected( _ ) => ANNOVATE_PROTECTED,
+        AnnoError::InvalidInput { .. } => ANNOVATE_INVALID_ARGUMENT,
+        _ => ANNOVATE_ERROR
+    }
+}
+
+/// Run `body`, turning its error and any panic into a code and the last error
+fn guard<F: FnOnce() -> Result<(), ( c_int, String )>>( body: F ) -> c_int {
+    match panic::catch_unwind( AssertUnwindSafe( body ) ) {
+        Ok( Ok( () ) ) => ANNOVATE_OK,
+        Ok( Err( ( code, message ) ) ) => {
+            set_last_error( &message );
+            code
+        },
+        Err( _ ) => {
+            set_last_error( "internal error (panic)" );
+            ANNOVATE_PANIC
+        }
+    }
+}
+
+fn from_anno_error( error: AnnoError ) -> ( c_int, String ) {
+    ( error_code( &error ), error.to_string() )
+}
+
+/// The UTF-8 text of `text`, `None` for `NULL`
+unsafe fn optional_str<'a>( text: *const c_char, name: &str ) -> Result<Option<&'a str>, ( c_int, String )> {
+    if text.is_null() {
+        return Ok( None );
+    }
+    CStr::from_ptr( text ).to_str().map( Some ).map_err( |_| ( ANNOVATE_INVALID_ARGUMENT, format!( "{} is not UTF-8", name ) ) )
+}
+
+unsafe fn required_str<'a>( text: *const c_char, name: &str ) -> Result<&'a str, ( c_int, String )> {
+    match try!( optional_str( text, name ) ) {
+        Some( text ) => Ok( text ),
+        None => Err( ( ANNOVATE_INVALID_ARGUMENT, format!( "{} is NULL", name ) ) )
+    }
+}
+
+fn null_handle() -> ( c_int, String ) {
+    ( ANNOVATE_INVALID_ARGUMENT, "the annovate handle is NULL".to_string() )
+}
+
+unsafe fn handle<'a>( anno: *mut Annovate ) -> Result<&'a mut Annovate, ( c_int, String )> {
+    anno.as_mut().ok_or( null_handle() )
+}
+
+/// Open the meta file at `path` and store a handle in `*anno`. A meta file opened for writing is locked until
+/// `annovate_close` and created if it does not exist.
+#[no_mangle]
+pub unsafe extern "C" fn annovate_open( path: *const c_char, read_only: c_int, anno: *mut *mut Annovate ) -> c_int {
+    guard( || {
+        if anno.is_null() {
+            return Err( ( ANNOVATE_INVALID_ARGUMENT, "the output pointer is NULL".to_string() ) );
+        }
+        let path = try!( required_str( path, "path" ) );
+        let mode = if read_only != 0 { OpenMode::ReadOnly } else { OpenMode::ReadWrite };
+        let opened = try!( Annovate::open( Path::new( path ), mode ).map_err( from_anno_error ) );
+        *anno = Box::into_raw( Box::new( opened ) );
+        Ok( () )
+    } )
+}
+
+/// Store the most recent value of `key` of `filename` (or the directory) in `*value`
+#[no_mangle]
+pub unsafe extern "C" fn annovate_get( anno: *const Annovate, filename: *const c_char, key: *const c_char, value: *mut *mut c_char ) -> c_int {
+    guard( || {
+        let anno = try!( anno.as_ref().ok_or( null_handle() ) );
+        if value.is_null() {
+            return Err( ( ANNOVATE_INVALID_ARGUMENT, "the output pointer is NULL".to_string() ) );
+        }
+        let key = try!( required_str( key, "key" ) );
+        let found = match try!( optional_str( filename, "filename" ) ) {
+            Some( filename ) => anno.get_file_annotation( filename, key ),
+            None => anno.get_directory_annotation( key )
+        };
+        let found = try!( found.ok_or( ( ANNOVATE_NOT_FOUND, format!( "no annotation with the key `{}`", key ) ) ) );
+        let text = try!( CString::new( found.value.as_str() ).map_err( |_| ( ANNOVATE_INVALID_ARGUMENT, format!( "the value of `{}` contains NUL", key ) ) ) );
+        *value = text.into_raw();
+        Ok( () )
+    } )
+}
+
+/// Add an annotation to `filename` (or the directory). It is written by `annovate_save`.
+#[no_mangle]
+pub unsafe extern "C" fn annovate_put( anno: *mut Annovate, filename: *const c_char, key: *const c_char, value: *const c_char, context: *const c_char ) -> c_int {
+    guard( || {
+        let anno = try!( handle( anno ) );
+        let key = try!( required_str( key, "key" ) );
+        let value = try!( required_str( value, "value" ) );
+        let context = match try!( optional_str( context, "context" ) ) {
+            Some( context ) => context.to_string(),
+            None => context::library_context()
+        };
+        let annotation = Annotation::new( key.to_string(), value.to_string(), context ).with_created( Timestamp::now() );
+        let added = match try!( optional_str( filename, "filename" ) ) {
+            Some( filename ) => anno.add_file_annotation( filename, annotation ),
+            None => anno.add_directory_annotation( annotation )
+        };
+        added.map_err( from_anno_error )
+    } )
+}
+
+/// Write the meta file
+#[no_mangle]
+pub unsafe extern "C" fn annovate_save( anno: *mut Annovate ) -> c_int {
+    guard( || {
+        let anno = try!( handle( anno ) );
+        anno.save().map_err( from_anno_error )
+    } )
+}
+
+/// Release the handle and its lock without saving. `NULL` is ignored.
+#[no_mangle]
+pub unsafe extern "C" fn annovate_close( anno: *mut Annovate ) {
+    if !anno.is_null() {
+        drop( Box::from_raw( anno ) );
+    }
+}
+
+/// Free a value of `annovate_get`. `NULL` is ignored.
+#[no_mangle]
+pub unsafe extern "C" fn annovate_free_string( text: *mut c_char ) {
+    if !text.is_null() {
+        drop( CString::from_raw( text ) );
+    }
+}
+
+/// Description of the last error of this thread, `NULL` if there was none. It stays valid until another call of
+/// this thread fails.
+#[no_mangle]
+pub extern "C" fn annovate_last_error() -> *const c_char {
+    LAST_ERROR.with( |last| last.borrow().as_ref().map( |message| message.as_ptr() ).unwrap_or( ptr::null() ) )
+}
@@ -0,0 +1,236 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use {Annovate, Annotation, AnnoContainer, AnnoError, PutPolicy, Target, Timestamp, format_annotation_block, parse_annotation_block};
+use subdir;
+
+/// Notes ref under which annotations are stored (`refs/notes/annovate`)
+pub const NOTES_REF: &'static str = "annovate";
+
+/// Provenance of a single file as recorded by git
+pub struct FileHistory {
+    pub first_commit_date: String,
+    pub last_commit_date: String,
+    pub last_author: String,
+    pub first_message: String
+}
+
+/// Run git inside `dir` and return its standard output
+pub fn run_git( dir: &Path, args: &[&str] ) -> Result<String, AnnoError> {
+    let output = try!( Command::new( "git" ).current_dir( dir ).args( args ).output() );
+    if !output.status.success() {
+        let msg = String::from_utf8_lossy( &output.stderr ).trim().to_string();
+        return Err( AnnoError::GitError( msg ) );
+    }
+    Ok( String::from_utf8_lossy( &output.stdout ).into_owned() )
+}
+
+/// List all files tracked by git below `dir` (paths are relative to `dir`)
+pub fn tracked_files( dir: &Path ) -> Result<Vec<String>, AnnoError> {
+    //without -z, git quotes names with non-ASCII characters like "caf\303\251.txt"
+    let listing = try!( run_git( dir, &[ "ls-files", "-z" ] ) );
+    Ok( listing.split( '\0' ).filter( |name| !name.is_empty() ).map( |name| name.to_string() ).collect() )
+}
+
+/// Collect the commit history of a single file. Returns `None` for files without commits.
+pub fn file_history( dir: &Path, filename: &str ) -> Result<Option<FileHistory>, AnnoError> {
+    let log = try!( run_git( dir, &[ "log", "--follow", "--date=iso", "--format=%ad%x09%an%x09%s", "--", filename ] ) );
+    let commits: Vec<Vec<&str>> = log.lines().map( |l| l.splitn( 3, '\t' ).collect() ).collect();
+    let newest = match commits.first() {
+        Some( c ) => c,
+        None => return Ok( None )
+    };
+    let oldest = commits.last().unwrap(); //there is at least one commit
+    Ok( Some( FileHistory {
+        first_commit_date: oldest[ 0 ].to_string(),
+        last_commit_date: newest[ 0 ].to_string(),
+        last_author: newest.get( 1 ).unwrap_or( &"" ).to_string(),
+        first_message: oldest.get( 2 ).unwrap_or( &"" ).to_string()
+    } ) )
+}
+
+/// Annotate every tracked file in `dir` with its first/last commit date and last author. The values replace those
+/// of an earlier import. Returns the number of files that were annotated.
+pub fn import_history( anno: &mut Annovate, dir: &Path, with_message: bool, context: &str ) -> Result<usize, AnnoError> {
+    let mut count = 0;
+    for filename in try!( tracked_files( dir ) ) {
+        let history = match try!( file_history( dir, &filename ) ) {
+            Some( h ) => h,
+            None => continue
+        };
+        let filename = Target::file( &filename ).to_string();
+        let mut entries = vec![ ( "first commit", history.first_commit_date ),
+                                ( "last commit", history.last_commit_date ),
+                                ( "last author", history.last_author ) ];
+        if with_message {
+            entries.push( ( "first commit message", history.first_message ) );
+        }
+        for ( key, value ) in entries {
+            try!( anno.put_file_annotation( &filename, Annotation::new( key.to_string(), value, context.to_string() ), PutPolicy::Replace ) );
+        }
+        count += 1;
+    }
+    Ok( count )
+}
+
+/// Object id of the blob holding the current content of `filename` (the blob is written to the object database)
+fn blob_id( dir: &Path, filename: &str ) -> Result<String, AnnoError> {
+    let id = try!( run_git( dir, &[ "hash-object", "-w", "--", filename ] ) );
+    Ok( id.trim().to_string() )
+}
+
+fn add_note( dir: &Path, object: &str, annotations: &AnnoContainer ) -> Result<(), AnnoError> {
+    let text = format_annotation_block( annotations );
+    try!( run_git( dir, &[ "notes", "--ref", NOTES_REF, "add", "-f", "-m", &text, object ] ) );
+    Ok( () )
+}
+
+/// Whether `filename` is an existing file of `dir` that a note can be attached to, and not a part of a file
+/// (`data.csv#column:a`, `#L5`), a URL, a subdirectory or a deleted file
+fn has_blob( dir: &Path, filename: &str ) -> bool {
+    let target = Target::parse( filename );
+    !target.is_sub_file() && !target.is_remote() && !subdir::is_subdirectory_entry( filename ) && dir.join( &target.file ).is_file()
+}
+
+/// Attach the annotations of `filenames` (all annotated files if empty) as git notes on their blobs. Parts of
+/// files, URLs, subdirectories and missing files are skipped. Directory annotations are attached to the HEAD
+/// commit; `import_notes` finds them on later commits as well. Every blob is looked up before the first note is
+/// written. Returns the number of notes written.
+pub fn export_notes( anno: &Annovate, dir: &Path, filenames: &[String] ) -> Result<usize, AnnoError> {
+    let filenames = if filenames.is_empty() { anno.get_files() } else { filenames.to_vec() };
+    let mut notes = Vec::new();
+    for filename in filenames.iter().filter( |f| has_blob( dir, f ) ) {
+        if let Some( annotations ) = anno.get_file_annotations( filename ).filter( |annos| !annos.is_empty() ) {
+            notes.push( ( try!( blob_id( dir, &Target::parse( filename ).file ) ), annotations ) );
+        }
+    }
+    if !anno.get_directory_annotations().is_empty() {
+        let head = try!( run_git( dir, &[ "rev-parse", "--verify", "HEAD" ] ) );
+        notes.push( ( head.trim().to_string(), anno.get_directory_annotations() ) );
+    }
+    for &( ref object, annotations ) in &notes {
+        try!( add_note( dir, object, annotations ) );
+    }
+    Ok( notes.len() )
+}
+
+/// Whether `annotations` has an entry with the key, value and context of `annotation`. Timestamps and IDs are not
+/// compared, they need not survive the round trip through a note.
+fn has_entry( annotations: &AnnoContainer, annotation: &Annotation ) -> bool {
+    annotations.iter().any( |a| a.key == annotation.key && a.value == annotation.value && a.context == annotation.context )
+}
+
+/// Annotations of a note that are not yet contained in `existing`
+fn unseen_annotations( existing: Option<&AnnoContainer>, note: &str ) -> Result<AnnoContainer, AnnoError> {
+    let mut result = AnnoContainer::new();
+    for annotation in try!( parse_annotation_block( note ) ) {
+        let known = existing.map( |annos| has_entry( annos, &annotation ) ).unwrap_or( false );
+        if !known && !has_entry( &result, &annotation ) {
+            result.push( annotation );
+        }
+    }
+    Ok( result )
+}
+
+/// Read annotations back from git notes on the blobs of tracked files, and the directory annotations from the
+/// most recent commit with a note. Annotations that already exist are not duplicated. Returns the number of notes read.
+pub fn import_notes( anno: &mut Annovate, dir: &Path ) -> Result<usize, AnnoError> {
+    let mut noted_objects = HashMap::new();
+    for line in try!( run_git( dir, &[ "notes", "--ref", NOTES_REF, "list" ] ) ).lines() {
+        let mut parts = line.split_whitespace();
+        if let ( Some( note ), Some( object ) ) = ( parts.next(), parts.next() ) {
+            noted_objects.insert( object.to_string(), note.to_string() );
+        }
+    }
+
+    let mut count = 0;
+    for filename in try!( tracked_files( dir ) ) {
+        let blob = try!( run_git( dir, &[ "hash-object", "--", &filename ] ) );
+        let filename = Target::file( &filename ).to_string();
+        if let Some( note ) = noted_objects.get( blob.trim() ) {
+            let text = try!( run_git( dir, &[ "cat-file", "blob", note ] ) );
+            let annotations = try!( unseen_annotations( anno.get_file_annotations( &filename ), &text ) );
+            for annotation in annotations {
+                try!( anno.add_file_annotation( &filename, annotation ) );
+            }
+            count += 1;
+        }
+    }
+
+    //the directory was exported at HEAD or at one of the commits before it
+    let commits = try!( run_git( dir, &[ "rev-list", "HEAD" ] ) );
+    if let Some( note ) = commits.lines().filter_map( |commit| noted_objects.get( commit.trim() ) ).next() {
+        let text = try!( run_git( dir, &[ "cat-file", "blob", note ] ) );
+        let annotations = try!( unseen_annotations( Some( anno.get_directory_annotations() ), &text ) );
+        for annotation in annotations {
+            try!( anno.add_directory_annotation( annotation ) );
+        }
+        count += 1;
+    }
+    Ok( count )
+}
+
+/// Key of the directory annotation that `record_snapshot` adds
+pub const SNAPSHOT_KEY: &'static str = "snapshot";
+
+/// Line that marks hooks written by `install_hook`
+const HOOK_MARKER: &'static str = "# installed by annovate";
+
+/// Name of the checked out branch, `None` for a detached HEAD
+pub fn current_branch( dir: &Path ) -> Result<Option<String>, AnnoError> {
+    let branch = try!( run_git( dir, &[ "rev-parse", "--abbrev-ref", "HEAD" ] ) ).trim().to_string();
+    Ok( if branch.is_empty() || branch == "HEAD" { None } else { Some( branch ) } )
+}
+
+/// Abbreviated hash of the HEAD commit
+pub fn short_head( dir: &Path ) -> Result<String, AnnoError> {
+    run_git( dir, &[ "rev-parse", "--short", "HEAD" ] ).map( |hash| hash.trim().to_string() )
+}
+
+/// Record the HEAD commit as directory annotation `snapshot`, e.g. after every commit. Returns the hash.
+pub fn record_snapshot( anno: &mut Annovate, dir: &Path, context: &str ) -> Result<String, AnnoError> {
+    let head = try!( short_head( dir ) );
+    try!( anno.add_directory_annotation( Annotation::new( SNAPSHOT_KEY.to_string(), head.clone(), context.to_string() ).with_created( Timestamp::now() ) ) );
+    Ok( head )
+}
+
+/// Quote text for `sh`
+fn shell_quote( text: &str ) -> String {
+    format!( "'{}'", text.replace( '\'', "'\\''" ) )
+}
+
+/// Install the git hook `name` (e.g. `post-commit`) of the repository of `dir`, which runs `command`.
+/// A hook that annovate did not write is not replaced. Returns the path of the hook.
+pub fn install_hook( dir: &Path, name: &str, command: &[&str] ) -> Result<PathBuf, AnnoError> {
+    let hook = dir.join( try!( run_git( dir, &[ "rev-parse", "--git-path", &format!( "hooks/{}", name ) ] ) ).trim() );
+    let mut existing = String::new();
+    if let Ok( mut file ) = File::open( &hook ) {
+        try!( file.read_to_string( &mut existing ).map_err( |e| AnnoError::io( &hook, e ) ) );
+        if !existing.contains( HOOK_MARKER ) {
+            return Err( AnnoError::GitError( format!( "{} exists already and was not installed by annovate", hook.to_string_lossy() ) ) );
+        }
+    }
+    if let Some( hooks_dir ) = hook.parent() {
+        try!( fs::create_dir_all( hooks_dir ).map_err( |e| AnnoError::io( hooks_dir, e ) ) );
+    }
+    let command: Vec<String> = command.iter().map( |arg| shell_quote( arg ) ).collect();
+    let script = format!( "#!/bin/sh\n{}\n{}\n", HOOK_MARKER, command.join( " " ) );
+    let mut file = try!( File::create( &hook ).map_err( |e| AnnoError::io( &hook, e ) ) );
+    try!( file.write_all( script.as_bytes() ).map_err( |e| AnnoError::io( &hook, e ) ) );
+    try!( make_executable( &hook ) );
+    Ok( hook )
+}
+
+#[cfg(unix)]
+fn make_executable( path: &Path ) -> Result<(), AnnoError> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions( path, fs::Permissions::from_mode( 0o755 ) ).map_err( |e| AnnoError::io( path, e ) )
+}
+
+#[cfg(not(unix))]
+fn make_executable( _path: &Path ) -> Result<(), AnnoError> {
+    Ok( () ) //git for Windows runs hooks with its own shell
+}
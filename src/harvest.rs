@@ -0,0 +1,120 @@
+//Annotations embedded in plaintext files as comments like `// anno: key = value`.
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use {Annovate, Annotation, AnnoError, Target, Timestamp};
+
+/// Marker that follows the comment start
+pub const DEFAULT_MARKER: &'static str = "anno:";
+
+/// How a comment starts and, for block comments, ends
+#[derive(Clone, PartialEq, Debug)]
+pub struct CommentSyntax {
+    pub start: String,
+    pub end: Option<String>
+}
+
+impl CommentSyntax {
+    pub fn line( start: &str ) -> CommentSyntax {
+        CommentSyntax { start: start.to_string(), end: None }
+    }
+
+    pub fn block( start: &str, end: &str ) -> CommentSyntax {
+        CommentSyntax { start: start.to_string(), end: Some( end.to_string() ) }
+    }
+}
+
+/// Extracts `key = value` pairs from comments that start with the marker
+#[derive(Clone, PartialEq, Debug)]
+pub struct Harvester {
+    pub syntaxes: Vec<CommentSyntax>,
+    pub marker: String
+}
+
+impl Default for Harvester {
+    /// Comments of C-like languages, scripts, SQL, Lisp, TeX and HTML/XML
+    fn default() -> Harvester {
+        //block comments come first, `<!--` also contains `--`
+        Harvester::new( vec![ CommentSyntax::block( "/*", "*/" ),
+                              CommentSyntax::block( "<!--", "-->" ),
+                              CommentSyntax::line( "//" ),
+                              CommentSyntax::line( "#" ),
+                              CommentSyntax::line( "--" ),
+                              CommentSyntax::line( ";" ),
+                              CommentSyntax::line( "%" ) ] )
+    }
+}
+
+impl Harvester {
+    pub fn new( syntaxes: Vec<CommentSyntax> ) -> Harvester {
+        Harvester { syntaxes: syntaxes, marker: DEFAULT_MARKER.to_string() }
+    }
+
+    /// Key and value of a marker comment. Leading code (`x = 1; // anno: ...`) is allowed.
+    fn harvest_line( &self, line: &str ) -> Option<( String, String )> {
+        for syntax in &self.syntaxes {
+            let mut search_from = 0;
+            while let Some( pos ) = line[ search_from.. ].find( syntax.start.as_str() ) {
+                let comment = line[ search_from + pos + syntax.start.len().. ].trim_start();
+                search_from += pos + syntax.start.len();
+                if !comment.starts_with( self.marker.as_str() ) {
+                    continue;
+                }
+                let mut body = &comment[ self.marker.len().. ];
+                if let Some( ref end ) = syntax.end {
+                    body = match body.find( end.as_str() ) {
+                        Some( end_pos ) => &body[ ..end_pos ],
+                        None => body
+                    };
+                }
+                let ( key, value ) = match body.find( '=' ) {
+                    Some( eq ) => ( body[ ..eq ].trim(), body[ eq + 1.. ].trim() ),
+                    None => continue
+                };
+                if !key.is_empty() {
+                    return Some( ( key.to_string(), value.to_string() ) );
+                }
+            }
+        }
+        None
+    }
+
+    /// All marker comments of a text in the order they appear
+    pub fn harvest_text( &self, text: &str ) -> Vec<( String, String )> {
+        text.lines().filter_map( |line| self.harvest_line( line ) ).collect()
+    }
+
+    /// Marker comments of a file. Files that are not UTF-8 text have none.
+    pub fn harvest_file( &self, path: &Path ) -> Result<Vec<( String, String )>, AnnoError> {
+        let mut text = String::new();
+        let mut file = try!( File::open( path ).map_err( |e| AnnoError::io( path, e ) ) );
+        match file.read_to_string( &mut text ) {
+            Ok( _ ) => Ok( self.harvest_text( &text ) ),
+            Err( ref e ) if e.kind() == io::ErrorKind::InvalidData => Ok( vec![] ),
+            Err( e ) => Err( AnnoError::io( path, e ) )
+        }
+    }
+}
+
+impl Annovate {
+    /// Add the marker comments of `dir/filename` as annotations of `filename` with the context
+    /// `<context>, harvested from <filename>`. Values that are already the most recent value of their key
+    /// are skipped, so harvesting again only adds changes. Returns the number of added annotations.
+    pub fn harvest( &mut self, dir: &Path, filename: &str, harvester: &Harvester, context: &str ) -> Result<usize, AnnoError> {
+        let pairs = try!( harvester.harvest_file( &dir.join( Target::parse( filename ).file ) ) );
+        let now = Timestamp::now();
+        let mut added = 0;
+        for ( key, value ) in pairs {
+            let current = self.get_file_annotations( filename ).and_then( |annos| annos.iter().rev().find( |a| a.key == key ) ).map( |a| a.value.clone() );
+            if current.as_ref() == Some( &value ) {
+                continue;
+            }
+            let harvest_context = format!( "{}, harvested from {}", context, filename );
+            try!( self.add_file_annotation( filename, Annotation::new( key, value, harvest_context ).with_created( now ) ) );
+            added += 1;
+        }
+        Ok( added )
+    }
+}
@@ -0,0 +1,172 @@
+//Content hashes of annotated files. `snapshot` stores them as annotations, `report --verify` compares
+//them with the files on disk to find files that changed since they were annotated.
+
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use {Annovate, Annotation, AnnoError, Target, Timestamp};
+
+/// Key of the annotation with the SHA-256 hash of a file. The leading dot hides it like a dotfile.
+pub const CHECKSUM_KEY: &'static str = ".sha256";
+
+const ROUND_CONSTANTS: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2
+];
+
+const INITIAL_STATE: [u32; 8] = [ 0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19 ];
+
+/// Incremental SHA-256 (FIPS 180-4)
+#[derive(Clone)]
+pub struct Sha256 {
+    state: [u32; 8],
+    block: [u8; 64],
+    block_len: usize,
+    total_len: u64
+}
+
+impl Sha256 {
+    pub fn new() -> Sha256 {
+        Sha256 { state: INITIAL_STATE, block: [ 0; 64 ], block_len: 0, total_len: 0 }
+    }
+
+    pub fn update( &mut self, mut data: &[u8] ) {
+        self.total_len += data.len() as u64;
+        while !data.is_empty() {
+            let n = ( 64 - self.block_len ).min( data.len() );
+            self.block[ self.block_len..self.block_len + n ].copy_from_slice( &data[ ..n ] );
+            self.block_len += n;
+            data = &data[ n.. ];
+            if self.block_len == 64 {
+                let block = self.block;
+                self.compress( &block );
+                self.block_len = 0;
+            }
+        }
+    }
+
+    pub fn finish( mut self ) -> [u8; 32] {
+        let bit_len = self.total_len * 8;
+        self.update( &[ 0x80 ] );
+        while self.block_len != 56 {
+            self.update( &[ 0 ] );
+        }
+        let mut length = [ 0u8; 8 ];
+        for i in 0..8 {
+            length[ i ] = ( bit_len >> ( 56 - 8 * i ) ) as u8;
+        }
+        self.update( &length );
+        let mut digest = [ 0u8; 32 ];
+        for ( i, word ) in self.state.iter().enumerate() {
+            for j in 0..4 {
+                digest[ 4 * i + j ] = ( word >> ( 24 - 8 * j ) ) as u8;
+            }
+        }
+        digest
+    }
+
+    fn compress( &mut self, block: &[u8; 64] ) {
+        let mut w = [ 0u32; 64 ];
+        for i in 0..16 {
+            w[ i ] = ( block[ 4 * i ] as u32 ) << 24 | ( block[ 4 * i + 1 ] as u32 ) << 16 | ( block[ 4 * i + 2 ] as u32 ) << 8 | block[ 4 * i + 3 ] as u32;
+        }
+        for i in 16..64 {
+            let s0 = w[ i - 15 ].rotate_right( 7 ) ^ w[ i - 15 ].rotate_right( 18 ) ^ ( w[ i - 15 ] >> 3 );
+            let s1 = w[ i - 2 ].rotate_right( 17 ) ^ w[ i - 2 ].rotate_right( 19 ) ^ ( w[ i - 2 ] >> 10 );
+            w[ i ] = w[ i - 16 ].wrapping_add( s0 ).wrapping_add( w[ i - 7 ] ).wrapping_add( s1 );
+        }
+        let mut v = self.state;
+        for i in 0..64 {
+            let s1 = v[ 4 ].rotate_right( 6 ) ^ v[ 4 ].rotate_right( 11 ) ^ v[ 4 ].rotate_right( 25 );
+            let choice = ( v[ 4 ] & v[ 5 ] ) ^ ( !v[ 4 ] & v[ 6 ] );
+            let t1 = v[ 7 ].wrapping_add( s1 ).wrapping_add( choice ).wrapping_add( ROUND_CONSTANTS[ i ] ).wrapping_add( w[ i ] );
+            let s0 = v[ 0 ].rotate_right( 2 ) ^ v[ 0 ].rotate_right( 13 ) ^ v[ 0 ].rotate_right( 22 );
+            let majority = ( v[ 0 ] & v[ 1 ] ) ^ ( v[ 0 ] & v[ 2 ] ) ^ ( v[ 1 ] & v[ 2 ] );
+            let t2 = s0.wrapping_add( majority );
+            v = [ t1.wrapping_add( t2 ), v[ 0 ], v[ 1 ], v[ 2 ], v[ 3 ].wrapping_add( t1 ), v[ 4 ], v[ 5 ], v[ 6 ] ];
+        }
+        for i in 0..8 {
+            self.state[ i ] = self.state[ i ].wrapping_add( v[ i ] );
+        }
+    }
+}
+
+/// Lowercase hexadecimal SHA-256 of `data`
+pub fn sha256_hex( data: &[u8] ) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update( data );
+    to_hex( &hasher.finish() )
+}
+
+fn to_hex( bytes: &[u8] ) -> String {
+    bytes.iter().map( |b| format!( "{:02x}", b ) ).collect()
+}
+
+/// Lowercase hexadecimal SHA-256 of the content of a file
+pub fn hash_file( path: &Path ) -> Result<String, AnnoError> {
+    let mut file = try!( File::open( path ).map_err( |e| AnnoError::io( path, e ) ) );
+    let mut hasher = Sha256::new();
+    let mut buffer = [ 0u8; 64 * 1024 ];
+    loop {
+        let n = try!( file.read( &mut buffer ).map_err( |e| AnnoError::io( path, e ) ) );
+        if n == 0 {
+            break;
+        }
+        hasher.update( &buffer[ ..n ] );
+    }
+    Ok( to_hex( &hasher.finish() ) )
+}
+
+impl Annovate {
+    /// Annotated files (no URLs or sub-file targets) that exist in `dir`
+    fn files_on_disk( &self, dir: &Path ) -> Vec<String> {
+        self.iter_files().filter( |f| {
+            let target = Target::parse( f );
+            !target.is_remote() && !target.is_sub_file() && dir.join( &target.file ).is_file()
+        } ).map( |f| f.to_string() ).collect()
+    }
+
+    /// Most recent checksum stored for a file
+    pub fn checksum( &self, filename: &str ) -> Option<&str> {
+        self.get_file_annotations( filename )
+            .and_then( |annos| annos.iter().rev().find( |a| a.key == CHECKSUM_KEY ) )
+            .map( |a| a.value.as_str() )
+    }
+
+    /// Store the hash of every annotated file in `dir` whose checksum is missing or outdated.
+    /// Returns the number of stored checksums.
+    pub fn record_checksums( &mut self, dir: &Path, context: &str ) -> Result<usize, AnnoError> {
+        let mut count = 0;
+        for filename in self.files_on_disk( dir ) {
+            let hash = try!( hash_file( &dir.join( Target::parse( &filename ).file ) ) );
+            if self.checksum( &filename ) != Some( hash.as_str() ) {
+                //the old checksum is replaced, the file keeps its place
+                let mut annotations: Vec<Annotation> = self.files[ &filename ].iter().filter( |a| a.key != CHECKSUM_KEY ).cloned().collect();
+                annotations.push( Annotation::new( CHECKSUM_KEY.to_string(), hash, context.to_string() ).with_created( Timestamp::now() ) );
+                try!( self.set_file_annotations( &filename, annotations ) );
+                count += 1;
+            }
+        }
+        Ok( count )
+    }
+
+    /// Files in `dir` whose content differs from their stored checksum. Files without checksum are not listed.
+    pub fn changed_files( &self, dir: &Path ) -> Result<Vec<String>, AnnoError> {
+        let mut changed = Vec::new();
+        for filename in self.files_on_disk( dir ) {
+            if let Some( stored ) = self.checksum( &filename ) {
+                if try!( hash_file( &dir.join( Target::parse( &filename ).file ) ) ) != stored {
+                    changed.push( filename );
+                }
+            }
+        }
+        Ok( changed )
+    }
+}
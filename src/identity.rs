@@ -0,0 +1,152 @@
+//Files identified by their content. With `identity = "hash"` in the configuration, every annotated file gets its
+//SHA-256 checksum (`.sha256`, see `hash`) when it is annotated, and the checksums serve as index from content to
+//filename. A file that was renamed or moved outside of annovate still finds its metadata:
+//
+//    $ anno put data.csv source "sensor 3"
+//    $ mv data.csv sensor3.csv
+//    $ anno query sensor3.csv              shows the annotations of data.csv with a note
+//    $ anno relink
+//    data.csv -> sensor3.csv
+//
+//`relink` looks for annotated files that no longer exist and moves their annotations to the unannotated file with
+//the same content. A file whose content changed after its checksum was stored cannot be found anymore; `snapshot`
+//stores the current checksums.
+
+use std::collections::HashMap;
+use std::fs::read_dir;
+use std::path::Path;
+
+use {Annovate, Annotation, AnnoError, Target, Timestamp};
+use hash::{hash_file, CHECKSUM_KEY};
+use ignore::IgnoreRules;
+
+/// What identifies an annotated file
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum IdentityMode {
+    /// Only the filename
+    Name,
+    /// The filename, and the content hash if the file is not found by name
+    Hash
+}
+
+impl Default for IdentityMode {
+    fn default() -> IdentityMode {
+        IdentityMode::Name
+    }
+}
+
+impl IdentityMode {
+    pub fn from_name( name: &str ) -> Option<IdentityMode> {
+        match name {
+            "name" => Some( IdentityMode::Name ),
+            "hash" => Some( IdentityMode::Hash ),
+            _ => None
+        }
+    }
+}
+
+/// Files below `dir` (relative to `root`, with `/` between directories) that are no hidden, ignored or other meta files.
+/// Directories with their own meta file called `meta_name` are left out.
+fn collect_files( root: &Path, relative: &str, meta_name: &str, ignore: &IgnoreRules, found: &mut Vec<String> ) -> Result<(), AnnoError> {
+    let dir = root.join( relative );
+    if !relative.is_empty() && dir.join( meta_name ).exists() {
+        return Ok( () );
+    }
+    for entry in try!( read_dir( &dir ).map_err( |e| AnnoError::io( &dir, e ) ) ) {
+        let entry = try!( entry );
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with( "." ) {
+            continue;
+        }
+        let path = if relative.is_empty() { name } else { format!( "{}/{}", relative, name ) };
+        let file_type = try!( entry.file_type() );
+        if ignore.is_ignored( &path, file_type.is_dir() ) {
+            continue;
+        }
+        if file_type.is_dir() { //symlinks are not followed to avoid cycles
+            try!( collect_files( root, &path, meta_name, ignore, found ) );
+        } else if file_type.is_file() {
+            found.push( path );
+        }
+    }
+    Ok( () )
+}
+
+impl Annovate {
+    /// Annotated files by their most recent checksum, in the order of the meta file
+    pub fn hash_index( &self ) -> HashMap<String, Vec<String>> {
+        let mut index: HashMap<String, Vec<String>> = HashMap::new();
+        for file in self.iter_files() {
+            if let Some( hash ) = self.checksum( file ) {
+                index.entry( hash.to_string() ).or_insert_with( Vec::new ).push( file.to_string() );
+            }
+        }
+        index
+    }
+
+    /// The annotated file with the same content as `filename` (relative to the directory of the meta file), `None`
+    /// if `filename` does not exist or no annotated file or more than one has its checksum
+    pub fn find_by_content( &self, filename: &str ) -> Result<Option<String>, AnnoError> {
+        let path = self.resolve( filename );
+        if !path.is_file() {
+            return Ok( None );
+        }
+        let hash = try!( hash_file( &path ) );
+        match self.hash_index().remove( &hash ) {
+            Some( ref files ) if files.len() == 1 => Ok( Some( files[ 0 ].clone() ) ),
+            _ => Ok( None )
+        }
+    }
+
+    /// Store the checksum of the annotated files in `dir` that have none yet. Returns the number of stored checksums.
+    pub fn record_missing_checksums( &mut self, dir: &Path, context: &str ) -> Result<usize, AnnoError> {
+        let missing: Vec<String> = self.iter_files().filter( |f| {
+            let target = Target::parse( f );
+            !target.is_remote() && !target.is_sub_file() && self.checksum( f ).is_none() && dir.join( &target.file ).is_file()
+        } ).map( |f| f.to_string() ).collect();
+        for filename in &missing {
+            let hash = try!( hash_file( &dir.join( Target::parse( filename ).file ) ) );
+            try!( self.add_file_annotation( filename, Annotation::new( CHECKSUM_KEY.to_string(), hash, context.to_string() ).with_created( Timestamp::now() ) ) );
+        }
+        Ok( missing.len() )
+    }
+
+    /// Move the annotations of annotated files that do not exist in `dir` anymore to the file below `dir` without
+    /// annotations that has their checksum. Files without a unique match are left alone.
+    /// Returns the pairs `( old, new )` of moved files, sorted by the old name.
+    pub fn relink( &mut self, dir: &Path ) -> Result<Vec<( String, String )>, AnnoError> {
+        try!( self.check_writable() );
+        let mut orphans: HashMap<String, Vec<String>> = HashMap::new();
+        for ( hash, files ) in self.hash_index() {
+            let gone: Vec<String> = files.into_iter().filter( |f| { let target = Target::parse( f ); !target.is_remote() && !dir.join( &target.file ).exists() } ).collect();
+            if gone.len() == 1 {
+                orphans.insert( hash, gone );
+            }
+        }
+        if orphans.is_empty() {
+            return Ok( Vec::new() );
+        }
+        let meta_name = self.filename.file_name().map( |n| n.to_string_lossy().into_owned() ).unwrap_or_default();
+        let mut candidates = Vec::new();
+        try!( collect_files( dir, "", &meta_name, &try!( IgnoreRules::load( dir ) ), &mut candidates ) );
+        let mut matches: HashMap<String, Vec<String>> = HashMap::new();
+        for candidate in candidates.into_iter().filter( |c| self.get_file_annotations( c ).is_none() ) {
+            let hash = try!( hash_file( &dir.join( &candidate ) ) );
+            if orphans.contains_key( &hash ) {
+                matches.entry( hash ).or_insert_with( Vec::new ).push( candidate );
+            }
+        }
+        let mut moved = Vec::new();
+        for ( hash, found ) in matches {
+            //copies of the file have the same content, which of them is the original is unknown
+            if found.len() == 1 {
+                moved.push( ( orphans[ &hash ][ 0 ].clone(), found[ 0 ].clone() ) );
+            }
+        }
+        moved.sort();
+        for &( ref old, ref new ) in &moved {
+            try!( self.rename_file( old, new ) );
+        }
+        Ok( moved )
+    }
+}
@@ -0,0 +1,84 @@
+//Annotations made by `anno put` get an ID that stays the same when the meta file is rewritten, so that one
+//entry of a key with several entries can be changed or removed:
+//
+//    >status
+//    =wip
+//    <annovate program, 16.10.2026 2:11:56
+//    !2026-10-16T02:11:56Z
+//    %3f9a1c2e7b4d5a60
+//
+//The `%` line ends the annotation. Older annotations and those made by other means have no ID.
+
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use time;
+
+use {Annovate, AnnoError, Annotation};
+use hash::sha256_hex;
+
+/// Number of hex digits of a generated ID
+pub const ID_LENGTH: usize = 16;
+
+/// Distinguishes the IDs generated by one process within the same nanosecond
+static GENERATED: AtomicUsize = AtomicUsize::new( 0 );
+
+/// A new ID of `ID_LENGTH` hex digits, derived from the time, the process and a counter
+pub fn generate_id() -> String {
+    let now = time::get_time();
+    let seed = format!( "{}.{}.{}.{}", now.sec, now.nsec, process::id(), GENERATED.fetch_add( 1, Ordering::Relaxed ) );
+    sha256_hex( seed.as_bytes() )[ ..ID_LENGTH ].to_string()
+}
+
+/// Whether `id` may be written as `%` line: letters, digits, `-` and `_`
+pub fn is_valid_id( id: &str ) -> bool {
+    !id.is_empty() && id.chars().all( |c| c.is_ascii_alphanumeric() || c == '-' || c == '_' )
+}
+
+impl Annovate {
+    /// The annotation with the ID and its target, `None` for the directory
+    pub fn find_id( &self, id: &str ) -> Option<( Option<&str>, &Annotation )> {
+        self.iter_all_annotations().find( |&( _, a )| a.id.as_ref().map( |i| i == id ).unwrap_or( false ) )
+    }
+
+    /// Remove the annotation with the ID. A file without annotations and tags is dropped afterwards.
+    /// A protected annotation is only removed if protection is overridden.
+    pub fn remove_id( &mut self, id: &str ) -> Result<bool, AnnoError> {
+        try!( self.check_writable() );
+        let target = match self.find_id( id ) {
+            Some( ( target, annotation ) ) => {
+                try!( self.check_unprotected( annotation ) );
+                target.map( |t| t.to_string() )
+            },
+            None => return Ok( false )
+        };
+        let has_id = |a: &Annotation| a.id.as_ref().map( |i| i == id ).unwrap_or( false );
+        match target {
+            Some( filename ) => self.retain_file_annotations( &filename, |_, a| !has_id( a ) ).map( |removed| removed > 0 ),
+            None => {
+                self.dir.retain( |a| !has_id( a ) );
+                Ok( self.mark_modified( true, true ) )
+            }
+        }
+    }
+
+    /// Replace the value of the annotation with the ID; key, context, times and type stay the same.
+    /// A protected annotation is only changed if protection is overridden.
+    pub fn amend_id( &mut self, id: &str, value: String ) -> Result<bool, AnnoError> {
+        try!( self.check_writable() );
+        if let Some( ( _, annotation ) ) = self.find_id( id ) {
+            try!( self.check_unprotected( annotation ) );
+        }
+        let found = self.dir.iter_mut().chain( self.files.values_mut().flat_map( |annos| annos.iter_mut() ) )
+                                       .find( |a| a.id.as_ref().map( |i| i == id ).unwrap_or( false ) );
+        let changed = match found {
+            Some( annotation ) if annotation.value != value => {
+                annotation.value = value;
+                true
+            },
+            Some( _ ) => false,
+            None => return Ok( false )
+        };
+        Ok( self.mark_modified( changed, true ) )
+    }
+}
@@ -0,0 +1,132 @@
+//Files that reports, statistics and recursive commands leave out. A file called `.annovateignore` next to the meta
+//file lists patterns like a `.gitignore` file, one per line:
+//
+//    # build artifacts
+//    *.o
+//    target/
+//    /notes.txt
+//    docs/**/*.tmp
+//    !keep.o
+//
+//Blank lines and lines starting with `#` are skipped. A pattern without `/` matches the name in every directory,
+//otherwise it matches the path relative to the directory of the ignore file (a leading `/` only anchors the
+//pattern). A trailing `/` matches directories only and `**` stands for any number of directories. `!` includes
+//a file again that an earlier pattern ignored, unless one of its parent directories is ignored. The last matching
+//pattern decides. Wildcards match a leading `.` as well, like in git.
+
+use std::fs::File;
+use std::io::{ErrorKind, Read};
+use std::path::Path;
+
+use {Annovate, AnnoError, Pattern};
+
+/// Name of the ignore file in the directory of the meta file
+pub const IGNORE_FILE: &'static str = ".annovateignore";
+
+#[derive(Clone, PartialEq, Debug)]
+enum Segment {
+    /// `**`
+    AnyDirs,
+    Name( Pattern )
+}
+
+#[derive(Clone, PartialEq, Debug)]
+struct Rule {
+    segments: Vec<Segment>,
+    negated: bool,
+    dir_only: bool
+}
+
+/// The patterns of an ignore file, see above
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct IgnoreRules {
+    rules: Vec<Rule>
+}
+
+fn matches_segments( segments: &[Segment], names: &[&str] ) -> bool {
+    match segments.first() {
+        None => names.is_empty(),
+        Some( &Segment::AnyDirs ) => matches_segments( &segments[ 1.. ], names ) || ( !names.is_empty() && matches_segments( segments, &names[ 1.. ] ) ),
+        Some( &Segment::Name( ref pattern ) ) => !names.is_empty() && pattern.matches( names[ 0 ] ) && matches_segments( &segments[ 1.. ], &names[ 1.. ] )
+    }
+}
+
+impl IgnoreRules {
+    /// Parse the content of an ignore file. Fails if a line is no valid pattern.
+    pub fn parse( text: &str ) -> Result<IgnoreRules, AnnoError> {
+        let mut rules = Vec::new();
+        for line in text.lines() {
+            let mut line = line.trim_right();
+            if line.is_empty() || line.starts_with( "#" ) {
+                continue;
+            }
+            let negated = line.starts_with( "!" );
+            if negated {
+                line = &line[ 1.. ];
+            }
+            let dir_only = line.ends_with( "/" );
+            line = line.trim_right_matches( '/' );
+            //a pattern with a `/` before its end is relative to the ignore file
+            let anchored = line.contains( '/' );
+            if line.starts_with( "/" ) {
+                line = &line[ 1.. ];
+            }
+            if line.is_empty() {
+                continue;
+            }
+            let mut segments = if anchored { Vec::new() } else { vec![ Segment::AnyDirs ] };
+            for part in line.split( '/' ).filter( |p| !p.is_empty() ) {
+                segments.push( if part == "**" { Segment::AnyDirs } else { Segment::Name( try!( Pattern::new( part ) ).matching_hidden() ) } );
+            }
+            rules.push( Rule { segments: segments, negated: negated, dir_only: dir_only } );
+        }
+        Ok( IgnoreRules { rules: rules } )
+    }
+
+    /// The rules of the ignore file in `dir`, none if there is no ignore file
+    pub fn load( dir: &Path ) -> Result<IgnoreRules, AnnoError> {
+        let path = dir.join( IGNORE_FILE );
+        let mut text = String::new();
+        match File::open( &path ) {
+            Ok( mut file ) => try!( file.read_to_string( &mut text ).map_err( |e| AnnoError::io( &path, e ) ) ),
+            Err( ref e ) if e.kind() == ErrorKind::NotFound => return Ok( IgnoreRules::default() ),
+            Err( e ) => return Err( AnnoError::io( &path, e ) )
+        };
+        IgnoreRules::parse( &text )
+    }
+
+    pub fn is_empty( &self ) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Whether `path` (relative to the directory of the ignore file, with `/` between directories) is ignored,
+    /// itself or because one of its parent directories is
+    pub fn is_ignored( &self, path: &str, is_dir: bool ) -> bool {
+        if self.rules.is_empty() {
+            return false;
+        }
+        let names: Vec<&str> = path.split( '/' ).filter( |n| !n.is_empty() && *n != "." ).collect();
+        ( 1..names.len() ).any( |end| self.decides( &names[ ..end ], true ) ) || self.decides( &names, is_dir )
+    }
+
+    /// Whether the last rule that matches `names` itself ignores it
+    fn decides( &self, names: &[&str], is_dir: bool ) -> bool {
+        match self.rules.iter().rev().find( |rule| ( is_dir || !rule.dir_only ) && matches_segments( &rule.segments, names ) ) {
+            Some( rule ) => !rule.negated,
+            None => false
+        }
+    }
+}
+
+impl Annovate {
+    /// The rules of the ignore file next to the meta file
+    pub fn ignore_rules( &self ) -> Result<IgnoreRules, AnnoError> {
+        IgnoreRules::load( &self.base_dir() )
+    }
+
+    /// Whether `path` (relative to the directory of the meta file) is left out by the ignore file next to the meta file
+    pub fn is_ignored( &self, path: &str ) -> Result<bool, AnnoError> {
+        let rules = try!( self.ignore_rules() );
+        Ok( rules.is_ignored( path, self.base_dir().join( path ).is_dir() ) )
+    }
+}
@@ -0,0 +1,105 @@
+//Nested directories can each have their own meta file. A file belongs to the meta file of the nearest directory
+//above it, `sub/deep/file.txt` for example to `sub/.annovate` as `deep/file.txt`, and inherits the annotations of the
+//directories it is in:
+//
+//    .annovate          directory annotations, entry `sub/`
+//    sub/.annovate      directory annotations, entry `deep/`, entry `deep/file.txt`
+//
+//The inherited annotations are ordered from the outermost directory to the innermost one. A directory is described
+//by the `name/` entry in the meta file of its parent before its own directory annotations. Like newer entries of a
+//key, the annotations closer to the file come later and take precedence.
+
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+use {AnnoContainer, AnnoError};
+use reader;
+use subdir;
+
+/// The meta file a path belongs to
+#[derive(Clone, PartialEq, Debug)]
+pub struct MetaOwner {
+    pub meta_file: PathBuf,
+    /// Name of the path in the meta file, with `/` between directories
+    pub name: String
+}
+
+/// The directory of `path`, with symbolic links and `..` resolved. `None` if it does not exist.
+fn containing_directory( path: &Path ) -> Option<PathBuf> {
+    let parent = match path.parent() {
+        Some( parent ) if parent != Path::new( "" ) => parent,
+        _ => Path::new( "." )
+    };
+    parent.canonicalize().ok()
+}
+
+/// Names of the directories from `ancestor` down to `dir`, which is inside it
+fn components_between( ancestor: &Path, dir: &Path ) -> Vec<String> {
+    dir.strip_prefix( ancestor ).map( |rest| rest.components().map( |c| c.as_os_str().to_string_lossy().into_owned() ).collect() )
+                                .unwrap_or( Vec::new() )
+}
+
+/// The meta file called `meta_name` in the nearest directory above `path` and the name of `path` in it.
+/// `None` if no directory above `path` has a meta file.
+pub fn find_owner( path: &Path, meta_name: &str ) -> Option<MetaOwner> {
+    let file_name = match path.file_name() {
+        Some( file_name ) => file_name.to_string_lossy().into_owned(),
+        None => return None
+    };
+    let dir = match containing_directory( path ) {
+        Some( dir ) => dir,
+        None => return None
+    };
+    dir.ancestors().find( |d| d.join( meta_name ).is_file() ).map( |owner| {
+        let mut parts = components_between( owner, &dir );
+        parts.push( file_name );
+        MetaOwner { meta_file: owner.join( meta_name ), name: parts.join( "/" ) }
+    } )
+}
+
+/// The annotations that `name` of `meta_file` (`None` for its directory) inherits from the directory of
+/// `meta_file` and the directories above it, outermost first. The annotations of `name` itself are not included.
+pub fn inherited_annotations( meta_file: &Path, name: Option<&str> ) -> Result<AnnoContainer, AnnoError> {
+    let meta_name = match meta_file.file_name() {
+        Some( meta_name ) => meta_name.to_os_string(),
+        None => return Ok( AnnoContainer::new() )
+    };
+    let meta_dir = match containing_directory( meta_file ) {
+        Some( meta_dir ) => meta_dir,
+        None => return Ok( AnnoContainer::new() )
+    };
+    //the directory that `name` is in
+    let mut target_dir = meta_dir.clone();
+    if let Some( name ) = name {
+        let mut dirs: Vec<&str> = name.split( '/' ).filter( |part| !part.is_empty() ).collect();
+        dirs.pop();
+        for dir in dirs {
+            target_dir.push( dir );
+        }
+    }
+
+    //( depth of the described directory, depth of the meta file, annotations )
+    let mut layers: Vec<( usize, usize, AnnoContainer )> = Vec::new();
+    for owner in meta_dir.ancestors().filter( |d| d.join( &meta_name ).is_file() ) {
+        let depth = owner.components().count();
+        let path = owner.join( &meta_name );
+        let mut entries = Vec::new();
+        let mut prefix = String::new();
+        for part in components_between( owner, &target_dir ) {
+            prefix = if prefix.is_empty() { part } else { format!( "{}/{}", prefix, part ) };
+            entries.push( subdir::subdirectory_entry( &prefix ) );
+        }
+        let wanted: BTreeSet<&str> = entries.iter().map( |e| e.as_str() ).collect();
+        let ( dir, targets ) = try!( reader::read_selected( &path, |n| wanted.contains( n ) ) );
+        //the directory of the meta file itself is what a query of the directory shows
+        if name.is_some() || owner != meta_dir.as_path() {
+            layers.push( ( depth, depth, dir ) );
+        }
+        for ( entry, annotations ) in targets {
+            let described = depth + entry.split( '/' ).filter( |part| !part.is_empty() ).count();
+            layers.push( ( described, depth, annotations ) );
+        }
+    }
+    layers.sort_by( |a, b| ( a.0, a.1 ).cmp( &( b.0, b.1 ) ) );
+    Ok( layers.into_iter().flat_map( |( _, _, annotations )| annotations ).collect() )
+}
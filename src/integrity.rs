@@ -0,0 +1,155 @@
+//Integrity footer of a meta file. When the save options ask for it (`integrity = "warn"` or `"error"` in the
+//configuration), `save` ends the file with the SHA-256 hash of everything before the footer:
+//
+//    @data.csv
+//    >source
+//    =sensor 3
+//    <annovate program, 1.3.2016 12:30:00
+//    $sha256:4420f3bed9d2c2f9db06c6af150d6cf634a1e1ea03111a881c51620bb6b53c2f
+//
+//A file whose hash does not match was edited outside of annovate or truncated. Delta records that `--append`
+//adds after the footer are not covered by it. TOML files carry the footer as a comment, `# sha256:...`.
+//A file that has a footer keeps it when it is saved, also without the option.
+
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use {Annovate, AnnoError};
+use hash::sha256_hex;
+
+/// Start of the footer line of the line format
+pub const FOOTER_PREFIX: &'static str = "$sha256:";
+
+/// Start of the footer line of TOML files
+pub const TOML_FOOTER_PREFIX: &'static str = "# sha256:";
+
+/// Whether the footer is written and what a file that does not match it means
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum IntegrityPolicy {
+    /// No footer is written, a footer that is there is not checked
+    #[default]
+    Off,
+    /// Warn about a file that does not match its footer
+    Warn,
+    /// Refuse to work with a file that does not match its footer
+    Error
+}
+
+impl IntegrityPolicy {
+    pub fn from_name( name: &str ) -> Option<IntegrityPolicy> {
+        match name {
+            "off" => Some( IntegrityPolicy::Off ),
+            "warn" => Some( IntegrityPolicy::Warn ),
+            "error" => Some( IntegrityPolicy::Error ),
+            _ => None
+        }
+    }
+
+    pub fn is_enabled( &self ) -> bool {
+        *self != IntegrityPolicy::Off
+    }
+}
+
+/// Result of comparing a file with its footer
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum IntegrityStatus {
+    /// The hash matches the content before the footer
+    Valid,
+    /// The file has no footer
+    Missing,
+    /// The content was changed after the footer was written
+    Mismatch
+}
+
+/// The hash of a footer line, `None` if the line is no footer
+pub fn footer_hash( line: &str ) -> Option<&str> {
+    let line = line.trim_right_matches( '\r' );
+    let hash = if line.starts_with( FOOTER_PREFIX ) {
+        &line[ FOOTER_PREFIX.len().. ]
+    } else if line.starts_with( TOML_FOOTER_PREFIX ) {
+        &line[ TOML_FOOTER_PREFIX.len().. ]
+    } else {
+        return None;
+    };
+    if hash.len() == 64 && hash.chars().all( |c| c.is_digit( 16 ) && !c.is_uppercase() ) { Some( hash ) } else { None }
+}
+
+/// Start and end (after the line break) of the last footer line in `text` with its hash
+fn find_footer( text: &str ) -> Option<( usize, usize, &str )> {
+    let mut found = None;
+    let mut start = 0;
+    for line in text.split_terminator( '\n' ) {
+        let end = ::std::cmp::min( start + line.len() + 1, text.len() );
+        if let Some( hash ) = footer_hash( line ) {
+            found = Some( ( start, end, hash ) );
+        }
+        start = end;
+    }
+    found
+}
+
+/// Whether `text` has a footer
+pub fn has_footer( text: &str ) -> bool {
+    find_footer( text ).is_some()
+}
+
+/// `text` without its footer line
+pub fn strip_footer( text: &str ) -> String {
+    match find_footer( text ) {
+        Some( ( start, end, _ ) ) => format!( "{}{}", &text[ ..start ], &text[ end.. ] ),
+        None => text.to_string()
+    }
+}
+
+/// `text` with a footer line for its content, `toml` selects the comment of TOML files
+pub fn with_footer( text: &str, toml: bool ) -> String {
+    let line_break = if text.contains( "\r\n" ) { "\r\n" } else { "\n" };
+    let mut result = text.to_string();
+    if !result.is_empty() && !result.ends_with( '\n' ) {
+        result.push_str( line_break );
+    }
+    let hash = sha256_hex( result.as_bytes() );
+    result.push_str( if toml { TOML_FOOTER_PREFIX } else { FOOTER_PREFIX } );
+    result.push_str( &hash );
+    result.push_str( line_break );
+    result
+}
+
+/// Compare `text` with its footer. Only delta records may follow the footer.
+pub fn verify( text: &str ) -> IntegrityStatus {
+    match find_footer( text ) {
+        None => IntegrityStatus::Missing,
+        Some( ( start, end, hash ) ) => {
+            let rest = &text[ end.. ];
+            if sha256_hex( text[ ..start ].as_bytes() ) == hash && ( rest.is_empty() || rest.starts_with( '&' ) ) {
+                IntegrityStatus::Valid
+            } else {
+                IntegrityStatus::Mismatch
+            }
+        }
+    }
+}
+
+/// Compare the meta file at `path` with its footer
+pub fn verify_file( path: &Path ) -> Result<IntegrityStatus, AnnoError> {
+    let mut content = Vec::new();
+    let mut file = try!( File::open( path ).map_err( |e| AnnoError::io( path, e ) ) );
+    try!( file.read_to_end( &mut content ).map_err( |e| AnnoError::io( path, e ) ) );
+    Ok( verify( &String::from_utf8_lossy( &content ) ) )
+}
+
+/// Fails with `AnnoError::IntegrityMismatch` if the meta file at `path` does not match its footer or has none
+pub fn check_file( path: &Path ) -> Result<(), AnnoError> {
+    match try!( verify_file( path ) ) {
+        IntegrityStatus::Valid => Ok( () ),
+        _ => Err( AnnoError::IntegrityMismatch( path.to_path_buf() ) )
+    }
+}
+
+impl Annovate {
+    /// Compare the file on disk with its footer, see `check_file`
+    pub fn verify_integrity( &self ) -> Result<(), AnnoError> {
+        check_file( &self.filename )
+    }
+}
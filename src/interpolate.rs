@@ -0,0 +1,136 @@
+//Values that refer to other annotations, e.g. `${dir:project}-v${file:version}`. `${dir:<key>}` stands for the
+//most recent value of a directory annotation, `${file:<key>}` for that of the file whose annotations are shown.
+//`$$` is a literal `$`. References are only replaced when asked for (`--resolve`), the stored value keeps them.
+
+use std::fmt;
+
+use {Annovate, AnnoContainer, AnnoError, Annotation};
+
+/// Where a referenced key is looked up
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Scope {
+    Dir,
+    File
+}
+
+impl Scope {
+    fn from_name( name: &str ) -> Option<Scope> {
+        match name {
+            "dir" => Some( Scope::Dir ),
+            "file" => Some( Scope::File ),
+            _ => None
+        }
+    }
+
+    fn name( &self ) -> &'static str {
+        match *self {
+            Scope::Dir => "dir",
+            Scope::File => "file"
+        }
+    }
+}
+
+/// Why a value could not be resolved
+#[derive(Clone, PartialEq, Debug)]
+pub enum ResolveError {
+    /// A `${` without `}` or with a scope other than `dir` and `file`
+    Syntax( String ),
+    /// A reference to a key without value, like `file:version`
+    Missing( String ),
+    /// References that lead back to the first one, like `file:a`, `dir:b`, `file:a`
+    Cycle( Vec<String> )
+}
+
+impl fmt::Display for ResolveError {
+    fn fmt( &self, f: &mut fmt::Formatter ) -> fmt::Result {
+        match *self {
+            ResolveError::Syntax( ref text ) => write!( f, "invalid reference `{}` (use ${{dir:<key>}} or ${{file:<key>}})", text ),
+            ResolveError::Missing( ref reference ) => write!( f, "`{}` has no value", reference ),
+            ResolveError::Cycle( ref references ) => write!( f, "circular references: {}", references.join( " -> " ) )
+        }
+    }
+}
+
+impl From<ResolveError> for AnnoError {
+    fn from( err: ResolveError ) -> AnnoError {
+        AnnoError::FormatError( err.to_string() )
+    }
+}
+
+/// Resolves the references of the annotations of the directory or of one file
+pub struct Resolver<'a> {
+    dir: &'a AnnoContainer,
+    file: Option<&'a AnnoContainer>
+}
+
+impl<'a> Resolver<'a> {
+    /// `file` are the annotations `${file:...}` refers to, `None` where there is no file
+    pub fn new( dir: &'a AnnoContainer, file: Option<&'a AnnoContainer> ) -> Resolver<'a> {
+        Resolver { dir: dir, file: file }
+    }
+
+    /// The value of `annotation` of `scope` with all references replaced
+    pub fn resolve( &self, scope: Scope, annotation: &Annotation ) -> Result<String, ResolveError> {
+        let mut stack = vec![ ( scope, annotation.key.clone() ) ];
+        self.resolve_value( &annotation.value, &mut stack )
+    }
+
+    fn lookup( &self, scope: Scope, key: &str ) -> Option<&'a Annotation> {
+        let annotations = match scope {
+            Scope::Dir => Some( self.dir ),
+            Scope::File => self.file
+        };
+        annotations.and_then( |annos| annos.iter().rev().find( |a| a.key == key ) )
+    }
+
+    fn resolve_value( &self, value: &str, stack: &mut Vec<( Scope, String )> ) -> Result<String, ResolveError> {
+        let mut result = String::new();
+        let mut rest = value;
+        while let Some( pos ) = rest.find( '$' ) {
+            result.push_str( &rest[ ..pos ] );
+            rest = &rest[ pos.. ];
+            if rest.starts_with( "$$" ) {
+                result.push( '$' );
+                rest = &rest[ 2.. ];
+                continue;
+            }
+            if !rest.starts_with( "${" ) {
+                result.push( '$' );
+                rest = &rest[ 1.. ];
+                continue;
+            }
+            let end = match rest.find( '}' ) {
+                Some( end ) => end,
+                None => return Err( ResolveError::Syntax( rest.to_string() ) )
+            };
+            let reference = &rest[ 2..end ];
+            let ( scope, key ) = match reference.find( ':' ).and_then( |colon| Scope::from_name( &reference[ ..colon ] ).map( |s| ( s, &reference[ colon + 1.. ] ) ) ) {
+                Some( ( scope, key ) ) if !key.is_empty() => ( scope, key ),
+                _ => return Err( ResolveError::Syntax( rest[ ..end + 1 ].to_string() ) )
+            };
+            let name = |&( scope, ref key ): &( Scope, String )| format!( "{}:{}", scope.name(), key );
+            if let Some( first ) = stack.iter().position( |r| r.0 == scope && r.1 == key ) {
+                let mut cycle: Vec<String> = stack[ first.. ].iter().map( &name ).collect();
+                cycle.push( name( &( scope, key.to_string() ) ) );
+                return Err( ResolveError::Cycle( cycle ) );
+            }
+            let referenced = match self.lookup( scope, key ) {
+                Some( annotation ) => annotation,
+                None => return Err( ResolveError::Missing( name( &( scope, key.to_string() ) ) ) )
+            };
+            stack.push( ( scope, key.to_string() ) );
+            result.push_str( &try!( self.resolve_value( &referenced.value, stack ) ) );
+            stack.pop();
+            rest = &rest[ end + 1.. ];
+        }
+        result.push_str( rest );
+        Ok( result )
+    }
+}
+
+impl Annovate {
+    /// Resolver for the references of the directory annotations (`filename` is `None`) or those of a file
+    pub fn resolver<'a>( &'a self, filename: Option<&str> ) -> Resolver<'a> {
+        Resolver::new( &self.dir, filename.and_then( |f| self.files.get( f ) ) )
+    }
+}
@@ -0,0 +1,120 @@
+//The journal keeps the content a meta file had before each save, so that changes can be undone.
+//Every entry is a header line `%<timestamp> <length>` followed by `length` bytes of the old content and a newline.
+//Only the most recent entries are kept, `SaveOptions::journal_entries` of them.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use {Annovate, AnnoError, Timestamp, write_atomically};
+use data::AnnovateData;
+
+/// Journal of `path`: `<name>.log` next to it
+pub fn journal_path( path: &Path ) -> PathBuf {
+    let mut name = path.file_name().map( |n| n.to_os_string() ).unwrap_or_default();
+    name.push( ".log" );
+    path.with_file_name( name )
+}
+
+/// How many entries the journal keeps unless configured otherwise
+pub const DEFAULT_JOURNAL_ENTRIES: usize = 50;
+
+/// A state of the meta file before it was saved
+pub struct JournalEntry {
+    pub saved: Timestamp,
+    pub content: String
+}
+
+fn invalid( msg: &str ) -> AnnoError {
+    AnnoError::FormatError( format!( "broken journal: {}", msg ) )
+}
+
+/// All entries of a journal, oldest first. A missing journal has no entries.
+pub fn read_journal( path: &Path ) -> Result<Vec<JournalEntry>, AnnoError> {
+    let mut text = String::new();
+    match File::open( path ) {
+        Ok( mut file ) => { try!( file.read_to_string( &mut text ) ); },
+        Err( ref e ) if e.kind() == io::ErrorKind::NotFound => return Ok( vec![] ),
+        Err( e ) => return Err( AnnoError::io( path, e ) )
+    }
+    let mut entries = Vec::new();
+    let mut rest = text.as_str();
+    while !rest.is_empty() {
+        let header_end = match rest.find( '\n' ) {
+            Some( pos ) => pos,
+            None => return Err( invalid( "incomplete header" ) )
+        };
+        if !rest.starts_with( '%' ) {
+            return Err( invalid( "invalid header" ) );
+        }
+        let mut parts = rest[ 1..header_end ].splitn( 2, ' ' );
+        let saved = parts.next().and_then( Timestamp::parse );
+        let length = parts.next().and_then( |l| l.parse::<usize>().ok() );
+        let ( saved, length ) = match ( saved, length ) {
+            ( Some( saved ), Some( length ) ) => ( saved, length ),
+            _ => return Err( invalid( "invalid header" ) )
+        };
+        let start = header_end + 1;
+        if rest.len() < start + length + 1 || !rest.is_char_boundary( start + length ) {
+            return Err( invalid( "truncated entry" ) );
+        }
+        entries.push( JournalEntry { saved: saved, content: rest[ start..start + length ].to_string() } );
+        rest = &rest[ start + length + 1.. ];
+    }
+    Ok( entries )
+}
+
+fn format_entry( entry: &JournalEntry ) -> String {
+    format!( "%{} {}\n{}\n", entry.saved, entry.content.len(), entry.content )
+}
+
+/// Append the content a file had before saving and remove the oldest entries beyond the `keep` most recent ones
+pub fn append_entry( path: &Path, content: &str, keep: usize ) -> Result<(), AnnoError> {
+    {
+        let mut file = try!( OpenOptions::new().create( true ).append( true ).open( path ).map_err( |e| AnnoError::io( path, e ) ) );
+        let entry = JournalEntry { saved: Timestamp::now(), content: content.to_string() };
+        try!( file.write_all( format_entry( &entry ).as_bytes() ).map_err( |e| AnnoError::io( path, e ) ) );
+    }
+    let entries = try!( read_journal( path ) );
+    if entries.len() > keep {
+        let text: String = entries[ entries.len() - keep.. ].iter().map( format_entry ).collect();
+        try!( write_atomically( path, text.as_bytes() ) );
+    }
+    Ok( () )
+}
+
+/// Remove the `count` most recent entries
+pub fn drop_entries( path: &Path, count: usize ) -> Result<(), AnnoError> {
+    let mut entries = try!( read_journal( path ) );
+    let keep = entries.len().saturating_sub( count );
+    entries.truncate( keep );
+    let text: String = entries.iter().map( format_entry ).collect();
+    write_atomically( path, text.as_bytes() )
+}
+
+impl Annovate {
+    /// Go back to the state before the last `steps` saves. Returns how many steps could be undone.
+    /// The journal is shortened when the result is saved.
+    pub fn undo( &mut self, steps: usize ) -> Result<usize, AnnoError> {
+        try!( self.check_writable() );
+        let entries = try!( read_journal( &journal_path( &self.filename ) ) );
+        let available = entries.len().saturating_sub( self.undone.get() );
+        let steps = ::std::cmp::min( steps, available );
+        if steps == 0 {
+            return Ok( 0 );
+        }
+        let entry = &entries[ available - steps ];
+        let restored = if entry.content.trim().is_empty() {
+            Annovate::from_data( AnnovateData::default(), &self.filename )
+        } else {
+            Annovate::from_data( try!( self.backend.parse( &entry.content ) ), &self.filename )
+        };
+        self.dir = restored.dir;
+        self.files = restored.files;
+        self.file_order = restored.file_order;
+        self.dir_tags = restored.dir_tags;
+        self.tags = restored.tags;
+        self.undone.set( self.undone.get() + steps );
+        Ok( self.mark_modified( true, steps ) )
+    }
+}
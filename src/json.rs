@@ -0,0 +1,177 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+use rustc_serialize::json::{self, Json};
+
+use {Annovate, Annotation, AnnoContainer, AnnoError, Fragment, TagSet, Timestamp, ValueType, is_valid_tag};
+use ids::is_valid_id;
+
+fn annotations_to_json( annotations: &AnnoContainer ) -> Json {
+    Json::Array( annotations.iter().map( |anno| {
+        let mut object = BTreeMap::new();
+        object.insert( "key".to_string(), Json::String( anno.key.clone() ) );
+        object.insert( "value".to_string(), Json::String( anno.value.clone() ) );
+        object.insert( "context".to_string(), Json::String( anno.context.clone() ) );
+        if let Some( created ) = anno.created {
+            object.insert( "created".to_string(), Json::String( created.to_string() ) );
+        }
+        if let Some( expires ) = anno.expires {
+            object.insert( "expires".to_string(), Json::String( expires.to_string() ) );
+        }
+        if let Some( ref region ) = anno.region {
+            object.insert( "region".to_string(), Json::String( region.to_string() ) );
+        }
+        if let Some( ref id ) = anno.id {
+            object.insert( "id".to_string(), Json::String( id.clone() ) );
+        }
+        if anno.protected {
+            object.insert( "protected".to_string(), Json::Boolean( true ) );
+        }
+        if let Some( value_type ) = anno.value_type {
+            object.insert( "type".to_string(), Json::String( value_type.name().to_string() ) );
+        }
+        Json::Object( object )
+    } ).collect() )
+}
+
+fn annotations_from_json( value: &Json, what: &str ) -> Result<AnnoContainer, AnnoError> {
+    let list = match value.as_array() {
+        Some( list ) => list,
+        None => return Err( AnnoError::FormatError( format!( "annotations of {} must be an array", what ) ) )
+    };
+    let mut result = AnnoContainer::new();
+    for entry in list {
+        let field = |name: &str| entry.find( name ).and_then( |v| v.as_string() ).map( |v| v.to_string() );
+        let mut annotation = match ( field( "key" ), field( "value" ) ) {
+            ( Some( key ), Some( value ) ) => Annotation::new( key, value, field( "context" ).unwrap_or( String::new() ) ),
+            _ => return Err( AnnoError::FormatError( format!( "annotation of {} needs string fields `key` and `value`", what ) ) )
+        };
+        if let Some( created ) = field( "created" ) {
+            match Timestamp::parse( &created ) {
+                Some( created ) => annotation.created = Some( created ),
+                None => return Err( AnnoError::FormatError( format!( "invalid timestamp `{}` in annotation of {}", created, what ) ) )
+            }
+        }
+        if let Some( expires ) = field( "expires" ) {
+            match Timestamp::parse( &expires ) {
+                Some( expires ) => annotation.expires = Some( expires ),
+                None => return Err( AnnoError::FormatError( format!( "invalid timestamp `{}` in annotation of {}", expires, what ) ) )
+            }
+        }
+        if let Some( region ) = field( "region" ) {
+            match Fragment::parse_region( &region ) {
+                Some( region ) => annotation.region = Some( region ),
+                None => return Err( AnnoError::FormatError( format!( "invalid region `{}` in annotation of {}", region, what ) ) )
+            }
+        }
+        if let Some( id ) = field( "id" ) {
+            if !is_valid_id( &id ) {
+                return Err( AnnoError::FormatError( format!( "invalid ID `{}` in annotation of {}", id, what ) ) );
+            }
+            annotation.id = Some( id );
+        }
+        match entry.find( "protected" ) {
+            Some( &Json::Boolean( protected ) ) => annotation.protected = protected,
+            Some( _ ) => return Err( AnnoError::FormatError( format!( "`protected` of an annotation of {} must be true or false", what ) ) ),
+            None => {}
+        }
+        if let Some( name ) = field( "type" ) {
+            match ValueType::from_name( &name ) {
+                Some( value_type ) => annotation = annotation.with_type( value_type ),
+                None => return Err( AnnoError::FormatError( format!( "unknown type `{}` in annotation of {}", name, what ) ) )
+            }
+        }
+        result.push( annotation );
+    }
+    Ok( result )
+}
+
+fn tags_to_json( tags: &TagSet ) -> Json {
+    Json::Array( tags.iter().map( |t| Json::String( t.clone() ) ).collect() )
+}
+
+fn tags_from_json( value: &Json, what: &str ) -> Result<TagSet, AnnoError> {
+    let list = match value.as_array() {
+        Some( list ) => list,
+        None => return Err( AnnoError::FormatError( format!( "tags of {} must be an array", what ) ) )
+    };
+    let mut result = TagSet::new();
+    for entry in list {
+        match entry.as_string() {
+            Some( tag ) if is_valid_tag( tag ) => { result.insert( tag.to_string() ); },
+            _ => return Err( AnnoError::FormatError( format!( "tags of {} must be words without whitespace", what ) ) )
+        }
+    }
+    Ok( result )
+}
+
+impl Annovate {
+    /// Serialize all annotations as JSON: `{"directory": [...], "files": {"name": [...]}}`
+    /// where every annotation is an object with `key`, `value`, `context` and optionally `created`, `expires`, `region`, `id`,
+    /// `protected` and `type`.
+    /// Tags are written as `"directory_tags": [...]` and `"tags": {"name": [...]}`.
+    pub fn to_json( &self ) -> String {
+        let mut files = BTreeMap::new();
+        for ( name, annotations ) in &self.files {
+            files.insert( name.clone(), annotations_to_json( annotations ) );
+        }
+        let mut tags = BTreeMap::new();
+        for ( name, file_tags ) in &self.tags {
+            tags.insert( name.clone(), tags_to_json( file_tags ) );
+        }
+        let mut root = BTreeMap::new();
+        root.insert( "directory".to_string(), annotations_to_json( &self.dir ) );
+        root.insert( "files".to_string(), Json::Object( files ) );
+        if !self.dir_tags.is_empty() {
+            root.insert( "directory_tags".to_string(), tags_to_json( &self.dir_tags ) );
+        }
+        if !tags.is_empty() {
+            root.insert( "tags".to_string(), Json::Object( tags ) );
+        }
+        format!( "{}", json::as_pretty_json( &Json::Object( root ) ) )
+    }
+
+    /// Create annotations from the output of `to_json`. Nothing is read from or written to `filename`
+    /// until the result is saved.
+    pub fn from_json( text: &str, filename: &Path ) -> Result<Annovate, AnnoError> {
+        let root = match Json::from_str( text ) {
+            Ok( root ) => root,
+            Err( e ) => return Err( AnnoError::FormatError( format!( "invalid JSON: {}", e ) ) )
+        };
+        let dir = match root.find( "directory" ) {
+            Some( value ) => try!( annotations_from_json( value, "the directory" ) ),
+            None => AnnoContainer::new()
+        };
+        let mut files = HashMap::new();
+        if let Some( value ) = root.find( "files" ) {
+            let object = match value.as_object() {
+                Some( object ) => object,
+                None => return Err( AnnoError::FormatError( "`files` must be an object".to_string() ) )
+            };
+            for ( name, annotations ) in object {
+                files.insert( name.clone(), try!( annotations_from_json( annotations, name ) ) );
+            }
+        }
+        let dir_tags = match root.find( "directory_tags" ) {
+            Some( value ) => try!( tags_from_json( value, "the directory" ) ),
+            None => TagSet::new()
+        };
+        let mut tags = HashMap::new();
+        if let Some( value ) = root.find( "tags" ) {
+            let object = match value.as_object() {
+                Some( object ) => object,
+                None => return Err( AnnoError::FormatError( "`tags` must be an object".to_string() ) )
+            };
+            for ( name, file_tags ) in object {
+                let file_tags = try!( tags_from_json( file_tags, name ) );
+                if !file_tags.is_empty() {
+                    files.entry( name.clone() ).or_insert( AnnoContainer::new() );
+                    tags.insert( name.clone(), file_tags );
+                }
+            }
+        }
+        let mut file_order: Vec<String> = files.keys().cloned().collect();
+        file_order.sort();
+        Ok( Annovate::from_content( dir, files, file_order, dir_tags, tags, filename ) )
+    }
+}
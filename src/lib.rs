@@ -1,62 +1,529 @@
 extern crate time;
+extern crate rustc_serialize;
+extern crate regex;
+extern crate unicode_normalization;
+#[cfg(feature = "xattr")]
+extern crate libc;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(feature = "crypt")]
+extern crate chacha20;
+#[cfg(feature = "crypt")]
+extern crate getrandom;
+#[cfg(feature = "crypt")]
+extern crate hmac;
+#[cfg(feature = "crypt")]
+extern crate pbkdf2;
+#[cfg(feature = "crypt")]
+extern crate sha2;
 
-use std::io::{BufRead, BufReader, Write};
+use std::io::{BufRead, Read, Write};
 use std::io;
 use std::collections::hash_map::HashMap;
+use std::collections::{BTreeSet, HashSet};
+use std::slice;
+use std::vec;
 use std::path::{Path,PathBuf};
-use std::fs::File;
+use std::fs::{self, File};
 use std::fmt;
+use std::cell::Cell;
+use std::rc::Rc;
+use std::time::Duration;
 
-#[derive(Clone)]
+pub mod backend;
+pub mod backup;
+pub mod changelog;
+pub mod changes;
+pub mod config;
+pub mod context;
+pub mod copy;
+pub mod crypt;
+pub mod data;
+pub mod delta;
+pub mod dictionary;
+pub mod diff;
+pub mod doctor;
+pub mod expiry;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod git;
+pub mod harvest;
+pub mod hash;
+pub mod identity;
+pub mod ids;
+pub mod ignore;
+pub mod inherit;
+pub mod integrity;
+pub mod interpolate;
+pub mod journal;
+mod json;
+pub mod lock;
+pub mod merge;
+pub mod migrate;
+pub mod namespace;
+pub mod normalization;
+pub mod pattern;
+pub mod progress;
+pub mod protect;
+pub mod quota;
+pub mod reader;
+pub mod report;
+pub mod schema;
+pub mod search;
+pub mod set;
+pub mod sort;
+pub mod stats;
+pub mod subdir;
+pub mod substitute;
+mod tags;
+mod target;
+pub mod template;
+pub mod timestamp;
+mod toml;
+pub mod transaction;
+pub mod tree;
+pub mod validate;
+pub mod value;
+pub mod watch;
+#[cfg(feature = "xattr")]
+pub mod xattr;
+
+pub use target::{Target, Fragment, is_valid_url, looks_like_url, parse_range};
+pub use timestamp::Timestamp;
+pub use lock::DEFAULT_LOCK_TIMEOUT;
+pub use tags::is_valid_tag;
+pub use pattern::Pattern;
+pub use namespace::KeyMatcher;
+pub use context::ContextBuilder;
+pub use config::Config;
+pub use reader::{AnnovateReader, Event};
+pub use backend::{Backend, LineBackend, TomlBackend, backend_for_path};
+pub use value::{Value, ValueType};
+
+use lock::FileLock;
+use backup::BackupPolicy;
+use copy::CopySpec;
+use integrity::IntegrityPolicy;
+use progress::{Progress, ProgressHandler};
+use quota::{Quota, QuotaWarning};
+
+/// One entry of a file or the directory. New fields may be added, so annotations are made with `Annotation::new`
+/// or `Annotation::builder` instead of a struct expression.
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
 pub struct Annotation {
     pub key: String,
     pub value: String,
-    pub context: String
+    pub context: String,
+    /// When the annotation was made. Written as `!` line after the context.
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub created: Option<Timestamp>,
+    /// When the annotation expires and is hidden (see `expiry`). Written as `~` line after the creation time.
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub expires: Option<Timestamp>,
+    /// Lines or bytes of the file the annotation is about, `None` for the whole file. Written as `*` line after the
+    /// expiry time: `*L10-20`
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub region: Option<Fragment>,
+    /// Stable ID to address the annotation (see `ids`). Written as `%` line at the end of the annotation.
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub id: Option<String>,
+    /// Whether the annotation may only be changed or removed with an override (see `protect`). Written as
+    /// `^protected` line before the ID.
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "::std::ops::Not::not"))]
+    pub protected: bool,
+    /// Type of the value, `None` for text. Written after the key: `>size:int`
+    #[cfg_attr(feature = "serde", serde(rename = "type", default, skip_serializing_if = "Option::is_none"))]
+    pub value_type: Option<ValueType>
 }
 
 impl Annotation {
     pub fn new( key: String, value: String, context: String ) -> Annotation {
-        Annotation { key: key, value: value, context: context }
+        Annotation { key: key, value: value, context: context, created: None, expires: None, region: None, id: None, protected: false, value_type: None }
+    }
+
+    /// The same annotation with a creation time
+    pub fn with_created( self, created: Timestamp ) -> Annotation {
+        Annotation { created: Some( created ), ..self }
+    }
+
+    /// The same annotation with an expiry time
+    pub fn with_expires( self, expires: Timestamp ) -> Annotation {
+        Annotation { expires: Some( expires ), ..self }
+    }
+
+    /// The same annotation about a range of lines or bytes
+    pub fn with_region( self, region: Fragment ) -> Annotation {
+        Annotation { region: Some( region ), ..self }
+    }
+
+    /// The same annotation with an ID
+    pub fn with_id( self, id: String ) -> Annotation {
+        Annotation { id: Some( id ), ..self }
+    }
+
+    /// The same annotation, protected or not
+    pub fn with_protected( self, protected: bool ) -> Annotation {
+        Annotation { protected: protected, ..self }
+    }
+
+    /// Builder for an annotation with optional fields: `Annotation::builder( "key", "value" ).context( "x" ).build()`
+    pub fn builder( key: &str, value: &str ) -> AnnotationBuilder {
+        AnnotationBuilder { annotation: Annotation::new( key.to_string(), value.to_string(), String::new() ) }
+    }
+}
+
+/// Builds an `Annotation`, see `Annotation::builder`. Fields that are not set stay empty.
+#[derive(Clone, Debug)]
+pub struct AnnotationBuilder {
+    annotation: Annotation
+}
+
+impl AnnotationBuilder {
+    pub fn context( mut self, context: &str ) -> AnnotationBuilder {
+        self.annotation.context = context.to_string();
+        self
+    }
+
+    pub fn created( mut self, created: Timestamp ) -> AnnotationBuilder {
+        self.annotation.created = Some( created );
+        self
+    }
+
+    pub fn expires( mut self, expires: Timestamp ) -> AnnotationBuilder {
+        self.annotation.expires = Some( expires );
+        self
+    }
+
+    pub fn region( mut self, region: Fragment ) -> AnnotationBuilder {
+        self.annotation.region = Some( region );
+        self
+    }
+
+    pub fn id( mut self, id: &str ) -> AnnotationBuilder {
+        self.annotation.id = Some( id.to_string() );
+        self
+    }
+
+    pub fn protected( mut self, protected: bool ) -> AnnotationBuilder {
+        self.annotation.protected = protected;
+        self
+    }
+
+    /// Type of the value, `Text` for none
+    pub fn value_type( mut self, value_type: ValueType ) -> AnnotationBuilder {
+        self.annotation = self.annotation.with_type( value_type );
+        self
+    }
+
+    pub fn build( self ) -> Annotation {
+        self.annotation
     }
 }
 
 pub type AnnoContainer = Vec<Annotation>;
 
+/// Tags of a file or directory, written as `+tag` lines at the beginning of its section
+pub type TagSet = BTreeSet<String>;
+
+/// Order in which file sections are written
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SectionOrder {
+    /// In the order in which the files were first annotated (or appear in the loaded file)
+    Insertion,
+    /// Sorted by filename
+    Alphabetical
+}
+
+/// Options that control how `save` and `save_as` write the file
+#[derive(Clone, Debug)]
+pub struct SaveOptions {
+    pub order: SectionOrder,
+    /// Keep unchanged sections of the existing file where they are. New sections are appended in `order`.
+    /// If false, the whole file is written in `order`.
+    pub preserve_layout: bool,
+    /// Keep the previous content in the journal (`<name>.log`) so that the save can be undone
+    pub journal: bool,
+    /// Number of saves that the journal keeps, the oldest are removed
+    pub journal_entries: usize,
+    /// Copy the previous content to `<name>.bak.1` and keep older copies as `.bak.2`, ...
+    pub backups: BackupPolicy,
+    /// End the file with the hash of its content if enabled, see `integrity`
+    pub integrity: IntegrityPolicy
+}
+
+impl Default for SaveOptions {
+    fn default() -> SaveOptions {
+        SaveOptions { order: SectionOrder::Insertion, preserve_layout: true, journal: true, journal_entries: journal::DEFAULT_JOURNAL_ENTRIES,
+                      backups: BackupPolicy::default(), integrity: IntegrityPolicy::default() }
+    }
+}
+
+/// Whether an annovate file may be changed
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum OpenMode {
+    /// The file is neither locked nor created, changes and saving fail with `AnnoError::ReadOnly`
+    ReadOnly,
+    ReadWrite
+}
+
+/// How an annovate file with errors is read
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ParseMode {
+    /// Any error fails with `AnnoError::ParseError`
+    Strict,
+    /// Lines that `doctor` would repair are skipped and collected in `Annovate::parse_warnings`, the rest is
+    /// loaded. Only the line format is read leniently.
+    Lenient
+}
+
 pub struct Annovate {
     dir: AnnoContainer,
     files: HashMap<String, AnnoContainer>,
-    save_changes: bool,
-    filename: PathBuf
+    file_order: Vec<String>,
+    dir_tags: TagSet,
+    tags: HashMap<String, TagSet>, //every tagged target also has an entry in `files`
+    mode: OpenMode,
+    /// Annotations or tags changed since the file was read or saved
+    modified: Cell<bool>,
+    save_options: SaveOptions,
+    backend: Rc<dyn Backend>,
+    filename: PathBuf,
+    lock: Option<FileLock>,
+    lock_timeout: Duration,
+    undone: Cell<usize>, //journal entries that `undo` went back and that are dropped on save
+    pre_save_hook: Option<PreSaveHook>,
+    override_protection: bool, //protected annotations may be changed
+    parse_warnings: Vec<doctor::Problem>, //what a lenient parse skipped
+    delta_records: Cell<usize>, //read from the file and not yet folded into its sections by saving
+    quota: Quota,
+    quota_warnings: Vec<QuotaWarning>, //limits that added annotations exceeded
+    progress_handler: Option<ProgressHandler>
+}
+
+impl Annovate {
+    /// In-memory annovate file with these annotations and tags and the default settings. Every `Annovate` is made here,
+    /// also those that are read from a file or copied.
+    fn from_content( dir: AnnoContainer, files: HashMap<String, AnnoContainer>, file_order: Vec<String>, dir_tags: TagSet,
+                     tags: HashMap<String, TagSet>, filename: &Path ) -> Annovate {
+        Annovate { dir: dir, files: files, file_order: file_order, dir_tags: dir_tags, tags: tags,
+                   mode: OpenMode::ReadWrite, modified: Cell::new( false ), save_options: SaveOptions::default(), backend: backend_for_path( filename ), filename: filename.to_path_buf(),
+                   lock: None, lock_timeout: DEFAULT_LOCK_TIMEOUT, undone: Cell::new( 0 ), pre_save_hook: None, override_protection: false, parse_warnings: Vec::new(), delta_records: Cell::new( 0 ),
+                   quota: Quota::default(), quota_warnings: Vec::new(), progress_handler: None }
+    }
+}
+
+/// A copy with the same annotations, tags, file and settings. The copy holds no lock, so saving it to the
+/// file of the original waits until the original is dropped, and it has no pre-save hook.
+impl Clone for Annovate {
+    fn clone( &self ) -> Annovate {
+        let mut copy = Annovate::from_content( self.dir.clone(), self.files.clone(), self.file_order.clone(), self.dir_tags.clone(), self.tags.clone(), &self.filename );
+        copy.mode = self.mode;
+        copy.modified.set( self.modified.get() );
+        copy.save_options = self.save_options.clone();
+        copy.backend = self.backend.clone();
+        copy.lock_timeout = self.lock_timeout;
+        copy.undone.set( self.undone.get() );
+        copy.override_protection = self.override_protection;
+        copy.parse_warnings = self.parse_warnings.clone();
+        copy.delta_records.set( self.delta_records.get() );
+        copy.quota = self.quota;
+        copy.quota_warnings = self.quota_warnings.clone();
+        copy.progress_handler = self.progress_handler.clone();
+        copy
+    }
 }
 
+/// Check of an annovate file before it is saved, e.g. for team policies. An `Err` with the reason prevents the save.
+pub type PreSaveHook = Box<dyn Fn( &Annovate ) -> Result<(), String>>;
+
 #[derive(Debug)]
 pub enum AnnoError {
-    ParseError( u64, char ),
-    IOError( io::Error )
+    /// Line `line` starts with `found` (a space for the end of the file). `expected` lists the leaders that
+    /// are allowed at this point; it is `None` if the leader is allowed but the rest of the line is invalid.
+    ParseError { path: Option<PathBuf>, line: u64, found: char, expected: Option<String> },
+    IOError { path: Option<PathBuf>, source: io::Error },
+    GitError( String ),
+    TemplateError( String ),
+    FormatError( String ),
+    SaveError( PathBuf, io::Error ),
+    /// Another process holds the lock file
+    Locked( PathBuf ),
+    /// Change of a file that was opened with `OpenMode::ReadOnly`
+    ReadOnly,
+    /// The pre-save hook refused the content, with its reason
+    Rejected( String ),
+    /// Change of a protected annotation with this key without override
+    Protected( String ),
+    /// Saving an annovate file that was built in memory before `Annovate::set_path` gave it a file
+    NoPath,
+    /// The meta file does not match its integrity footer or has none, see `integrity`
+    IntegrityMismatch( PathBuf ),
+    /// An encrypted value could not be decrypted with the passphrase, see `crypt`
+    Decryption,
+    /// A key, value, context or filename that cannot be stored, see `validate`. `input` is cut if it is long.
+    InvalidInput { what: &'static str, input: String, reason: String },
+    /// The operation needs a feature that this build of annovate does not have, like `crypt`
+    Unsupported( &'static str )
 }
 
 impl fmt::Display for AnnoError {
     fn fmt( &self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
-            AnnoError::ParseError( line, symbol ) => write!( f, "Invalid token `{}` at the beginning of line {}", symbol, line ),
-            AnnoError::IOError( ref ioe ) => write!( f, "IO error: {}", ioe ),
+            AnnoError::ParseError { ref path, line, found, ref expected } => {
+                if found == ' ' {
+                    try!( write!( f, "Unexpected end of file at line {}", line ) );
+                } else {
+                    try!( write!( f, "Invalid token `{}` at the beginning of line {}", found, line ) );
+                }
+                if let Some( ref path ) = *path {
+                    try!( write!( f, " of {}", path.display() ) );
+                }
+                match *expected {
+                    Some( ref leaders ) => {
+                        let leaders: Vec<String> = leaders.chars().map( |c| format!( "`{}`", c ) ).collect();
+                        write!( f, " (expected {})", leaders.join( ", " ) )
+                    },
+                    None => Ok( () )
+                }
+            },
+            AnnoError::IOError { path: Some( ref path ), ref source } => write!( f, "IO error in {}: {}", path.display(), source ),
+            AnnoError::IOError { path: None, ref source } => write!( f, "IO error: {}", source ),
+            AnnoError::GitError( ref msg ) => write!( f, "Git error: {}", msg ),
+            AnnoError::TemplateError( ref msg ) => write!( f, "Template error: {}", msg ),
+            AnnoError::FormatError( ref msg ) => write!( f, "Format error: {}", msg ),
+            AnnoError::SaveError( ref path, ref ioe ) => write!( f, "Failed to replace {}: {}", path.display(), ioe ),
+            AnnoError::Locked( ref path ) => write!( f, "The file is in use by another process (remove {} if that process is gone)", path.display() ),
+            AnnoError::ReadOnly => write!( f, "The file was opened read-only" ),
+            AnnoError::Rejected( ref reason ) => write!( f, "The changes were rejected: {}", reason ),
+            AnnoError::NoPath => write!( f, "The annotations have no file to be saved to" ),
+            AnnoError::Protected( ref key ) => write!( f, "The annotation `{}` is protected", key ),
+            AnnoError::IntegrityMismatch( ref path ) => write!( f, "{} does not match its integrity footer (edited outside of annovate or truncated)", path.display() ),
+            AnnoError::Decryption => write!( f, "The value could not be decrypted (wrong passphrase or changed value)" ),
+            AnnoError::InvalidInput { what, ref input, ref reason } => write!( f, "Invalid {} {:?}: the {} {}", what, input, what, reason ),
+            AnnoError::Unsupported( feature ) => write!( f, "annovate was built without the feature `{}`", feature ),
+        }
+    }
+}
+
+impl std::error::Error for AnnoError {
+    fn source( &self ) -> Option<&( dyn std::error::Error + 'static )> {
+        match *self {
+            AnnoError::IOError { ref source, .. } | AnnoError::SaveError( _, ref source ) => Some( source ),
+            _ => None
         }
     }
 }
 
 impl From<io::Error> for AnnoError {
     fn from( err: io::Error ) -> AnnoError {
-        AnnoError::IOError( err )
+        AnnoError::IOError { path: None, source: err }
+    }
+}
+
+impl AnnoError {
+    /// IO error while accessing `path`
+    pub fn io( path: &Path, err: io::Error ) -> AnnoError {
+        AnnoError::IOError { path: Some( path.to_path_buf() ), source: err }
+    }
+
+    /// Record the file that caused a parse or IO error, unless the error already names one
+    pub fn at( self, file: &Path ) -> AnnoError {
+        match self {
+            AnnoError::ParseError { path: None, line, found, expected } => AnnoError::ParseError { path: Some( file.to_path_buf() ), line: line, found: found, expected: expected },
+            AnnoError::IOError { path: None, source } => AnnoError::io( file, source ),
+            other => other
+        }
+    }
+
+    /// Short name of the kind of error for programs, like `parse` or `locked`
+    pub fn kind( &self ) -> &'static str {
+        match *self {
+            AnnoError::ParseError { .. } => "parse",
+            AnnoError::IOError { .. } => "io",
+            AnnoError::GitError( _ ) => "git",
+            AnnoError::TemplateError( _ ) => "template",
+            AnnoError::FormatError( _ ) => "format",
+            AnnoError::SaveError( _, _ ) => "save",
+            AnnoError::Locked( _ ) => "locked",
+            AnnoError::ReadOnly => "read-only",
+            AnnoError::Rejected( _ ) => "rejected",
+            AnnoError::Protected( _ ) => "protected",
+            AnnoError::NoPath => "no-path",
+            AnnoError::IntegrityMismatch( _ ) => "integrity",
+            AnnoError::Decryption => "decryption",
+            AnnoError::InvalidInput { .. } => "invalid-input",
+            AnnoError::Unsupported( _ ) => "unsupported"
+        }
+    }
+
+    /// The file that the error is about, if it names one
+    pub fn path( &self ) -> Option<&Path> {
+        match *self {
+            AnnoError::ParseError { path: Some( ref path ), .. } | AnnoError::IOError { path: Some( ref path ), .. } |
+            AnnoError::SaveError( ref path, _ ) | AnnoError::Locked( ref path ) | AnnoError::IntegrityMismatch( ref path ) => Some( path ),
+            _ => None
+        }
+    }
+
+    /// The line of the meta file that a parse error is about
+    pub fn line( &self ) -> Option<u64> {
+        match *self {
+            AnnoError::ParseError { line, .. } => Some( line ),
+            _ => None
+        }
+    }
+}
+
+/// Leaders that may follow a line starting with `last_leader`
+fn expected_leaders( last_leader: char, allow_sections: bool, in_file_section: bool, in_deltas: bool ) -> String {
+    match last_leader {
+        '>' => "=\\".to_string(),
+        '=' | '\\' => "=\\<".to_string(),
+        _ => {
+            let mut leaders = ">+".to_string();
+            if last_leader == '<' {
+                leaders.push( '!' );
+            }
+            if last_leader == '<' || last_leader == '!' {
+                leaders.push( '~' );
+            }
+            if last_leader == '<' || last_leader == '!' || last_leader == '~' {
+                leaders.push( '*' );
+            }
+            if last_leader == '<' || last_leader == '!' || last_leader == '~' || last_leader == '*' {
+                leaders.push( '^' );
+            }
+            if last_leader == '<' || last_leader == '!' || last_leader == '~' || last_leader == '*' || last_leader == '^' {
+                leaders.push( '%' );
+            }
+            if allow_sections && !in_deltas {
+                leaders.push( '@' );
+                if in_file_section {
+                    leaders.push( '#' );
+                }
+            }
+            if allow_sections {
+                leaders.push( '&' );
+            }
+            leaders
+        }
     }
 }
 
-fn test_leader( last_leader: char, legal_chars: &str, current_leader: char, line_no: u64 ) -> Result<(), AnnoError> {
+fn invalid_line( line_no: u64, leader: char ) -> AnnoError {
+    AnnoError::ParseError { path: None, line: line_no, found: leader, expected: None }
+}
+
+/// Fails unless `last_leader` is one of `legal_chars`. `expected` is only computed for the error.
+fn test_leader<F: Fn() -> String>( last_leader: char, legal_chars: &str, current_leader: char, line_no: u64, expected: F ) -> Result<(), AnnoError> {
     for c in legal_chars.chars() {
         if c == last_leader {
             return Ok( () )
         }
     }
-    Err( AnnoError::ParseError( line_no, current_leader ) )
+    Err( AnnoError::ParseError { path: None, line: line_no, found: current_leader, expected: Some( expected() ) } )
 }
 
 fn extract_line_parts<'a>( line: &'a str ) -> ( char, &'a str ) {
@@ -69,174 +536,3640 @@ fn extract_line_parts<'a>( line: &'a str ) -> ( char, &'a str ) {
     }
 }
 
-fn create_new_annovate_file( filepath: &Path, creation_reason: &str ) -> io::Result<()> {
-    let mut new_file = try!( File::create( filepath ) );
+fn create_new_annovate_file( filepath: &Path, backend: &dyn Backend, creation_reason: &str ) -> Result<(), AnnoError> {
     let now = time::now();
     let timestring = format!( "{}.{}.{} {}:{}:{}", now.tm_mday, now.tm_mon + 1, now.tm_year + 1900, now.tm_hour, now.tm_min, now.tm_sec );
-    write!( new_file, ">creation time\n={}\n<{}, {}\n", timestring, timestring, creation_reason );
-    Ok( try!( new_file.flush() ) )
+    let text = format!( ">creation time\n={}\n<{}, {}\n", timestring, timestring, creation_reason );
+    let new_file = Annovate::from_data( try!( LineBackend.parse( &text ) ), filepath );
+    let mut out = try!( File::create( filepath ).map_err( |e| AnnoError::io( filepath, e ) ) );
+    out.write_all( backend.serialize( &new_file, None ).as_bytes() ).and_then( |_| out.flush() ).map_err( |e| AnnoError::io( filepath, e ) )
 }
 
-fn parse_annovate_file( filepath: &Path ) -> Result<Annovate, AnnoError> {
-    let mut result = Annovate {
-        filename: filepath.to_path_buf(),
-        dir: vec![],
-        files: HashMap::new(),
-        save_changes: true
+fn parse_annovate_file( filepath: &Path, mode: OpenMode, parse_mode: ParseMode, backend: Rc<dyn Backend>, lock_timeout: Duration, progress: Option<ProgressHandler> ) -> Result<Annovate, AnnoError> {
+    //reading does not wait for other processes
+    let lock = if mode == OpenMode::ReadOnly { None } else {
+        match FileLock::acquire( filepath, lock_timeout ) {
+            Ok( lock ) => Some( lock ),
+            //a directory we may not write to can still be read, saving will fail anyway
+            Err( AnnoError::IOError { source: ref e, .. } ) if e.kind() == io::ErrorKind::PermissionDenied => None,
+            Err( e ) => return Err( e )
+        }
     };
 
-    let fd = match File::open( filepath ) {
+    let mut fd = match File::open( filepath ) {
         Ok( file_handle ) => file_handle,
+        Err( e ) if mode == OpenMode::ReadOnly => return Err( AnnoError::io( filepath, e ) ),
         Err( _ ) => {
-            try!( create_new_annovate_file( filepath, "new annovate file" ) );
-            try!( File::open( filepath ) )
+            try!( create_new_annovate_file( filepath, &*backend, "new annovate file" ) );
+            try!( File::open( filepath ).map_err( |e| AnnoError::io( filepath, e ) ) )
         }
     };
-    let reader = BufReader::new( fd );
-
-    let mut work_with_dir_fields = true;
-    let mut current_file = String::new();
-
-    let mut current_key = String::new();
-    let mut current_value = String::new();
-
-    let mut last_leader = ' '; //dummy value
-    let mut line_no = 1u64;
-    for line_result in reader.lines() {
-        let line = try!( line_result );
-        let ( leader, rest ) = extract_line_parts( &line );
-        if leader == '@' {
-            try!( test_leader( last_leader, "@< ", leader, line_no ) );
-            result.files.insert( rest.to_string(), vec![] );
-            current_file = rest.to_string();
-            work_with_dir_fields = false;
-        } else if leader == '>' {
-            try!( test_leader( last_leader, "@< ", leader, line_no ) );
-            current_key = rest.to_string();
-            current_value = String::new();
-        } else if leader == '=' {
-            try!( test_leader( last_leader, ">=", leader, line_no ) );
-            if current_value != "" {
-                current_value.push_str( "\n" ); //separate lines with newline
-            }
-            current_value.push_str( rest );
-        } else if leader == '<' {
-            try!( test_leader( last_leader, "=>", leader, line_no ) );
-            let anno = Annotation{
-                key: current_key.clone(),
-                value: current_value.clone(),
-                context: rest.to_string()
-            };
+    let size = fd.metadata().ok().map( |m| m.len() );
+    let content = try!( progress::read_with_progress( &mut fd, size, progress.as_ref().map( |handler| &**handler as &dyn Fn( &Progress ) ) ).map_err( |e| AnnoError::io( filepath, e ) ) );
+    let delta_count = if backend.name() == LineBackend.name() { delta::count_records( &content ) } else { 0 };
+    let parsed = match String::from_utf8( content ) {
+        Ok( text ) => backend.parse( &text ).map_err( |e| ( e, text.into_bytes() ) ),
+        Err( e ) => Err( ( AnnoError::io( filepath, io::Error::new( io::ErrorKind::InvalidData, "stream did not contain valid UTF-8" ) ), e.into_bytes() ) )
+    };
+    let mut parse_warnings = Vec::new();
+    let data = match parsed {
+        Ok( data ) => data,
+        //what doctor would repair is skipped by parsing the repaired text instead
+        Err( ( _, content ) ) if parse_mode == ParseMode::Lenient && backend.name() == LineBackend.name() => {
+            let checkup = doctor::examine( &content );
+            parse_warnings = checkup.problems;
+            try!( backend.parse( &checkup.repaired ).map_err( |e| e.at( filepath ) ) )
+        },
+        Err( ( e, _ ) ) => return Err( e.at( filepath ) )
+    };
+    let mut result = Annovate::from_data( data, filepath );
+    result.mode = mode;
+    result.backend = backend;
+    result.lock = lock;
+    result.lock_timeout = lock_timeout;
+    result.parse_warnings = parse_warnings;
+    result.delta_records.set( delta_count );
+    result.progress_handler = progress;
+    Ok( result )
+}
 
-            if work_with_dir_fields { //then fill dir
-                result.dir.push( anno );
-            } else {
-                let mut entry = result.files.get_mut( &current_file ).unwrap();
-                entry.push( anno );
+/// Make room in `annotations` for `new` as `policy` says: earlier entries of its key and region count, those of other
+/// regions stay. Returns false if it is not to be added.
+fn prepare_put( annotations: &mut AnnoContainer, new: &Annotation, policy: PutPolicy, override_protection: bool ) -> Result<bool, AnnoError> {
+    let earlier = |a: &Annotation| a.key == new.key && a.region == new.region;
+    match policy {
+        PutPolicy::Append => Ok( true ),
+        PutPolicy::IfAbsent => Ok( !annotations.iter().any( |a| earlier( a ) ) ),
+        PutPolicy::Replace => {
+            if !override_protection && annotations.iter().any( |a| earlier( a ) && a.protected ) {
+                return Err( AnnoError::Protected( new.key.clone() ) );
+            }
+            annotations.retain( |a| !earlier( a ) );
+            Ok( true )
+        }
+    }
+}
+
+/// Parse annovate formatted text. Annotations and tags before the first `@file` line go into `dir` and `dir_tags`.
+fn parse_annotations<R: BufRead>( reader: R,
+                                  dir: &mut AnnoContainer,
+                                  files: &mut HashMap<String, AnnoContainer>,
+                                  file_order: &mut Vec<String>,
+                                  dir_tags: &mut TagSet,
+                                  tags: &mut HashMap<String, TagSet>,
+                                  allow_sections: bool ) -> Result<(), AnnoError> {
+    let events = if allow_sections { AnnovateReader::new( reader ) } else { AnnovateReader::without_sections( reader ) };
+    let mut current_file: Option<String> = None;
+    for event in events {
+        match try!( event ) {
+            Event::Delta( None ) => current_file = None,
+            Event::FileStart( name ) | Event::Delta( Some( name ) ) => {
+                if !files.contains_key( &name ) {
+                    file_order.push( name.clone() );
+                }
+                files.entry( name.clone() ).or_insert( vec![] ); //duplicate sections are merged
+                current_file = Some( name );
+            },
+            Event::Tag( tag ) => match current_file {
+                None => { dir_tags.insert( tag ); },
+                Some( ref file ) => { tags.entry( file.clone() ).or_insert( TagSet::new() ).insert( tag ); }
+            },
+            Event::Annotation( anno ) => match current_file {
+                None => dir.push( anno ),
+                Some( ref file ) => files.get_mut( file ).unwrap().push( anno ) //the section was created above
+            }
+        }
+    }
+    Ok( () )
+}
+
+/// Parse the body of one section: its tags and annotations
+fn parse_section_body( text: &str ) -> Result<( TagSet, AnnoContainer ), AnnoError> {
+    let mut tags = TagSet::new();
+    let mut annotations = AnnoContainer::new();
+    if text.trim().is_empty() {
+        return Ok( ( tags, annotations ) );
+    }
+    try!( parse_annotations( text.as_bytes(), &mut annotations, &mut HashMap::new(), &mut vec![], &mut tags, &mut HashMap::new(), false ) );
+    Ok( ( tags, annotations ) )
+}
+
+/// Parse a single block of annotations (no `@file` sections) as produced by `format_annotation_block`.
+/// Tag lines are accepted but not returned.
+pub fn parse_annotation_block( text: &str ) -> Result<AnnoContainer, AnnoError> {
+    parse_section_body( text ).map( |( _, annotations )| annotations )
+}
+
+/// Value lines with a carriage return or trailing whitespace would not survive reading,
+/// they are written with a `\` leader and backslash escapes (`\\`, `\r`, `\t` and `\s` for a space) instead of `=`
+fn needs_escape( line: &str ) -> bool {
+    line.contains( '\r' ) || line.ends_with( char::is_whitespace )
+}
+
+fn escape_value_line( line: &str ) -> String {
+    let content_end = line.trim_right().len();
+    let mut result = String::new();
+    for ( pos, c ) in line.char_indices() {
+        match c {
+            '\\' => result.push_str( "\\\\" ),
+            '\r' => result.push_str( "\\r" ),
+            '\t' if pos >= content_end => result.push_str( "\\t" ),
+            ' ' if pos >= content_end => result.push_str( "\\s" ),
+            c => result.push( c )
+        }
+    }
+    result
+}
+
+/// Inverse of `escape_value_line`. `None` for unknown escape sequences.
+fn unescape_value_line( line: &str ) -> Option<String> {
+    let mut result = String::new();
+    let mut chars = line.chars();
+    while let Some( c ) = chars.next() {
+        if c != '\\' {
+            result.push( c );
+            continue;
+        }
+        match chars.next() {
+            Some( '\\' ) => result.push( '\\' ),
+            Some( 'r' ) => result.push( '\r' ),
+            Some( 't' ) => result.push( '\t' ),
+            Some( 's' ) => result.push( ' ' ),
+            _ => return None
+        }
+    }
+    Some( result )
+}
+
+/// Base64 of binary values is written in lines of this length
+const BINARY_LINE_LENGTH: usize = 76;
+
+/// The lines that follow the value of an annotation: context, creation and expiry time, region, flags and ID
+fn write_trailer<W: Write>( out: &mut W, anno: &Annotation ) -> io::Result<()> {
+    try!( write!( out, "<{}\n", anno.context ) );
+    if let Some( created ) = anno.created {
+        try!( write!( out, "!{}\n", created ) );
+    }
+    if let Some( expires ) = anno.expires {
+        try!( write!( out, "~{}\n", expires ) );
+    }
+    if let Some( ref region ) = anno.region {
+        try!( write!( out, "*{}\n", region ) );
+    }
+    if anno.protected {
+        try!( write!( out, "^{}\n", protect::PROTECTED_FLAG ) );
+    }
+    if let Some( ref id ) = anno.id {
+        try!( write!( out, "%{}\n", id ) );
+    }
+    Ok( () )
+}
+
+fn write_annotations<W: Write>( out: &mut W, annotations: &AnnoContainer ) -> io::Result<()> {
+    for anno in annotations {
+        try!( write!( out, ">{}\n", value::format_typed_key( &anno.key, anno.value_type ) ) );
+        if anno.value_type == Some( ValueType::Binary ) {
+            //`=b64:` marks the value as binary, the following lines continue the base64 text
+            let encoded: Vec<char> = anno.value.chars().filter( |c| !c.is_whitespace() ).collect();
+            try!( write!( out, "=b64:" ) );
+            for ( i, chunk ) in encoded.chunks( BINARY_LINE_LENGTH ).enumerate() {
+                try!( write!( out, "{}{}\n", if i > 0 { "=" } else { "" }, chunk.iter().collect::<String>() ) );
+            }
+            if encoded.is_empty() {
+                try!( write!( out, "\n" ) );
             }
         } else {
-             return Err( AnnoError::ParseError( line_no, leader ) );
+            //every part between newlines is one line, so empty leading and trailing lines are kept
+            let lines: Vec<&str> = if anno.value.is_empty() { vec![] } else { anno.value.split( '\n' ).collect() };
+            for ( i, line ) in lines.into_iter().enumerate() {
+                //text that starts like a binary value is escaped
+                if needs_escape( line ) || ( i == 0 && line.starts_with( "b64:" ) ) {
+                    try!( write!( out, "\\{}\n", escape_value_line( line ) ) );
+                } else {
+                    try!( write!( out, "={}\n", line ) );
+                }
+            }
         }
-        last_leader = leader;
-        line_no += 1;
+        try!( write_trailer( out, anno ) );
     }
-    if last_leader == '<' {
-        Ok( result )
-    } else {
-         Err( AnnoError::ParseError( line_no, ' ' ) )
+    Ok( () )
+}
+
+/// Temporary file next to `path` that is renamed to `path` once it is completely written
+fn temporary_path( path: &Path ) -> PathBuf {
+    let mut name = path.file_name().map( |n| n.to_os_string() ).unwrap_or_default();
+    name.push( format!( ".tmp{}", std::process::id() ) );
+    path.with_file_name( name )
+}
+
+/// Whether `name` is the meta file called `meta_name` or a file that annovate keeps next to it: its lock, journal,
+/// backups, the temporary file of a save and the ignore file. Reports and listings of files leave them out.
+pub fn is_meta_companion( meta_name: &str, name: &str ) -> bool {
+    let meta_path = Path::new( meta_name );
+    let same_name = |path: PathBuf| path.file_name().map( |n| n == name ).unwrap_or( false );
+    if name == meta_name || name == ignore::IGNORE_FILE || same_name( lock::lock_path( meta_path ) ) || same_name( journal::journal_path( meta_path ) ) {
+        return true;
+    }
+    //`.bak.<n>` of `backup::backup_path` and `.tmp<pid>` of `temporary_path`
+    let numbered = |suffix: &str| name.starts_with( meta_name ) && name[ meta_name.len().. ].starts_with( suffix ) &&
+        name.len() > meta_name.len() + suffix.len() && name[ meta_name.len() + suffix.len().. ].chars().all( |c| c.is_ascii_digit() );
+    numbered( ".bak." ) || numbered( ".tmp" )
+}
+
+/// Write `content` to a temporary file, sync it to disk and rename it to `path`,
+/// so that `path` either keeps its old content or has the complete new content.
+fn write_atomically( path: &Path, content: &[u8] ) -> Result<(), AnnoError> {
+    write_atomically_with_progress( path, content, None )
+}
+
+/// Like `write_atomically`, reporting the written bytes to `progress`
+fn write_atomically_with_progress( path: &Path, content: &[u8], progress: Option<&dyn Fn( &Progress )> ) -> Result<(), AnnoError> {
+    let tmp_path = temporary_path( path );
+    {
+        let mut tmp = try!( File::create( &tmp_path ).map_err( |e| AnnoError::io( &tmp_path, e ) ) );
+        try!( progress::write_with_progress( &mut tmp, content, progress ).and_then( |_| tmp.flush() ).and_then( |_| tmp.sync_all() ).map_err( |e| AnnoError::io( &tmp_path, e ) ) );
+    }
+    if let Err( e ) = fs::rename( &tmp_path, path ) {
+        let _ = fs::remove_file( &tmp_path );
+        return Err( AnnoError::SaveError( path.to_path_buf(), e ) );
+    }
+    //make the rename itself durable; directories cannot be opened on every platform, so this is best effort
+    if let Some( dir ) = path.parent() {
+        let dir = if dir == Path::new( "" ) { Path::new( "." ) } else { dir };
+        if let Ok( handle ) = File::open( dir ) {
+            let _ = handle.sync_all();
+        }
+    }
+    Ok( () )
+}
+
+/// Raw text of a section of an existing file together with its parsed annotations
+struct RawSection {
+    header: String,
+    target: String,
+    is_file: bool,
+    body: String,
+    tags: TagSet,
+    annotations: AnnoContainer
+}
+
+/// Split the content of a meta file into sections (directory annotations first).
+/// Returns `None` if the content cannot be parsed or contains duplicate sections.
+fn split_sections( text: &str ) -> Option<Vec<RawSection>> {
+    let mut sections = vec![ RawSection { header: String::new(), target: String::new(), is_file: false, body: String::new(), tags: TagSet::new(), annotations: vec![] } ];
+    let mut base: Option<String> = None;
+    for line in text.split_inclusive( '\n' ) {
+        let ( leader, rest ) = extract_line_parts( line.trim_right_matches( '\n' ) );
+        if leader == '&' {
+            break; //delta records are folded into the sections they belong to
+        }
+        let target = if leader == '@' {
+            base = Some( rest.to_string() );
+            Some( ( rest.to_string(), true ) )
+        } else if leader == '#' && base.is_some() {
+            match Fragment::parse( rest ) {
+                Some( fragment ) => Some( ( Target { file: base.clone().unwrap(), fragment: Some( fragment ) }.to_string(), false ) ),
+                None => return None
+            }
+        } else {
+            None
+        };
+        match target {
+            Some( ( target, is_file ) ) => {
+                if sections.iter().any( |s| s.target == target && s.is_file == is_file ) {
+                    return None;
+                }
+                sections.push( RawSection { header: line.to_string(), target: target, is_file: is_file, body: String::new(), tags: TagSet::new(), annotations: vec![] } );
+            },
+            None => sections.last_mut().unwrap().body.push_str( line )
+        }
+    }
+    for section in sections.iter_mut() {
+        match parse_section_body( &section.body ) {
+            Ok( ( tags, annotations ) ) => {
+                section.tags = tags;
+                section.annotations = annotations;
+            },
+            Err( _ ) => return None
+        }
     }
+    Some( sections )
+}
+
+/// Render annotations in the annovate text format, without any `@file` header
+pub fn format_annotation_block( annotations: &AnnoContainer ) -> String {
+    let mut buffer = Vec::new();
+    write_annotations( &mut buffer, annotations ).unwrap(); //writing into a Vec cannot fail
+    String::from_utf8( buffer ).unwrap() //all parts are valid UTF-8 strings
+}
+
+/// Render the body of a section: tags first, then the annotations
+fn format_section_body( tags: Option<&TagSet>, annotations: &AnnoContainer ) -> String {
+    let mut out = String::new();
+    for tag in tags.into_iter().flat_map( |t| t.iter() ) {
+        out.push_str( &format!( "+{}\n", tag ) );
+    }
+    out.push_str( &format_annotation_block( annotations ) );
+    out
 }
 
 
 impl Annovate {
-    /// Create new annovation file and return annotation object
+    /// Create new annovation file and return annotation object.
+    /// The file stays locked against other processes until the object is dropped.
+    /// Files ending in `.toml` are read and written as TOML.
     pub fn new( file: &Path ) -> Result<Annovate, AnnoError> {
-        parse_annovate_file( file )
+        parse_annovate_file( file, OpenMode::ReadWrite, ParseMode::Strict, backend_for_path( file ), DEFAULT_LOCK_TIMEOUT, None )
+    }
+
+    /// Annovate file that only exists in memory: nothing is read, created or locked. `save` fails with
+    /// `AnnoError::NoPath` until `set_path` gives it a file.
+    pub fn empty() -> Annovate {
+        Annovate::from_data( data::AnnovateData::default(), Path::new( "" ) )
+    }
+
+    /// Like `empty`, with room for the annotations of `files` files
+    pub fn with_capacity( files: usize ) -> Annovate {
+        let mut anno = Annovate::empty();
+        anno.files.reserve( files );
+        anno.file_order.reserve( files );
+        anno
+    }
+
+    /// Like `new`, but in read-only mode the file is neither locked nor created if it does not exist
+    pub fn open( file: &Path, mode: OpenMode ) -> Result<Annovate, AnnoError> {
+        parse_annovate_file( file, mode, ParseMode::Strict, backend_for_path( file ), DEFAULT_LOCK_TIMEOUT, None )
+    }
+
+    /// Like `open`, but the file is read and saved with `backend` whatever its name is
+    pub fn open_with_backend( file: &Path, mode: OpenMode, backend: Rc<dyn Backend> ) -> Result<Annovate, AnnoError> {
+        parse_annovate_file( file, mode, ParseMode::Strict, backend, DEFAULT_LOCK_TIMEOUT, None )
+    }
+
+    /// Like `new`, but wait at most `lock_timeout` for another process to release the file
+    pub fn with_lock_timeout( file: &Path, lock_timeout: Duration ) -> Result<Annovate, AnnoError> {
+        parse_annovate_file( file, OpenMode::ReadWrite, ParseMode::Strict, backend_for_path( file ), lock_timeout, None )
+    }
+
+    /// Like `open`, but with the given parse mode, waiting at most `lock_timeout` for another process to release the file
+    pub fn open_with_parse_mode( file: &Path, mode: OpenMode, parse_mode: ParseMode, lock_timeout: Duration ) -> Result<Annovate, AnnoError> {
+        parse_annovate_file( file, mode, parse_mode, backend_for_path( file ), lock_timeout, None )
+    }
+
+    /// Like `open_with_parse_mode`, reporting to `progress` while the file is read. The handler stays set for saving,
+    /// see `set_progress_handler`.
+    pub fn open_with_progress( file: &Path, mode: OpenMode, parse_mode: ParseMode, lock_timeout: Duration, progress: ProgressHandler ) -> Result<Annovate, AnnoError> {
+        parse_annovate_file( file, mode, parse_mode, backend_for_path( file ), lock_timeout, Some( progress ) )
+    }
+
+    /// Number of delta records that were read from the file and are folded into its sections on the next save
+    pub fn delta_records( &self ) -> usize {
+        self.delta_records.get()
+    }
+
+    /// Problems that were skipped when the file was read with `ParseMode::Lenient`
+    pub fn parse_warnings( &self ) -> &[doctor::Problem] {
+        &self.parse_warnings
+    }
+
+    pub fn mode( &self ) -> OpenMode {
+        self.mode
+    }
+
+    /// Whether annotations or tags changed since the file was read or saved
+    pub fn is_modified( &self ) -> bool {
+        self.modified.get()
+    }
+
+    /// Fails in read-only mode. Every method that changes annotations or tags checks this first.
+    fn check_writable( &self ) -> Result<(), AnnoError> {
+        match self.mode {
+            OpenMode::ReadOnly => Err( AnnoError::ReadOnly ),
+            OpenMode::ReadWrite => Ok( () )
+        }
+    }
+
+    /// Fails with `AnnoError::Protected` for a change of `annotation` if it is protected and protection is not overridden
+    fn check_unprotected( &self, annotation: &Annotation ) -> Result<(), AnnoError> {
+        if annotation.protected && !self.override_protection {
+            Err( AnnoError::Protected( annotation.key.clone() ) )
+        } else {
+            Ok( () )
+        }
+    }
+
+    /// Remember that something changed if `changed` is true and pass it on
+    fn mark_modified<T>( &self, changed: bool, result: T ) -> T {
+        if changed {
+            self.modified.set( true );
+        }
+        result
     }
 
     /// Write annovate file to disk
     pub fn save( &self ) -> Result<(), AnnoError> {
+        if self.filename.as_os_str().is_empty() {
+            return Err( AnnoError::NoPath );
+        }
         self.save_as( &self.filename )
     }
 
-    pub fn save_as( &self, outfile: &Path ) -> Result<(), AnnoError> {
-        let mut file = try!( File::create( outfile ) );
+    /// The file that `save` writes, empty for an annovate file that only exists in memory
+    pub fn path( &self ) -> &Path {
+        &self.filename
+    }
 
-        fn write_annotations( file: &mut File, annotations: &AnnoContainer ) -> Result<(), AnnoError> {
-            for anno in annotations {
-                try!( write!( file, ">{}\n", anno.key ) );
-                for line in anno.value.lines() {
-                    try!( write!( file, "={}\n", line ) );
-                }
-                try!( write!( file, "<{}\n", anno.context ) );
-            }
-            Ok( () )
+    /// Let `save` write to `path` in the format its name asks for. The lock of the previous file is released,
+    /// the new file is locked while it is saved. Everything counts as changed, as the file may differ.
+    pub fn set_path( &mut self, path: &Path ) {
+        self.lock = None;
+        self.filename = path.to_path_buf();
+        self.backend = backend_for_path( path );
+        self.modified.set( true );
+    }
+
+    /// Write to `outfile`. Sections of an existing file whose annotations did not change are kept byte by byte,
+    /// so that only the modified parts of the file differ after saving.
+    pub fn save_as( &self, outfile: &Path ) -> Result<(), AnnoError> {
+        if outfile == self.filename.as_path() {
+            try!( self.check_writable() );
         }
-        
-        try!( write_annotations( &mut file, &self.dir ) );
+        if let Some( ref hook ) = self.pre_save_hook {
+            try!( hook( self ).map_err( AnnoError::Rejected ) );
+        }
+        let mut previous = String::new();
+        let have_previous = match File::open( outfile ) {
+            Ok( mut old_file ) => old_file.read_to_string( &mut previous ).is_ok(),
+            Err( _ ) => false
+        };
+        //another file is written in the format its name asks for
+        let backend = if outfile == self.filename.as_path() { self.backend.clone() } else { backend_for_path( outfile ) };
+        //the layout of a file with skipped lines is not kept, the lines would come back
+        let keep_layout = have_previous && self.parse_warnings.is_empty();
+        let previous_content = integrity::strip_footer( &previous );
+        let text = backend.serialize( self, if keep_layout { Some( &previous_content ) } else { None } );
+        //a footer is kept once the file has one
+        let text = if self.save_options.integrity.is_enabled() || ( have_previous && integrity::has_footer( &previous ) ) {
+            integrity::with_footer( &text, backend.name() == TomlBackend.name() )
+        } else {
+            text
+        };
 
-        for anno_file in self.files.keys() {
-            try!( write!( file, "@{}\n", anno_file ) );
-            for annotations in self.files.get( anno_file ) {
-                try!( write_annotations( &mut file, annotations ) );
+        //a file other than the one we hold the lock for is locked while it is written
+        let holds_lock = self.lock.is_some() && outfile == self.filename.as_path();
+        let _lock = if holds_lock { None } else { Some( try!( FileLock::acquire( outfile, self.lock_timeout ) ) ) };
+        if self.save_options.journal && outfile == self.filename.as_path() {
+            let journal = journal::journal_path( outfile );
+            if self.undone.get() > 0 {
+                try!( journal::drop_entries( &journal, self.undone.get() ) );
+                self.undone.set( 0 );
+            } else if have_previous && previous != text {
+                try!( journal::append_entry( &journal, &previous, self.save_options.journal_entries ) );
             }
         }
+        if have_previous && previous != text && outfile == self.filename.as_path() {
+            try!( backup::rotate_backups( outfile, &previous, self.save_options.backups ) );
+        }
+        try!( write_atomically_with_progress( outfile, text.as_bytes(), self.progress() ) );
+        if outfile == self.filename.as_path() {
+            self.modified.set( false );
+            self.delta_records.set( 0 );
+        }
         Ok( () )
     }
 
-    /// Get a vector of filenames (copied strings)
-    pub fn get_files( &self ) -> Vec<String> {
-        let mut result = Vec::new();
-        for file in self.files.keys() {
-            result.push( file.clone() );
-        }
-        result
+    /// Replace all annotations with those of `other`, keeping the filename, lock and save options of `self`
+    pub fn replace_annotations( &mut self, other: Annovate ) -> Result<(), AnnoError> {
+        try!( self.check_writable() );
+        self.dir = other.dir;
+        self.files = other.files;
+        self.file_order = other.file_order;
+        self.dir_tags = other.dir_tags;
+        self.tags = other.tags;
+        self.modified.set( true );
+        Ok( () )
     }
 
-    pub fn get_directory_annotations( &self ) -> &AnnoContainer {
-        &self.dir
+    pub fn backend( &self ) -> &dyn Backend {
+        &*self.backend
     }
 
-    pub fn get_file_annotations( &self, filename: &str ) -> Option<&AnnoContainer> {
-        self.files.get( filename )
+    /// Format in which `save` writes the file, e.g. to convert it
+    pub fn set_backend( &mut self, backend: Rc<dyn Backend> ) {
+        self.backend = backend;
     }
 
-    pub fn add_directory_annotation( &mut self, anno: Annotation ) -> () {
-        self.dir.push( anno );
+    /// Run `hook` before every save. It is not copied by `snapshot`.
+    pub fn set_pre_save_hook( &mut self, hook: PreSaveHook ) {
+        self.pre_save_hook = Some( hook );
     }
 
-    pub fn remove_directory_annotation_entries( &mut self, key: &str ) -> bool {
-        let old_length = self.dir.len();
-        self.dir.retain( |x| x.key != key ); //delete all existing annotations with the key
-        old_length > self.dir.len() //return true if there was an entry that was removed
+    /// Report the written bytes to `handler` when saving
+    pub fn set_progress_handler( &mut self, handler: ProgressHandler ) {
+        self.progress_handler = Some( handler );
     }
 
-    pub fn add_file_annotation( &mut self, filename: &str, anno: Annotation ) -> () {
-        let mut vals = self.files.entry( filename.to_string() ).or_insert( AnnoContainer::new() );
-        vals.push( anno )
+    fn progress( &self ) -> Option<&dyn Fn( &Progress )> {
+        self.progress_handler.as_ref().map( |handler| &**handler as &dyn Fn( &Progress ) )
     }
 
-    pub fn remove_file_annotation_entries( &mut self, filename: &str, key: &str ) -> bool {
-        let entries = self.files.get_mut( filename );
-        match entries {
-            Some( vals ) => {
-                let old_length = vals.len();
-                vals.retain( |x| x.key != key );
-                return old_length > vals.len();
-            },
-            None => return false
+    pub fn save_options( &self ) -> &SaveOptions {
+        &self.save_options
+    }
+
+    pub fn set_save_options( &mut self, options: SaveOptions ) {
+        self.save_options = options;
+    }
+
+    /// All keys of the file map in the order of the save options
+    fn ordered_keys( &self ) -> Vec<&String> {
+        let mut keys: Vec<&String> = self.files.keys().collect();
+        match self.save_options.order {
+            SectionOrder::Alphabetical => keys.sort(),
+            SectionOrder::Insertion => {
+                let position: HashMap<&String, usize> = self.file_order.iter().enumerate().map( |( i, k )| ( k, i ) ).collect();
+                keys.sort_by_key( |k| ( position.get( k ).cloned().unwrap_or( std::usize::MAX ), k.to_string() ) );
+            }
+        }
+        keys
+    }
+
+    /// Files and the keys of their sub-file targets
+    fn file_groups( &self ) -> Vec<( String, Vec<String> )> {
+        let mut groups: Vec<( String, Vec<String> )> = Vec::new();
+        for key in self.ordered_keys() {
+            let target = Target::parse( key );
+            let index = match groups.iter().position( |g| g.0 == target.file ) {
+                Some( index ) => index,
+                None => { groups.push( ( target.file.clone(), vec![] ) ); groups.len() - 1 }
+            };
+            if target.is_sub_file() {
+                groups[ index ].1.push( key.clone() );
+            }
         }
+        groups
     }
 
-    pub fn drop_file_annotations( &mut self, filename: &str ) -> bool {
-        self.files.remove( filename ).is_some()
+    fn render_fragment( &self, out: &mut String, key: &str ) {
+        let fragment = Target::parse( key ).fragment.unwrap(); //only called for sub-file targets
+        out.push_str( &format!( "#{}\n", fragment ) );
+        out.push_str( &format_section_body( self.tags.get( key ), &self.files[ key ] ) );
     }
-}
 
-//TODO write tests to make it rock solid
-#[cfg(test)]
-mod tests {
-    #[test]
-    fn it_works() {
+    //sub-file targets are written as `#fragment` sections below their file
+    fn render_group( &self, out: &mut String, file: &str, fragments: &[String] ) {
+        out.push_str( &format!( "@{}\n", file ) );
+        if let Some( annotations ) = self.files.get( file ) {
+            out.push_str( &format_section_body( self.tags.get( file ), annotations ) );
+        }
+        for key in fragments {
+            self.render_fragment( out, key );
+        }
+    }
+
+    /// Generate the whole file from scratch
+    fn render( &self ) -> String {
+        let mut out = format_section_body( Some( &self.dir_tags ), &self.dir );
+        for ( file, fragments ) in self.file_groups() {
+            self.render_group( &mut out, &file, &fragments );
+        }
+        out
     }
+
+    /// Apply the current state to the previous file content. Returns `None` if the old content cannot be split into sections.
+    fn patch( &self, old: &str ) -> Option<String> {
+        let sections = match split_sections( old ) {
+            Some( sections ) => sections,
+            None => return None
+        };
+
+        fn push_line( out: &mut String, text: &str ) {
+            out.push_str( text );
+            if !text.is_empty() && !text.ends_with( '\n' ) {
+                out.push( '\n' );
+            }
+        }
+        fn keep_or_render( out: &mut String, section: &RawSection, tags: Option<&TagSet>, current: &AnnoContainer ) {
+            let same_tags = match tags { Some( tags ) => section.tags == *tags, None => section.tags.is_empty() };
+            if same_tags && section.annotations == *current {
+                push_line( out, &section.body );
+            } else {
+                out.push_str( &format_section_body( tags, current ) );
+            }
+        }
+
+        let groups = self.file_groups();
+        let mut written: HashSet<String> = HashSet::new();
+        let mut out = String::new();
+        keep_or_render( &mut out, &sections[ 0 ], Some( &self.dir_tags ), &self.dir );
+
+        let mut current_group: Option<&( String, Vec<String> )> = None;
+        for section in &sections[ 1.. ] {
+            if section.is_file {
+                if let Some( group ) = current_group {
+                    for key in group.1.iter().filter( |k| !written.contains( *k ) ) {
+                        self.render_fragment( &mut out, key );
+                    }
+                }
+                current_group = groups.iter().find( |g| g.0 == section.target );
+                if current_group.is_some() {
+                    push_line( &mut out, &section.header );
+                    if let Some( annotations ) = self.files.get( &section.target ) {
+                        keep_or_render( &mut out, section, self.tags.get( &section.target ), annotations );
+                    }
+                    written.insert( section.target.clone() );
+                }
+            } else if let ( Some( _ ), Some( annotations ) ) = ( current_group, self.files.get( &section.target ) ) {
+                push_line( &mut out, &section.header );
+                keep_or_render( &mut out, section, self.tags.get( &section.target ), annotations );
+                written.insert( section.target.clone() );
+            }
+        }
+        if let Some( group ) = current_group {
+            for key in group.1.iter().filter( |k| !written.contains( *k ) ) {
+                self.render_fragment( &mut out, key );
+            }
+        }
+
+        for &( ref file, ref fragments ) in groups.iter().filter( |g| !written.contains( &g.0 ) ) {
+            self.render_group( &mut out, file, fragments );
+        }
+        Some( out )
+    }
+
+    /// Most recent value of `key` of every file that has it, in the order of `get_files`
+    pub fn get_values( &self, key: &str ) -> Vec<( String, &str )> {
+        self.iter_files().filter_map( |f| {
+            self.files[ f ].iter().rev().find( |a| a.key == key ).map( |a| ( f.to_string(), a.value.as_str() ) )
+        } ).collect()
+    }
+
+    /// All values `key` had for a file, oldest first. Entries without creation time come first,
+    /// entries of the same time keep their order in the file.
+    pub fn annotation_history( &self, filename: &str, key: &str ) -> Vec<&Annotation> {
+        match self.files.get( filename ) {
+            Some( annotations ) => history_of( annotations, key ),
+            None => Vec::new()
+        }
+    }
+
+    /// Like `annotation_history` for the directory
+    pub fn directory_annotation_history( &self, key: &str ) -> Vec<&Annotation> {
+        history_of( &self.dir, key )
+    }
+
+    /// Directory of the meta file. Annotated filenames are relative to it.
+    pub fn base_dir( &self ) -> PathBuf {
+        match self.filename.parent() {
+            Some( dir ) if dir != Path::new( "" ) => dir.to_path_buf(),
+            _ => PathBuf::from( "." )
+        }
+    }
+
+    /// Path of an annotated file on disk; parts of files resolve to their file
+    pub fn resolve( &self, filename: &str ) -> PathBuf {
+        self.base_dir().join( Target::parse( filename ).file )
+    }
+
+    /// Get a vector of filenames (copied strings) in the order of the save options
+    pub fn get_files( &self ) -> Vec<String> {
+        self.iter_files().map( |f| f.to_string() ).collect()
+    }
+
+    /// Filenames in the order of the save options, without copying them
+    pub fn iter_files<'a>( &'a self ) -> impl Iterator<Item=&'a str> {
+        self.ordered_keys().into_iter().map( |f| f.as_str() )
+    }
+
+    /// All annotations: first those of the directory (with `None` as filename), then those of each file
+    pub fn iter_all_annotations<'a>( &'a self ) -> AllAnnotations<'a> {
+        AllAnnotations { anno: self, dir: self.dir.iter(), files: self.ordered_keys().into_iter(), current: None }
+    }
+
+    /// Get all sub-file targets (columns, line ranges, ...) of a file, sorted by name
+    pub fn get_sub_targets( &self, filename: &str ) -> Vec<String> {
+        let mut result: Vec<String> = self.files.keys()
+                                                .filter( |k| {
+                                                    let target = Target::parse( k );
+                                                    target.is_sub_file() && target.file == filename
+                                                } )
+                                                .cloned()
+                                                .collect();
+        result.sort();
+        result
+    }
+
+    pub fn get_directory_annotations( &self ) -> &AnnoContainer {
+        &self.dir
+    }
+
+    pub fn get_file_annotations( &self, filename: &str ) -> Option<&AnnoContainer> {
+        self.files.get( filename )
+    }
+
+    /// The most recent annotation of the directory with the key
+    pub fn get_directory_annotation( &self, key: &str ) -> Option<&Annotation> {
+        self.dir.iter().rev().find( |a| a.key == key )
+    }
+
+    /// The most recent annotation of a file with the key
+    pub fn get_file_annotation( &self, filename: &str, key: &str ) -> Option<&Annotation> {
+        self.files.get( filename ).and_then( |annotations| annotations.iter().rev().find( |a| a.key == key ) )
+    }
+
+    /// Add an annotation to the directory. A new value for a protected key fails unless protection is overridden.
+    pub fn add_directory_annotation( &mut self, anno: Annotation ) -> Result<(), AnnoError> {
+        try!( self.check_writable() );
+        try!( validate::check_annotation( &anno ) );
+        if protect::is_protected( &self.dir, &anno.key ) && !self.override_protection {
+            return Err( AnnoError::Protected( anno.key ) );
+        }
+        let key = anno.key.clone();
+        self.dir.push( anno );
+        let warnings = quota::apply_quota( self.quota, None, &mut self.dir, &key );
+        self.quota_warnings.extend( warnings );
+        Ok( self.mark_modified( true, () ) )
+    }
+
+    /// Like `remove_file_annotation_entries` for the directory
+    pub fn remove_directory_annotation_entries( &mut self, key: &str ) -> Result<bool, AnnoError> {
+        self.remove_directory_annotation_entries_matching( key, KeyMatcher::Exact )
+    }
+
+    /// Like `remove_directory_annotation_entries`, with keys compared by `matcher`
+    pub fn remove_directory_annotation_entries_matching( &mut self, key: &str, matcher: KeyMatcher ) -> Result<bool, AnnoError> {
+        try!( self.check_writable() );
+        if let Some( protected ) = self.dir.iter().find( |x| x.key_matches_with( key, matcher ) && protect::is_protected( &self.dir, &x.key ) ) {
+            if !self.override_protection {
+                return Err( AnnoError::Protected( protected.key.clone() ) );
+            }
+        }
+        let old_length = self.dir.len();
+        self.dir.retain( |x| !x.key_matches_with( key, matcher ) ); //delete all existing annotations with the key
+        let removed = old_length > self.dir.len(); //return true if there was an entry that was removed
+        Ok( self.mark_modified( removed, removed ) )
+    }
+
+    /// Like `remove_file_annotation` for the directory. A protected entry is only removed if protection is overridden.
+    pub fn remove_directory_annotation( &mut self, key: &str, value: &str ) -> Result<usize, AnnoError> {
+        self.retain_directory_annotations( |_, a| a.key != key || a.value != value )
+    }
+
+    /// Like `remove_nth_file_annotation` for the directory
+    pub fn remove_nth_directory_annotation( &mut self, key: &str, index: usize ) -> Result<bool, AnnoError> {
+        let position = match self.dir.iter().enumerate().filter( |&( _, a )| a.key == key ).nth( index ) {
+            Some( ( position, _ ) ) => position,
+            None => return Ok( false )
+        };
+        self.retain_directory_annotations( |i, _| i != position ).map( |removed| removed > 0 )
+    }
+
+    /// Remove all annotations and tags of the directory. Fails if one of the annotations is protected, unless
+    /// protection is overridden.
+    pub fn drop_directory_annotations( &mut self ) -> Result<bool, AnnoError> {
+        let removed = try!( self.retain_directory_annotations( |_, _| false ) );
+        let had_tags = !self.dir_tags.is_empty();
+        self.dir_tags.clear();
+        Ok( self.mark_modified( had_tags, removed > 0 || had_tags ) )
+    }
+
+    /// Keep the annotations of the directory for which `keep` (with their position) is true. Returns the number
+    /// of removed annotations.
+    fn retain_directory_annotations<F: Fn( usize, &Annotation ) -> bool>( &mut self, keep: F ) -> Result<usize, AnnoError> {
+        try!( self.check_writable() );
+        if let Some( ( _, protected ) ) = self.dir.iter().enumerate().find( |&( i, a )| !keep( i, a ) && a.protected ) {
+            try!( self.check_unprotected( protected ) );
+        }
+        let old_length = self.dir.len();
+        let kept: AnnoContainer = self.dir.drain( .. ).enumerate().filter( |&( i, ref a )| keep( i, a ) ).map( |( _, a )| a ).collect();
+        self.dir = kept;
+        let removed = old_length - self.dir.len();
+        Ok( self.mark_modified( removed > 0, removed ) )
+    }
+
+    /// Add an annotation to a file. Limits of the quota that this exceeds are recorded in `quota_warnings`.
+    pub fn add_file_annotation( &mut self, filename: &str, anno: Annotation ) -> Result<(), AnnoError> {
+        try!( self.check_writable() );
+        try!( validate::check_filename( filename ) );
+        try!( validate::check_annotation( &anno ) );
+        if !self.files.contains_key( filename ) {
+            self.file_order.push( filename.to_string() );
+        }
+        let key = anno.key.clone();
+        let annotations = self.files.entry( filename.to_string() ).or_insert( AnnoContainer::new() );
+        annotations.push( anno );
+        let warnings = quota::apply_quota( self.quota, Some( filename ), annotations, &key );
+        self.quota_warnings.extend( warnings );
+        Ok( self.mark_modified( true, () ) )
+    }
+
+    /// Add an annotation to a file as `policy` says. Returns whether it was added. Replacing protected
+    /// entries fails unless protection is overridden.
+    pub fn put_file_annotation( &mut self, filename: &str, anno: Annotation, policy: PutPolicy ) -> Result<bool, AnnoError> {
+        try!( self.check_writable() );
+        //checked before `Replace` removes anything
+        try!( validate::check_filename( filename ) );
+        try!( validate::check_annotation( &anno ) );
+        if let Some( annotations ) = self.files.get_mut( filename ) {
+            if !try!( prepare_put( annotations, &anno, policy, self.override_protection ) ) {
+                return Ok( false );
+            }
+        }
+        self.add_file_annotation( filename, anno ).map( |_| true )
+    }
+
+    /// Like `put_file_annotation` for the directory
+    pub fn put_directory_annotation( &mut self, anno: Annotation, policy: PutPolicy ) -> Result<bool, AnnoError> {
+        try!( self.check_writable() );
+        try!( validate::check_annotation( &anno ) );
+        if !try!( prepare_put( &mut self.dir, &anno, policy, self.override_protection ) ) {
+            return Ok( false );
+        }
+        self.add_directory_annotation( anno ).map( |_| true )
+    }
+
+    /// Replace all annotations of a file. An empty container drops the file unless it has tags.
+    pub fn set_file_annotations( &mut self, filename: &str, annotations: AnnoContainer ) -> Result<(), AnnoError> {
+        try!( self.check_writable() );
+        try!( validate::check_filename( filename ) );
+        for annotation in &annotations {
+            try!( validate::check_annotation( annotation ) );
+        }
+        if annotations.is_empty() && !self.tags.contains_key( filename ) {
+            try!( self.drop_file_annotations( filename ) );
+            return Ok( () );
+        }
+        if !self.files.contains_key( filename ) {
+            self.file_order.push( filename.to_string() );
+        }
+        let changed = self.files.insert( filename.to_string(), annotations.clone() ).as_ref() != Some( &annotations );
+        Ok( self.mark_modified( changed, () ) )
+    }
+
+    /// Remove the annotations of a file with the key, or all keys of a namespace for `<namespace>.*`.
+    /// A file without annotations and tags is dropped afterwards.
+    pub fn remove_file_annotation_entries( &mut self, filename: &str, key: &str ) -> Result<bool, AnnoError> {
+        self.remove_file_annotation_entries_matching( filename, key, KeyMatcher::Exact )
+    }
+
+    /// Like `remove_file_annotation_entries`, with keys compared by `matcher`
+    pub fn remove_file_annotation_entries_matching( &mut self, filename: &str, key: &str, matcher: KeyMatcher ) -> Result<bool, AnnoError> {
+        self.retain_file_annotations( filename, |_, a| !a.key_matches_with( key, matcher ) ).map( |removed| removed > 0 )
+    }
+
+    /// Remove the annotations of a file with exactly this key and value. Returns how many were removed.
+    pub fn remove_file_annotation( &mut self, filename: &str, key: &str, value: &str ) -> Result<usize, AnnoError> {
+        self.retain_file_annotations( filename, |_, a| a.key != key || a.value != value )
+    }
+
+    /// Remove the `index`th annotation (counting from 0) of a file with this key, in the order of the meta file
+    pub fn remove_nth_file_annotation( &mut self, filename: &str, key: &str, index: usize ) -> Result<bool, AnnoError> {
+        try!( self.check_writable() );
+        let position = match self.files.get( filename ).and_then( |vals| vals.iter().enumerate().filter( |&( _, a )| a.key == key ).nth( index ) ) {
+            Some( ( position, _ ) ) => position,
+            None => return Ok( false )
+        };
+        self.retain_file_annotations( filename, |i, _| i != position ).map( |removed| removed > 0 )
+    }
+
+    /// Keep the annotations of a file for which `keep` (with their position) is true and drop the
+    /// file once nothing is left. Returns the number of removed annotations. Fails if one of the removed
+    /// annotations is protected, unless protection is overridden.
+    fn retain_file_annotations<F: Fn( usize, &Annotation ) -> bool>( &mut self, filename: &str, keep: F ) -> Result<usize, AnnoError> {
+        try!( self.check_writable() );
+        if let Some( vals ) = self.files.get( filename ) {
+            if let Some( ( _, protected ) ) = vals.iter().enumerate().find( |&( i, a )| !keep( i, a ) && a.protected ) {
+                try!( self.check_unprotected( protected ) );
+            }
+        }
+        let ( removed, now_empty ) = match self.files.get_mut( filename ) {
+            Some( vals ) => {
+                let old_length = vals.len();
+                let kept: AnnoContainer = vals.drain( .. ).enumerate().filter( |&( i, ref a )| keep( i, a ) ).map( |( _, a )| a ).collect();
+                *vals = kept;
+                ( old_length - vals.len(), vals.is_empty() )
+            },
+            None => return Ok( 0 )
+        };
+        if now_empty && !self.tags.contains_key( filename ) {
+            try!( self.drop_file_annotations( filename ) );
+        }
+        Ok( self.mark_modified( removed > 0, removed ) )
+    }
+
+    /// Remove all annotations and tags of a file. Fails if one of the annotations is protected, unless protection
+    /// is overridden.
+    pub fn drop_file_annotations( &mut self, filename: &str ) -> Result<bool, AnnoError> {
+        try!( self.check_writable() );
+        if let Some( protected ) = self.files.get( filename ).and_then( |vals| vals.iter().find( |a| a.protected ) ) {
+            try!( self.check_unprotected( protected ) );
+        }
+        self.file_order.retain( |f| f != filename );
+        self.tags.remove( filename );
+        let dropped = self.files.remove( filename ).is_some();
+        Ok( self.mark_modified( dropped, dropped ) )
+    }
+
+    /// Remove the annotations and tags of `filename` (not those of its columns, line ranges etc.) and return
+    /// the annotations, e.g. to `absorb_file` them into another meta file. `None` if the file has no annotations.
+    pub fn extract_file( &mut self, filename: &str ) -> Result<Option<AnnoContainer>, AnnoError> {
+        try!( self.check_writable() );
+        let annotations = self.files.remove( filename );
+        if annotations.is_some() {
+            self.file_order.retain( |f| f != filename );
+            self.tags.remove( filename );
+        }
+        Ok( self.mark_modified( annotations.is_some(), annotations ) )
+    }
+
+    /// Append annotations to those of `filename`, e.g. those of `extract_file` of another meta file
+    pub fn absorb_file( &mut self, filename: &str, annotations: AnnoContainer ) -> Result<(), AnnoError> {
+        try!( self.check_writable() );
+        if annotations.is_empty() {
+            return Ok( () );
+        }
+        if !self.files.contains_key( filename ) {
+            self.file_order.push( filename.to_string() );
+        }
+        self.files.entry( filename.to_string() ).or_insert( AnnoContainer::new() ).extend( annotations );
+        Ok( self.mark_modified( true, () ) )
+    }
+
+    /// Copy the most recent entry of each key (or only of `keys`) from `src` to `dst`.
+    /// The copies get the context `<context>, copied from <src>`. Returns the number of copied annotations.
+    /// See `copy` for more destinations and renamed keys.
+    pub fn copy_annotations( &mut self, src: &str, dst: &str, keys: Option<&[&str]>, context: &str ) -> Result<usize, AnnoError> {
+        let keys: Vec<String> = keys.unwrap_or( &[] ).iter().map( |k| k.to_string() ).collect();
+        self.copy( &CopySpec::new( src, &[ dst.to_string() ] ).with_keys( &keys ).with_context( context ) )
+    }
+
+    /// Move all annotations of `old` (including its columns, line ranges etc.) to `new`.
+    /// Annotations that `new` already has are kept in front, tags are merged. Returns false if `old` has no annotations.
+    pub fn rename_file( &mut self, old: &str, new: &str ) -> Result<bool, AnnoError> {
+        try!( self.check_writable() );
+        try!( validate::check_filename( new ) );
+        let keys: Vec<String> = self.file_order.iter().filter( |k| Target::parse( k ).file == old ).cloned().collect();
+        for key in &keys {
+            let annotations = self.files.remove( key ).unwrap(); //key comes from the map
+            self.file_order.retain( |f| f != key );
+            let mut target = Target::parse( key );
+            target.file = new.to_string();
+            let new_key = target.to_string();
+            if !self.files.contains_key( &new_key ) {
+                self.file_order.push( new_key.clone() );
+            }
+            if let Some( tags ) = self.tags.remove( key ) {
+                self.tags.entry( new_key.clone() ).or_insert( TagSet::new() ).extend( tags );
+            }
+            self.files.entry( new_key ).or_insert( AnnoContainer::new() ).extend( annotations );
+        }
+        Ok( self.mark_modified( !keys.is_empty(), !keys.is_empty() ) )
+    }
+
+    /// Change the key `old` of every annotation in `scope` to `new`, keeping values, times and the order of entries.
+    /// With a `context`, the rename is recorded in the contexts as `<context>, renamed from <old>`.
+    /// Returns the number of renamed annotations.
+    pub fn rename_key( &mut self, old: &str, new: &str, scope: KeyScope, context: Option<&str> ) -> Result<usize, AnnoError> {
+        try!( self.check_writable() );
+        try!( validate::check_key( new ) );
+        if let Some( context ) = context {
+            try!( validate::check_context( context ) );
+        }
+        let mut count = 0;
+        {
+            let mut containers: Vec<&mut AnnoContainer> = Vec::new();
+            match scope {
+                KeyScope::All => {
+                    containers.push( &mut self.dir );
+                    containers.extend( self.files.values_mut() );
+                },
+                KeyScope::File( ref file ) => containers.extend( self.files.get_mut( file ) )
+            }
+            for annotation in containers.into_iter().flat_map( |c| c.iter_mut() ).filter( |a| a.key == old ) {
+                annotation.key = new.to_string();
+                if let Some( context ) = context {
+                    annotation.context = format!( "{}; {}, renamed from {}", annotation.context, context, old );
+                }
+                count += 1;
+            }
+        }
+        Ok( self.mark_modified( count > 0, count ) )
+    }
+
+    /// Keep only the `keep` most recent entries of every key of the directory and the files (at least one)
+    /// and remove the older ones. Returns the number of removed entries. Delta records of the file count as a
+    /// change, saving folds them into the sections.
+    pub fn compact( &mut self, keep: usize ) -> Result<usize, AnnoError> {
+        try!( self.check_writable() );
+        let keep = keep.max( 1 );
+        let mut removed = 0;
+        for annotations in Some( &mut self.dir ).into_iter().chain( self.files.values_mut() ) {
+            let mut seen: HashMap<String, usize> = HashMap::new();
+            //walk from the most recent entry backwards
+            let mut kept: AnnoContainer = annotations.drain( .. ).rev().filter( |a| {
+                let count = seen.entry( a.key.clone() ).or_insert( 0 );
+                *count += 1;
+                *count <= keep
+            } ).collect();
+            kept.reverse();
+            removed += seen.values().map( |&count| count.saturating_sub( keep ) ).sum::<usize>();
+            *annotations = kept;
+        }
+        Ok( self.mark_modified( removed > 0 || self.delta_records.get() > 0, removed ) )
+    }
+
+    /// Keep the annotations of the directory (`None` as filename) and the files for which `keep` is true. Files
+    /// without annotations and tags are dropped afterwards. Fails without removing anything if a protected
+    /// annotation would be removed and protection is not overridden. Returns the number of removed annotations.
+    pub fn retain_annotations<F: FnMut( Option<&str>, &Annotation ) -> bool>( &mut self, mut keep: F ) -> Result<usize, AnnoError> {
+        try!( self.check_writable() );
+        //decide about every annotation first, so that a protected one stops the change as a whole
+        let dir_kept: Vec<bool> = self.dir.iter().map( |a| keep( None, a ) ).collect();
+        let mut files_kept: Vec<( String, Vec<bool> )> = Vec::new();
+        for file in self.iter_files() {
+            files_kept.push( ( file.to_string(), self.files[ file ].iter().map( |a| keep( Some( file ), a ) ).collect() ) );
+        }
+        let removed_dir = self.dir.iter().zip( dir_kept.iter() ).filter( |&( _, &kept )| !kept ).map( |( a, _ )| a );
+        let removed_files = files_kept.iter().flat_map( |&( ref file, ref kept )| {
+            self.files[ file ].iter().zip( kept.iter() ).filter( |&( _, &kept )| !kept ).map( |( a, _ )| a )
+        } );
+        if let Some( protected ) = removed_dir.chain( removed_files ).find( |a| a.protected ) {
+            try!( self.check_unprotected( protected ) );
+        }
+        let mut removed = try!( self.retain_directory_annotations( |i, _| dir_kept[ i ] ) );
+        for &( ref file, ref kept ) in &files_kept {
+            removed += try!( self.retain_file_annotations( file, |i, _| kept[ i ] ) );
+        }
+        Ok( removed )
+    }
+
+    /// Replace the value of every annotation of the directory (`None` as filename) and the files by what
+    /// `f` returns for its key and value. Fails without changing anything if the value of a protected annotation
+    /// would change and protection is not overridden. Returns the number of changed values.
+    pub fn map_values<F: FnMut( Option<&str>, &str, &str ) -> String>( &mut self, mut f: F ) -> Result<usize, AnnoError> {
+        try!( self.check_writable() );
+        //`None` for the values that stay the same
+        let mut changes: Vec<( Option<String>, Vec<Option<String>> )> = Vec::new();
+        for ( file, annotations ) in Some( ( None, &self.dir ) ).into_iter().chain( self.iter_files().map( |f| ( Some( f ), &self.files[ f ] ) ) ) {
+            let mut values: Vec<Option<String>> = Vec::new();
+            for annotation in annotations {
+                let value = f( file, &annotation.key, &annotation.value );
+                if value == annotation.value {
+                    values.push( None );
+                } else {
+                    try!( validate::check_value( &value ) );
+                    values.push( Some( value ) );
+                }
+            }
+            changes.push( ( file.map( |f| f.to_string() ), values ) );
+        }
+        for &( ref file, ref values ) in &changes {
+            let annotations = match *file { Some( ref file ) => &self.files[ file ], None => &self.dir };
+            if let Some( protected ) = annotations.iter().zip( values.iter() ).find( |&( a, v )| v.is_some() && a.protected ) {
+                try!( self.check_unprotected( protected.0 ) );
+            }
+        }
+        let mut count = 0;
+        for ( file, values ) in changes {
+            let annotations = match file { Some( ref file ) => self.files.get_mut( file ).unwrap(), None => &mut self.dir }; //the files were listed above
+            for ( annotation, value ) in annotations.iter_mut().zip( values.into_iter() ) {
+                if let Some( value ) = value {
+                    annotation.value = value;
+                    count += 1;
+                }
+            }
+        }
+        Ok( self.mark_modified( count > 0, count ) )
+    }
+
+    /// Use `/` as path separator in all filenames, e.g. for files annotated on Windows.
+    /// Files whose names then coincide are merged like by `rename_file`. Returns the number of renamed files.
+    pub fn normalize_separators( &mut self ) -> Result<usize, AnnoError> {
+        try!( self.check_writable() );
+        let names: BTreeSet<String> = self.files.keys().map( |k| Target::parse( k ) )
+                                                       .filter( |t| !t.is_remote() && t.file.contains( '\\' ) )
+                                                       .map( |t| t.file ).collect();
+        for name in &names {
+            try!( self.rename_file( name, &Target::file( name ).with_slashes().file ) );
+        }
+        Ok( names.len() )
+    }
+}
+
+/// Entries of `key` among `annotations`, oldest first, see `Annovate::annotation_history`
+fn history_of<'a>( annotations: &'a AnnoContainer, key: &str ) -> Vec<&'a Annotation> {
+    let mut history: Vec<&Annotation> = annotations.iter().filter( |a| a.key == key ).collect();
+    history.sort_by_key( |a| a.created );
+    history
+}
+
+/// What `Annovate::put_file_annotation` and `put_directory_annotation` do with earlier entries of the key and region
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum PutPolicy {
+    /// Add the annotation after the earlier entries, which stay in the history
+    #[default]
+    Append,
+    /// Remove the earlier entries first
+    Replace,
+    /// Only add the annotation if the key has no entry yet
+    IfAbsent
+}
+
+/// Annotations that `Annovate::rename_key` changes
+#[derive(Clone, PartialEq, Debug)]
+pub enum KeyScope {
+    /// Those of the directory and all files
+    All,
+    /// Only those of one file
+    File( String )
+}
+
+/// Iterator over all annotations of an `Annovate`, see `iter_all_annotations`
+pub struct AllAnnotations<'a> {
+    anno: &'a Annovate,
+    dir: slice::Iter<'a, Annotation>,
+    files: vec::IntoIter<&'a String>,
+    current: Option<( &'a str, slice::Iter<'a, Annotation> )>
+}
+
+impl<'a> Iterator for AllAnnotations<'a> {
+    type Item = ( Option<&'a str>, &'a Annotation );
+
+    fn next( &mut self ) -> Option<Self::Item> {
+        if let Some( annotation ) = self.dir.next() {
+            return Some( ( None, annotation ) );
+        }
+        loop {
+            if let Some( ( file, ref mut annotations ) ) = self.current {
+                if let Some( annotation ) = annotations.next() {
+                    return Some( ( Some( file ), annotation ) );
+                }
+            }
+            match self.files.next() {
+                Some( file ) => self.current = Some( ( file.as_str(), self.anno.files[ file ].iter() ) ),
+                None => return None
+            }
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a Annovate {
+    type Item = ( Option<&'a str>, &'a Annotation );
+    type IntoIter = AllAnnotations<'a>;
+
+    fn into_iter( self ) -> AllAnnotations<'a> {
+        self.iter_all_annotations()
+    }
+}
+
+//TODO write tests to make it rock solid
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn it_works() {
+    }
+
+    #[test]
+    fn split_quoted_csv_header() {
+        use dictionary::split_fields;
+        assert_eq!( split_fields( "id,\"name, full\",\"say \"\"hi\"\"\"", ',' ),
+                    vec![ "id", "name, full", "say \"hi\"" ] );
+        assert_eq!( split_fields( "a\tb", '\t' ), vec![ "a", "b" ] );
+    }
+
+    #[test]
+    fn parse_targets() {
+        use super::{Target, Fragment};
+        let column = Target::parse( "data.csv#column:age" );
+        assert_eq!( column.file, "data.csv" );
+        assert_eq!( column.fragment, Some( Fragment::Column( "age".to_string() ) ) );
+        assert_eq!( column.to_string(), "data.csv#column:age" );
+        assert_eq!( Target::parse( "issue#12.txt" ), Target::file( "issue#12.txt" ) );
+        assert_eq!( Target::parse( "notes.txt#L10-20" ), Target::lines( "notes.txt", 10, 20 ) );
+        assert_eq!( Target::parse( "notes.txt#L7" ).to_string(), "notes.txt#L7" );
+        assert!( !Target::parse( "notes.txt#L20-10" ).is_sub_file() );
+        assert!( Target::parse( "https://example.org/page#L1" ).is_remote() );
+        assert!( !Target::parse( "https://example.org/page#L1" ).is_sub_file() );
+        assert_eq!( Target::parse( "data.bin#B0-511" ), Target::bytes( "data.bin", 0, 511 ) );
+        assert_eq!( Target::bytes( "data.bin", 8, 8 ).to_string(), "data.bin#B8" );
+        assert!( !Target::parse( "notes.txt#L0-3" ).is_sub_file() );
+        assert!( Fragment::Lines( 10, 20 ).overlaps( &Fragment::Lines( 20, 30 ) ) );
+        assert!( !Fragment::Lines( 10, 20 ).overlaps( &Fragment::Lines( 21, 30 ) ) );
+        assert!( !Fragment::Lines( 10, 20 ).overlaps( &Fragment::Bytes( 10, 20 ) ) );
+        assert!( Fragment::Column( "age".to_string() ).overlaps( &Fragment::Column( "age".to_string() ) ) );
+        assert_eq!( Target::malformed_fragment( "data.csv#column:" ), Some( "#column:" ) );
+        assert_eq!( Target::malformed_fragment( "data.csv#column:age" ), None );
+        assert_eq!( Target::malformed_fragment( "issue#12.txt" ), None );
+        assert_eq!( Target::malformed_fragment( "notes#Later" ), None );
+        //a malformed part is not stored as part of a filename
+        let mut anno = super::Annovate::from_json( "{}", ::std::path::Path::new( ".annovate" ) ).unwrap();
+        let annotation = super::Annotation::new( "unit".to_string(), "years".to_string(), "c".to_string() );
+        match anno.add_file_annotation( "data.csv#column:", annotation.clone() ) {
+            Err( super::AnnoError::InvalidInput { .. } ) => {},
+            other => panic!( "data.csv#column: was accepted: {:?}", other )
+        }
+        assert!( anno.add_file_annotation( "data.csv#column:age", annotation.clone() ).is_ok() );
+
+        //a `#` of the filename that would start a part is doubled
+        for &( file, text ) in &[ ( "notes#L5", "notes##L5" ), ( "a#column:x", "a##column:x" ), ( "a##b", "a###b" ), ( "notes#L20-10", "notes##L20-10" ),
+                                 ( "issue#12.txt", "issue#12.txt" ), ( "a#", "a#" ) ] {
+            assert_eq!( Target::file( file ).to_string(), text );
+            assert_eq!( Target::parse( text ), Target::file( file ) );
+        }
+        assert_eq!( Target::lines( "a#", 1, 1 ).to_string(), "a###L1" );
+        assert_eq!( Target::parse( "a###L1" ), Target::lines( "a#", 1, 1 ) );
+        assert_eq!( Target::parse( "notes##L5#L1" ), Target::lines( "notes#L5", 1, 1 ) );
+        assert_eq!( Target::lines( "a#column:", 1, 1 ).to_string(), "a##column:#L1" );
+        assert_eq!( Target::parse( "https://example.org/page#L1" ).to_string(), "https://example.org/page#L1" );
+        assert_eq!( Target::malformed_fragment( "notes##L20-10" ), None );
+        assert!( anno.add_file_annotation( "notes##L20-10", annotation ).is_ok() );
+    }
+
+    #[test]
+    fn report_summary_without_companions() {
+        use std::env;
+        use std::fs::{self, File};
+        use std::path::Path;
+        use super::{Annovate, Annotation};
+        use journal::journal_path;
+        use lock::lock_path;
+        use report::{self, ReportOptions, ReportSummary};
+        let dir = env::temp_dir().join( format!( "annovate-test-summary-{}", ::std::process::id() ) );
+        fs::create_dir_all( &dir ).unwrap();
+        File::create( dir.join( "a" ) ).unwrap();
+        File::create( dir.join( "b" ) ).unwrap();
+        let meta_file = dir.join( ".annovate" );
+        let mut anno = Annovate::new( &meta_file ).unwrap();
+        anno.add_file_annotation( "a", Annotation::new( "k".to_string(), "v".to_string(), "c".to_string() ) ).unwrap();
+        anno.save().unwrap();
+        anno.add_file_annotation( "a", Annotation::new( "k".to_string(), "w".to_string(), "c".to_string() ) ).unwrap();
+        anno.save().unwrap();
+        //the lock is held until `anno` is dropped
+        assert!( lock_path( &meta_file ).exists() && journal_path( &meta_file ).exists() );
+        let result = report::report_directory( &anno, &dir, Path::new( "" ), ReportOptions::default() ).unwrap();
+        assert_eq!( result.summary(), ReportSummary { annotated: 1, changed: 0, missing: 0, unannotated: 1 } );
+        assert_eq!( anno.statistics().unwrap().unannotated_files, vec![ "b" ] );
+        drop( anno );
+        fs::remove_dir_all( &dir ).unwrap();
+    }
+
+    #[test]
+    fn meta_companions() {
+        use super::is_meta_companion;
+        for name in &[ ".annovate", ".annovate.lock", ".annovate.log", ".annovate.bak.1", ".annovate.bak.12", ".annovate.tmp4711", ".annovateignore" ] {
+            assert!( is_meta_companion( ".annovate", name ), "{}", name );
+        }
+        for name in &[ ".annovate.bak.", ".annovate.bak.x", ".annovate.tmp", ".annovate.old", "data.lock", "meta.toml.log" ] {
+            assert!( !is_meta_companion( ".annovate", name ), "{}", name );
+        }
+        assert!( is_meta_companion( "meta.toml", "meta.toml.log" ) );
+    }
+
+    #[test]
+    fn malformed_fragments() {
+        use super::Target;
+        //reversed ranges, line 0 and fragments without a range or column
+        for &( target, fragment ) in &[ ( "notes.txt#L20-10", "#L20-10" ), ( "data.bin#B9-1", "#B9-1" ), ( "notes.txt#L0", "#L0" ),
+                                       ( "notes.txt#L", "#L" ), ( "data.bin#B", "#B" ), ( "notes.txt#L-3", "#L-3" ) ] {
+            assert_eq!( Target::malformed_fragment( target ), Some( fragment ) );
+            assert!( super::validate::check_filename( target ).is_err() );
+        }
+        for target in &[ "notes.txt#L10-20", "notes.txt#L7", "data.bin#B0", "#notes.txt#", "https://example.org/a#L9-1" ] {
+            assert_eq!( Target::malformed_fragment( target ), None );
+        }
+    }
+
+    #[test]
+    fn doctor_repairs_structure() {
+        use doctor::{examine, ProblemKind};
+        let clean = ">k\n=v\n<c\n@a\n>k\n=v\n<c\n#L1\n>k\n<c\n";
+        let checkup = examine( clean.as_bytes() );
+        assert!( checkup.problems.is_empty() );
+        assert_eq!( checkup.repaired, clean );
+
+        let broken = b"@a\n>k\n=v\n<c\n\n@b\n>x\n=\xff\n<c\n@a\n>k2\n=v2\nmore\n";
+        let checkup = examine( broken );
+        let kinds: Vec<ProblemKind> = checkup.problems.iter().map( |p| p.kind.clone() ).collect();
+        assert_eq!( kinds, vec![ ProblemKind::BlankLine,
+                                 ProblemKind::InvalidEncoding,
+                                 ProblemKind::DuplicateSection( "a".to_string(), 1 ),
+                                 ProblemKind::TruncatedEntry,
+                                 ProblemKind::MissingValueLeader ] );
+        assert_eq!( checkup.repaired, "@a\n>k\n=v\n<c\n>k2\n=v2\n=more\n<repaired by anno doctor\n@b\n>x\n=\u{fffd}\n<c\n" );
+    }
+
+    #[test]
+    fn template_definition_roundtrip() {
+        use template::Template;
+        let template = Template::parse( "dataset", "description\nlicense=CC-BY\nsource={file}.src\n" );
+        assert_eq!( template.blanks(), vec![ "description" ] );
+        assert_eq!( template.definition(), "description\nlicense=CC-BY\nsource={file}.src" );
+        assert_eq!( Template::parse( "dataset", &template.definition() ), template );
+    }
+
+    #[test]
+    fn save_keeps_unchanged_sections() {
+        use std::env;
+        use std::fs::{self, File};
+        use std::io::{Read, Write};
+        use super::{Annovate, Annotation};
+
+        let path = env::temp_dir().join( format!( "annovate-test-save-{}", ::std::process::id() ) );
+        let original = ">k\n=v   \n<c\n@b\n>x\n=1\n<c\n@a\n>y\n=2\n<c\n";
+        File::create( &path ).unwrap().write_all( original.as_bytes() ).unwrap();
+
+        let mut anno = Annovate::new( &path ).unwrap();
+        anno.add_file_annotation( "a", Annotation::new( "z".to_string(), "3".to_string(), "c".to_string() ) ).unwrap();
+        anno.save().unwrap();
+
+        let mut saved = String::new();
+        File::open( &path ).unwrap().read_to_string( &mut saved ).unwrap();
+        fs::remove_file( &path ).unwrap();
+        fs::remove_file( ::journal::journal_path( &path ) ).unwrap();
+        assert_eq!( saved, ">k\n=v   \n<c\n@b\n>x\n=1\n<c\n@a\n>y\n=2\n<c\n>z\n=3\n<c\n" );
+    }
+
+    #[test]
+    fn json_roundtrip() {
+        use std::path::Path;
+        use super::{Annovate, Annotation};
+        let json = r#"{"directory": [{"key": "k", "value": "v", "context": "c"}],
+                       "files": {"a.txt": [{"key": "multi", "value": "l1\nl2"}]}}"#;
+        let anno = Annovate::from_json( json, Path::new( "unused" ) ).unwrap();
+        assert_eq!( anno.get_directory_annotations(), &vec![ Annotation::new( "k".to_string(), "v".to_string(), "c".to_string() ) ] );
+        assert_eq!( anno.get_file_annotations( "a.txt" ).unwrap()[ 0 ].value, "l1\nl2" );
+
+        let again = Annovate::from_json( &anno.to_json(), Path::new( "unused" ) ).unwrap();
+        assert_eq!( again.to_json(), anno.to_json() );
+        assert!( Annovate::from_json( r#"{"files": {"a": [{"key": 1}]}}"#, Path::new( "unused" ) ).is_err() );
+    }
+
+    #[test]
+    fn section_order() {
+        use std::path::Path;
+        use super::{Annovate, Annotation, SaveOptions, SectionOrder};
+        let mut anno = Annovate::from_json( "{}", Path::new( "unused" ) ).unwrap();
+        for name in &[ "c", "a#L1", "b", "a" ] {
+            anno.add_file_annotation( name, Annotation::new( "k".to_string(), "v".to_string(), String::new() ) ).unwrap();
+        }
+        assert_eq!( anno.get_files(), vec![ "c", "a#L1", "b", "a" ] );
+        assert_eq!( anno.render(), "@c\n>k\n=v\n<\n@a\n>k\n=v\n<\n#L1\n>k\n=v\n<\n@b\n>k\n=v\n<\n" );
+
+        anno.set_save_options( SaveOptions { order: SectionOrder::Alphabetical, preserve_layout: false, journal: false, ..SaveOptions::default() } );
+        assert_eq!( anno.get_files(), vec![ "a", "a#L1", "b", "c" ] );
+    }
+
+    #[test]
+    fn search_annotations() {
+        use std::path::Path;
+        use super::Annovate;
+        use search::{SearchQuery, Field};
+        let json = r#"{"directory": [{"key": "owner", "value": "Alice", "context": ""}],
+                       "files": {"a.txt": [{"key": "description", "value": "notes by alice", "context": "import"}]}}"#;
+        let anno = Annovate::from_json( json, Path::new( "unused" ) ).unwrap();
+
+        let hits = anno.search( &SearchQuery::substring( "alice", false ) );
+        assert_eq!( hits.len(), 1 );
+        assert_eq!( hits[ 0 ].file, Some( "a.txt".to_string() ) );
+        assert_eq!( hits[ 0 ].fields, vec![ Field::Value ] );
+        assert_eq!( anno.search( &SearchQuery::substring( "ALICE", true ) ).len(), 2 );
+        assert_eq!( anno.search( &SearchQuery::regex( "^(owner|import)$", false ).unwrap() ).len(), 2 );
+        assert!( SearchQuery::regex( "(", false ).is_err() );
+    }
+
+    #[test]
+    fn timestamps() {
+        use super::{Annotation, Timestamp, parse_annotation_block, format_annotation_block};
+        use timestamp::{sort_by_created, created_between};
+        let ts = Timestamp::parse( "2016-03-01T12:30:00Z" ).unwrap();
+        assert_eq!( ts.seconds(), 1456835400 );
+        assert_eq!( ts.to_string(), "2016-03-01T12:30:00Z" );
+        assert!( Timestamp::parse( "1.3.2016 12:30" ).is_none() );
+
+        let text = ">a\n=1\n<c\n!2016-03-01T12:30:00Z\n>b\n=2\n<c\n";
+        let mut annotations = parse_annotation_block( text ).unwrap();
+        assert_eq!( annotations[ 0 ].created, Some( ts ) );
+        assert_eq!( annotations[ 1 ].created, None );
+        assert_eq!( format_annotation_block( &annotations ), text );
+        assert!( parse_annotation_block( ">a\n=1\n!2016-03-01T12:30:00Z\n<c\n" ).is_err() );
+
+        annotations.push( Annotation::new( "c".to_string(), "3".to_string(), String::new() ).with_created( Timestamp::from_seconds( 0 ) ) );
+        sort_by_created( &mut annotations );
+        let keys: Vec<&str> = annotations.iter().map( |a| a.key.as_str() ).collect();
+        assert_eq!( keys, vec![ "b", "c", "a" ] );
+        assert_eq!( created_between( &annotations, Some( Timestamp::from_seconds( 1 ) ), None ).len(), 1 );
+        assert_eq!( created_between( &annotations, None, Some( ts ) ).len(), 1 );
+    }
+
+    #[test]
+    fn lock_file() {
+        use std::env;
+        use std::time::Duration;
+        use super::{Annovate, AnnoError};
+        use lock::{FileLock, is_stale, lock_path};
+
+        let path = env::temp_dir().join( format!( "annovate-test-lock-{}", ::std::process::id() ) );
+        {
+            let anno = Annovate::new( &path ).unwrap();
+            assert!( lock_path( &path ).exists() );
+            match FileLock::acquire( &path, Duration::from_millis( 0 ) ) {
+                Err( AnnoError::Locked( p ) ) => assert_eq!( p, lock_path( &path ) ),
+                _ => panic!( "lock was acquired twice" )
+            }
+            anno.save().unwrap(); //holding the lock does not block saving
+        }
+        assert!( !lock_path( &path ).exists() );
+        let lock = FileLock::acquire( &path, Duration::from_millis( 0 ) ).unwrap();
+        assert!( Annovate::with_lock_timeout( &path, Duration::from_millis( 100 ) ).is_err() );
+        assert!( !is_stale( lock.path() ) ); //this process is running
+        drop( lock );
+
+        //the lock of a process that no longer runs is broken
+        ::std::fs::write( lock_path( &path ), "4000000000\n" ).unwrap();
+        if cfg!( unix ) {
+            assert!( is_stale( &lock_path( &path ) ) );
+            drop( FileLock::acquire( &path, Duration::from_millis( 0 ) ).unwrap() );
+        }
+        //a lock file without a process id is still being written
+        ::std::fs::write( lock_path( &path ), "" ).unwrap();
+        assert!( !is_stale( &lock_path( &path ) ) );
+        ::std::fs::remove_file( lock_path( &path ) ).ok();
+        ::std::fs::remove_file( &path ).unwrap();
+    }
+
+    #[test]
+    fn value_roundtrip() {
+        use super::{Annotation, parse_annotation_block, format_annotation_block};
+        let values = [ ">x", "<y", "=z", "@w", "#L1", "!t", "", "\nleading", "trailing\n", "\n\n", "  indented",
+                       "trailing space  ", "tab\t", "a\r\nb", "cr\r", "back\\slash ", "\\s", "a\n\nb", "nbsp\u{a0}" ];
+        let annotations: Vec<Annotation> = values.iter().map( |v| Annotation::new( "k".to_string(), v.to_string(), "c".to_string() ) ).collect();
+        let text = format_annotation_block( &annotations );
+        assert_eq!( parse_annotation_block( &text ).unwrap(), annotations );
+        assert!( text.contains( ">k\n\\back\\\\slash\\s\n" ) );
+        assert!( parse_annotation_block( ">k\n\\bad\\x\n<c\n" ).is_err() );
+    }
+
+    #[test]
+    fn set_file_annotations() {
+        use std::path::Path;
+        use super::{Annovate, parse_annotation_block};
+        let mut anno = Annovate::from_json( "{}", Path::new( "unused" ) ).unwrap();
+        anno.set_file_annotations( "a", parse_annotation_block( ">k\n=v\n<c\n" ).unwrap() ).unwrap();
+        assert_eq!( anno.get_files(), vec![ "a".to_string() ] );
+        assert_eq!( anno.get_file_annotations( "a" ).unwrap()[ 0 ].value, "v" );
+        anno.set_file_annotations( "a", vec![] ).unwrap();
+        assert!( anno.get_files().is_empty() );
+    }
+
+    #[test]
+    fn tags() {
+        use std::collections::HashMap;
+        use super::{Annovate, TagSet, parse_annotations};
+        let mut anno = Annovate::from_json( "{}", ::std::path::Path::new( "unused" ) ).unwrap();
+        assert!( anno.add_tag( "a", "urgent" ).unwrap() );
+        assert!( !anno.add_tag( "a", "urgent" ).unwrap() );
+        assert!( !anno.add_tag( "a", "two words" ).unwrap() );
+        anno.add_tag( "b", "draft" ).unwrap();
+        anno.add_directory_tag( "project" ).unwrap();
+        assert_eq!( anno.files_with_tag( "urgent" ), vec![ "a".to_string() ] );
+        let text = anno.render();
+        assert_eq!( text, "+project\n@a\n+urgent\n@b\n+draft\n" );
+
+        let ( mut dir, mut files, mut order, mut dir_tags, mut tags ) = ( vec![], HashMap::new(), vec![], TagSet::new(), HashMap::new() );
+        parse_annotations( text.as_bytes(), &mut dir, &mut files, &mut order, &mut dir_tags, &mut tags, true ).unwrap();
+        assert!( dir_tags.contains( "project" ) );
+        assert_eq!( tags[ "b" ].iter().collect::<Vec<_>>(), vec![ "draft" ] );
+
+        assert!( anno.remove_tag( "b", "draft" ).unwrap() );
+        assert_eq!( anno.get_files(), vec![ "a".to_string() ] ); //b had nothing else
+    }
+
+    #[test]
+    fn undo_saves() {
+        use std::env;
+        use std::fs::{self, File};
+        use std::io::Write;
+        use super::{Annovate, Annotation, SaveOptions};
+        use journal::{journal_path, read_journal};
+
+        let path = env::temp_dir().join( format!( "annovate-test-undo-{}", ::std::process::id() ) );
+        File::create( &path ).unwrap().write_all( b">k\n=1\n<c\n" ).unwrap();
+        for value in &[ "2", "3" ] {
+            let mut anno = Annovate::new( &path ).unwrap();
+            anno.add_directory_annotation( Annotation::new( "k".to_string(), value.to_string(), "c".to_string() ) ).unwrap();
+            anno.save().unwrap();
+        }
+        assert_eq!( read_journal( &journal_path( &path ) ).unwrap().len(), 2 );
+
+        let mut anno = Annovate::new( &path ).unwrap();
+        assert_eq!( anno.undo( 1 ).unwrap(), 1 );
+        assert_eq!( anno.get_directory_annotations().len(), 2 );
+        assert_eq!( anno.undo( 5 ).unwrap(), 1 ); //only one more step is in the journal
+        assert_eq!( anno.get_directory_annotations().len(), 1 );
+        anno.save().unwrap();
+        assert!( read_journal( &journal_path( &path ) ).unwrap().is_empty() );
+        drop( anno );
+
+        //only the most recent saves are kept
+        for value in &[ "4", "5", "6" ] {
+            let mut anno = Annovate::new( &path ).unwrap();
+            anno.set_save_options( SaveOptions { journal_entries: 2, ..SaveOptions::default() } );
+            anno.add_directory_annotation( Annotation::new( "k".to_string(), value.to_string(), "c".to_string() ) ).unwrap();
+            anno.save().unwrap();
+        }
+        let entries = read_journal( &journal_path( &path ) ).unwrap();
+        assert_eq!( entries.len(), 2 );
+        assert!( entries[ 1 ].content.contains( "=5" ) && !entries[ 0 ].content.contains( "=5" ) );
+        fs::remove_file( &path ).unwrap();
+        fs::remove_file( journal_path( &path ) ).unwrap();
+    }
+
+    #[test]
+    fn merge_strategies() {
+        use std::path::Path;
+        use super::Annovate;
+        use merge::MergeStrategy;
+        let ours_json = r#"{"files": {"a": [{"key": "k", "value": "old", "context": "annovate program, 1.3.2016 12:00:00"}]}}"#;
+        let theirs_json = r#"{"files": {"a": [{"key": "k", "value": "new", "context": "annovate program, 2.3.2016 9:00:00"}],
+                                        "b": [{"key": "k", "value": "b", "context": ""}]}}"#;
+        let theirs = Annovate::from_json( theirs_json, Path::new( "unused" ) ).unwrap();
+        let latest = |anno: &Annovate| anno.get_file_annotations( "a" ).unwrap().last().unwrap().value.clone();
+
+        for &( strategy, expected ) in &[ ( MergeStrategy::Ours, "old" ), ( MergeStrategy::Theirs, "new" ),
+                                          ( MergeStrategy::Union, "new" ), ( MergeStrategy::NewestByContext, "new" ) ] {
+            let mut ours = Annovate::from_json( ours_json, Path::new( "unused" ) ).unwrap();
+            let report = ours.merge( &theirs, strategy ).unwrap();
+            assert_eq!( latest( &ours ), expected );
+            assert_eq!( report.conflicts.len(), 1 );
+            assert!( ours.get_file_annotations( "b" ).is_some() );
+        }
+
+        let mut newer = Annovate::from_json( &ours_json.replace( "1.3.2016", "3.3.2016" ), Path::new( "unused" ) ).unwrap();
+        assert!( !newer.merge( &theirs, MergeStrategy::NewestByContext ).unwrap().conflicts[ 0 ].took_theirs );
+        assert_eq!( latest( &newer ), "old" );
+    }
+
+    #[test]
+    fn iterate_annotations() {
+        use std::path::Path;
+        use super::Annovate;
+        let json = r#"{"directory": [{"key": "d", "value": "1", "context": ""}],
+                       "files": {"b": [{"key": "x", "value": "2", "context": ""}, {"key": "y", "value": "3", "context": ""}],
+                                 "a": [], "c": [{"key": "z", "value": "4", "context": ""}]}}"#;
+        let anno = Annovate::from_json( json, Path::new( "unused" ) ).unwrap();
+        assert_eq!( anno.iter_files().collect::<Vec<_>>(), vec![ "a", "b", "c" ] );
+        let all: Vec<( Option<&str>, &str )> = anno.iter_all_annotations().map( |( f, a )| ( f, a.key.as_str() ) ).collect();
+        assert_eq!( all, vec![ ( None, "d" ), ( Some( "b" ), "x" ), ( Some( "b" ), "y" ), ( Some( "c" ), "z" ) ] );
+        assert_eq!( ( &anno ).into_iter().count(), 4 );
+    }
+
+    #[test]
+    fn copy_annotations() {
+        use std::path::Path;
+        use super::Annovate;
+        let json = r#"{"files": {"a": [{"key": "x", "value": "1", "context": ""}, {"key": "y", "value": "2", "context": ""},
+                                       {"key": "x", "value": "3", "context": ""}]}}"#;
+        let mut anno = Annovate::from_json( json, Path::new( "unused" ) ).unwrap();
+        assert_eq!( anno.copy_annotations( "a", "b", None, "test" ).unwrap(), 2 );
+        let copied: Vec<( &str, &str )> = anno.get_file_annotations( "b" ).unwrap().iter().map( |a| ( a.key.as_str(), a.value.as_str() ) ).collect();
+        assert_eq!( copied, vec![ ( "y", "2" ), ( "x", "3" ) ] );
+        assert_eq!( anno.get_file_annotations( "b" ).unwrap()[ 0 ].context, "test, copied from a" );
+        assert_eq!( anno.copy_annotations( "a", "c", Some( &[ "y" ] ), "" ).unwrap(), 1 );
+        assert_eq!( anno.copy_annotations( "missing", "c", None, "" ).unwrap(), 0 );
+    }
+
+    #[test]
+    fn watch_new_files() {
+        use std::env;
+        use std::fs::{self, File};
+        use std::time::Duration;
+        use watch::{PollingWatcher, Watcher};
+
+        let dir = env::temp_dir().join( format!( "annovate-test-watch-{}", ::std::process::id() ) );
+        fs::create_dir( &dir ).unwrap();
+        File::create( dir.join( "old" ) ).unwrap();
+        let mut watcher = PollingWatcher::new( &dir, Duration::from_millis( 10 ), false ).unwrap();
+        File::create( dir.join( "new" ) ).unwrap();
+        File::create( dir.join( ".hidden" ) ).unwrap();
+        assert_eq!( watcher.next_new_files().unwrap(), vec![ "new".to_string() ] );
+        fs::remove_dir_all( &dir ).unwrap();
+    }
+
+    #[test]
+    fn validate_urls() {
+        use super::is_valid_url;
+        assert!( is_valid_url( "https://example.org/data?x=1" ) );
+        assert!( is_valid_url( "s3://bucket/key" ) );
+        assert!( !is_valid_url( "https:///nohost" ) );
+        assert!( !is_valid_url( "1http://example.org" ) );
+        assert!( !is_valid_url( "http://exa mple.org" ) );
+    }
+
+    #[test]
+    fn validate_schema() {
+        use std::path::Path;
+        use super::{Annovate, Annotation};
+        use super::schema::{self, Schema, ViolationKind};
+        let mut anno = Annovate::from_json( "{}", Path::new( "unused" ) ).unwrap();
+        anno.add_file_annotation( "a.csv", Annotation::new( "owner".to_string(), "jb".to_string(), "test".to_string() ) ).unwrap();
+        anno.add_file_annotation( "b.csv", Annotation::new( "owner".to_string(), "J B".to_string(), "test".to_string() ) ).unwrap();
+        anno.add_file_annotation( "b.csv", Annotation::new( "description".to_string(), "data".to_string(), "test".to_string() ) ).unwrap();
+        anno.add_file_annotation( "b.csv#column:age", Annotation::new( "unit".to_string(), "years".to_string(), "test".to_string() ) ).unwrap();
+        assert!( Schema::parse( "owner=[" ).is_err() );
+        schema::define_schema( &mut anno, &Schema::parse( "description\nowner=^[a-z]+$" ).unwrap(), "test" ).unwrap();
+        let schema = schema::find_schema( &anno ).unwrap().unwrap();
+        assert_eq!( schema.definition(), "description\nowner=^[a-z]+$" );
+        let violations = anno.validate( &schema );
+        assert_eq!( violations.len(), 2 );
+        assert_eq!( ( violations[ 0 ].file.as_str(), violations[ 0 ].key.as_str(), &violations[ 0 ].kind ), ( "a.csv", "description", &ViolationKind::Missing ) );
+        assert_eq!( ( violations[ 1 ].file.as_str(), violations[ 1 ].key.as_str() ), ( "b.csv", "owner" ) );
+        assert_eq!( violations[ 1 ].kind, ViolationKind::Mismatch( "J B".to_string() ) );
+    }
+
+    #[test]
+    fn error_details() {
+        use std::env;
+        use std::error::Error;
+        use std::fs::{self, File};
+        use std::io::Write;
+        use super::{Annovate, AnnoError};
+
+        let path = env::temp_dir().join( format!( "annovate-test-error-{}", ::std::process::id() ) );
+        File::create( &path ).unwrap().write_all( b">k\n=1\n>l\n" ).unwrap();
+        match Annovate::new( &path ) {
+            Err( AnnoError::ParseError { path: Some( ref p ), line: 3, found: '>', expected: Some( ref leaders ) } ) => {
+                assert_eq!( p, &path );
+                assert_eq!( leaders, "=\\<" );
+            },
+            other => panic!( "unexpected result {:?}", other.err() )
+        }
+        let err = Annovate::new( &path ).err().unwrap();
+        assert_eq!( ( err.kind(), err.path(), err.line() ), ( "parse", Some( path.as_path() ), Some( 3 ) ) );
+        fs::remove_file( &path ).unwrap();
+
+        let missing = AnnoError::io( &path, ::std::io::Error::new( ::std::io::ErrorKind::NotFound, "gone" ) );
+        assert!( missing.to_string().contains( &path.display().to_string() ) );
+        assert!( missing.source().is_some() );
+        assert_eq!( ( missing.kind(), missing.path(), missing.line() ), ( "io", Some( path.as_path() ), None ) );
+        let protected = AnnoError::Protected( "owner".to_string() );
+        assert_eq!( ( protected.kind(), protected.path(), protected.line() ), ( "protected", None, None ) );
+    }
+
+    #[test]
+    fn file_patterns() {
+        use std::env;
+        use std::fs::{self, File};
+        use super::{Annovate, Annotation, Pattern};
+
+        let pattern = Pattern::new( "*.csv" ).unwrap();
+        assert!( pattern.matches( "data.csv" ) && !pattern.matches( ".hidden.csv" ) );
+        assert!( !pattern.matches( "sub/data.csv" ) && !pattern.matches( "data.csv#column:age" ) );
+        assert!( Pattern::new( "data[0-9]?.[!t]sv" ).unwrap().matches( "data1a.csv" ) );
+        assert!( !Pattern::new( "data[0-9]?.[!t]sv" ).unwrap().matches( "data1a.tsv" ) );
+        assert!( Pattern::new( "\\*" ).unwrap().matches( "*" ) );
+        assert!( Pattern::new( "data[0-9" ).is_err() );
+
+        let dir = env::temp_dir().join( format!( "annovate-test-pattern-{}", ::std::process::id() ) );
+        fs::create_dir_all( dir.join( "sub" ) ).unwrap();
+        File::create( dir.join( "a.csv" ) ).unwrap();
+        File::create( dir.join( "sub/c.csv" ) ).unwrap();
+        {
+            let mut anno = Annovate::new( &dir.join( ".annovate" ) ).unwrap();
+            anno.add_file_annotation( "b.csv", Annotation::new( "k".to_string(), "v".to_string(), "test".to_string() ) ).unwrap();
+            anno.add_file_annotation( "b.txt", Annotation::new( "k".to_string(), "v".to_string(), "test".to_string() ) ).unwrap();
+            assert_eq!( anno.files_matching( &pattern ), vec![ "a.csv", "b.csv" ] );
+            assert_eq!( anno.files_matching( &Pattern::new( "sub/*" ).unwrap() ), vec![ "sub/c.csv" ] );
+        }
+        fs::remove_dir_all( &dir ).unwrap();
+    }
+
+    #[test]
+    fn harvest_markers() {
+        use std::env;
+        use std::fs::{self, File};
+        use std::io::Write;
+        use std::path::Path;
+        use super::Annovate;
+        use harvest::{CommentSyntax, Harvester};
+
+        let harvester = Harvester::default();
+        let text = "fn main() {} // anno: author = jb\n# anno: license=MIT\n<!-- anno: status = draft -->\n/* anno: x = 1 */ // not a marker\n// anno:= no key\n";
+        assert_eq!( harvester.harvest_text( text ), vec![ ( "author".to_string(), "jb".to_string() ), ( "license".to_string(), "MIT".to_string() ),
+                                                          ( "status".to_string(), "draft".to_string() ), ( "x".to_string(), "1".to_string() ) ] );
+        assert!( Harvester::new( vec![ CommentSyntax::line( "!" ) ] ).harvest_text( text ).is_empty() );
+
+        let dir = env::temp_dir().join( format!( "annovate-test-harvest-{}", ::std::process::id() ) );
+        fs::create_dir_all( &dir ).unwrap();
+        File::create( dir.join( "script.sh" ) ).unwrap().write_all( b"# anno: description = backup script\n" ).unwrap();
+        let mut anno = Annovate::from_json( "{}", Path::new( "unused" ) ).unwrap();
+        assert_eq!( anno.harvest( &dir, "script.sh", &harvester, "test" ).unwrap(), 1 );
+        assert_eq!( anno.harvest( &dir, "script.sh", &harvester, "test" ).unwrap(), 0 ); //unchanged values are not added again
+        assert_eq!( anno.get_file_annotations( "script.sh" ).unwrap()[ 0 ].context, "test, harvested from script.sh" );
+        fs::remove_dir_all( &dir ).unwrap();
+    }
+
+    #[cfg(feature = "xattr")]
+    #[test]
+    fn xattr_sync() {
+        use std::env;
+        use std::fs::{self, File};
+        use std::path::Path;
+        use super::{Annovate, Annotation};
+        use xattr;
+
+        let dir = env::temp_dir().join( format!( "annovate-test-xattr-{}", ::std::process::id() ) );
+        fs::create_dir_all( &dir ).unwrap();
+        File::create( dir.join( "a" ) ).unwrap();
+        let mut anno = Annovate::from_json( "{}", Path::new( "unused" ) ).unwrap();
+        anno.add_file_annotation( "a", Annotation::new( "k".to_string(), "old".to_string(), "test".to_string() ) ).unwrap();
+        anno.add_file_annotation( "a", Annotation::new( "k".to_string(), "new".to_string(), "test".to_string() ) ).unwrap();
+        anno.add_file_annotation( "gone", Annotation::new( "k".to_string(), "v".to_string(), "test".to_string() ) ).unwrap();
+        if xattr::set_attribute( &dir.join( "a" ), "user.annovate.stale", "x" ).is_err() {
+            fs::remove_dir_all( &dir ).unwrap();
+            return; //the file system of the temporary directory has no user attributes
+        }
+        assert_eq!( anno.push_xattrs( &dir ).unwrap(), 1 );
+        assert_eq!( xattr::read_annotations( &dir.join( "a" ) ).unwrap(), vec![ ( "k".to_string(), "new".to_string() ) ] );
+        xattr::set_attribute( &dir.join( "a" ), "user.annovate.k", "newer" ).unwrap();
+        assert_eq!( anno.pull_xattrs( &dir, "test" ).unwrap(), 1 );
+        assert_eq!( anno.get_file_annotations( "a" ).unwrap()[ 2 ].value, "newer" );
+        fs::remove_dir_all( &dir ).unwrap();
+    }
+
+    #[test]
+    fn change_sets() {
+        use std::path::Path;
+        use super::{Annovate, Annotation};
+        use changes::{Change, ChangeSet};
+
+        let json = r#"{"files": {"a": [{"key": "k", "value": "1", "context": ""}, {"key": "k", "value": "1", "context": ""}],
+                                 "b": [{"key": "x", "value": "2", "context": ""}]}}"#;
+        let mut anno = Annovate::from_json( json, Path::new( "unused" ) ).unwrap();
+        let before = anno.snapshot();
+        anno.remove_file_annotation_entries( "b", "x" ).unwrap();
+        anno.add_file_annotation( "a", Annotation::new( "k".to_string(), "1".to_string(), "".to_string() ) ).unwrap();
+        anno.add_tag( "a", "raw" ).unwrap();
+        anno.add_directory_annotation( Annotation::new( "d".to_string(), "3".to_string(), "".to_string() ) ).unwrap();
+        let changes = ChangeSet::between( &before, &anno );
+        assert_eq!( changes.len(), 4 );
+        let targets: Vec<Option<&str>> = changes.targets.iter().map( |t| t.target.as_ref().map( |s| s.as_str() ) ).collect();
+        assert_eq!( targets, vec![ None, Some( "a" ), Some( "b" ) ] );
+        assert_eq!( changes.targets[ 1 ].changes[ 1 ], Change::TagAdded( "raw".to_string() ) );
+        assert_eq!( changes.targets[ 2 ].changes, vec![ Change::Removed( Annotation::new( "x".to_string(), "2".to_string(), "".to_string() ) ) ] );
+        assert!( ChangeSet::between( &anno, &anno.snapshot() ).is_empty() );
+    }
+
+    #[test]
+    fn typed_values() {
+        use super::{Annotation, Value, ValueType, parse_annotation_block, format_annotation_block};
+        use value::{split_typed_key, format_typed_key};
+
+        assert_eq!( Value::parse( " 42", ValueType::Int ).unwrap(), Value::Int( 42 ) );
+        assert!( Value::parse( "4.2", ValueType::Int ).is_err() );
+        assert_eq!( Value::parse( "Yes", ValueType::Bool ).unwrap(), Value::Bool( true ) );
+        assert_eq!( Value::parse( "2016-3-1", ValueType::Date ).unwrap().to_string(), "2016-03-01" );
+        assert_eq!( Value::parse( "a, b,,c", ValueType::List ).unwrap(), Value::List( vec![ "a".to_string(), "b".to_string(), "c".to_string() ] ) );
+        assert!( Value::Int( 9 ) < Value::Int( 10 ) && Value::Int( 10 ) < Value::Text( "9".to_string() ) );
+
+        assert_eq!( split_typed_key( "size:int" ), ( "size".to_string(), Some( ValueType::Int ) ) );
+        assert_eq!( split_typed_key( "template:raw" ), ( "template:raw".to_string(), None ) );
+        assert_eq!( format_typed_key( "template:int", None ), "template:int:text" );
+
+        let annotations = vec![ Annotation::new( "size".to_string(), "10".to_string(), "c".to_string() ).with_type( ValueType::Int ),
+                                Annotation::new( "template:date".to_string(), "x".to_string(), "c".to_string() ) ];
+        let text = format_annotation_block( &annotations );
+        assert!( text.starts_with( ">size:int\n" ) );
+        assert_eq!( parse_annotation_block( &text ).unwrap(), annotations );
+        assert_eq!( annotations[ 0 ].typed_value().unwrap(), Value::Int( 10 ) );
+    }
+
+    #[test]
+    fn resolve_paths() {
+        use std::path::{Path, PathBuf};
+        use super::Annovate;
+        let anno = Annovate::from_json( "{}", Path::new( "data/sub/.annovate" ) ).unwrap();
+        assert_eq!( anno.base_dir(), PathBuf::from( "data/sub" ) );
+        assert_eq!( anno.resolve( "table.csv#column:age" ), PathBuf::from( "data/sub/table.csv" ) );
+        assert_eq!( Annovate::from_json( "{}", Path::new( ".annovate" ) ).unwrap().base_dir(), PathBuf::from( "." ) );
+    }
+
+    #[test]
+    fn data_roundtrip() {
+        use std::path::Path;
+        use super::{Annovate, Annotation, ValueType};
+
+        let mut anno = Annovate::from_json( "{}", Path::new( "unused" ) ).unwrap();
+        anno.add_file_annotation( "b", Annotation::new( "size".to_string(), "3".to_string(), "c".to_string() ).with_type( ValueType::Int ) ).unwrap();
+        anno.add_file_annotation( "a", Annotation::new( "k".to_string(), "v".to_string(), "c".to_string() ) ).unwrap();
+        anno.add_tag( "a", "raw" ).unwrap();
+        anno.add_directory_tag( "project" ).unwrap();
+        let data = anno.to_data();
+        assert_eq!( data.files.iter().map( |f| f.name.as_str() ).collect::<Vec<_>>(), vec![ "b", "a" ] );
+        assert_eq!( data.files[ 1 ].tags, vec![ "raw" ] );
+        let restored = Annovate::from_data( data.clone(), Path::new( "unused" ) );
+        assert_eq!( restored.to_data(), data );
+    }
+
+    #[test]
+    fn get_values() {
+        use std::path::Path;
+        use super::Annovate;
+        let json = r#"{"files": {"a": [{"key": "k", "value": "1", "context": ""}, {"key": "k", "value": "2", "context": ""}],
+                                 "b": [{"key": "x", "value": "3", "context": ""}],
+                                 "c": [{"key": "k", "value": "4", "context": ""}]}}"#;
+        let anno = Annovate::from_json( json, Path::new( "unused" ) ).unwrap();
+        assert_eq!( anno.get_values( "k" ), vec![ ( "a".to_string(), "2" ), ( "c".to_string(), "4" ) ] );
+        assert!( anno.get_values( "missing" ).is_empty() );
+    }
+
+    #[test]
+    fn context_builder() {
+        use std::path::Path;
+        use context::{ContextBuilder, ContextProvider};
+        struct Fixed( Option<&'static str> );
+        impl ContextProvider for Fixed {
+            fn describe( &self ) -> Option<String> {
+                self.0.map( |s| s.to_string() )
+            }
+        }
+        let builder = ContextBuilder::new( "annovate program" ).with( Box::new( Fixed( Some( "user alice" ) ) ) )
+                                                              .with( Box::new( Fixed( None ) ) )
+                                                              .with( Box::new( Fixed( Some( "git 1a2b3c4" ) ) ) );
+        assert_eq!( builder.build(), "annovate program, user alice, git 1a2b3c4" );
+        assert!( ContextBuilder::from_fields( "p", "user,host,git,time", Path::new( "." ) ).is_ok() );
+        assert!( ContextBuilder::from_fields( "p", "user,planet", Path::new( "." ) ).is_err() );
+        assert_eq!( ContextBuilder::from_fields( "p", "", Path::new( "." ) ).unwrap().build(), "p" );
+    }
+
+    #[test]
+    fn read_events() {
+        use super::{AnnovateReader, Annotation, Event, Timestamp};
+        let text = ">a\n=1\n<c\n!2016-03-01T12:30:00Z\n@f\n+t\n>b\n=2\n<c\n#L1-2\n>c\n=3\n<c\n";
+        let events: Vec<Event> = AnnovateReader::new( text.as_bytes() ).map( |e| e.unwrap() ).collect();
+        let created = Timestamp::parse( "2016-03-01T12:30:00Z" ).unwrap();
+        let anno = |k: &str, v: &str| Annotation::new( k.to_string(), v.to_string(), "c".to_string() );
+        assert_eq!( events, vec![ Event::Annotation( anno( "a", "1" ).with_created( created ) ),
+                                  Event::FileStart( "f".to_string() ),
+                                  Event::Tag( "t".to_string() ),
+                                  Event::Annotation( anno( "b", "2" ) ),
+                                  Event::FileStart( "f#L1-2".to_string() ),
+                                  Event::Annotation( anno( "c", "3" ) ) ] );
+        let mut broken = AnnovateReader::new( ">a\n=1\n".as_bytes() );
+        assert!( broken.next().unwrap().is_err() );
+        assert!( broken.next().is_none() );
+    }
+
+    #[test]
+    fn binary_values() {
+        use super::{Annotation, ValueType, parse_annotation_block, format_annotation_block};
+        let bytes: Vec<u8> = ( 0..200 ).map( |i| i as u8 ).collect();
+        let binary = Annotation::new_binary( "thumbnail".to_string(), &bytes, "c".to_string() );
+        assert_eq!( binary.value_type, Some( ValueType::Binary ) );
+        assert_eq!( binary.value_bytes(), bytes );
+        let looks_binary = Annotation::new( "note".to_string(), "b64:not encoded".to_string(), "c".to_string() );
+        let empty = Annotation::new_binary( "empty".to_string(), &[], "c".to_string() );
+        let annotations = vec![ binary, looks_binary, empty ];
+        let text = format_annotation_block( &annotations );
+        assert!( text.starts_with( ">thumbnail\n=b64:AAECAw" ) );
+        assert!( text.contains( "\\b64:not encoded\n" ) );
+        assert_eq!( parse_annotation_block( &text ).unwrap(), annotations );
+        assert_eq!( annotations[ 1 ].value_bytes(), b"b64:not encoded".to_vec() );
+        assert!( parse_annotation_block( ">k\n=b64:!!\n<c\n" ).is_err() );
+    }
+
+    #[test]
+    fn remove_single_entries() {
+        use std::path::Path;
+        use super::{Annovate, Annotation};
+        let mut anno = Annovate::from_json( "{}", Path::new( "unused" ) ).unwrap();
+        for value in &[ "a", "b", "a", "c" ] {
+            anno.add_file_annotation( "f", Annotation::new( "k".to_string(), value.to_string(), "c".to_string() ) ).unwrap();
+        }
+        assert_eq!( anno.remove_file_annotation( "f", "k", "a" ).unwrap(), 2 );
+        assert_eq!( anno.remove_file_annotation( "f", "k", "x" ).unwrap(), 0 );
+        assert!( anno.remove_nth_file_annotation( "f", "k", 1 ).unwrap() );
+        assert!( !anno.remove_nth_file_annotation( "f", "k", 1 ).unwrap() );
+        let values: Vec<&str> = anno.get_file_annotations( "f" ).unwrap().iter().map( |a| a.value.as_str() ).collect();
+        assert_eq!( values, vec![ "b" ] );
+        assert!( anno.remove_nth_file_annotation( "f", "k", 0 ).unwrap() );
+        assert!( anno.get_file_annotations( "f" ).is_none() );
+    }
+
+    #[test]
+    fn checksums() {
+        use std::env;
+        use std::fs::{self, File};
+        use std::io::Write;
+        use std::path::Path;
+        use super::{Annovate, Annotation};
+        use hash::{sha256_hex, CHECKSUM_KEY};
+
+        assert_eq!( sha256_hex( b"" ), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855" );
+        assert_eq!( sha256_hex( b"abc" ), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad" );
+        assert_eq!( sha256_hex( &[ b'a'; 1000 ] ), "41edece42d63e8d9bf515a9ba6932e1c20cbc9f5a5d134645adb5db1b9737ea3" );
+
+        let dir = env::temp_dir().join( format!( "annovate-test-checksums-{}", ::std::process::id() ) );
+        fs::create_dir_all( &dir ).unwrap();
+        File::create( dir.join( "data.csv" ) ).unwrap().write_all( b"a,b\n" ).unwrap();
+        let mut anno = Annovate::from_json( "{}", Path::new( "unused" ) ).unwrap();
+        anno.add_file_annotation( "data.csv", Annotation::new( "k".to_string(), "v".to_string(), "c".to_string() ) ).unwrap();
+        assert_eq!( anno.record_checksums( &dir, "c" ).unwrap(), 1 );
+        assert_eq!( anno.record_checksums( &dir, "c" ).unwrap(), 0 );
+        assert!( anno.changed_files( &dir ).unwrap().is_empty() );
+        File::create( dir.join( "data.csv" ) ).unwrap().write_all( b"a,b,c\n" ).unwrap();
+        assert_eq!( anno.changed_files( &dir ).unwrap(), vec![ "data.csv".to_string() ] );
+        assert_eq!( anno.record_checksums( &dir, "c" ).unwrap(), 1 );
+        assert_eq!( anno.get_file_annotations( "data.csv" ).unwrap().iter().filter( |a| a.key == CHECKSUM_KEY ).count(), 1 );
+        fs::remove_dir_all( &dir ).unwrap();
+    }
+
+    #[test]
+    fn annovate_set() {
+        use std::path::Path;
+        use super::{Annovate, Annotation};
+        use set::AnnovateSet;
+        let mut set = AnnovateSet::new();
+        for &( dir, file, value ) in &[ ( "raw", "a.csv", "1" ), ( "clean", "a.csv", "2" ) ] {
+            let mut anno = Annovate::from_json( "{}", Path::new( "unused" ) ).unwrap();
+            anno.add_file_annotation( file, Annotation::new( "k".to_string(), value.to_string(), "c".to_string() ) ).unwrap();
+            set.add( Path::new( dir ), anno );
+        }
+        assert_eq!( set.get_files(), vec![ "raw/a.csv".to_string(), "clean/a.csv".to_string() ] );
+        assert_eq!( set.get_file_annotations( "clean/a.csv" ).unwrap()[ 0 ].value, "2" );
+        assert!( set.get_file_annotations( "a.csv" ).is_none() );
+        assert_eq!( set.get_values( "k" ), vec![ ( "raw/a.csv".to_string(), "1" ), ( "clean/a.csv".to_string(), "2" ) ] );
+    }
+
+    #[test]
+    fn read_only_mode() {
+        use std::env;
+        use std::time::{Duration, Instant};
+        use super::{Annovate, Annotation, AnnoError, OpenMode};
+        use lock::{FileLock, lock_path};
+
+        let path = env::temp_dir().join( format!( "annovate-test-readonly-{}", ::std::process::id() ) );
+        assert!( Annovate::open( &path, OpenMode::ReadOnly ).is_err() ); //missing files are not created
+        {
+            let mut anno = Annovate::open( &path, OpenMode::ReadWrite ).unwrap();
+            assert!( !anno.is_modified() );
+            anno.add_file_annotation( "a", Annotation::new( "k".to_string(), "v".to_string(), "c".to_string() ) ).unwrap();
+            assert!( anno.is_modified() );
+            anno.save().unwrap();
+            assert!( !anno.is_modified() );
+        }
+        //reading does not wait for the lock of another writer
+        let writer = FileLock::acquire( &path, Duration::from_millis( 0 ) ).unwrap();
+        let start = Instant::now();
+        let mut anno = Annovate::open( &path, OpenMode::ReadOnly ).unwrap();
+        assert!( start.elapsed() < Duration::from_secs( 1 ) );
+        drop( writer );
+        assert!( !lock_path( &path ).exists() );
+        assert_eq!( anno.mode(), OpenMode::ReadOnly );
+        match anno.add_tag( "a", "raw" ) {
+            Err( AnnoError::ReadOnly ) => {},
+            _ => panic!( "changed a read-only file" )
+        }
+        assert!( !anno.is_modified() );
+        assert!( anno.save().is_err() );
+        assert_eq!( anno.get_file_annotations( "a" ).unwrap().len(), 1 );
+        ::std::fs::remove_file( &path ).unwrap();
+        ::std::fs::remove_file( ::journal::journal_path( &path ) ).unwrap();
+    }
+
+    #[test]
+    fn sort_files() {
+        use std::path::Path;
+        use super::{Annovate, Annotation, Timestamp};
+        use sort::SortOrder;
+        let mut anno = Annovate::from_json( "{}", Path::new( "unused" ) ).unwrap();
+        let entries = [ ( "a", "size", "10", 300 ), ( "b", "size", "9", 100 ), ( "b", "owner", "jb", 200 ), ( "c", "owner", "jb", 400 ) ];
+        for &( file, key, value, created ) in entries.iter() {
+            let annotation = Annotation::new( key.to_string(), value.to_string(), "c".to_string() ).with_created( Timestamp::from_seconds( created ) );
+            anno.add_file_annotation( file, annotation ).unwrap();
+        }
+        assert_eq!( anno.sorted_files( SortOrder::Name, "", true ), vec![ "c", "b", "a" ] );
+        assert_eq!( anno.sorted_files( SortOrder::Value, "size", false ), vec![ "c", "a", "b" ] ); //text without a type
+        assert_eq!( anno.sorted_files( SortOrder::Time, "", false ), vec![ "b", "a", "c" ] );
+        assert_eq!( anno.sorted_files( SortOrder::KeyCount, "", true ), vec![ "b", "c", "a" ] );
+        assert_eq!( SortOrder::from_name( "key-count" ), Some( SortOrder::KeyCount ) );
+        assert_eq!( SortOrder::from_name( "size" ), None );
+    }
+
+    #[test]
+    fn sort_annotations() {
+        use super::{Annotation, AnnoContainer, Timestamp};
+        use sort::{self, AnnotationOrder};
+        let entries = [ ( "size", "10", "b", Some( 300 ) ), ( "owner", "jb", "a", Some( 100 ) ), ( "size", "9", "a", None ), ( "notes", "x", "c", Some( 200 ) ) ];
+        let annotations: AnnoContainer = entries.iter().map( |&( key, value, context, created )| {
+            let mut annotation = Annotation::new( key.to_string(), value.to_string(), context.to_string() );
+            annotation.created = created.map( Timestamp::from_seconds );
+            annotation
+        } ).collect();
+        let sorted = |order: AnnotationOrder, descending: bool| -> Vec<String> {
+            let mut sorted = annotations.clone();
+            sort::sort_annotations( &mut sorted, order, descending );
+            sorted.into_iter().map( |a| a.value ).collect()
+        };
+        assert_eq!( sorted( AnnotationOrder::Key, false ), vec![ "x", "jb", "10", "9" ] ); //equal keys stay in order
+        assert_eq!( sorted( AnnotationOrder::Key, true ), vec![ "10", "9", "jb", "x" ] );
+        assert_eq!( sorted( AnnotationOrder::Time, false ), vec![ "9", "jb", "x", "10" ] );
+        assert_eq!( sorted( AnnotationOrder::Context, true ), vec![ "x", "10", "jb", "9" ] );
+        assert_eq!( AnnotationOrder::from_name( "context" ), Some( AnnotationOrder::Context ) );
+        assert_eq!( AnnotationOrder::from_name( "value" ), None );
+        let values = |page: AnnoContainer| -> Vec<String> { page.into_iter().map( |a| a.value ).collect() };
+        assert_eq!( values( sort::paginate( annotations.clone(), 1, Some( 2 ) ) ), vec![ "jb", "9" ] );
+        assert_eq!( values( sort::paginate( annotations.clone(), 3, None ) ), vec![ "x" ] );
+        assert!( sort::paginate( annotations.clone(), 4, Some( 1 ) ).is_empty() );
+    }
+
+    #[test]
+    fn diff() {
+        use std::path::Path;
+        use super::Annovate;
+        use diff::DiffEntry;
+        let ours = r#"{"dir": [{"key": "owner", "value": "jb", "context": ""}],
+                       "files": {"a": [{"key": "k", "value": "1", "context": ""}, {"key": "k", "value": "2", "context": ""}, {"key": "x", "value": "3", "context": ""}],
+                                 "b": [{"key": "k", "value": "4", "context": ""}]}}"#;
+        let theirs = r#"{"dir": [{"key": "owner", "value": "jb", "context": "other"}],
+                         "files": {"a": [{"key": "k", "value": "2", "context": ""}, {"key": "y", "value": "5", "context": ""}],
+                                   "c": [{"key": "k", "value": "6", "context": ""}]}}"#;
+        let ours = Annovate::from_json( ours, Path::new( "unused" ) ).unwrap();
+        let theirs = Annovate::from_json( theirs, Path::new( "unused" ) ).unwrap();
+        let report = ours.diff( &theirs );
+        let names: Vec<Option<&str>> = report.targets.iter().map( |t| t.target.as_ref().map( |s| s.as_str() ) ).collect();
+        assert_eq!( names, vec![ Some( "a" ), Some( "b" ), Some( "c" ) ] ); //history and context do not count
+        match report.targets[ 0 ].entries.as_slice() {
+            [ DiffEntry::Removed( x ), DiffEntry::Added( y ) ] => assert_eq!( ( x.key.as_str(), y.key.as_str() ), ( "x", "y" ) ),
+            other => panic!( "unexpected entries {:?}", other )
+        }
+        assert_eq!( report.counts(), ( 2, 2, 0 ) );
+        let changed = Annovate::from_json( r#"{"files": {"b": [{"key": "k", "value": "7", "context": ""}]}}"#, Path::new( "unused" ) ).unwrap();
+        match ours.diff( &changed ).targets[ 1 ].entries[ 0 ] {
+            DiffEntry::Changed( ref a, ref b ) => assert_eq!( ( a.value.as_str(), b.value.as_str() ), ( "4", "7" ) ),
+            ref other => panic!( "unexpected entry {:?}", other )
+        }
+        assert!( ours.diff( &ours.snapshot() ).is_empty() );
+    }
+
+    #[test]
+    fn toml_backend() {
+        use std::path::Path;
+        use super::{Annovate, Annotation, Backend, LineBackend, TomlBackend, Timestamp, ValueType};
+        let mut anno = Annovate::from_json( "{}", Path::new( "unused.toml" ) ).unwrap();
+        anno.add_directory_tag( "project" ).unwrap();
+        anno.add_directory_annotation( Annotation::new( "owner".to_string(), "jb".to_string(), "c".to_string() ) ).unwrap();
+        let value = Annotation::new( "note".to_string(), "say \"hi\"\n\tand\\bye\u{1}".to_string(), "".to_string() );
+        anno.add_file_annotation( "a.csv", value.with_created( Timestamp::from_seconds( 1456835400 ) ) ).unwrap();
+        anno.add_file_annotation( "a.csv#column:age", Annotation::new( "size".to_string(), "3".to_string(), "c".to_string() ).with_type( ValueType::Int ) ).unwrap();
+        anno.add_tag( "a.csv", "raw" ).unwrap();
+        assert_eq!( anno.backend().name(), "toml" );
+        let text = TomlBackend.serialize( &anno, None );
+        assert!( text.starts_with( "directory_tags = [\"project\"]\n\n[[directory]]\nkey = \"owner\"\n" ) );
+        assert!( text.contains( "created = 2016-03-01T12:30:00Z\n" ) );
+        assert_eq!( TomlBackend.parse( &text ).unwrap(), anno.to_data() );
+        assert_eq!( LineBackend.parse( &LineBackend.serialize( &anno, None ) ).unwrap(), anno.to_data() );
+
+        let written_by_hand = r#"# comment
+[[files]]
+name = 'b'  # literal string
+tags = [
+  "x",
+  'y',
+]
+
+[[files.annotations]]
+key = "k"
+value = """
+line 1
+line \
+    2"""
+context = '''c:\dir'''
+"#;
+        let data = TomlBackend.parse( written_by_hand ).unwrap();
+        assert_eq!( data.files[ 0 ].tags, vec![ "x", "y" ] );
+        assert_eq!( data.files[ 0 ].annotations[ 0 ].value, "line 1\nline 2" );
+        assert_eq!( data.files[ 0 ].annotations[ 0 ].context, "c:\\dir" );
+        assert!( TomlBackend.parse( "[[files]]\ntags = []\n" ).is_err() ); //no name
+        assert!( TomlBackend.parse( "[[directory]]\nkey = \"k\"\nvalue = \"v\"\nkey = \"x\"\n" ).is_err() );
+        assert!( TomlBackend.parse( "[files]\nname = \"a\"\n" ).is_err() );
+        assert!( TomlBackend.parse( "[[directory]]\nkey = \"k\"\nvalue = \"v\" x\n" ).is_err() );
+    }
+
+    #[test]
+    fn windows_files() {
+        use std::env;
+        use std::fs;
+        use std::path::Path;
+        use super::{Annovate, Annotation, Target};
+        use doctor::examine;
+
+        let crlf = "+project\r\n>k\r\n=v\r\n\\a\\r\r\n<c\r\n!2016-03-01T12:30:00Z\r\n@dir\\a.csv\r\n>k\r\n=v\r\n<c\r\n#L1\r\n>k\r\n=w\r\n<c\r\n";
+        assert!( examine( crlf.as_bytes() ).problems.is_empty() );
+        let path = env::temp_dir().join( format!( "annovate-test-crlf-{}", ::std::process::id() ) );
+        fs::write( &path, crlf ).unwrap();
+        {
+            let mut anno = Annovate::new( &path ).unwrap();
+            assert_eq!( anno.get_directory_annotations()[ 0 ].value, "v\na\r" );
+            assert_eq!( anno.get_file_annotations( "dir\\a.csv" ).unwrap()[ 0 ].value, "v" );
+            anno.add_file_annotation( "b", Annotation::new( "k".to_string(), "v".to_string(), "c".to_string() ) ).unwrap();
+            anno.save().unwrap();
+        }
+        let saved = fs::read_to_string( &path ).unwrap();
+        assert!( saved.starts_with( crlf ) ); //unchanged sections are kept, line endings included
+        assert!( saved.ends_with( "@b\r\n>k\r\n=v\r\n<c\r\n" ) );
+
+        let mut anno = Annovate::new( &path ).unwrap();
+        assert_eq!( Target::parse( "dir\\a.csv#L1" ).with_slashes().to_string(), "dir/a.csv#L1" );
+        assert_eq!( Target::parse( "http://host/a\\b" ).with_slashes().file, "http://host/a\\b" );
+        assert_eq!( anno.normalize_separators().unwrap(), 1 );
+        assert_eq!( anno.get_files(), vec![ "b", "dir/a.csv", "dir/a.csv#L1" ] );
+        assert_eq!( anno.resolve( "dir/a.csv#L1" ), Path::new( &anno.base_dir() ).join( "dir/a.csv" ) );
+        drop( anno );
+        fs::remove_file( &path ).unwrap();
+        fs::remove_file( ::journal::journal_path( &path ) ).unwrap();
+    }
+
+    #[test]
+    #[cfg(windows)]
+    fn windows_meta_path() {
+        use std::env;
+        use std::fs;
+        use super::Annovate;
+        //the temporary directory has backslashes as separators
+        let path = env::temp_dir().join( format!( "annovate-test-windows-{}", ::std::process::id() ) );
+        let anno = Annovate::new( &path ).unwrap();
+        assert_eq!( anno.base_dir(), env::temp_dir() );
+        drop( anno );
+        fs::remove_file( &path ).unwrap();
+    }
+
+    #[test]
+    fn annotation_history() {
+        use std::path::Path;
+        use super::{Annovate, Annotation, Timestamp};
+        let mut anno = Annovate::from_json( "{}", Path::new( "unused" ) ).unwrap();
+        let entries = [ ( "k", "merged later", Some( 200 ) ), ( "x", "other key", None ), ( "k", "first", Some( 100 ) ), ( "k", "untimed", None ), ( "k", "last", Some( 300 ) ) ];
+        for &( key, value, created ) in entries.iter() {
+            let mut annotation = Annotation::new( key.to_string(), value.to_string(), "c".to_string() );
+            annotation.created = created.map( Timestamp::from_seconds );
+            anno.add_file_annotation( "f", annotation ).unwrap();
+        }
+        let values: Vec<&str> = anno.annotation_history( "f", "k" ).iter().map( |a| a.value.as_str() ).collect();
+        assert_eq!( values, vec![ "untimed", "first", "merged later", "last" ] );
+        assert!( anno.annotation_history( "f", "missing" ).is_empty() );
+        assert!( anno.annotation_history( "g", "k" ).is_empty() );
+    }
+
+    #[test]
+    fn pre_save_hook() {
+        use std::env;
+        use std::fs;
+        use super::{Annovate, Annotation, AnnoError};
+        let path = env::temp_dir().join( format!( "annovate-test-hook-{}", ::std::process::id() ) );
+        let mut anno = Annovate::new( &path ).unwrap();
+        anno.set_pre_save_hook( Box::new( |anno| {
+            let empty = anno.iter_all_annotations().any( |( _, a )| a.key == "description" && a.value.trim().is_empty() );
+            if empty { Err( "empty description".to_string() ) } else { Ok( () ) }
+        } ) );
+        anno.add_file_annotation( "a", Annotation::new( "description".to_string(), " ".to_string(), "c".to_string() ) ).unwrap();
+        match anno.save() {
+            Err( AnnoError::Rejected( reason ) ) => assert_eq!( reason, "empty description" ),
+            _ => panic!( "the hook did not reject the save" )
+        }
+        assert!( anno.is_modified() );
+        assert!( !fs::read_to_string( &path ).unwrap().contains( "@a" ) );
+        anno.add_file_annotation( "a", Annotation::new( "description".to_string(), "data".to_string(), "c".to_string() ) ).unwrap();
+        anno.remove_file_annotation( "a", "description", " " ).unwrap();
+        anno.save().unwrap();
+        drop( anno );
+        fs::remove_file( &path ).unwrap();
+        fs::remove_file( ::journal::journal_path( &path ) ).unwrap();
+    }
+
+    #[test]
+    fn migrate_legacy_files() {
+        use std::env;
+        use std::fs;
+        use super::{Annovate, Annotation};
+        use migrate;
+        let dir = env::temp_dir().join( format!( "annovate-test-migrate-{}", ::std::process::id() ) );
+        fs::create_dir_all( &dir ).unwrap();
+        assert!( migrate::legacy_files( &dir, ".annovate" ).is_empty() );
+        {
+            let mut legacy = Annovate::new( &dir.join( ".annotave" ) ).unwrap();
+            legacy.add_file_annotation( "a", Annotation::new( "k".to_string(), "old".to_string(), "c".to_string() ) ).unwrap();
+            legacy.save().unwrap();
+        }
+        assert_eq!( migrate::legacy_files( &dir, ".annovate" ), vec![ dir.join( ".annotave" ) ] );
+        let migrations = migrate::migrate( &dir, ".annovate" ).unwrap();
+        assert_eq!( migrations.len(), 1 );
+        assert!( !migrations[ 0 ].merged );
+        assert!( !dir.join( ".annotave" ).exists() && !dir.join( ".annotave.log" ).exists() );
+        assert!( dir.join( ".annovate.log" ).exists() && migrations[ 0 ].kept_journal.is_none() );
+        assert_eq!( Annovate::new( &dir.join( ".annovate" ) ).unwrap().get_file_annotations( "a" ).unwrap()[ 0 ].value, "old" );
+
+        //another configured name merges the default file into an existing one
+        {
+            let mut meta = Annovate::new( &dir.join( ".meta" ) ).unwrap();
+            meta.add_file_annotation( "b", Annotation::new( "k".to_string(), "new".to_string(), "c".to_string() ) ).unwrap();
+            meta.save().unwrap();
+        }
+        let migrations = migrate::migrate( &dir, ".meta" ).unwrap();
+        assert_eq!( migrations.len(), 1 );
+        assert!( migrations[ 0 ].merged );
+        //the journal of the merged file is kept instead of being deleted
+        assert_eq!( migrations[ 0 ].kept_journal, Some( dir.join( ".annovate.log.migrated" ) ) );
+        assert!( dir.join( ".annovate.log.migrated" ).exists() && !dir.join( ".annovate.log" ).exists() );
+        let anno = Annovate::new( &dir.join( ".meta" ) ).unwrap();
+        assert_eq!( anno.get_files(), vec![ "b", "a" ] );
+        assert!( !dir.join( ".annovate" ).exists() );
+        drop( anno );
+        fs::remove_dir_all( &dir ).unwrap();
+    }
+
+    #[test]
+    fn config_settings() {
+        use std::env;
+        use std::fs;
+        use std::path::{Path, PathBuf};
+        use config::{ColorMode, Config};
+        use identity::IdentityMode;
+        use integrity::IntegrityPolicy;
+        use quota::{Quota, QuotaAction};
+        let dir = env::temp_dir().join( format!( "annovate-test-config-{}", ::std::process::id() ) );
+        fs::create_dir_all( dir.join( "project/sub" ) ).unwrap();
+        let text = format!( "# defaults\nmeta_name = \".meta\"\ncontext_fields = [\"user\", \"time\"]\ncolor = \"never\"\n\n\
+                             [directories.\"{0}\"]\nlist_key = \"title\"\n\n[directories.'{0}/project']\ndotfiles = true\ncolor = \"always\"\nbackups = 3\nintegrity = \"error\"\nidentity = \"hash\"\n",
+                             dir.to_string_lossy() );
+        let config = Config::parse( &text ).unwrap();
+        assert_eq!( config.directories.len(), 2 );
+        let settings = config.settings_for( &dir.join( "project/sub" ) );
+        assert_eq!( settings.meta_name.as_ref().map( |n| n.as_str() ), Some( ".meta" ) );
+        assert_eq!( settings.context_fields(), "user,time" );
+        assert_eq!( ( settings.color(), settings.list_key(), settings.dotfiles() ), ( ColorMode::Always, "title", true ) );
+        assert_eq!( settings.backups(), 3 );
+        assert_eq!( settings.integrity(), IntegrityPolicy::Error );
+        assert_eq!( settings.identity(), IdentityMode::Hash );
+        let settings = config.settings_for( &dir );
+        assert_eq!( ( settings.color(), settings.list_key(), settings.dotfiles() ), ( ColorMode::Never, "title", false ) );
+        let settings = config.settings_for( Path::new( "/" ) );
+        assert_eq!( ( settings.list_key(), settings.dotfiles(), settings.backups() ), ( "description", false, 0 ) );
+
+        assert!( Config::parse( "colour = \"never\"" ).is_err() );
+        assert!( Config::parse( "backups = many" ).is_err() );
+        assert_eq!( Config::parse( "journal = 5" ).unwrap().defaults.journal(), 5 );
+        assert_eq!( Config::parse( "journal = false" ).unwrap().defaults.journal(), 0 );
+        assert_eq!( Config::default().defaults.journal(), ::journal::DEFAULT_JOURNAL_ENTRIES );
+        assert!( Config::parse( "integrity = \"maybe\"" ).is_err() );
+        assert!( Config::parse( "identity = \"inode\"" ).is_err() );
+        assert_eq!( Config::parse( "encrypt_keys = [\"secret.*\", \"pin\"]" ).unwrap().defaults.encrypt_keys(), vec![ "secret.*", "pin" ] );
+        assert_eq!( Config::parse( "max_per_key = 5\nquota = \"dedup\"" ).unwrap().defaults.quota(),
+                    Quota { max_per_target: None, max_per_key: Some( 5 ), action: QuotaAction::Dedup } );
+        assert!( Config::parse( "max_per_file = lots" ).is_err() );
+        assert!( Config::parse( "quota = \"drop\"" ).is_err() );
+        assert_eq!( Config::parse( "language = \"de\"" ).unwrap().defaults.language(), Some( "de" ) );
+        assert!( Config::parse( "language = [\"de\"]" ).is_err() );
+        assert_eq!( config.settings_for( &dir ).language(), None );
+        assert!( Config::parse( "dotfiles = \"yes\"" ).is_err() );
+        assert!( Config::parse( "[files]\nname = \"a\"" ).is_err() );
+        assert!( Config::parse( "color = \"blue\"" ).is_err() );
+        assert_eq!( Config::load( &dir.join( "missing.toml" ) ).unwrap(), Config::default() );
+        assert_eq!( Config::default().settings_for( &PathBuf::from( "." ) ).context_fields(), "time" );
+        fs::remove_dir_all( &dir ).unwrap();
+    }
+
+    #[test]
+    fn parallel_report() {
+        use std::env;
+        use std::fs::{self, File};
+        use std::path::Path;
+        use super::{Annovate, Annotation};
+        use report::{self, FileKind, ReportOptions, ReportStatus, ReportSummary};
+        let dir = env::temp_dir().join( format!( "annovate-test-report-{}", ::std::process::id() ) );
+        for sub in &[ "a", "b", "b/c", ".hidden" ] {
+            fs::create_dir_all( dir.join( sub ) ).unwrap();
+            File::create( dir.join( sub ).join( "data" ) ).unwrap();
+            let mut anno = Annovate::new( &dir.join( sub ).join( ".annovate" ) ).unwrap();
+            anno.add_file_annotation( "data", Annotation::new( "k".to_string(), "v".to_string(), "c".to_string() ) ).unwrap();
+            anno.add_file_annotation( "gone", Annotation::new( "k".to_string(), "v".to_string(), "c".to_string() ) ).unwrap();
+            anno.save().unwrap();
+        }
+        let serial = report::report_tree( &dir, &dir, ".annovate", false, ReportOptions::default(), 1 ).unwrap();
+        let parallel = report::report_tree( &dir, &dir, ".annovate", false, ReportOptions::default(), 4 ).unwrap();
+        assert_eq!( serial, parallel );
+        let names: Vec<( &str, &str )> = parallel.lines.iter().map( |l| ( l.status.marker(), l.name.as_str() ) ).collect();
+        //the meta files and their journals are not files without metadata
+        assert_eq!( names, vec![ ( "=", "a/data" ), ( "+", "a/gone" ), ( "-", "b/c" ), ( "=", "b/c/data" ), ( "+", "b/c/gone" ),
+                                 ( "=", "b/data" ), ( "+", "b/gone" ) ] );
+        assert_eq!( parallel.summary(), ReportSummary { annotated: 3, changed: 0, missing: 3, unannotated: 1 } );
+        assert_eq!( parallel.summary().total(), parallel.lines.len() );
+        assert!( parallel.has_unannotated( ".annovate" ) ); //b/c
+        let orphaned = parallel.clone().with_status( &[ ReportStatus::Missing ] );
+        assert_eq!( orphaned.lines.iter().map( |l| l.name.as_str() ).collect::<Vec<_>>(), vec![ "a/gone", "b/c/gone", "b/gone" ] );
+        assert!( !orphaned.has_unannotated( ".annovate" ) );
+        assert!( !report::report_tree( &dir.join( "a" ), &dir.join( "a" ), ".annovate", false, ReportOptions::default(), 1 ).unwrap().has_unannotated( ".annovate" ) );
+        assert_eq!( parallel.with_kind( FileKind::Dir ).lines.len(), 1 );
+        let anno = Annovate::new( &dir.join( "a/.annovate" ) ).unwrap();
+        let lines = report::report_directory( &anno, &dir.join( "a" ), Path::new( "" ), ReportOptions::default() ).unwrap();
+        assert_eq!( lines.lines.iter().filter( |l| l.status == ReportStatus::Missing ).count(), 1 );
+        assert!( report::report_tree( &dir.join( "missing" ), &dir, ".annovate", false, ReportOptions::default(), 2 ).is_err() );
+        drop( anno );
+        fs::remove_dir_all( &dir ).unwrap();
+    }
+
+    #[test]
+    fn statistics() {
+        use std::env;
+        use std::fs::{self, File};
+        use super::{Annovate, Annotation};
+        let dir = env::temp_dir().join( format!( "annovate-test-stats-{}", ::std::process::id() ) );
+        fs::create_dir_all( &dir ).unwrap();
+        for name in &[ "a", "b", "c", ".hidden" ] {
+            File::create( dir.join( name ) ).unwrap();
+        }
+        let mut anno = Annovate::new( &dir.join( ".annovate" ) ).unwrap();
+        let annotation = |key: &str, value: &str| Annotation::new( key.to_string(), value.to_string(), "c".to_string() );
+        anno.add_file_annotation( "a", annotation( "description", "x" ) ).unwrap();
+        anno.add_file_annotation( "a", annotation( "description", "y" ) ).unwrap();
+        anno.add_file_annotation( "a", annotation( ".sha256", "0" ) ).unwrap();
+        anno.add_file_annotation( "b", annotation( "description", "z" ) ).unwrap();
+        anno.add_file_annotation( "b", annotation( "author", "me" ) ).unwrap();
+        anno.add_file_annotation( "gone", annotation( "author", "me" ) ).unwrap();
+        let stats = anno.statistics().unwrap();
+        assert_eq!( stats.annotated_files, 3 );
+        assert_eq!( ( stats.annotations, stats.directory_annotations ), ( 6, 1 ) );
+        assert_eq!( stats.key_counts, vec![ ( "author".to_string(), 2 ), ( "description".to_string(), 2 ) ] );
+        assert_eq!( stats.unannotated_files, vec![ "c" ] );
+        assert_eq!( stats.average_per_file(), 2.0 );
+        drop( anno );
+        fs::remove_dir_all( &dir ).unwrap();
+    }
+
+    #[test]
+    fn rename_key() {
+        use std::path::Path;
+        use super::{Annovate, Annotation, KeyScope};
+        let mut anno = Annovate::from_json( "{}", Path::new( ".annovate" ) ).unwrap();
+        let annotation = |key: &str, value: &str| Annotation::new( key.to_string(), value.to_string(), "c".to_string() );
+        anno.add_directory_annotation( annotation( "autor", "dir" ) ).unwrap();
+        anno.add_file_annotation( "a", annotation( "autor", "old" ) ).unwrap();
+        anno.add_file_annotation( "a", annotation( "size", "1" ) ).unwrap();
+        anno.add_file_annotation( "a", annotation( "autor", "new" ) ).unwrap();
+        anno.add_file_annotation( "b", annotation( "autor", "b" ) ).unwrap();
+        assert_eq!( anno.rename_key( "autor", "author", KeyScope::File( "b".to_string() ), Some( "me" ) ).unwrap(), 1 );
+        let renamed = &anno.get_file_annotations( "b" ).unwrap()[ 0 ];
+        assert_eq!( ( renamed.key.as_str(), renamed.value.as_str(), renamed.context.as_str() ), ( "author", "b", "c; me, renamed from autor" ) );
+        assert_eq!( anno.rename_key( "autor", "author", KeyScope::All, None ).unwrap(), 3 );
+        let keys: Vec<&str> = anno.get_file_annotations( "a" ).unwrap().iter().map( |a| a.key.as_str() ).collect();
+        assert_eq!( keys, vec![ "author", "size", "author" ] );
+        assert_eq!( anno.get_file_annotations( "a" ).unwrap()[ 2 ].context, "c" );
+        assert_eq!( anno.get_directory_annotations().last().unwrap().key, "author" );
+        assert_eq!( anno.rename_key( "autor", "author", KeyScope::All, None ).unwrap(), 0 );
+        assert_eq!( anno.rename_key( "size", "bytes", KeyScope::File( "missing".to_string() ), None ).unwrap(), 0 );
+    }
+
+    #[test]
+    fn git_hook() {
+        use std::env;
+        use std::fs::{self, File};
+        use std::io::{Read, Write};
+        use git;
+        let dir = env::temp_dir().join( format!( "annovate-test-git-{}", ::std::process::id() ) );
+        fs::create_dir_all( &dir ).unwrap();
+        if git::run_git( &dir, &[ "init", "-q", "-b", "main" ] ).is_err() {
+            fs::remove_dir_all( &dir ).unwrap();
+            return; //git is not available
+        }
+        File::create( dir.join( "a" ) ).unwrap();
+        git::run_git( &dir, &[ "add", "a" ] ).unwrap();
+        git::run_git( &dir, &[ "-c", "user.name=test", "-c", "user.email=test@example.org", "commit", "-q", "-m", "a" ] ).unwrap();
+        assert_eq!( git::current_branch( &dir ).unwrap(), Some( "main".to_string() ) );
+
+        let hook = git::install_hook( &dir, "post-commit", &[ "anno", "-m", "it's/.annovate", "git-hook", "run" ] ).unwrap();
+        let mut script = String::new();
+        File::open( &hook ).unwrap().read_to_string( &mut script ).unwrap();
+        assert!( script.ends_with( "'anno' '-m' 'it'\\''s/.annovate' 'git-hook' 'run'\n" ) );
+        assert!( git::install_hook( &dir, "post-commit", &[ "anno" ] ).is_ok() );
+        File::create( &hook ).unwrap().write_all( b"#!/bin/sh\necho mine\n" ).unwrap();
+        assert!( git::install_hook( &dir, "post-commit", &[ "anno" ] ).is_err() );
+
+        let mut anno = super::Annovate::new( &dir.join( ".annovate" ) ).unwrap();
+        let head = git::record_snapshot( &mut anno, &dir, "c" ).unwrap();
+        assert_eq!( anno.get_directory_annotations().last().unwrap().value, head );
+        drop( anno );
+        fs::remove_dir_all( &dir ).unwrap();
+    }
+
+    #[test]
+    fn git_import() {
+        use std::env;
+        use std::fs::{self, File};
+        use std::io::Write;
+        use git;
+        let dir = env::temp_dir().join( format!( "annovate-test-git-import-{}", ::std::process::id() ) );
+        fs::create_dir_all( &dir ).unwrap();
+        if git::run_git( &dir, &[ "init", "-q" ] ).is_err() {
+            fs::remove_dir_all( &dir ).unwrap();
+            return; //git is not available
+        }
+        //notes are commits as well
+        git::run_git( &dir, &[ "config", "user.name", "test" ] ).unwrap();
+        git::run_git( &dir, &[ "config", "user.email", "test@example.org" ] ).unwrap();
+        File::create( dir.join( "a.txt" ) ).unwrap().write_all( b"a" ).unwrap();
+        File::create( dir.join( "caf\u{e9}.txt" ) ).unwrap().write_all( b"b" ).unwrap();
+        git::run_git( &dir, &[ "add", "a.txt", "caf\u{e9}.txt" ] ).unwrap();
+        git::run_git( &dir, &[ "commit", "-q", "-m", "first" ] ).unwrap();
+        assert_eq!( git::tracked_files( &dir ).unwrap(), vec![ "a.txt", "caf\u{e9}.txt" ] );
+
+        let mut anno = super::Annovate::new( &dir.join( ".annovate" ) ).unwrap();
+        for _ in 0..2 {
+            assert_eq!( git::import_history( &mut anno, &dir, true, "c" ).unwrap(), 2 );
+        }
+        let keys: Vec<&str> = anno.get_file_annotations( "caf\u{e9}.txt" ).unwrap().iter().map( |a| a.key.as_str() ).collect();
+        assert_eq!( keys, vec![ "first commit", "last commit", "last author", "first commit message" ] );
+        assert_eq!( anno.get_file_annotation( "a.txt", "last author" ).unwrap().value, "test" );
+        drop( anno );
+
+        //notes are found for every tracked file and only read once
+        let mut anno = super::Annovate::new( &dir.join( "notes.annovate" ) ).unwrap();
+        anno.add_file_annotation( "caf\u{e9}.txt", super::Annotation::new( "k".to_string(), "v".to_string(), "c".to_string() ).with_created( super::Timestamp::now() ) ).unwrap();
+        //parts of files, URLs, subdirectories and missing files have no blob
+        for target in &[ "a.txt#L1", "a.txt#column:x", "https://example.org/", "sub/", "gone.txt" ] {
+            anno.add_file_annotation( target, super::Annotation::new( "k".to_string(), "v".to_string(), "c".to_string() ) ).unwrap();
+        }
+        //a note on the file and one with the `creation time` of the directory on HEAD
+        assert_eq!( git::export_notes( &anno, &dir, &[] ).unwrap(), 2 );
+        anno.drop_file_annotations( "caf\u{e9}.txt" ).unwrap();
+        //the note of the directory is still found after the next commit
+        git::run_git( &dir, &[ "commit", "-q", "--allow-empty", "-m", "second" ] ).unwrap();
+        for _ in 0..2 {
+            assert_eq!( git::import_notes( &mut anno, &dir ).unwrap(), 2 );
+        }
+        assert_eq!( anno.get_file_annotations( "caf\u{e9}.txt" ).unwrap().len(), 1 );
+        assert_eq!( anno.get_directory_annotations().len(), 1 );
+        drop( anno );
+        fs::remove_dir_all( &dir ).unwrap();
+    }
+
+    #[test]
+    fn compact() {
+        use std::path::Path;
+        use super::{Annovate, Annotation};
+        let mut anno = Annovate::from_json( "{}", Path::new( ".annovate" ) ).unwrap();
+        let annotation = |key: &str, value: &str| Annotation::new( key.to_string(), value.to_string(), "c".to_string() );
+        for value in &[ "1", "2", "3" ] {
+            anno.add_file_annotation( "a", annotation( "k", value ) ).unwrap();
+            anno.add_file_annotation( "a", annotation( "size", value ) ).unwrap();
+            anno.add_directory_annotation( annotation( "d", value ) ).unwrap();
+        }
+        anno.add_file_annotation( "b", annotation( "k", "b" ) ).unwrap();
+        assert_eq!( anno.compact( 2 ).unwrap(), 3 );
+        let values: Vec<( &str, &str )> = anno.get_file_annotations( "a" ).unwrap().iter().map( |a| ( a.key.as_str(), a.value.as_str() ) ).collect();
+        assert_eq!( values, vec![ ( "k", "2" ), ( "size", "2" ), ( "k", "3" ), ( "size", "3" ) ] );
+        assert_eq!( anno.compact( 0 ).unwrap(), 3 );
+        assert_eq!( anno.get_file_annotations( "a" ).unwrap().len(), 2 );
+        assert_eq!( anno.get_file_annotations( "b" ).unwrap().len(), 1 );
+        assert_eq!( anno.get_directory_annotations().iter().map( |a| a.value.as_str() ).collect::<Vec<_>>(), vec![ "3" ] );
+        assert_eq!( anno.compact( 1 ).unwrap(), 0 );
+    }
+
+    #[test]
+    fn move_file_annotations() {
+        use std::path::Path;
+        use super::{Annovate, Annotation};
+        let mut anno = Annovate::from_json( "{}", Path::new( ".annovate" ) ).unwrap();
+        let mut other = Annovate::from_json( "{}", Path::new( "other/.annovate" ) ).unwrap();
+        let annotation = |key: &str, value: &str| Annotation::new( key.to_string(), value.to_string(), "c".to_string() );
+        anno.add_file_annotation( "a", annotation( "k", "1" ) ).unwrap();
+        anno.add_file_annotation( "b", annotation( "k", "2" ) ).unwrap();
+        anno.add_tag( "a", "raw" ).unwrap();
+        other.add_file_annotation( "a", annotation( "k", "0" ) ).unwrap();
+        assert_eq!( anno.extract_file( "missing" ).unwrap(), None );
+        let extracted = anno.extract_file( "a" ).unwrap().unwrap();
+        assert_eq!( extracted, vec![ annotation( "k", "1" ) ] );
+        assert_eq!( anno.get_files(), vec![ "b" ] );
+        assert!( anno.get_tags( "a" ).is_none() );
+        other.absorb_file( "a", extracted ).unwrap();
+        other.absorb_file( "c", Vec::new() ).unwrap();
+        assert_eq!( other.get_files(), vec![ "a" ] );
+        let values: Vec<&str> = other.get_file_annotations( "a" ).unwrap().iter().map( |a| a.value.as_str() ).collect();
+        assert_eq!( values, vec![ "0", "1" ] );
+    }
+
+    #[test]
+    fn lenient_parse() {
+        use std::env;
+        use std::fs;
+        use std::time::Duration;
+        use super::{Annovate, Annotation, OpenMode, ParseMode};
+        let dir = env::temp_dir().join( format!( "annovate-test-lenient-{}", ::std::process::id() ) );
+        fs::create_dir_all( &dir ).unwrap();
+        let path = dir.join( ".annovate" );
+        fs::write( &path, b"@a\n>k\n=v\n<c\n?junk\n@b\n>x\n=\xff\n<c\n" ).unwrap();
+        assert!( Annovate::open( &path, OpenMode::ReadOnly ).is_err() );
+
+        let mut anno = Annovate::open_with_parse_mode( &path, OpenMode::ReadWrite, ParseMode::Lenient, Duration::from_secs( 1 ) ).unwrap();
+        assert_eq!( anno.parse_warnings().len(), 2 );
+        assert_eq!( anno.iter_files().collect::<Vec<&str>>(), vec![ "a", "b" ] );
+        anno.add_file_annotation( "a", Annotation::new( "k2".to_string(), "v2".to_string(), String::new() ) ).unwrap();
+        anno.save().unwrap();
+        drop( anno );
+        //saving drops what was skipped
+        let reread = Annovate::open( &path, OpenMode::ReadOnly ).unwrap();
+        assert_eq!( reread.iter_files().count(), 2 );
+        assert_eq!( reread.get_file_annotations( "a" ).unwrap().len(), 2 );
+        fs::remove_dir_all( &dir ).unwrap();
+    }
+
+    #[test]
+    fn key_namespaces() {
+        use std::path::Path;
+        use super::{Annovate, Annotation};
+        use namespace::{key_matches, local_name, namespace};
+        assert_eq!( namespace( "quality.review.date" ), Some( "quality.review" ) );
+        assert_eq!( local_name( "quality.score" ), "score" );
+        assert_eq!( namespace( ".sha256" ), None );
+        assert!( key_matches( "quality.*", "quality.review.date" ) );
+        assert!( !key_matches( "quality.*", "qualityx.score" ) );
+        assert!( !key_matches( "quality.*", "quality" ) );
+        assert!( !key_matches( "quality", "quality.score" ) );
+
+        let mut anno = Annovate::from_json( "{}", Path::new( ".annovate" ) ).unwrap();
+        for key in &[ "quality.score", "quality.reviewed_by", "description" ] {
+            anno.add_file_annotation( "a", Annotation::new( key.to_string(), "v".to_string(), String::new() ) ).unwrap();
+        }
+        assert_eq!( anno.keys_in_namespace( "quality" ), vec![ "quality.reviewed_by", "quality.score" ] );
+        assert!( anno.remove_file_annotation_entries( "a", "quality.*" ).unwrap() );
+        let keys: Vec<&str> = anno.get_file_annotations( "a" ).unwrap().iter().map( |a| a.key.as_str() ).collect();
+        assert_eq!( keys, vec![ "description" ] );
+    }
+
+    #[test]
+    fn append_delta_records() {
+        use std::env;
+        use std::fs;
+        use std::time::Duration;
+        use super::{Annovate, Annotation, OpenMode};
+        use delta::append_annotations;
+        use doctor::examine;
+        let dir = env::temp_dir().join( format!( "annovate-test-delta-{}", ::std::process::id() ) );
+        fs::create_dir_all( &dir ).unwrap();
+        let path = dir.join( ".annovate" );
+        let anno = |key: &str, value: &str| Annotation::new( key.to_string(), value.to_string(), "c".to_string() );
+        let timeout = Duration::from_secs( 1 );
+        append_annotations( &path, Some( "a" ), &vec![ anno( "rows", "10" ) ], timeout ).unwrap();
+        append_annotations( &path, None, &vec![ anno( "project", "p" ) ], timeout ).unwrap();
+        append_annotations( &path, Some( "a#L1-2" ), &vec![ anno( "k", "v" ) ], timeout ).unwrap();
+        append_annotations( &path, Some( "a" ), &vec![ anno( "rows", "20" ) ], timeout ).unwrap();
+        let content = fs::read( &path ).unwrap();
+        assert!( examine( &content ).problems.is_empty() );
+
+        let mut loaded = Annovate::new( &path ).unwrap();
+        assert_eq!( loaded.delta_records(), 4 );
+        assert_eq!( loaded.get_directory_annotations().last().unwrap().value, "p" );
+        let values: Vec<&str> = loaded.get_file_annotations( "a" ).unwrap().iter().map( |a| a.value.as_str() ).collect();
+        assert_eq!( values, vec![ "10", "20" ] );
+        assert_eq!( loaded.compact( 2 ).unwrap(), 0 );
+        assert!( loaded.is_modified() );
+        loaded.save().unwrap();
+        drop( loaded );
+
+        assert!( !fs::read_to_string( &path ).unwrap().contains( "\n&" ) );
+        let folded = Annovate::open( &path, OpenMode::ReadOnly ).unwrap();
+        assert_eq!( folded.delta_records(), 0 );
+        assert_eq!( folded.get_file_annotations( "a#L1-2" ).unwrap().len(), 1 );
+        assert_eq!( folded.get_file_annotations( "a" ).unwrap().len(), 2 );
+        fs::remove_dir_all( &dir ).unwrap();
+    }
+
+    #[test]
+    fn resolve_references() {
+        use std::path::Path;
+        use super::{Annovate, Annotation};
+        use interpolate::{ResolveError, Scope};
+        let mut anno = Annovate::from_json( "{}", Path::new( ".annovate" ) ).unwrap();
+        let annotation = |key: &str, value: &str| Annotation::new( key.to_string(), value.to_string(), String::new() );
+        anno.add_directory_annotation( annotation( "project", "apollo" ) ).unwrap();
+        anno.add_directory_annotation( annotation( "loop", "${dir:loop2}" ) ).unwrap();
+        anno.add_directory_annotation( annotation( "loop2", "x${dir:loop}" ) ).unwrap();
+        anno.add_file_annotation( "a", annotation( "version", "3" ) ).unwrap();
+        anno.add_file_annotation( "a", annotation( "release", "${dir:project}-v${file:version} ($$5)" ) ).unwrap();
+        anno.add_file_annotation( "a", annotation( "broken", "${file:nothing}" ) ).unwrap();
+
+        let resolver = anno.resolver( Some( "a" ) );
+        assert_eq!( resolver.resolve( Scope::File, &annotation( "release", "${dir:project}-v${file:version} ($$5)" ) ),
+                    Ok( "apollo-v3 ($5)".to_string() ) );
+        assert_eq!( resolver.resolve( Scope::File, &annotation( "broken", "${file:nothing}" ) ),
+                    Err( ResolveError::Missing( "file:nothing".to_string() ) ) );
+        assert_eq!( resolver.resolve( Scope::File, &annotation( "x", "${disk:a}" ) ), Err( ResolveError::Syntax( "${disk:a}".to_string() ) ) );
+        assert_eq!( anno.resolver( None ).resolve( Scope::Dir, &annotation( "loop", "${dir:loop2}" ) ),
+                    Err( ResolveError::Cycle( vec![ "dir:loop".to_string(), "dir:loop2".to_string(), "dir:loop".to_string() ] ) ) );
+        assert_eq!( anno.resolver( None ).resolve( Scope::Dir, &annotation( "v", "${file:version}" ) ),
+                    Err( ResolveError::Missing( "file:version".to_string() ) ) );
+    }
+
+    #[test]
+    fn filter_by_context() {
+        use super::{AnnoContainer, Annotation};
+        use search::{ContextFilter, SearchQuery};
+        let mut annotations: AnnoContainer = vec![ Annotation::new( "a".to_string(), "1".to_string(), "alice, 1.2.2024".to_string() ),
+                                                   Annotation::new( "b".to_string(), "2".to_string(), "pipeline v2".to_string() ),
+                                                   Annotation::new( "c".to_string(), "3".to_string(), "Alice".to_string() ) ];
+        let keys = |annos: &AnnoContainer| annos.iter().map( |a| a.key.clone() ).collect::<Vec<String>>();
+        assert_eq!( keys( &annotations.filter_by_context( &SearchQuery::substring( "alice", false ) ) ), vec![ "a" ] );
+        assert_eq!( keys( &annotations.filter_by_context( &SearchQuery::substring( "alice", true ) ) ), vec![ "a", "c" ] );
+        annotations.retain_by_context( &SearchQuery::regex( "^pipeline v[0-9]+$", false ).unwrap() );
+        assert_eq!( keys( &annotations ), vec![ "b" ] );
+    }
+
+    #[test]
+    fn in_memory_construction() {
+        use std::env;
+        use std::fs;
+        use super::{Annovate, AnnoError, Annotation, OpenMode};
+        let dir = env::temp_dir().join( format!( "annovate-test-memory-{}", ::std::process::id() ) );
+        fs::create_dir_all( &dir ).unwrap();
+        let path = dir.join( ".annovate" );
+
+        let mut anno = Annovate::with_capacity( 10 );
+        anno.add_file_annotation( "a", Annotation::new( "k".to_string(), "v".to_string(), String::new() ) ).unwrap();
+        match anno.save() {
+            Err( AnnoError::NoPath ) => {},
+            other => panic!( "saved without a path: {:?}", other )
+        }
+        let mut copy = anno.clone();
+        copy.add_file_annotation( "b", Annotation::new( "k".to_string(), "w".to_string(), String::new() ) ).unwrap();
+        assert_eq!( anno.iter_files().count(), 1 );
+        assert_eq!( copy.iter_files().count(), 2 );
+        assert!( !path.exists() );
+
+        copy.set_path( &path );
+        assert_eq!( copy.path(), path.as_path() );
+        copy.save().unwrap();
+        let saved = Annovate::open( &path, OpenMode::ReadOnly ).unwrap();
+        assert_eq!( saved.get_file_annotations( "b" ).unwrap()[ 0 ].value, "w" );
+        assert!( Annovate::empty().path().as_os_str().is_empty() );
+        fs::remove_dir_all( &dir ).unwrap();
+    }
+
+    #[test]
+    fn annotation_expiry() {
+        use std::path::Path;
+        use super::{Annovate, Annotation, Timestamp, format_annotation_block, parse_annotation_block};
+        use expiry::{expiry_time, parse_duration, retain_unexpired};
+        assert_eq!( parse_duration( "7d" ), Some( 7 * 24 * 60 * 60 ) );
+        assert_eq!( parse_duration( "30m" ), Some( 30 * 60 ) );
+        assert_eq!( parse_duration( "d" ), None );
+        assert_eq!( parse_duration( "-1h" ), None );
+        assert_eq!( parse_duration( "3x" ), None );
+        let now = Timestamp::from_seconds( 1456835400 );
+        assert_eq!( expiry_time( "1h", now ), Some( Timestamp::from_seconds( 1456839000 ) ) );
+        assert_eq!( expiry_time( "2016-03-01T12:30:00Z", now ), Some( now ) );
+        assert_eq!( expiry_time( "soon", now ), None );
+
+        let annotation = |key: &str, expires: Option<i64>| {
+            let annotation = Annotation::new( key.to_string(), "v".to_string(), "c".to_string() ).with_created( now );
+            match expires {
+                Some( seconds ) => annotation.with_expires( Timestamp::from_seconds( seconds ) ),
+                None => annotation
+            }
+        };
+        let mut annotations = vec![ annotation( "a", Some( 0 ) ), annotation( "b", None ), annotation( "c", Some( 1456839000 ) ) ];
+        let text = format_annotation_block( &annotations );
+        assert!( text.contains( "!2016-03-01T12:30:00Z\n~2016-03-01T13:30:00Z\n" ) );
+        assert_eq!( parse_annotation_block( &text ).unwrap(), annotations );
+        assert!( parse_annotation_block( ">a\n=1\n<c\n~2016-03-01T13:30:00Z\n" ).unwrap()[ 0 ].expires.is_some() );
+        assert!( parse_annotation_block( ">a\n=1\n~2016-03-01T13:30:00Z\n" ).is_err() );
+        assert!( parse_annotation_block( ">a\n=1\n<c\n~tomorrow\n" ).is_err() );
+        assert_eq!( retain_unexpired( &mut annotations, now ), 1 );
+        assert_eq!( annotations.len(), 2 );
+
+        let mut anno = Annovate::from_json( "{}", Path::new( ".annovate" ) ).unwrap();
+        anno.add_directory_annotation( annotation( "d", Some( 0 ) ) ).unwrap();
+        anno.add_file_annotation( "f", annotation( "k", None ) ).unwrap();
+        anno.add_file_annotation( "f", annotation( "k", Some( 1 ) ) ).unwrap();
+        anno.add_file_annotation( "g", annotation( "k", Some( 1 ) ) ).unwrap();
+        assert_eq!( anno.get_unexpired_values( "k", now ), vec![ ( "f".to_string(), "v" ) ] );
+        assert_eq!( anno.prune_expired( now ).unwrap(), 3 );
+        assert!( anno.get_directory_annotations().is_empty() );
+        assert_eq!( anno.get_file_annotations( "f" ).unwrap().len(), 1 );
+        assert!( anno.get_file_annotations( "g" ).is_none() );
+        assert_eq!( anno.prune_expired( now ).unwrap(), 0 );
+    }
+
+    #[test]
+    fn annotation_regions() {
+        use std::path::Path;
+        use super::{Annovate, Annotation, Backend, Fragment, PutPolicy, TomlBackend, format_annotation_block, parse_annotation_block};
+        use doctor::{examine, ProblemKind};
+        let annotation = |value: &str, region: Option<Fragment>| {
+            let annotation = Annotation::new( "anomaly".to_string(), value.to_string(), "c".to_string() );
+            match region {
+                Some( region ) => annotation.with_region( region ),
+                None => annotation
+            }
+        };
+        let annotations = vec![ annotation( "glitch", Some( Fragment::Lines( 10, 20 ) ) ), annotation( "header", Some( Fragment::Bytes( 0, 511 ) ) ),
+                                annotation( "file", None ) ];
+        let text = format_annotation_block( &annotations );
+        assert!( text.starts_with( ">anomaly\n=glitch\n<c\n*L10-20\n>anomaly\n=header\n<c\n*B0-511\n" ) );
+        assert_eq!( parse_annotation_block( &text ).unwrap(), annotations );
+        assert_eq!( Annotation::builder( "anomaly", "glitch" ).context( "c" ).region( Fragment::Lines( 10, 20 ) ).build(), annotations[ 0 ] );
+        assert!( parse_annotation_block( ">a\n=1\n<c\n!2016-03-01T12:30:00Z\n*L7\n^protected\n%id-1\n" ).unwrap()[ 0 ].region.is_some() );
+        assert!( parse_annotation_block( ">a\n=1\n<c\n*column:age\n" ).is_err() );
+        assert!( parse_annotation_block( ">a\n=1\n<c\n*L20-10\n" ).is_err() );
+        assert!( parse_annotation_block( ">a\n=1\n<c\n^protected\n*L7\n" ).is_err() );
+        assert!( parse_annotation_block( ">a\n=1\n*L7\n" ).is_err() );
+        assert_eq!( examine( b">a\n=1\n<c\n*L0\n" ).problems[ 0 ].kind, ProblemKind::InvalidRegion( "L0".to_string() ) );
+        assert_eq!( examine( b"*L7\n" ).problems[ 0 ].kind, ProblemKind::OrphanedRegion );
+
+        //entries of other regions stay when a key is replaced
+        let mut anno = Annovate::from_json( "{}", Path::new( "unused.toml" ) ).unwrap();
+        for annotation in &annotations {
+            anno.add_file_annotation( "data.csv", annotation.clone() ).unwrap();
+        }
+        assert!( anno.put_file_annotation( "data.csv", annotation( "sensor", Some( Fragment::Lines( 10, 20 ) ) ), PutPolicy::Replace ).unwrap() );
+        assert!( !anno.put_file_annotation( "data.csv", annotation( "x", Some( Fragment::Bytes( 0, 511 ) ) ), PutPolicy::IfAbsent ).unwrap() );
+        let values: Vec<&str> = anno.get_file_annotations( "data.csv" ).unwrap().iter().map( |a| a.value.as_str() ).collect();
+        assert_eq!( values, vec![ "header", "file", "sensor" ] );
+        assert!( TomlBackend.serialize( &anno, None ).contains( "region = \"B0-511\"\n" ) );
+        assert_eq!( TomlBackend.parse( &TomlBackend.serialize( &anno, None ) ).unwrap(), anno.to_data() );
+        assert_eq!( Annovate::from_json( &anno.to_json(), Path::new( ".annovate" ) ).unwrap().get_file_annotations( "data.csv" ),
+                    anno.get_file_annotations( "data.csv" ) );
+    }
+
+    #[test]
+    fn annotation_ids() {
+        use std::path::Path;
+        use super::{Annovate, Annotation, format_annotation_block, parse_annotation_block};
+        use ids::{ID_LENGTH, generate_id, is_valid_id};
+        let ( first, second ) = ( generate_id(), generate_id() );
+        assert_ne!( first, second );
+        assert_eq!( first.len(), ID_LENGTH );
+        assert!( is_valid_id( &first ) );
+        assert!( !is_valid_id( "" ) );
+        assert!( !is_valid_id( "a b" ) );
+
+        let annotation = |key: &str, value: &str, id: &str| Annotation::new( key.to_string(), value.to_string(), "c".to_string() ).with_id( id.to_string() );
+        let annotations = vec![ annotation( "k", "1", "id-1" ), annotation( "k", "2", "id-2" ) ];
+        let text = format_annotation_block( &annotations );
+        assert!( text.starts_with( ">k\n=1\n<c\n%id-1\n" ) );
+        assert_eq!( parse_annotation_block( &text ).unwrap(), annotations );
+        assert!( parse_annotation_block( ">k\n=1\n<c\n!2016-03-01T12:30:00Z\n~2016-03-01T13:30:00Z\n%x\n" ).unwrap()[ 0 ].id.is_some() );
+        assert!( parse_annotation_block( ">k\n=1\n%x\n" ).is_err() );
+        assert!( parse_annotation_block( ">k\n=1\n<c\n%x y\n" ).is_err() );
+
+        let mut anno = Annovate::from_json( "{}", Path::new( ".annovate" ) ).unwrap();
+        anno.add_directory_annotation( annotation( "d", "x", "dir" ) ).unwrap();
+        for a in annotations {
+            anno.add_file_annotation( "f", a ).unwrap();
+        }
+        assert_eq!( anno.find_id( "id-2" ).map( |( target, a )| ( target, a.value.as_str() ) ), Some( ( Some( "f" ), "2" ) ) );
+        assert_eq!( anno.find_id( "dir" ).map( |( target, _ )| target ), Some( None ) );
+        assert!( anno.amend_id( "id-1", "one".to_string() ).unwrap() );
+        assert!( !anno.amend_id( "missing", "one".to_string() ).unwrap() );
+        assert!( anno.remove_id( "id-2" ).unwrap() );
+        assert!( anno.remove_id( "dir" ).unwrap() );
+        assert!( !anno.remove_id( "id-2" ).unwrap() );
+        let values: Vec<&str> = anno.get_file_annotations( "f" ).unwrap().iter().map( |a| a.value.as_str() ).collect();
+        assert_eq!( values, vec![ "one" ] );
+        assert!( anno.get_directory_annotations().is_empty() );
+        assert!( anno.remove_id( "id-1" ).unwrap() );
+        assert!( anno.get_file_annotations( "f" ).is_none() );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn report_file_kinds() {
+        use std::env;
+        use std::fs::{self, File};
+        use std::os::unix::fs::symlink;
+        use std::path::Path;
+        use super::{Annovate, Annotation};
+        use report::{self, FileKind, ReportOptions};
+        let dir = env::temp_dir().join( format!( "annovate-test-kinds-{}", ::std::process::id() ) );
+        fs::create_dir_all( dir.join( "sub" ) ).unwrap();
+        File::create( dir.join( "data" ) ).unwrap();
+        symlink( "data", dir.join( "link" ) ).unwrap();
+        symlink( "nowhere", dir.join( "broken" ) ).unwrap();
+        symlink( "sub", dir.join( "dirlink" ) ).unwrap();
+        let mut anno = Annovate::new( &dir.join( ".annovate" ) ).unwrap();
+        anno.add_file_annotation( "link", Annotation::new( "k".to_string(), "v".to_string(), "c".to_string() ) ).unwrap();
+        anno.add_file_annotation( "gone", Annotation::new( "k".to_string(), "v".to_string(), "c".to_string() ) ).unwrap();
+        let kinds = |options: ReportOptions| -> Vec<( String, Option<FileKind> )> {
+            report::report_directory( &anno, &dir, Path::new( "" ), options ).unwrap().lines.into_iter()
+                   .filter( |l| !l.name.starts_with( ".annovate" ) ).map( |l| ( l.name, l.kind ) ).collect()
+        };
+        let name = |name: &str, kind: Option<FileKind>| ( name.to_string(), kind );
+        assert_eq!( kinds( ReportOptions::default() ), vec![ name( "broken", Some( FileKind::BrokenLink ) ), name( "data", Some( FileKind::File ) ),
+                                                             name( "dirlink", Some( FileKind::Symlink ) ), name( "gone", None ),
+                                                             name( "link", Some( FileKind::Symlink ) ), name( "sub", Some( FileKind::Dir ) ) ] );
+        let followed = kinds( ReportOptions { follow_links: true, ..ReportOptions::default() } );
+        assert!( followed.contains( &name( "dirlink", Some( FileKind::Dir ) ) ) );
+        assert!( followed.contains( &name( "link", Some( FileKind::File ) ) ) );
+        assert!( followed.contains( &name( "broken", Some( FileKind::BrokenLink ) ) ) );
+        assert_eq!( FileKind::from_name( "broken-link" ), Some( FileKind::BrokenLink ) );
+        assert_eq!( FileKind::from_name( "folder" ), None );
+        assert_eq!( FileKind::Dir.marker(), "/" );
+        drop( anno );
+        fs::remove_dir_all( &dir ).unwrap();
+    }
+
+    #[test]
+    fn backup_rotation() {
+        use std::env;
+        use std::fs::{self, File};
+        use std::io::{Read, Write};
+        use super::{Annovate, Annotation, SaveOptions};
+        use backup::{BackupPolicy, backup_path, list_backups};
+        use journal::journal_path;
+
+        let path = env::temp_dir().join( format!( "annovate-test-backup-{}", ::std::process::id() ) );
+        File::create( &path ).unwrap().write_all( b">k\n=1\n<c\n" ).unwrap();
+        for value in &[ "2", "3", "4" ] {
+            let mut anno = Annovate::new( &path ).unwrap();
+            anno.set_save_options( SaveOptions { backups: BackupPolicy::keep( 2 ), ..SaveOptions::default() } );
+            anno.add_directory_annotation( Annotation::new( "k".to_string(), value.to_string(), "c".to_string() ) ).unwrap();
+            anno.save().unwrap();
+        }
+        assert_eq!( list_backups( &path ), vec![ 1, 2 ] ); //the content before the first save was shifted out
+        let mut text = String::new();
+        File::open( backup_path( &path, 2 ) ).unwrap().read_to_string( &mut text ).unwrap();
+        assert_eq!( text.matches( ">k" ).count(), 2 );
+
+        let mut anno = Annovate::new( &path ).unwrap();
+        assert!( !anno.restore_backup( 3 ).unwrap() );
+        assert!( anno.restore_backup( 2 ).unwrap() );
+        assert_eq!( anno.get_directory_annotations().len(), 2 );
+        anno.set_save_options( SaveOptions { backups: BackupPolicy::keep( 1 ), ..SaveOptions::default() } );
+        anno.save().unwrap();
+        assert_eq!( list_backups( &path ), vec![ 1 ] ); //fewer backups to keep also removes the older ones
+        drop( anno );
+        fs::remove_file( backup_path( &path, 1 ) ).unwrap();
+        fs::remove_file( journal_path( &path ) ).unwrap();
+        fs::remove_file( &path ).unwrap();
+    }
+
+    #[test]
+    fn key_matchers() {
+        use super::{Annovate, Annotation, KeyMatcher};
+        let matches = |matcher: KeyMatcher, name: &str, key: &str| Annotation::new( key.to_string(), String::new(), String::new() ).key_matches_with( name, matcher );
+        assert!( !matches( KeyMatcher::Exact, "Description", "description" ) );
+        assert!( matches( KeyMatcher::IgnoreCase, "Description", "description" ) );
+        assert!( !matches( KeyMatcher::IgnoreCase, "last-modified", "LastModified" ) );
+        assert!( matches( KeyMatcher::Fuzzy, "last-modified", "LastModified" ) );
+        assert!( matches( KeyMatcher::Fuzzy, "descripton", "description" ) ); //one character missing
+        assert!( matches( KeyMatcher::Fuzzy, "descriptiom", "description" ) );
+        assert!( !matches( KeyMatcher::Fuzzy, "descriptn", "description" ) );
+        assert!( !matches( KeyMatcher::Fuzzy, "tag", "tags" ) ); //too short for a typo
+        assert!( matches( KeyMatcher::IgnoreCase, "Quality.*", "quality.score" ) );
+        assert!( !matches( KeyMatcher::Exact, "Quality.*", "quality.score" ) );
+
+        let mut anno = Annovate::empty();
+        anno.add_directory_annotation( Annotation::new( "Author".to_string(), "me".to_string(), String::new() ) ).unwrap();
+        assert!( !anno.remove_directory_annotation_entries( "author" ).unwrap() );
+        assert!( anno.remove_directory_annotation_entries_matching( "author", KeyMatcher::IgnoreCase ).unwrap() );
+        assert!( anno.get_directory_annotations().is_empty() );
+    }
+
+    #[test]
+    fn protected_annotations() {
+        use std::path::Path;
+        use super::{Annovate, AnnoError, Annotation, format_annotation_block, parse_annotation_block};
+        let annotation = |key: &str, value: &str| Annotation::new( key.to_string(), value.to_string(), "c".to_string() );
+        let annotations = vec![ annotation( "creation time", "2016-03-01" ).with_protected( true ).with_id( "x".to_string() ) ];
+        let text = format_annotation_block( &annotations );
+        assert!( text.ends_with( "<c\n^protected\n%x\n" ) );
+        assert_eq!( parse_annotation_block( &text ).unwrap(), annotations );
+        assert!( parse_annotation_block( ">k\n=1\n<c\n^readonly\n" ).is_err() );
+        assert!( parse_annotation_block( ">k\n=1\n<c\n%x\n^protected\n" ).is_err() );
+
+        let mut anno = Annovate::from_json( "{}", Path::new( ".annovate" ) ).unwrap();
+        anno.add_directory_annotation( annotation( "creation time", "unknown" ) ).unwrap();
+        anno.add_directory_annotation( annotations[ 0 ].clone() ).unwrap();
+        anno.add_directory_annotation( annotation( "status", "new" ) ).unwrap();
+        match anno.add_directory_annotation( annotation( "creation time", "today" ) ) {
+            Err( AnnoError::Protected( ref key ) ) => assert_eq!( key, "creation time" ),
+            other => panic!( "unexpected result {:?}", other )
+        }
+        assert!( anno.remove_directory_annotation_entries( "creation time" ).is_err() );
+        assert!( anno.amend_id( "x", "today".to_string() ).is_err() );
+        assert!( anno.remove_directory_annotation_entries( "status" ).unwrap() );
+        assert_eq!( anno.get_directory_annotations().len(), 2 );
+        assert!( Annovate::from_json( &anno.to_json(), Path::new( ".annovate" ) ).unwrap().get_directory_annotations()[ 1 ].protected );
+
+        anno.set_override_protection( true );
+        anno.add_directory_annotation( annotation( "creation time", "today" ) ).unwrap();
+        anno.set_override_protection( false );
+        //the most recent entry decides, it is no longer protected
+        anno.add_directory_annotation( annotation( "creation time", "tomorrow" ) ).unwrap();
+        assert!( anno.remove_directory_annotation_entries( "creation time" ).unwrap() );
+    }
+
+    #[test]
+    fn transactions() {
+        use std::env;
+        use std::fs::{self, File};
+        use std::io::Write;
+        use super::{Annovate, Annotation, AnnoError, SaveOptions};
+
+        let path = env::temp_dir().join( format!( "annovate-test-transaction-{}", ::std::process::id() ) );
+        File::create( &path ).unwrap().write_all( b">k\n=1\n<c\n" ).unwrap();
+        let mut anno = Annovate::new( &path ).unwrap();
+        anno.set_save_options( SaveOptions { journal: false, ..SaveOptions::default() } );
+        let new = |value: &str| Annotation::new( "k".to_string(), value.to_string(), "c".to_string() );
+
+        //a failing closure leaves neither the memory nor the file changed
+        let result: Result<(), AnnoError> = anno.transaction( |tx| {
+            try!( tx.add_directory_annotation( new( "2" ) ) );
+            try!( tx.add_file_annotation( "a.txt", new( "3" ) ) );
+            Err( AnnoError::NoPath )
+        } );
+        assert!( result.is_err() );
+        assert_eq!( anno.get_directory_annotations().len(), 1 );
+        assert!( anno.get_file_annotations( "a.txt" ).is_none() );
+        assert!( !anno.is_modified() );
+
+        //a discarded transaction is reset as well
+        anno.transaction( |tx| { tx.discard(); tx.add_directory_annotation( new( "2" ) ) } ).unwrap();
+        assert_eq!( anno.get_directory_annotations().len(), 1 );
+
+        let count = anno.transaction( |tx| {
+            try!( tx.add_directory_annotation( new( "2" ) ) );
+            try!( tx.add_file_annotation( "a.txt", new( "3" ) ) );
+            Ok( tx.get_files().len() )
+        } ).unwrap();
+        assert_eq!( count, 1 );
+        assert!( !anno.is_modified() );
+        drop( anno );
+        let anno = Annovate::new( &path ).unwrap();
+        assert_eq!( anno.get_directory_annotations().len(), 2 );
+        assert_eq!( anno.get_file_annotations( "a.txt" ).unwrap().len(), 1 );
+        drop( anno );
+        fs::remove_file( &path ).unwrap();
+    }
+
+    #[test]
+    fn key_suggestions() {
+        use super::{Annovate, Annotation};
+        use namespace::{closest_key, edit_distance};
+        assert_eq!( edit_distance( "kitten", "sitting" ), 3 );
+        assert_eq!( edit_distance( "", "abc" ), 3 );
+        assert_eq!( edit_distance( "same", "same" ), 0 );
+        let keys = vec![ "description", "author", "date", "quality.score" ];
+        assert_eq!( closest_key( "desciption", keys.iter().cloned() ), Some( "description" ) );
+        assert_eq!( closest_key( "Autor", keys.iter().cloned() ), Some( "author" ) );
+        assert_eq!( closest_key( "title", keys.iter().cloned() ), None );
+        assert_eq!( closest_key( "quality.*", keys.iter().cloned() ), None );
+
+        let mut anno = Annovate::empty();
+        let new = |key: &str| Annotation::new( key.to_string(), "v".to_string(), "c".to_string() );
+        anno.add_file_annotation( "a.txt", new( "author" ) ).unwrap();
+        anno.add_file_annotation( "a.txt", new( "author" ) ).unwrap();
+        anno.add_file_annotation( "b.txt", new( ".sha256" ) ).unwrap();
+        anno.add_directory_annotation( new( "project" ) ).unwrap();
+        assert_eq!( anno.key_counts( None, false ).unwrap(), vec![ ( "author".to_string(), 2 ), ( "project".to_string(), 1 ) ] );
+        assert_eq!( anno.key_counts( Some( "b.txt" ), true ).unwrap(), vec![ ( ".sha256".to_string(), 1 ) ] );
+        assert_eq!( anno.key_counts( Some( "c.txt" ), false ), None );
+    }
+
+    #[test]
+    fn integrity_footer() {
+        use std::env;
+        use std::fs::{self, File, OpenOptions};
+        use std::io::{Read, Write};
+        use super::{Annovate, Annotation, AnnoError, SaveOptions};
+        use doctor::{self, ProblemKind};
+        use integrity::{self, IntegrityPolicy, IntegrityStatus};
+        use reader;
+
+        assert_eq!( integrity::verify( ">k\n=v\n<c\n" ), IntegrityStatus::Missing );
+        let text = integrity::with_footer( ">k\n=v\n<c", false );
+        assert_eq!( integrity::verify( &text ), IntegrityStatus::Valid );
+        assert_eq!( integrity::strip_footer( &text ), ">k\n=v\n<c\n" );
+        assert_eq!( integrity::verify( &text.replace( "=v", "=w" ) ), IntegrityStatus::Mismatch );
+        assert_eq!( integrity::verify( &format!( "{}&a.txt\n>k\n=v\n<c\n", text ) ), IntegrityStatus::Valid ); //delta records
+        assert_eq!( integrity::verify( &format!( "{}>k\n=v\n<c\n", text ) ), IntegrityStatus::Mismatch );
+        assert_eq!( integrity::verify( &integrity::with_footer( "k = 1\n", true ) ), IntegrityStatus::Valid );
+
+        let path = env::temp_dir().join( format!( "annovate-test-integrity-{}", ::std::process::id() ) );
+        File::create( &path ).unwrap().write_all( b">k\n=1\n<c\n" ).unwrap();
+        let mut anno = Annovate::new( &path ).unwrap();
+        assert!( anno.verify_integrity().is_err() );
+        anno.set_save_options( SaveOptions { journal: false, integrity: IntegrityPolicy::Warn, ..SaveOptions::default() } );
+        anno.add_file_annotation( "a.txt", Annotation::new( "k".to_string(), "2".to_string(), "c".to_string() ) ).unwrap();
+        anno.save().unwrap();
+        anno.verify_integrity().unwrap();
+        drop( anno );
+        //the footer is skipped by the readers and kept without the option
+        let mut anno = Annovate::new( &path ).unwrap();
+        assert_eq!( anno.get_file_annotations( "a.txt" ).unwrap().len(), 1 );
+        assert_eq!( reader::read_selected( &path, |_| true ).unwrap().1.len(), 1 );
+        anno.set_save_options( SaveOptions { journal: false, ..SaveOptions::default() } );
+        anno.add_directory_annotation( Annotation::new( "k".to_string(), "3".to_string(), "c".to_string() ) ).unwrap();
+        anno.save().unwrap();
+        anno.verify_integrity().unwrap();
+        drop( anno );
+        assert!( doctor::diagnose( &path ).unwrap().is_empty() );
+
+        OpenOptions::new().append( true ).open( &path ).unwrap().write_all( b">k\n=4\n<edited\n" ).unwrap();
+        match integrity::check_file( &path ) {
+            Err( AnnoError::IntegrityMismatch( ref p ) ) => assert_eq!( *p, path ),
+            other => panic!( "unexpected {:?}", other )
+        }
+        let problems = doctor::repair( &path ).unwrap();
+        assert_eq!( problems.iter().map( |p| p.kind.clone() ).collect::<Vec<_>>(), vec![ ProblemKind::IntegrityMismatch ] );
+        let mut repaired = String::new();
+        File::open( &path ).unwrap().read_to_string( &mut repaired ).unwrap();
+        assert_eq!( integrity::verify( &repaired ), IntegrityStatus::Missing );
+        fs::remove_file( doctor::backup_path( &path ) ).unwrap();
+        fs::remove_file( &path ).unwrap();
+    }
+
+    #[test]
+    fn copy_specs() {
+        use super::{Annovate, Annotation};
+        use copy::CopySpec;
+        assert_eq!( CopySpec::parse_map( "owner=original_owner, *=upstream.*" ),
+                    Some( vec![ ( "owner".to_string(), "original_owner".to_string() ), ( "*".to_string(), "upstream.*".to_string() ) ] ) );
+        assert_eq!( CopySpec::parse_map( "owner" ), None );
+        assert_eq!( CopySpec::parse_map( "owner=" ), None );
+
+        let mut anno = Annovate::empty();
+        for &( key, value ) in &[ ( "owner", "me" ), ( "license", "MIT" ), ( "size", "3" ), ( "owner", "you" ) ] {
+            anno.add_file_annotation( "src", Annotation::new( key.to_string(), value.to_string(), String::new() ) ).unwrap();
+        }
+        let destinations = vec![ "a".to_string(), "b".to_string() ];
+        let spec = CopySpec::new( "src", &destinations ).with_keys( &[ "owner".to_string(), "license".to_string() ] )
+                                                        .with_map( CopySpec::parse_map( "owner=original_owner,*=upstream.*" ).unwrap() );
+        assert_eq!( anno.copy( &spec ).unwrap(), 4 );
+        for dst in &destinations {
+            let copied: Vec<( &str, &str )> = anno.get_file_annotations( dst ).unwrap().iter().map( |a| ( a.key.as_str(), a.value.as_str() ) ).collect();
+            assert_eq!( copied, vec![ ( "upstream.license", "MIT" ), ( "original_owner", "you" ) ] );
+        }
+    }
+
+    #[test]
+    fn ignore_rules() {
+        use std::env;
+        use std::fs::{self, File};
+        use std::io::Write;
+        use std::path::Path;
+        use super::{Annovate, Annotation, Pattern};
+        use ignore::{IgnoreRules, IGNORE_FILE};
+        use report::{self, ReportOptions};
+        use tree::AnnovateTree;
+
+        let rules = IgnoreRules::parse( "# artifacts\n*.o\n!keep.o\ntarget/\n/notes.txt\ndocs/**/*.tmp\n\n" ).unwrap();
+        assert!( rules.is_ignored( "main.o", false ) );
+        assert!( rules.is_ignored( "src/.hidden.o", false ) );
+        assert!( !rules.is_ignored( "keep.o", false ) );
+        assert!( rules.is_ignored( "target", true ) );
+        assert!( !rules.is_ignored( "target", false ) );
+        assert!( rules.is_ignored( "target/debug/keep.o", false ) ); //a parent directory is ignored
+        assert!( rules.is_ignored( "notes.txt", false ) );
+        assert!( !rules.is_ignored( "sub/notes.txt", false ) );
+        assert!( rules.is_ignored( "docs/a.tmp", false ) );
+        assert!( rules.is_ignored( "docs/x/y/a.tmp", false ) );
+        assert!( !rules.is_ignored( "a.tmp", false ) );
+        assert!( IgnoreRules::parse( "data[0-9" ).is_err() );
+
+        let dir = env::temp_dir().join( format!( "annovate-test-ignore-{}", ::std::process::id() ) );
+        fs::create_dir_all( dir.join( "build/sub" ) ).unwrap();
+        for name in &[ "a.txt", "a.o", "build/sub/.annovate" ] {
+            File::create( dir.join( name ) ).unwrap();
+        }
+        let mut anno = Annovate::new( &dir.join( ".annovate" ) ).unwrap();
+        assert!( !anno.is_ignored( "a.o" ).unwrap() );
+        File::create( dir.join( IGNORE_FILE ) ).unwrap().write_all( b"*.o\nbuild/\n" ).unwrap();
+        assert!( anno.is_ignored( "a.o" ).unwrap() );
+        assert!( anno.is_ignored( "build" ).unwrap() );
+        assert!( !anno.is_ignored( "a.txt" ).unwrap() );
+
+        assert_eq!( anno.statistics().unwrap().unannotated_files, vec![ "a.txt" ] );
+        assert_eq!( anno.files_matching( &Pattern::new( "a.*" ).unwrap() ), vec![ "a.txt" ] );
+        assert_eq!( AnnovateTree::find( &dir, ".annovate", false ).unwrap(), vec![ Path::new( "" ).to_path_buf() ] ); //not build/sub
+        let names = |anno: &Annovate| -> Vec<String> {
+            report::report_directory( anno, &dir, Path::new( "" ), ReportOptions::default() ).unwrap().lines.into_iter().map( |l| l.name ).filter( |n| !n.starts_with( ".annovate" ) ).collect()
+        };
+        assert_eq!( names( &anno ), vec![ "a.txt" ] );
+        //annotated files are reported even if they are ignored
+        anno.add_file_annotation( "a.o", Annotation::new( "k".to_string(), "v".to_string(), String::new() ) ).unwrap();
+        assert_eq!( names( &anno ), vec![ "a.o", "a.txt" ] );
+        drop( anno );
+        fs::remove_dir_all( &dir ).unwrap();
+    }
+
+    #[test]
+    fn content_identity() {
+        use std::env;
+        use std::fs::{self, File};
+        use std::io::Write;
+        use super::{Annovate, Annotation};
+        use hash::CHECKSUM_KEY;
+        let dir = env::temp_dir().join( format!( "annovate-test-identity-{}", ::std::process::id() ) );
+        fs::create_dir_all( dir.join( "sub" ) ).unwrap();
+        for &( name, content ) in &[ ( "a.csv", "1,2" ), ( "b.csv", "3,4" ), ( "c.csv", "5,6" ) ] {
+            File::create( dir.join( name ) ).unwrap().write_all( content.as_bytes() ).unwrap();
+        }
+        let mut anno = Annovate::new( &dir.join( ".annovate" ) ).unwrap();
+        for name in &[ "a.csv", "b.csv", "c.csv", "gone.csv" ] {
+            anno.add_file_annotation( name, Annotation::new( "k".to_string(), name.to_string(), "c".to_string() ) ).unwrap();
+        }
+        assert_eq!( anno.record_missing_checksums( &dir, "c" ).unwrap(), 3 );
+        assert_eq!( anno.record_missing_checksums( &dir, "c" ).unwrap(), 0 );
+        assert_eq!( anno.hash_index().len(), 3 );
+
+        fs::rename( dir.join( "a.csv" ), dir.join( "sub/renamed.csv" ) ).unwrap();
+        fs::rename( dir.join( "b.csv" ), dir.join( "b2.csv" ) ).unwrap();
+        fs::copy( dir.join( "b2.csv" ), dir.join( "b3.csv" ) ).unwrap(); //two candidates, none is chosen
+        assert_eq!( anno.find_by_content( "sub/renamed.csv" ).unwrap(), Some( "a.csv".to_string() ) );
+        assert_eq!( anno.find_by_content( "missing.csv" ).unwrap(), None );
+        assert_eq!( anno.relink( &dir ).unwrap(), vec![ ( "a.csv".to_string(), "sub/renamed.csv".to_string() ) ] );
+        assert!( anno.get_file_annotations( "a.csv" ).is_none() );
+        assert_eq!( anno.get_file_annotations( "sub/renamed.csv" ).unwrap().iter().filter( |a| a.key != CHECKSUM_KEY ).next().unwrap().value, "a.csv" );
+        assert!( anno.get_file_annotations( "b.csv" ).is_some() );
+        assert!( anno.relink( &dir ).unwrap().is_empty() );
+        drop( anno );
+        fs::remove_dir_all( &dir ).unwrap();
+    }
+
+    #[test]
+    fn annotation_builder() {
+        use super::{Annotation, Timestamp, ValueType};
+        let created = Timestamp::now();
+        let built = Annotation::builder( "size", "42" ).context( "c" ).created( created ).id( "abc" ).protected( true )
+                                                     .value_type( ValueType::Int ).build();
+        let expected = Annotation::new( "size".to_string(), "42".to_string(), "c".to_string() ).with_created( created )
+                                 .with_id( "abc".to_string() ).with_protected( true ).with_type( ValueType::Int );
+        assert_eq!( built, expected );
+        assert_eq!( Annotation::builder( "k", "v" ).value_type( ValueType::Text ).build(), Annotation::new( "k".to_string(), "v".to_string(), String::new() ) );
+        assert_eq!( Annotation::builder( "k", "v" ).expires( created ).build().expires, Some( created ) );
+    }
+
+    #[test]
+    #[cfg(feature = "crypt")]
+    fn encrypted_values() {
+        use super::{AnnoError, Annotation, ValueType};
+        use crypt::{chacha20, hmac_sha256, pbkdf2_sha256, Keyring};
+        use rustc_serialize::hex::ToHex;
+        assert_eq!( hmac_sha256( b"Jefe", b"what do ya want for nothing?" ).to_hex(), "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843" );
+        let mut derived = [ 0u8; 64 ];
+        pbkdf2_sha256( b"passwd", b"salt", 1, &mut derived );
+        assert_eq!( derived[ ..16 ].to_hex(), "55ac046e56e3089fec1691c22544b605" );
+        assert_eq!( derived[ 48.. ].to_hex(), "7c71b845b1e30bd509112041d3a19783" );
+        //RFC 8439, 2.4.2
+        let mut key = [ 0u8; 32 ];
+        for ( i, byte ) in key.iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        let nonce = [ 0, 0, 0, 0, 0, 0, 0, 0x4a, 0, 0, 0, 0 ];
+        let mut text = b"Ladies and Gentlemen of the class of '99: If I could offer you only one tip for the future, sunscreen would be it.".to_vec();
+        chacha20( &key, &nonce, 1, &mut text );
+        assert_eq!( text[ ..16 ].to_hex(), "6e2e359a2568f98041ba0728dd0d6981" );
+        assert_eq!( text[ 112.. ].to_hex(), "874d" );
+
+        let keyring = Keyring::new( "correct horse" );
+        let secret = Annotation::new( "secret.note".to_string(), "line 1\nline 2".to_string(), "c".to_string() ).with_type( ValueType::List );
+        let encrypted = secret.clone().encrypted( &keyring ).unwrap();
+        assert!( encrypted.is_encrypted() && !secret.is_encrypted() );
+        assert!( !encrypted.value.contains( "line" ) && !encrypted.value.contains( '\n' ) );
+        assert_ne!( keyring.encrypt( "x" ).unwrap(), keyring.encrypt( "x" ).unwrap() ); //a new nonce for every value
+        assert_eq!( encrypted.decrypted( &keyring ).unwrap().value, "line 1\nline 2" );
+        assert_eq!( encrypted.value_type, None );
+        assert_eq!( secret.decrypted( &keyring ).unwrap(), secret );
+        match Keyring::new( "wrong" ).decrypt( &encrypted.value ) {
+            Err( AnnoError::Decryption ) => {},
+            other => panic!( "wrong passphrase accepted: {:?}", other )
+        }
+        let mut changed = encrypted.value.clone();
+        let last = if changed.ends_with( 'A' ) { "B" } else { "A" };
+        changed.pop();
+        changed.push_str( last );
+        assert!( keyring.decrypt( &changed ).is_err() );
+        assert!( keyring.decrypt( "enc:v1:AAAA" ).is_err() );
+    }
+
+    #[test]
+    fn quota_limits() {
+        use std::path::Path;
+        use super::{Annovate, Annotation};
+        use quota::{Quota, QuotaAction};
+        let mut anno = Annovate::from_json( "{}", Path::new( ".annovate" ) ).unwrap();
+        let annotation = |key: &str, value: &str| Annotation::new( key.to_string(), value.to_string(), "c".to_string() );
+        for value in &[ "1", "2", "1", "2" ] {
+            anno.add_file_annotation( "a", annotation( "k", value ) ).unwrap();
+        }
+        anno.add_directory_annotation( annotation( "d", "x" ) ).unwrap();
+        anno.add_directory_annotation( annotation( "d", "x" ) ).unwrap();
+        assert!( anno.quota_warnings().is_empty() );
+
+        anno.set_quota( Quota { max_per_target: Some( 4 ), max_per_key: Some( 3 ), action: QuotaAction::Warn } );
+        anno.add_file_annotation( "a", annotation( "k", "3" ) ).unwrap();
+        let warnings: Vec<( Option<&str>, usize )> = anno.quota_warnings().iter().map( |w| ( w.key.as_ref().map( |k| k.as_str() ), w.count ) ).collect();
+        assert_eq!( warnings, vec![ ( None, 5 ), ( Some( "k" ), 5 ) ] );
+        assert_eq!( anno.quota_warnings()[ 0 ].target, Some( "a".to_string() ) );
+        assert_eq!( anno.get_file_annotations( "a" ).unwrap().len(), 5 );
+
+        //only the earlier of equal entries are removed, so the most recent value stays the same
+        let mut anno = anno.clone();
+        anno.set_quota( Quota { max_per_target: Some( 4 ), max_per_key: None, action: QuotaAction::Dedup } );
+        let warnings = anno.quota_warnings().len();
+        anno.add_file_annotation( "a", annotation( "k", "2" ) ).unwrap();
+        let values: Vec<&str> = anno.get_file_annotations( "a" ).unwrap().iter().map( |a| a.value.as_str() ).collect();
+        assert_eq!( values, vec![ "1", "3", "2" ] );
+        assert_eq!( anno.quota_warnings().len(), warnings );
+
+        assert_eq!( anno.dedup_exact().unwrap(), 1 );
+        assert_eq!( anno.get_directory_annotations().len(), 1 );
+        assert_eq!( anno.dedup_exact().unwrap(), 0 );
+    }
+
+    #[test]
+    fn directory_annotations() {
+        use std::path::Path;
+        use super::{Annovate, Annotation, AnnoError, Timestamp};
+        let mut anno = Annovate::from_json( "{}", Path::new( ".annovate" ) ).unwrap();
+        let annotation = |key: &str, value: &str, seconds: i64| Annotation::new( key.to_string(), value.to_string(), "c".to_string() ).with_created( Timestamp::from_seconds( seconds ) );
+        anno.add_directory_annotation( annotation( "k", "2", 20 ) ).unwrap();
+        anno.add_directory_annotation( annotation( "k", "1", 10 ) ).unwrap();
+        anno.add_directory_annotation( annotation( "k", "2", 30 ) ).unwrap();
+        anno.add_directory_annotation( annotation( "other", "x", 40 ) ).unwrap();
+        anno.add_file_annotation( "a", annotation( "k", "f", 50 ) ).unwrap();
+        assert_eq!( anno.get_directory_annotation( "k" ).map( |a| a.created ), Some( Some( Timestamp::from_seconds( 30 ) ) ) );
+        assert_eq!( anno.get_file_annotation( "a", "k" ).map( |a| a.value.as_str() ), Some( "f" ) );
+        assert!( anno.get_directory_annotation( "missing" ).is_none() );
+        let history: Vec<&str> = anno.directory_annotation_history( "k" ).iter().map( |a| a.value.as_str() ).collect();
+        assert_eq!( history, vec![ "1", "2", "2" ] );
+
+        assert_eq!( anno.remove_directory_annotation( "k", "2" ).unwrap(), 2 );
+        assert_eq!( anno.remove_directory_annotation( "k", "2" ).unwrap(), 0 );
+        assert!( !anno.remove_nth_directory_annotation( "k", 1 ).unwrap() );
+        assert!( anno.remove_nth_directory_annotation( "k", 0 ).unwrap() );
+        assert_eq!( anno.get_directory_annotations().len(), 1 );
+
+        anno.add_directory_annotation( annotation( "fixed", "x", 60 ).with_protected( true ) ).unwrap();
+        anno.add_directory_tag( "raw" ).unwrap();
+        match anno.drop_directory_annotations() {
+            Err( AnnoError::Protected( key ) ) => assert_eq!( key, "fixed" ),
+            other => panic!( "protected annotation dropped: {:?}", other )
+        }
+        assert_eq!( anno.get_directory_annotations().len(), 2 );
+        anno.set_override_protection( true );
+        assert!( anno.drop_directory_annotations().unwrap() );
+        assert!( anno.get_directory_annotations().is_empty() && anno.get_directory_tags().is_empty() );
+        assert!( !anno.drop_directory_annotations().unwrap() );
+        assert_eq!( anno.get_files(), vec![ "a" ] );
+    }
+
+    #[test]
+    fn protected_file_entries() {
+        use std::path::Path;
+        use super::{Annovate, Annotation, AnnoError};
+        let annotation = |key: &str, value: &str| Annotation::new( key.to_string(), value.to_string(), "c".to_string() );
+        let mut anno = Annovate::from_json( "{}", Path::new( ".annovate" ) ).unwrap();
+        anno.add_file_annotation( "a", annotation( "id", "1" ).with_protected( true ) ).unwrap();
+        anno.add_file_annotation( "a", annotation( "status", "new" ) ).unwrap();
+        for result in vec![ anno.remove_file_annotation_entries( "a", "id" ).map( |_| () ),
+                            anno.remove_file_annotation( "a", "id", "1" ).map( |_| () ),
+                            anno.remove_nth_file_annotation( "a", "id", 0 ).map( |_| () ),
+                            anno.drop_file_annotations( "a" ).map( |_| () ) ] {
+            match result {
+                Err( AnnoError::Protected( key ) ) => assert_eq!( key, "id" ),
+                other => panic!( "protected annotation removed: {:?}", other )
+            }
+        }
+        assert_eq!( anno.get_file_annotations( "a" ).map( |a| a.len() ), Some( 2 ) );
+        //entries that are not protected can still be removed
+        assert!( anno.remove_file_annotation_entries( "a", "status" ).unwrap() );
+        anno.set_override_protection( true );
+        assert_eq!( anno.remove_file_annotation( "a", "id", "1" ).unwrap(), 1 );
+        assert!( anno.get_files().is_empty() );
+    }
+
+    #[test]
+    fn retain_and_map() {
+        use std::path::Path;
+        use super::{Annovate, Annotation, AnnoError};
+        use substitute::Substitution;
+        assert_eq!( Substitution::parse( "s/.*/\\U&/" ).unwrap().apply( "alice" ), "ALICE" );
+        assert_eq!( Substitution::parse( "s/(\\w+)@(\\w+)/\\2 \\L\\1\\E!/" ).unwrap().apply( "BOB@home" ), "home bob!" );
+        assert_eq!( Substitution::parse( "s/a/b/" ).unwrap().apply( "aaa" ), "baa" );
+        assert_eq!( Substitution::parse( "s/A/b/gi" ).unwrap().apply( "aAa" ), "bbb" );
+        assert_eq!( Substitution::parse( "s|/old/|/new/\\||" ).unwrap().apply( "/old/x" ), "/new/|x" );
+        assert_eq!( Substitution::parse( "s/x/\\&\\n/" ).unwrap().apply( "x" ), "&\n" );
+        assert!( Substitution::parse( "y/a/b/" ).is_err() );
+        assert!( Substitution::parse( "s/a/b" ).is_err() );
+        assert!( Substitution::parse( "s/a/b/q" ).is_err() );
+        assert!( Substitution::parse( "s/(/b/" ).is_err() );
+
+        let mut anno = Annovate::from_json( "{}", Path::new( ".annovate" ) ).unwrap();
+        let annotation = |key: &str, value: &str| Annotation::new( key.to_string(), value.to_string(), "c".to_string() );
+        anno.add_directory_annotation( annotation( "owner", "Alice" ) ).unwrap();
+        anno.add_file_annotation( "a", annotation( "owner", "BOB" ) ).unwrap();
+        anno.add_file_annotation( "a", annotation( "size", "10" ) ).unwrap();
+        anno.add_file_annotation( "b", annotation( "size", "20" ) ).unwrap();
+        let changed = anno.map_values( |_, key, value| if key == "owner" { value.to_lowercase() } else { value.to_string() } ).unwrap();
+        assert_eq!( changed, 2 );
+        assert_eq!( anno.get_directory_annotation( "owner" ).unwrap().value, "alice" );
+        assert_eq!( anno.get_file_annotation( "a", "owner" ).unwrap().value, "bob" );
+
+        let mut targets = Vec::new();
+        assert_eq!( anno.retain_annotations( |file, a| { targets.push( file.map( |f| f.to_string() ) ); a.key != "size" } ).unwrap(), 2 );
+        assert_eq!( targets, vec![ None, Some( "a".to_string() ), Some( "a".to_string() ), Some( "b".to_string() ) ] );
+        assert_eq!( anno.get_files(), vec![ "a" ] );
+
+        //a protected annotation stops the change as a whole
+        anno.add_file_annotation( "a", annotation( "id", "7" ).with_protected( true ) ).unwrap();
+        match anno.retain_annotations( |_, _| false ) {
+            Err( AnnoError::Protected( key ) ) => assert_eq!( key, "id" ),
+            other => panic!( "protected annotation removed: {:?}", other )
+        }
+        assert!( anno.map_values( |_, _, _| String::new() ).is_err() );
+        assert_eq!( anno.get_file_annotations( "a" ).unwrap().len(), 2 );
+        assert_eq!( anno.get_directory_annotations().len(), 1 );
+    }
+
+    #[test]
+    fn progress_handler() {
+        use std::cell::RefCell;
+        use std::env;
+        use std::fs::{self, File};
+        use std::io::Write;
+        use std::rc::Rc;
+        use std::time::Duration;
+        use super::{Annovate, Annotation, OpenMode, ParseMode};
+        use progress::{self, Progress, ProgressStage};
+
+        let path = env::temp_dir().join( format!( "annovate-test-progress-{}", ::std::process::id() ) );
+        let value = "x".repeat( 100 * 1024 );
+        File::create( &path ).unwrap().write_all( format!( ">k\n={}\n<c\n", value ).as_bytes() ).unwrap();
+        let size = fs::metadata( &path ).unwrap().len();
+
+        let reports: Rc<RefCell<Vec<Progress>>> = Rc::new( RefCell::new( Vec::new() ) );
+        let recorded = reports.clone();
+        let mut anno = Annovate::open_with_progress( &path, OpenMode::ReadWrite, ParseMode::Strict, Duration::from_secs( 0 ),
+                                                     Rc::new( move |p: &Progress| recorded.borrow_mut().push( *p ) ) ).unwrap();
+        assert_eq!( reports.borrow().len(), 3 ); //two chunks and the end
+        assert!( reports.borrow().iter().all( |p| p.stage == ProgressStage::Read ) );
+        assert!( !reports.borrow()[ 1 ].is_finished() );
+        assert_eq!( reports.borrow()[ 2 ], Progress { stage: ProgressStage::Read, done: size, total: Some( size ) } );
+
+        reports.borrow_mut().clear();
+        anno.add_file_annotation( "a", Annotation::new( "k".to_string(), "v".to_string(), "c".to_string() ) ).unwrap();
+        anno.save().unwrap();
+        let size = fs::metadata( &path ).unwrap().len();
+        assert_eq!( reports.borrow().last(), Some( &Progress { stage: ProgressStage::Save, done: size, total: Some( size ) } ) );
+        assert!( reports.borrow().iter().all( |p| p.stage == ProgressStage::Save && p.done <= size ) );
+        drop( anno );
+        fs::remove_file( &path ).unwrap();
+        let _ = fs::remove_file( ::journal::journal_path( &path ) );
+
+        //without a total, it is known at the end
+        let ends = RefCell::new( Vec::new() );
+        progress::read_with_progress( "abc".as_bytes(), None, Some( &|p: &Progress| ends.borrow_mut().push( ( p.done, p.total ) ) ) ).unwrap();
+        assert_eq!( ends.into_inner(), vec![ ( 3, None ), ( 3, Some( 3 ) ) ] );
+    }
+
+    #[test]
+    fn change_log() {
+        use std::path::Path;
+        use super::{Annovate, Annotation};
+        use changelog::{self, CHANGELOG_KEY};
+        use changes::ChangeSet;
+        let mut anno = Annovate::from_json( "{}", Path::new( ".annovate" ) ).unwrap();
+        let before = anno.snapshot();
+        anno.add_file_annotation( "a.csv", Annotation::new( "reviewed".to_string(), "yes".to_string(), "c".to_string() ) ).unwrap();
+        anno.add_tag( "a.csv", "raw" ).unwrap();
+        let details = changelog::describe_changes( &ChangeSet::between( &before, &anno ) );
+        assert_eq!( details, "a.csv: added `reviewed`\na.csv: tagged +raw" );
+        anno.record_change_in_context( "Review the raw data", &details, "c" ).unwrap();
+        anno.record_change( "Second", "" ).unwrap();
+        assert!( anno.record_change( "two\nlines", "" ).is_err() );
+        assert!( anno.record_change( " ", "" ).is_err() );
+
+        let log = anno.change_log();
+        assert_eq!( log.len(), 2 );
+        assert_eq!( ( log[ 0 ].summary.as_str(), log[ 0 ].details.as_str(), log[ 0 ].context.as_str() ), ( "Review the raw data", details.as_str(), "c" ) );
+        assert_eq!( ( log[ 1 ].summary.as_str(), log[ 1 ].details.as_str() ), ( "Second", "" ) );
+        assert!( log[ 1 ].context.starts_with( "annovate library" ) && log[ 1 ].created.is_some() );
+
+        //the entries are protected
+        assert!( anno.remove_directory_annotation( CHANGELOG_KEY, "Second" ).is_err() );
+        anno.set_override_protection( true );
+        assert_eq!( anno.remove_directory_annotation( CHANGELOG_KEY, "Second" ).unwrap(), 1 );
+        assert_eq!( anno.change_log().len(), 1 );
+    }
+
+    #[cfg(feature = "ffi")]
+    #[test]
+    fn ffi_roundtrip() {
+        use std::env;
+        use std::ffi::{CStr, CString};
+        use std::fs;
+        use std::ptr;
+        use ffi::*;
+
+        let path = env::temp_dir().join( format!( "annovate-test-ffi-{}", ::std::process::id() ) );
+        let c_path = CString::new( path.to_string_lossy().into_owned() ).unwrap();
+        let ( file, key, value ) = ( CString::new( "a.csv" ).unwrap(), CString::new( "owner" ).unwrap(), CString::new( "alice" ).unwrap() );
+        unsafe {
+            let mut anno = ptr::null_mut();
+            assert_eq!( annovate_open( c_path.as_ptr(), 0, &mut anno ), ANNOVATE_OK );
+            let mut found = ptr::null_mut();
+            assert_eq!( annovate_get( anno, file.as_ptr(), key.as_ptr(), &mut found ), ANNOVATE_NOT_FOUND );
+            assert!( !annovate_last_error().is_null() );
+            assert_eq!( annovate_put( anno, file.as_ptr(), key.as_ptr(), value.as_ptr(), ptr::null() ), ANNOVATE_OK );
+            assert_eq!( annovate_put( anno, file.as_ptr(), ptr::null(), value.as_ptr(), ptr::null() ), ANNOVATE_INVALID_ARGUMENT );
+            assert_eq!( annovate_save( anno ), ANNOVATE_OK );
+            annovate_close( anno );
+
+            let mut anno = ptr::null_mut();
+            assert_eq!( annovate_open( c_path.as_ptr(), 1, &mut anno ), ANNOVATE_OK );
+            assert_eq!( annovate_get( anno, file.as_ptr(), key.as_ptr(), &mut found ), ANNOVATE_OK );
+            assert_eq!( CStr::from_ptr( found ).to_str().unwrap(), "alice" );
+            annovate_free_string( found );
+            assert_eq!( annovate_put( anno, ptr::null(), key.as_ptr(), value.as_ptr(), ptr::null() ), ANNOVATE_READ_ONLY );
+            annovate_close( anno );
+        }
+        fs::remove_file( &path ).unwrap();
+        let _ = fs::remove_file( ::journal::journal_path( &path ) );
+    }
+
+    #[test]
+    fn put_policies() {
+        use std::path::Path;
+        use super::{Annovate, Annotation, PutPolicy};
+        let mut anno = Annovate::from_json( "{}", Path::new( ".annovate" ) ).unwrap();
+        let annotation = |value: &str| Annotation::new( "k".to_string(), value.to_string(), "c".to_string() );
+        let values = |anno: &Annovate| -> Vec<String> { anno.get_file_annotations( "a" ).unwrap().iter().map( |a| a.value.clone() ).collect() };
+        assert!( anno.put_file_annotation( "a", annotation( "1" ), PutPolicy::IfAbsent ).unwrap() );
+        assert!( !anno.put_file_annotation( "a", annotation( "2" ), PutPolicy::IfAbsent ).unwrap() );
+        assert!( anno.put_file_annotation( "a", annotation( "2" ), PutPolicy::default() ).unwrap() );
+        assert_eq!( values( &anno ), vec![ "1", "2" ] );
+        anno.add_file_annotation( "a", Annotation::new( "other".to_string(), "x".to_string(), "c".to_string() ) ).unwrap();
+        assert!( anno.put_file_annotation( "a", annotation( "3" ), PutPolicy::Replace ).unwrap() );
+        assert_eq!( values( &anno ), vec![ "x", "3" ] );
+
+        anno.put_directory_annotation( annotation( "d" ).with_protected( true ), PutPolicy::Replace ).unwrap();
+        assert!( anno.put_directory_annotation( annotation( "e" ), PutPolicy::Replace ).is_err() );
+        assert!( !anno.put_directory_annotation( annotation( "e" ), PutPolicy::IfAbsent ).unwrap() );
+        anno.add_file_annotation( "b", annotation( "p" ).with_protected( true ) ).unwrap();
+        assert!( anno.put_file_annotation( "b", annotation( "q" ), PutPolicy::Replace ).is_err() );
+        assert_eq!( anno.get_file_annotations( "b" ).unwrap().len(), 1 );
+        anno.set_override_protection( true );
+        assert!( anno.put_directory_annotation( annotation( "e" ), PutPolicy::Replace ).unwrap() );
+        assert_eq!( anno.get_directory_annotations().len(), 1 );
+    }
+
+    #[test]
+    fn unicode_normalization() {
+        use std::path::Path;
+        use super::{Annovate, Annotation};
+        use config::Config;
+        use doctor::{self, ProblemKind};
+        use normalization::{self, Normalization};
+        let ( composed, decomposed ) = ( "caf\u{e9}.txt", "cafe\u{301}.txt" );
+        assert_eq!( Normalization::Nfc.apply( decomposed ), composed );
+        assert_eq!( Normalization::Nfd.apply( composed ), decomposed );
+        assert_eq!( Normalization::Keep.apply( decomposed ), decomposed );
+        assert!( normalization::differ_only_in_normalization( composed, decomposed ) );
+        assert!( !normalization::differ_only_in_normalization( composed, composed ) );
+        assert_eq!( Config::parse( "normalization = \"nfd\"" ).unwrap().defaults.normalization(), Normalization::Nfd );
+        assert!( Config::parse( "normalization = \"nfkc\"" ).is_err() );
+
+        let mut anno = Annovate::from_json( "{}", Path::new( ".annovate" ) ).unwrap();
+        let annotation = |value: &str| Annotation::new( "k".to_string(), value.to_string(), "c".to_string() );
+        anno.add_file_annotation( composed, annotation( "linux" ) ).unwrap();
+        anno.add_file_annotation( &format!( "{}#L1-2", decomposed ), annotation( "part" ) ).unwrap();
+        anno.add_file_annotation( decomposed, annotation( "mac" ) ).unwrap();
+        assert_eq!( anno.normalize_filenames( Normalization::Nfc ).unwrap(), 1 );
+        let values: Vec<&str> = anno.get_file_annotations( composed ).unwrap().iter().map( |a| a.value.as_str() ).collect();
+        assert_eq!( values, vec![ "linux", "mac" ] );
+        assert!( anno.get_file_annotations( &format!( "{}#L1-2", composed ) ).is_some() );
+        assert!( anno.get_file_annotations( decomposed ).is_none() );
+        assert_eq!( anno.normalize_filenames( Normalization::Nfc ).unwrap(), 0 );
+
+        let text = format!( "@{}\n>k\n=linux\n<c\n@{}\n>k\n=mac\n<c\n", composed, decomposed );
+        let checkup = doctor::examine( text.as_bytes() );
+        assert_eq!( checkup.problems.len(), 1 );
+        assert_eq!( checkup.problems[ 0 ].kind, ProblemKind::NormalizationDuplicate( decomposed.to_string(), composed.to_string(), 1 ) );
+        assert_eq!( checkup.repaired, format!( "@{}\n>k\n=linux\n<c\n>k\n=mac\n<c\n", composed ) );
+    }
+
+    #[test]
+    fn input_validation() {
+        use std::path::Path;
+        use super::{Annovate, Annotation, AnnoError, Backend, KeyScope, LineBackend, PutPolicy};
+        use validate;
+        let annotation = |key: &str, value: &str, context: &str| Annotation::new( key.to_string(), value.to_string(), context.to_string() );
+        let mut anno = Annovate::from_json( "{}", Path::new( ".annovate" ) ).unwrap();
+        anno.add_file_annotation( "a", annotation( "notes", "two\nlines\twith a tab\r\n\u{7}", "c" ) ).unwrap();
+        for &( key, value, context ) in &[ ( "two\nlines", "v", "c" ), ( "", "v", "c" ), ( "key ", "v", "c" ), ( "k\u{7}", "v", "c" ),
+                                         ( "k", "v", "c\nx" ), ( "k", "v", "c\t" ) ] {
+            match anno.add_file_annotation( "a", annotation( key, value, context ) ) {
+                Err( AnnoError::InvalidInput { .. } ) => {},
+                other => panic!( "{:?} {:?} {:?} were accepted: {:?}", key, value, context, other )
+            }
+            assert!( anno.add_directory_annotation( annotation( key, value, context ) ).is_err() );
+        }
+        assert!( anno.add_file_annotation( &"k".repeat( validate::MAX_KEY_LENGTH + 1 ), annotation( "k", "v", "c" ) ).is_ok() );
+        assert!( anno.add_file_annotation( "a", annotation( &"k".repeat( validate::MAX_KEY_LENGTH + 1 ), "v", "c" ) ).is_err() );
+        assert!( anno.add_file_annotation( "b\nc", annotation( "k", "v", "c" ) ).is_err() );
+        assert!( anno.add_tag( "", "raw" ).is_err() );
+        //nothing is replaced by a rejected annotation
+        assert!( anno.put_file_annotation( "a", annotation( "notes", "v", "\n" ), PutPolicy::Replace ).is_err() );
+        assert_eq!( anno.get_file_annotations( "a" ).unwrap().len(), 1 );
+        assert!( anno.rename_file( "a", "b\n" ).is_err() );
+        assert!( anno.rename_key( "notes", "no\ntes", KeyScope::All, None ).is_err() );
+        assert!( anno.map_values( |_, _, _| "v".repeat( validate::MAX_VALUE_LENGTH + 1 ) ).is_err() );
+        assert_eq!( anno.get_file_annotation( "a", "notes" ).unwrap().value, "two\nlines\twith a tab\r\n\u{7}" );
+        let err = anno.add_directory_annotation( annotation( "a\nb", "v", "c" ) ).unwrap_err();
+        assert_eq!( err.to_string(), "Invalid key \"a\\nb\": the key contains a line break" );
+
+        //what was accepted survives saving and loading
+        assert_eq!( LineBackend.parse( &LineBackend.serialize( &anno, None ) ).unwrap(), anno.to_data() );
+    }
+
+    #[test]
+    fn subdirectory_entries() {
+        use std::env;
+        use std::fs::{self, File};
+        use std::path::Path;
+        use super::{Annovate, Annotation, AnnoError, Backend, LineBackend, PutPolicy};
+        use doctor::{self, ProblemKind};
+        use report::{self, FileKind, ReportOptions, ReportStatus};
+        let annotation = |value: &str| Annotation::new( "source".to_string(), value.to_string(), "c".to_string() );
+        let text = ">k\n=v\n<c\n@data/\n+raw\n>source\n=lab\n<c\n@data.csv\n>source\n=export\n<c\n";
+        let mut anno = Annovate::from_data( LineBackend.parse( text ).unwrap(), Path::new( ".annovate" ) );
+        assert_eq!( anno.get_subdirectories(), vec![ "data" ] );
+        assert_eq!( anno.get_subdirectory_annotation( "data/", "source" ).unwrap().value, "lab" );
+        assert!( anno.get_subdirectory_annotations( "data.csv" ).is_none() );
+        assert!( anno.get_tags( "data/" ).unwrap().contains( "raw" ) );
+        assert_eq!( LineBackend.serialize( &anno, None ), text );
+
+        assert!( anno.put_subdirectory_annotation( "data", annotation( "field" ), PutPolicy::Replace ).unwrap() );
+        anno.add_subdirectory_annotation( "plots", annotation( "script" ) ).unwrap();
+        assert!( anno.rename_subdirectory( "plots", "figures/" ).unwrap() );
+        assert_eq!( anno.get_subdirectories(), vec![ "data", "figures" ] );
+        assert_eq!( anno.get_subdirectory_annotations( "data" ).unwrap().len(), 1 );
+        assert!( anno.remove_subdirectory_annotation_entries( "figures", "source" ).unwrap() );
+        assert_eq!( anno.get_subdirectories(), vec![ "data" ] );
+        for name in &[ "/", "data/#L1-2" ] {
+            match anno.add_file_annotation( name, annotation( "v" ) ) {
+                Err( AnnoError::InvalidInput { .. } ) => {},
+                other => panic!( "{} was accepted: {:?}", name, other )
+            }
+        }
+        //a subdirectory has no parts
+        assert!( LineBackend.parse( "@data/\n#L1-2\n>k\n=v\n<c\n" ).is_err() );
+        let checkup = doctor::examine( b"@data/\n>k\n=v\n<c\n#L1-2\n>k\n=w\n<c\n" );
+        assert_eq!( checkup.problems.len(), 1 );
+        assert_eq!( checkup.problems[ 0 ].kind, ProblemKind::InvalidFragment( "L1-2".to_string() ) );
+        assert_eq!( checkup.repaired, "@data/\n>k\n=v\n<c\n" );
+
+        let dir = env::temp_dir().join( format!( "annovate-test-subdirs-{}", ::std::process::id() ) );
+        fs::create_dir_all( dir.join( "data" ) ).unwrap();
+        fs::create_dir_all( dir.join( "plain" ) ).unwrap();
+        File::create( dir.join( "data.csv" ) ).unwrap();
+        File::create( dir.join( "notes" ) ).unwrap();
+        anno.add_subdirectory_annotation( "notes", annotation( "v" ) ).unwrap();
+        anno.add_file_annotation( "plain", annotation( "v" ) ).unwrap();
+        let lines: Vec<( String, ReportStatus, Option<FileKind>, bool )> = report::report_directory( &anno, &dir, Path::new( "" ), ReportOptions::default() ).unwrap()
+            .lines.into_iter().map( |l| ( l.name.clone(), l.status, l.kind, l.is_subdirectory_entry() ) ).collect();
+        assert_eq!( lines, vec![ ( "data.csv".to_string(), ReportStatus::Annotated, Some( FileKind::File ), false ),
+                                 ( "data/".to_string(), ReportStatus::Annotated, Some( FileKind::Dir ), true ),
+                                 ( "notes".to_string(), ReportStatus::Unannotated, Some( FileKind::File ), false ),
+                                 ( "notes/".to_string(), ReportStatus::Missing, None, true ),
+                                 ( "plain".to_string(), ReportStatus::Annotated, Some( FileKind::Dir ), false ) ] );
+        fs::remove_dir_all( &dir ).unwrap();
+    }
+
+    #[test]
+    fn inherited_annotations() {
+        use std::env;
+        use std::fs::{self, File};
+        use std::io::Write;
+        use super::Annotation;
+        use inherit;
+        let dir = env::temp_dir().join( format!( "annovate-test-inherit-{}", ::std::process::id() ) );
+        fs::create_dir_all( dir.join( "sub/deep" ) ).unwrap();
+        File::create( dir.join( ".annovate" ) ).unwrap().write_all( b">project\n=outer\n<c\n>stage\n=none\n<c\n@sub/\n>stage\n=entry\n<c\n" ).unwrap();
+        File::create( dir.join( "sub/.annovate" ) ).unwrap().write_all( b">stage\n=raw\n<c\n@deep/\n>depth\n=2\n<c\n@deep/f.txt\n>own\n=yes\n<c\n" ).unwrap();
+
+        let owner = inherit::find_owner( &dir.join( "sub/deep/f.txt" ), ".annovate" ).unwrap();
+        assert_eq!( owner.meta_file, dir.canonicalize().unwrap().join( "sub/.annovate" ) );
+        assert_eq!( owner.name, "deep/f.txt" );
+        assert_eq!( inherit::find_owner( &dir.join( "sub/../x.txt" ), ".annovate" ).unwrap().name, "x.txt" );
+        assert!( inherit::find_owner( &dir.join( "missing/x.txt" ), ".annovate" ).is_none() );
+
+        let values = |annotations: &[Annotation]| annotations.iter().map( |a| format!( "{}={}", a.key, a.value ) ).collect::<Vec<String>>();
+        let inherited = inherit::inherited_annotations( &owner.meta_file, Some( &owner.name ) ).unwrap();
+        assert_eq!( values( &inherited ), vec![ "project=outer", "stage=none", "stage=entry", "stage=raw", "depth=2" ] );
+        //the directory of the meta file inherits from the directories above
+        let inherited = inherit::inherited_annotations( &owner.meta_file, None ).unwrap();
+        assert_eq!( values( &inherited ), vec![ "project=outer", "stage=none", "stage=entry" ] );
+        fs::remove_dir_all( &dir ).unwrap();
+    }
+
 }
@@ -0,0 +1,110 @@
+use std::cmp;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use AnnoError;
+
+/// How long `Annovate::new` waits for another process to release the lock
+pub const DEFAULT_LOCK_TIMEOUT: Duration = Duration::from_secs( 10 );
+
+const RETRY_INTERVAL: Duration = Duration::from_millis( 50 );
+
+//lock files of this process, so that they can be removed before `process::exit`, which skips destructors
+static HELD: Mutex<Vec<PathBuf>> = Mutex::new( Vec::new() );
+
+/// Lock file that guards `path` against concurrent modification: `<name>.lock` next to it
+pub fn lock_path( path: &Path ) -> PathBuf {
+    let mut name = path.file_name().map( |n| n.to_os_string() ).unwrap_or_default();
+    name.push( ".lock" );
+    path.with_file_name( name )
+}
+
+/// Whether the process with the id `pid` is running. Where this cannot be found out, it is assumed to run.
+#[cfg(target_os = "linux")]
+fn is_running( pid: u32 ) -> bool {
+    Path::new( "/proc" ).join( pid.to_string() ).exists()
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn is_running( pid: u32 ) -> bool {
+    use std::process::{Command, Stdio};
+    Command::new( "ps" ).args( &[ "-p", &pid.to_string() ] ).stdout( Stdio::null() ).stderr( Stdio::null() ).status()
+                       .map( |status| status.success() ).unwrap_or( true )
+}
+
+#[cfg(not(unix))]
+fn is_running( _pid: u32 ) -> bool {
+    true
+}
+
+/// Whether the lock file `lock` was left behind by a process that no longer runs. A lock file without a
+/// process id, e.g. one that its owner is still writing, is not stale.
+pub fn is_stale( lock: &Path ) -> bool {
+    let pid = match fs::read_to_string( lock ).ok().and_then( |text| text.trim().parse::<u32>().ok() ) {
+        Some( pid ) => pid,
+        None => return false
+    };
+    pid != process::id() && !is_running( pid )
+}
+
+/// Advisory lock on a meta file. The lock file contains the id of the owning process and is removed on drop.
+/// The lock file of a process that was killed is broken by the next process that wants the lock.
+#[derive(Debug)]
+pub struct FileLock {
+    path: PathBuf
+}
+
+impl FileLock {
+    /// Create the lock file for `path`, retrying until `timeout` has passed
+    pub fn acquire( path: &Path, timeout: Duration ) -> Result<FileLock, AnnoError> {
+        let lock = lock_path( path );
+        let start = Instant::now();
+        loop {
+            match OpenOptions::new().write( true ).create_new( true ).open( &lock ) {
+                Ok( mut file ) => {
+                    let _ = write!( file, "{}\n", process::id() ); //only informative
+                    HELD.lock().unwrap().push( lock.clone() );
+                    return Ok( FileLock { path: lock } );
+                },
+                Err( ref e ) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    if is_stale( &lock ) {
+                        match fs::remove_file( &lock ) {
+                            //another process may have broken it first
+                            Err( e ) if e.kind() != io::ErrorKind::NotFound => return Err( AnnoError::io( &lock, e ) ),
+                            _ => continue
+                        }
+                    }
+                    let waited = start.elapsed();
+                    if waited >= timeout {
+                        return Err( AnnoError::Locked( lock ) );
+                    }
+                    thread::sleep( cmp::min( RETRY_INTERVAL, timeout - waited ) );
+                },
+                Err( e ) => return Err( AnnoError::io( &lock, e ) )
+            }
+        }
+    }
+
+    pub fn path( &self ) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for FileLock {
+    fn drop( &mut self ) {
+        HELD.lock().unwrap().retain( |p| *p != self.path );
+        let _ = fs::remove_file( &self.path );
+    }
+}
+
+/// Remove all lock files held by this process. Call this before exiting without unwinding.
+pub fn release_all() {
+    for path in HELD.lock().unwrap().drain( .. ) {
+        let _ = fs::remove_file( &path );
+    }
+}
@@ -4,20 +4,68 @@ extern crate docopt;
 
 extern crate annovate;
 
-use std::cmp::max;
-use std::path::Path;
-use std::fs::{DirBuilder,read_dir};
-use std::collections::HashSet;
-use std::io::{stderr,Write};
+use std::path::{Path,PathBuf};
+use std::cell::Cell;
+use std::env;
+use std::fs::{DirBuilder,File,read_dir,remove_file,rename};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{stderr,stdin,BufRead,IsTerminal,Read,Write};
+use std::process::{Command, Stdio};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
 
 use docopt::Docopt;
+use rustc_serialize::json::Json;
 
-use annovate::{Annovate, Annotation, AnnoContainer};
+use annovate::{Annovate, Annotation, AnnoContainer, AnnoError, ContextBuilder, Fragment, KeyMatcher, KeyScope, OpenMode, ParseMode, Pattern, PutPolicy, Target, Timestamp, Value, ValueType, is_meta_companion, is_valid_tag, is_valid_url, looks_like_url, parse_range};
+use annovate::{format_annotation_block, parse_annotation_block};
+use annovate::backend::backend_by_name;
+use annovate::backup::{self, BackupPolicy};
+use annovate::changelog::{self, CHANGELOG_KEY};
+use annovate::changes::{Change, ChangeSet};
+use annovate::config::Config;
+use annovate::copy::CopySpec;
+use annovate::delta;
+use annovate::dictionary::DataDictionary;
+use annovate::doctor;
+use annovate::expiry;
+use annovate::harvest::Harvester;
+use annovate::ids;
+use annovate::inherit;
+use annovate::crypt::{self, Keyring};
+use annovate::identity::IdentityMode;
+use annovate::integrity::{self, IntegrityPolicy};
+use annovate::interpolate::{Resolver, Scope};
+use annovate::lock;
+use annovate::merge::MergeStrategy;
+use annovate::migrate;
+use annovate::namespace::closest_key;
+use annovate::normalization::Normalization;
+use annovate::pattern;
+use annovate::progress::{self, Progress, ProgressStage};
+use annovate::protect;
+use annovate::quota::QuotaWarning;
+use annovate::report::{self, FileKind, ReportOptions, ReportResult, ReportStatus};
+use annovate::set::AnnovateSet;
+use annovate::sort::{self, AnnotationOrder, SortOrder};
+use annovate::tree::AnnovateTree;
+use annovate::watch::{PollingWatcher, Watcher};
+use annovate::search::{ContextFilter, SearchQuery};
+use annovate::substitute::Substitution;
+use annovate::template::{self, Template};
+use annovate::schema::{self, Schema};
+
+mod display;
+mod messages;
+mod output;
+
+use display::{Colors, Style};
+use messages::{Locale, tr};
+use output::{DelimitedOutput, NulOutput, OutputFormat, output_format};
 
 //TODO add support for tap completion as descripted on docopt-rs homepage
 //TODO try out rustfmt
-//TODO maybe add rename function?
-//TODO maybe read metadata from the actual files themselves? Search for annovate tokens in plaintext code files
 
 const USAGE: &'static str = "
 Annovate - manage your files' metadata
@@ -25,62 +73,401 @@ Annovate - manage your files' metadata
 Usage:
   anno help
   anno [options] new <dirname>
-  anno [options] query <filename> [<key>...]
-  anno [options] query-dir [<key>...]
-  anno [options] put <filename> [(<key> <value>)]...
-  anno [options] put-batch <key> <value> [<filename>...]
-  anno [options] put-dir [(<key> <value>)]...
-  anno [options] list [<key>]
+  anno [options] query <filename> [<key>...] [--sort <order>] [--descending] [--offset <n>] [--limit <n>] [--inherit]
+  anno [options] query-dir [<key>...] [--sort <order>] [--descending] [--offset <n>] [--limit <n>] [--inherit]
+  anno [options] put <filename> [(<key> <value>)]... [--type <type>] [--template <name>]
+  anno [options] put <filename> <key> --value-file <path> [--type <type>]
+  anno [options] put-batch <key> <value> [<filename>...] [--type <type>]
+  anno [options] put-dir [(<key> <value>)]... [--type <type>]
+  anno [options] list [<key>] [--sort <order>] [--descending]
+  anno [options] tag <filename> [--] [<tag-change>...]
   anno [options] get <filename> <key>
   anno [options] get-dir <key>
-  anno [options] copy <filename> <filename2> [<key>...]
+  anno [options] get-many <key> [<filename>...]
+  anno [options] history <filename> <key>
+  anno [options] history-dir <key>
+  anno [options] log
+  anno [options] stats
+  anno [options] keys [--file <f>]
+  anno [options] copy <filename> <filename2> [<key>...] [--keys <keys>] [--map <mapping>]
   anno [options] rm-file-key <filename> [<key>...]
+  anno [options] rm-entry <filename> <key> (<value> | --index <n>)
+  anno [options] rm-id <id>
+  anno [options] amend <id> <new-value>
   anno [options] rm-dir-key [<key>...]
+  anno [options] rm-dir-entry <key> (<value> | --index <n>)
   anno [options] drop-file [<filename>...]
-  anno [options] report
+  anno [options] drop-dir
+  anno [options] rename <filename> <new-filename> [--fs]
+  anno [options] rename-key <old-key> <new-key> [--file <f>] [--record]
+  anno [options] apply --exec <expr> [<key>...]
+  anno [options] move-file <filename> --to <other-meta-file>
+  anno [options] edit <filename>
+  anno [options] undo [<count>]
+  anno [options] restore-backup [<number>]
+  anno [options] compact [--keep <n>]
+  anno [options] dedup
+  anno [options] prune-expired
+  anno [options] merge <other-file>
+  anno [options] diff <other-file>
+  anno [options] watch [--template <name>]
+  anno [options] report [--dir <path>] [--verify] [--jobs <n>] [--type <type>] [--follow-links] [--missing] [--orphaned]
+                        [--summary] [--fail-if-missing]
+  anno [options] collect <dir>...
+  anno [options] snapshot [--dir <path>]
+  anno [options] relink [--dir <path>]
+  anno [options] search <pattern> [--regex]
+  anno [options] harvest [<filename>...]
+  anno [options] sync-xattr (--push | --pull)
+  anno [options] import-git
+  anno [options] export-git-notes [<filename>...]
+  anno [options] import-git-notes
+  anno [options] git-hook (install | run)
+  anno [options] dictionary <filename>
+  anno [options] export
+  anno [options] import [<input>]
+  anno [options] doctor [--repair]
+  anno [options] migrate
+  anno [options] validate
+  anno [options] template define <name> <field>...
+  anno [options] template apply <name> <filename>...
+  anno [options] template show [<name>]
+  anno [options] schema define <field>...
+  anno [options] schema show
 
 Options:
-  -a                 Include all metadata entries, including overwritten entries
-  -m <meta-file>     Path to the meta file that should be used (default ./.annovate)
+  -a                 Include all metadata entries, including overwritten and expired entries
+  -m <meta-file>     Path to the meta file that should be used (default ./.annovate, or the name in the
+                     environment variable ANNOVATE_META_NAME)
   -M <meta-outfile>  Path to output meta file. Defaults to whatever -m is
   -d                 Also consider dotfiles when looking for missing metadata (currently not implemented)
+  -r                 Include the meta files of all subdirectories (list, report)
+  --tag <tag>        Only list files with this tag (list)
+  --value-file <path>  Read the value of the key from a file, e.g. a multi-line text (put); the value `-` reads
+                     it from stdin. The final line break is not part of the value.
+  --type <type>      Type of the values (put): text, int, bool, date (YYYY-MM-DD), list (comma separated)
+                     or binary (base64); for report the kind of files to show: file, dir, symlink, broken-link
+                     or other
+  --expires <time>   Let the new metadata of put, put-batch and put-dir expire after a time like 30m, 12h, 7d
+                     or 2w, or at a time like 2026-12-31T00:00:00Z; expired entries are hidden (see prune-expired)
+  --encrypt          Encrypt the new values of put, put-batch and put-dir with the passphrase in ANNOVATE_PASSPHRASE;
+                     query and get decrypt them if it is set (needs the feature `crypt`, which is on by default)
+  --protect          Protect the new metadata of put-dir: rm-dir-key, put-dir, amend and rm-id only change it
+                     with --force
+  --force            Change or remove protected metadata anyway, or use a meta file that does not match its
+                     integrity footer
+  --lines <range>    Let the new metadata of put be about lines of the file, e.g. 10-20 or 7 (its region);
+                     query only shows the metadata whose region or part overlaps these lines
+  --bytes <range>    Like --lines for bytes counted from 0, e.g. 0-511
+  --sort <order>     Sort the files (list): name, value (numbers and dates by their meaning if they have a type),
+                     time (last annotated) or key-count; sort the entries (query, query-dir): key, time or context
+  --descending       Sort in descending order (list, query, query-dir)
+  --offset <n>       Skip the first n entries (query, query-dir; of each part of the file)
+  --limit <n>        Show at most n entries (query, query-dir; of each part of the file)
+  --inherit          Also show the annotations of the directories above (query, query-dir); a closer directory wins.
+                     Without -m, query uses the meta file of the nearest directory above <filename>
+  --strategy <name>  How merge resolves keys with different values: ours, theirs, union
+                     or newest-by-context [default: union]
   -c                 Also print context information
+  --ids              Also print the IDs of the entries (query, query-dir); put, put-batch and put-dir give
+                     every new entry an ID for rm-id and amend
   -C <context>       Specify context for metadata
+  --context-fields <fields>  Parts of the context of new metadata, comma separated:
+                     user, host, branch (git branch), git (commit of HEAD) and time (default: time)
+  --context-git      Add the git branch and commit of HEAD to the context fields
+  --with-message     Also record the earliest commit message (import-git)
+  --format <format>  Format for dictionary (markdown or csv), export and import (json, toml or annovate).
+                     Meta files ending in .toml are always read and written as TOML
+  --repair           Repair the problems found by doctor (the old file is kept as <meta-file>.bak)
+  --append           Append the new metadata of put, put-batch and put-dir to the end of the meta file without
+                     reading or rewriting it (filenames are not expanded); compact or any other change folds them in
+  --replace          Remove the earlier entries of the keys of put, put-batch and put-dir before adding the new ones
+  --if-absent        Only add the new metadata of put, put-batch and put-dir for keys without an entry; without
+                     either option, new entries are added after the earlier ones, which stay in the history
+  --resolve          Replace references like ${dir:project} or ${file:version} in values (query, query-dir, get,
+                     get-dir); `$$` is a literal `$`
+  --lenient          Skip malformed lines of the meta file with a warning instead of failing; saving
+                     drops them (see doctor)
   -1                 Only list the most recent entry for a key
+  --plain            Print unaligned `key: value` lines without padding or headers
+  -0 --print0        Terminate every field with NUL instead of printing lines, for `xargs -0`: key, value
+                     (and context) of query, filename, value (and context) of list and get-many, status and
+                     filename of report, the value of get; no headers or headings
+  --output <format>  Output of query, list, search, history, collect, report, stats and diff: table, plain, csv,
+                     tsv or json (get-many: tsv, csv or json)
+  --template <name>  Template that watch applies to new files or put applies to the file
+  --no-color         Do not color the output (also when the environment variable NO_COLOR is set)
+  --push             Write the annotations to extended attributes of the files (sync-xattr)
+  --pull             Read annotations back from extended attributes (sync-xattr)
+  --dir <path>       Directory that report, snapshot and relink compare with the metadata (default: the directory of
+                     the meta file)
+  --fs               Also rename the file on disk (rename)
+  --file <f>         Only rename the key of this file (rename-key), only list the keys of this file (keys)
+  --to <other-meta-file>  Meta file that move-file moves the annotations to (created if it does not exist)
+  --record           Record the rename in the context of the renamed annotations (rename-key)
+  --exec <expr>      Substitution that apply runs on values like sed: s/pattern/replacement/ with the flags g (every
+                     match) and i (ignore case); & is the match, \\1 a group, \\L and \\U convert what follows to lower
+                     or upper case, e.g. 's/.*/\\L&/'
+  --keys <keys>      Keys that copy copies, comma separated; all arguments after the source file are then destinations
+  --map <mapping>    Names of the copied keys, comma separated, e.g. owner=original_owner or *=upstream.* for
+                     every other key (copy)
+  --hook <command>   Command that gets the new content of the meta file on stdin before it is saved
+                     (and its path in ANNOVATE_FILE); the changes are not saved unless it succeeds
+  --slashes          Store filenames with / instead of \\ as path separator, also those already in the meta file
+                     (always on Windows)
+  --regex            Treat the search pattern (and that of --context-match) as regular expression
+  --ignore-case      Ignore upper and lower case when searching and in the keys of query, query-dir, get, get-dir,
+                     rm-file-key and rm-dir-key
+  --fuzzy            Also find keys of query, query-dir, get, get-dir, rm-file-key and rm-dir-key that are spelled
+                     differently, e.g. `last-modified` for `LastModified` or with a typo
+  --context-match <pattern>  Only show entries whose context contains the pattern (query, query-dir, get, get-dir,
+                     list), e.g. a user or tool; a regular expression with --regex
+  --verify           Mark files whose content changed since the last snapshot with ~ (report)
+  --follow-links     Report symbolic links to existing files and directories as what they point to (report)
+  --jobs <n>         Number of threads that read the meta files and directories for report -r
+                     (default: the number of CPUs)
+  --missing          Only report files without metadata (-) (report)
+  --orphaned         Only report metadata of files that do not exist (+) (report)
+  --summary          Show the number of files of each status instead of the files (report)
+  --fail-if-missing  Exit with status 3 if a file other than the meta file has no metadata (report)
+  --dry-run          Show what a command would change instead of saving the meta file
+  --message <text>   Record what a command changed in the change log of the directory with this summary (see log)
+  --index <n>        Remove the n-th entry of the key, counting from 1 as listed by `query -a` (rm-entry,
+                     rm-dir-entry)
+  --keep <n>         Number of entries of each key that compact keeps [default: 1]
+  --wait <seconds>   Wait this long for another anno process to release the meta file [default: 10]
+  -q --quiet         Do not print warnings
+  --errors <format>  How errors are printed on stderr: text, or json for one object with the fields kind, path, line,
+                     message and code (the exit status) [default: text]
   -h --help          Show this help message
 
+Filenames of put, put-batch, rm-file-key and drop-file may contain the wildcards `*`, `?` and `[...]`
+(quote them for the shell); they match annotated files and files on disk.
+Filenames may address a part of a file: `data.csv#column:age` annotates the column `age` of data.csv
+and `notes.txt#L10-20` annotates the lines 10 to 20 of notes.txt, `data.bin#B0-511`
+its first 512 bytes. Querying a file also shows these parts. A `#` of a filename that would start such a part is
+doubled, e.g. `notes##L5` for the file notes#L5. An entry of the file itself can be about its lines or bytes
+as well (put --lines 10-20 or --bytes 0-511); query shows its region after the key, e.g. `anomaly #L10-20`.
+URLs like `https://example.org/dataset` can be annotated as well to describe related remote resources.
+A filename with a trailing slash like `data/` annotates a subdirectory; report only matches it with a directory.
+Keys may be grouped in namespaces like `quality.score` and `quality.reviewed_by`; the key `quality.*` of query,
+query-dir, rm-file-key and rm-dir-key stands for every key of the namespace `quality`.
+Defaults of the meta-file name, the context fields, colors (color = \"auto\", \"always\" or \"never\"), the key of list
+and -d can be set in ~/.config/annovate/config.toml (or the file in ANNOVATE_CONFIG), also for single directories
+in tables [directories.\"<path>\"]. With integrity = \"warn\" or \"error\" there, meta files end with a hash of their
+content that shows changes outside of annovate and truncated files. With identity = \"hash\", annotated files get
+their checksum, and query and get find the annotations of a renamed file by its content. The values of keys in
+encrypt_keys = [\"secret.*\"] are encrypted like with put --encrypt. With max_per_file = <n> or max_per_key = <n>
+there, put warns about files with more annotations or entries of a key; with quota = \"dedup\", it first removes
+entries with the same key and value as a later one. Messages and this help are in the language of ANNOVATE_LANG,
+then of language = \"de\" or \"en\" there, then of LC_ALL, LC_MESSAGES or LANG.
+A file .annovateignore next to the meta file lists patterns like .gitignore (`*.o`, `build/`, `!keep.o`) of files
+that report, stats, list -r and wildcards in filenames leave out.
+
+Exit status: 0 on success, 1 on errors, 2 for invalid arguments (also keys, values, contexts and filenames with
+line breaks or control characters), 3 if the file, key or entry has no metadata (e.g. get, report --fail-if-missing),
+4 if the meta file is malformed or doctor and validate find problems, 5 if another process holds the meta file.
+With --errors json, the kind of an error is e.g. usage, not-found, parse, locked, protected or invalid-input, and
+path and line are null unless the error names them.
+
 Explanation of subcommands:
   help: Display this help
   new: Create a new directory and put a annovate file into it
   query: List (specific or all) meta-properties of a file
   query-dir: List (specific or all) meta-properties of the directory
-  add: Add key-value pairs for a single file
+  add: Add key-value pairs for a single file. With --template, the fields of the template are added and its blanks asked for (or taken from the given pairs). A value `-` is read from stdin
   add-batch: Add one common key-value pair for several files
   add-dir: Add key-value pairs of the directory corresponding to the meta file
-  list: Show the value for a specific key for several files (default: description)
-  get: Print the value for a single key (and nothing more) for a file
+  list: Show the value for a specific key for several files (default: description, or list_key of the configuration)
+  tag: Add (+tag) or remove (-tag) tags of a file, or show its tags. Put `--` before the first -tag
+  get: Print the most recent value for a single key (and nothing more) for a file; with -a all values, oldest first. Up to version 0.1.1, get printed the oldest value
+  get-many: Print `filename<TAB>value` for all (or the given) files that have the key; `--output json` or `csv` for other formats
+  history: Show every value a key of a file had with its context and time, oldest first
+  history-dir: Show every value a key of the directory had with its context and time, oldest first
+  log: Show the change log that --message records with its context and time, newest first
+  keys: List the keys in use with the number of their entries, e.g. for completion; hidden keys like .sha256 with -a
+  stats: Count annotated files, annotations and the most common keys and list files without annotations; `--output json` for JSON
   get-dir: Print the value for a single key (and nothing more) for the directory
-  copy: Copy key-value pairs from an existing annotation to a new annotation. Context is `copy from filename`
+  copy: Copy key-value pairs from an existing annotation to new annotations of one or more files. Context is `copied from filename`
   rm-file: Remove all annotations for a file that have specific keys
+  rm-entry: Remove only the annotations of a file with this key and value, or the n-th one with the key
+  rm-id: Remove the entry with this ID (shown by query --ids)
+  amend: Replace the value of the entry with this ID; its key, context and time stay the same
   rm-dir: Remove all annotations for the directory that have specific keys
-  drop-file: Remove the metadata of specific files completely
-  report: Show an overview of which files in the current directory have (=) or have not (-) metadata and which files do not exist (+)
+  rm-dir-entry: Remove only the annotations of the directory with this key and value, or the n-th one with the key
+  drop-file: Remove the metadata of specific files completely; protected metadata only with --force
+  drop-dir: Remove the metadata and tags of the directory completely; protected metadata only with --force
+  search: Find annotations whose key, value or context contains a pattern
+  rename: Move all annotations of a file to a new filename
+  move-file: Move the annotations and tags of a file (including its columns, line ranges etc.) to another meta file, e.g. after moving the file to another directory
+  rename-key: Change a key of the directory and all files (or one file with --file), keeping values and history
+  apply: Change the values of the directory and all files (or only those of the given keys) with the substitution of --exec, keeping contexts and times; encrypted values and the keys of binary values stay as they are
+  watch: Wait for new files in the directory and ask for their description (or apply a template)
+  merge: Add the annotations of another meta file, e.g. a diverged copy from another machine
+  diff: Show which keys another meta file added (+), removed (-) or changed, e.g. before merging it
+  undo: Revert the last (or the last <count>) changes of the meta file; the journal keeps the last 50 saves, or `journal = <count>` of the configuration (0 for none)
+  restore-backup: Go back to the most recent (or the given) backup of the meta file; with `backups = <count>` in the configuration, backups are kept as <meta-file>.bak.1, .bak.2, ...
+  compact: Remove all but the most recent (or the --keep most recent) entries of every key of the directory and the files, and fold records appended with --append into the file
+  prune-expired: Remove the entries whose time given with --expires has passed
+  dedup: Remove the entries of the directory and the files that have the same key and value as a later entry
+  edit: Edit the annotations of a file in $VISUAL or $EDITOR (default: vi)
+  report: Show an overview of which files in the current directory have (=) or have not (-) metadata and which files do not exist (+). URLs are not checked. Directories end in /, symbolic links in @, broken links in ! and other special files in |
+  collect: Print the annotations of the meta files of several directories as one table, the filenames prefixed with their directory
+  snapshot: Store the SHA-256 checksum of every annotated file as hidden annotation `.sha256`; `report --verify` marks files that changed since with ~
+  relink: Move the annotations of files that no longer exist to the unannotated file with the same checksum, e.g. after renaming files without anno
+  harvest: Add annotations embedded in comments of files (or all files of the directory), like `// anno: key = value`
+  sync-xattr: Mirror the annotations in extended attributes `user.annovate.<key>` of the files (needs the feature `xattr`)
+  import-git: Annotate all files tracked by git with their first and last commit date and last author
+  export-git-notes: Attach annotations as git notes (refs/notes/annovate) to the files' blobs and the directory annotations to HEAD; parts of files, URLs, subdirectories and missing files are skipped
+  import-git-notes: Read annotations back from git notes; those of the directory from the most recent commit with a note
+  git-hook install: Install a post-commit hook in the git repository that records each commit as directory annotation `snapshot`
+  git-hook run: Record the commit of HEAD as directory annotation `snapshot` (what the hook does)
+  doctor: Check the meta file for structural problems and explain them
+  migrate: Rename meta files of the directory that have a legacy name (.annotave) or the default name to the configured one, merging them if it exists; a journal that cannot be taken over is kept as <name>.log.migrated
+  validate: Check that every file has the keys required by the schema and that their values match its patterns
+  template define: Define a template; each field is `key=value` or just `key` for a blank that is asked for when applying it
+  template apply: Add the fields of a template to files (`{file}` in values is replaced by the filename, `{now}` by the time)
+  template show: Show the definition of one or all templates
+  schema define: Define the schema; each field is `key` for a required key or `key=regex` for a required key whose value must match
+  schema show: Show the definition of the schema
+  export: Print all annotations in a machine readable format (default: json)
+  import: Replace all annotations with the content of a file (or stdin) written by export
+  dictionary: Generate a data dictionary for a CSV/TSV file from its file annotations and the annotations of its columns (<filename>#column:<name>)
 ";
 
+//exit codes, listed under "Exit status" in the help
+const EXIT_ERROR: i32 = 1;
+const EXIT_USAGE: i32 = 2;
+const EXIT_NOT_FOUND: i32 = 3;
+const EXIT_INVALID: i32 = 4;
+const EXIT_LOCKED: i32 = 5;
+
+/// Set by -q, warnings are not printed
+static QUIET: AtomicBool = AtomicBool::new( false );
+
 fn report_warning( msg: &str ) {
+    if QUIET.load( Ordering::Relaxed ) {
+        return;
+    }
     let mut stderr = stderr();
     let _ = stderr.write( b"[WARNING] " );
     let _ = stderr.write( msg.as_bytes() );
     let _ = stderr.write( b"\n" );
 }
 
+/// Warn about the parts of a meta file that a lenient parse skipped
+fn report_parse_warnings( meta_file: &str, warnings: &[doctor::Problem] ) {
+    for problem in warnings {
+        report_warning( &tr( "parse-warning", &[ meta_file, &problem.to_string() ] ) );
+    }
+}
+
 fn report_error( msg: &str ) -> ! {
-    use std::process::exit;
+    exit_with( EXIT_ERROR, msg )
+}
+
+/// Like `report_error` for a failed library call
+fn report_failure( msg: &str, err: &AnnoError ) -> ! {
+    exit_with_error( EXIT_ERROR, msg, err )
+}
+
+/// Set by --errors json, errors are printed as JSON objects
+static JSON_ERRORS: AtomicBool = AtomicBool::new( false );
+
+/// Print an error and exit with `code`, see the constants `EXIT_*`
+fn exit_with( code: i32, msg: &str ) -> ! {
+    fail( code, msg, None )
+}
+
+/// Like `exit_with` for a failed library call, whose kind, file and line --errors json shows
+fn exit_with_error( code: i32, msg: &str, err: &AnnoError ) -> ! {
+    fail( code, msg, Some( err ) )
+}
+
+/// Kind of an error for --errors json that is not the error of a library call
+fn exit_kind( code: i32 ) -> &'static str {
+    match code {
+        EXIT_USAGE => "usage",
+        EXIT_NOT_FOUND => "not-found",
+        EXIT_INVALID => "invalid",
+        EXIT_LOCKED => "locked",
+        _ => "error"
+    }
+}
+
+/// Print an error as text, or as JSON object with --errors json, and exit with `code`
+fn fail( code: i32, msg: &str, err: Option<&AnnoError> ) -> ! {
     let mut stderr = stderr();
-    let _ = stderr.write( b"[ERROR] " );
-    let _ = stderr.write( msg.as_bytes() );
-    let _ = stderr.write( b"\n" );
-    exit( 1 );
+    if JSON_ERRORS.load( Ordering::Relaxed ) {
+        let mut object = BTreeMap::new();
+        object.insert( "kind".to_string(), Json::String( err.map( |e| e.kind() ).unwrap_or( exit_kind( code ) ).to_string() ) );
+        object.insert( "path".to_string(), err.and_then( |e| e.path() ).map( |p| Json::String( p.to_string_lossy().into_owned() ) ).unwrap_or( Json::Null ) );
+        object.insert( "line".to_string(), err.and_then( |e| e.line() ).map( Json::U64 ).unwrap_or( Json::Null ) );
+        object.insert( "message".to_string(), Json::String( msg.to_string() ) );
+        object.insert( "code".to_string(), Json::I64( code as i64 ) );
+        let _ = writeln!( stderr, "{}", Json::Object( object ) );
+    } else {
+        let _ = stderr.write( b"[ERROR] " );
+        let _ = stderr.write( msg.as_bytes() );
+        let _ = stderr.write( b"\n" );
+    }
+    lock::release_all();
+    std::process::exit( code );
+}
+
+/// Exit after an error of opening the meta file `meta_file`
+fn report_open_error( meta_file: &str, err: AnnoError ) -> ! {
+    match err {
+        AnnoError::ParseError { .. } => exit_with_error( EXIT_INVALID, &tr( "run-doctor", &[ &err.to_string() ] ), &err ),
+        AnnoError::Locked( ref lock ) => exit_with_error( EXIT_LOCKED, &tr( "file-locked", &[ meta_file, &lock.to_string_lossy() ] ), &err ),
+        err => report_failure( &err.to_string(), &err )
+    }
+}
+
+/// Progress bar on stderr like `Saving [########------------]  40%`, removed when the stage is finished
+fn draw_progress( progress: &Progress ) {
+    const WIDTH: usize = 20;
+    let label = tr( match progress.stage {
+        ProgressStage::Read => "progress-read",
+        ProgressStage::Save => "progress-save",
+        ProgressStage::Entries => "progress-entries"
+    }, &[] );
+    let line = match ( progress.fraction(), progress.total ) {
+        ( Some( fraction ), Some( total ) ) => {
+            let filled = ( fraction * WIDTH as f64 ) as usize;
+            let count = if progress.stage == ProgressStage::Entries { format!( " {}/{}", progress.done, total ) } else { String::new() };
+            format!( "{} [{}{}] {:3}%{}", label, "#".repeat( filled ), "-".repeat( WIDTH - filled ), ( fraction * 100.0 ) as u32, count )
+        },
+        _ => format!( "{} {}", label, progress.done )
+    };
+    let mut err = stderr();
+    let _ = if progress.is_finished() { write!( err, "\r\x1b[K" ) } else { write!( err, "\r\x1b[K{}", line ) };
+    let _ = err.flush();
+}
+
+/// Warn about the targets that exceed the quota, once per target and limit with the final count
+fn report_quota_warnings( warnings: &[QuotaWarning] ) {
+    let directory_label = tr( "directory-label", &[] );
+    let mut latest: BTreeMap<( Option<&str>, Option<&str> ), &QuotaWarning> = BTreeMap::new();
+    for warning in warnings {
+        latest.insert( ( warning.target.as_ref().map( |t| t.as_str() ), warning.key.as_ref().map( |k| k.as_str() ) ), warning );
+    }
+    for warning in latest.values() {
+        let target = warning.target.as_ref().unwrap_or( &directory_label );
+        match warning.key {
+            Some( ref key ) => report_warning( &tr( "quota-key", &[ target, &warning.count.to_string(), key, &warning.limit.to_string() ] ) ),
+            None => report_warning( &tr( "quota-target", &[ target, &warning.count.to_string(), &warning.limit.to_string() ] ) )
+        }
+    }
+}
+
+/// Result of a library call that changes the annotations, or exit with its error
+fn checked<T>( result: Result<T, AnnoError> ) -> T {
+    result.unwrap_or_else( |e| match e {
+        AnnoError::Protected( ref key ) => report_failure( &tr( "protected-key", &[ key ] ), &e ),
+        AnnoError::InvalidInput { .. } => exit_with_error( EXIT_USAGE, &e.to_string(), &e ),
+        e => report_failure( &e.to_string(), &e )
+    } )
 }
 
 #[derive(Debug, RustcDecodable)]
@@ -96,71 +483,167 @@ struct Args {
     cmd_list: bool,
     cmd_get: bool,
     cmd_get_dir: bool,
+    cmd_get_many: bool,
+    cmd_history: bool,
+    cmd_history_dir: bool,
+    cmd_log: bool,
+    cmd_stats: bool,
+    cmd_keys: bool,
     cmd_report: bool,
+    cmd_snapshot: bool,
+    cmd_relink: bool,
+    cmd_collect: bool,
+    cmd_search: bool,
     cmd_rm_file_key: bool,
     cmd_rm_dir_key: bool,
+    cmd_rm_dir_entry: bool,
+    cmd_rm_entry: bool,
+    cmd_rm_id: bool,
+    cmd_amend: bool,
     cmd_drop_file: bool,
+    cmd_drop_dir: bool,
+    cmd_rename: bool,
+    cmd_rename_key: bool,
+    cmd_move_file: bool,
+    cmd_edit: bool,
+    cmd_undo: bool,
+    cmd_restore_backup: bool,
+    cmd_compact: bool,
+    cmd_dedup: bool,
+    cmd_prune_expired: bool,
+    cmd_merge: bool,
+    cmd_diff: bool,
+    cmd_copy: bool,
+    cmd_watch: bool,
+    cmd_harvest: bool,
+    cmd_sync_xattr: bool,
+    cmd_import_git: bool,
+    cmd_export_git_notes: bool,
+    cmd_import_git_notes: bool,
+    cmd_git_hook: bool,
+    cmd_install: bool,
+    cmd_run: bool,
+    cmd_dictionary: bool,
+    cmd_export: bool,
+    cmd_import: bool,
+    cmd_doctor: bool,
+    cmd_migrate: bool,
+    cmd_template: bool,
+    cmd_schema: bool,
+    cmd_validate: bool,
+    cmd_tag: bool,
+    cmd_define: bool,
+    cmd_apply: bool,
+    cmd_show: bool,
 
     arg_dirname: String,
+    arg_dir: Vec<String>,
     arg_filename: Vec<String>,
     arg_key: Vec<String>,
     arg_value: Vec<String>,
+    arg_name: String,
+    arg_new_filename: String,
+    arg_id: String,
+    arg_new_value: String,
+    arg_old_key: String,
+    arg_new_key: String,
+    arg_field: Vec<String>,
+    arg_tag_change: Vec<String>,
+    arg_count: String,
+    arg_number: String,
+    arg_other_file: String,
+    arg_filename2: String,
+    arg_input: String,
+    arg_pattern: String,
 
     flag_a: bool,
     flag_m: String,
     flag_M: String,
+    flag_wait: u64,
+    flag_index: Option<usize>,
+    flag_keep: usize,
+    flag_offset: Option<usize>,
+    flag_limit: Option<usize>,
+    flag_tag: String,
+    flag_type: String,
+    flag_value_file: String,
+    flag_sort: String,
+    flag_descending: bool,
+    flag_slashes: bool,
+    flag_hook: String,
+    flag_strategy: String,
+    flag_output: String,
+    flag_template: String,
     flag_d: bool,
     flag_c: bool,
+    flag_ids: bool,
     flag_C: String,
+    flag_context_fields: String,
+    flag_with_message: bool,
+    flag_context_git: bool,
+    flag_format: String,
+    flag_repair: bool,
+    flag_lenient: bool,
+    flag_context_match: String,
+    flag_resolve: bool,
+    flag_inherit: bool,
+    flag_quiet: bool,
+    flag_errors: String,
+    flag_append: bool,
+    flag_replace: bool,
+    flag_if_absent: bool,
+    flag_expires: String,
+    flag_protect: bool,
+    flag_encrypt: bool,
+    flag_force: bool,
+    flag_lines: String,
+    flag_bytes: String,
+    flag_plain: bool,
+    flag_print0: bool,
+    flag_no_color: bool,
+    flag_push: bool,
+    flag_pull: bool,
+    flag_dir: String,
+    flag_fs: bool,
+    flag_file: String,
+    flag_record: bool,
+    flag_exec: String,
+    flag_message: String,
+    flag_keys: String,
+    flag_map: String,
+    flag_to: String,
+    flag_dry_run: bool,
+    flag_verify: bool,
+    flag_follow_links: bool,
+    flag_jobs: Option<usize>,
+    flag_missing: bool,
+    flag_orphaned: bool,
+    flag_summary: bool,
+    flag_fail_if_missing: bool,
+    flag_r: bool,
+    flag_regex: bool,
+    flag_ignore_case: bool,
+    flag_fuzzy: bool,
     flag_h: bool,
     flag_help: bool
 }
 
 
-struct ColumnWidths {
-    key: usize,
-    value: usize,
-    context: usize
-}
-
-fn determine_column_widths( container: &AnnoContainer,
-                            padding: usize ) -> ColumnWidths {
-
-    let mut result = ColumnWidths{ key: 0, value: 0, context: 0 };
-    fn num_chars( string: &str ) -> usize {
-        string.chars().count()
-    }
-
-    for annotation in container {
-        result.key = max( num_chars( annotation.key.as_str() ),
-                          result.key );
-        result.context = max( num_chars( annotation.context.as_str() ),
-                              result.context );
-
-        for line in annotation.value.lines() {
-            result.value = max( num_chars( line ), result.value );
-        }
-    }
-    result.key += padding;
-    result.value += padding;
-    result.context += padding;
-    result
-}
-
 fn filter_duplicates( container: &AnnoContainer ) -> AnnoContainer {
     let mut result = AnnoContainer::new();
     let mut seen = HashSet::new();
     for anno in container.iter().rev() {
-        if seen.contains( &anno.key ) {
+        //entries of a key about different regions are no duplicates
+        if seen.contains( &( &anno.key, &anno.region ) ) {
             continue;
         }
-        seen.insert( &anno.key );
+        seen.insert( ( &anno.key, &anno.region ) );
         result.push( anno.clone() );
     }
     result
 }
 
-fn display_anno_container( container: &AnnoContainer, with_context: bool, show_duplicates: bool ) {
+fn display_anno_container( container: &AnnoContainer, with_context: bool, show_duplicates: bool, format: &dyn OutputFormat ) {
     let filtered_container: AnnoContainer;
     let container = if show_duplicates {
         container
@@ -168,264 +651,1814 @@ fn display_anno_container( container: &AnnoContainer, with_context: bool, show_d
         filtered_container = filter_duplicates( container );
         &filtered_container
     };
+    format.print_annotations( container, with_context );
+}
+
+/// Annotations with their region after the key, e.g. `anomaly #L10-20`
+fn label_regions( annotations: AnnoContainer ) -> AnnoContainer {
+    annotations.into_iter().map( |a| {
+        let mut a = a;
+        if let Some( ref region ) = a.region {
+            a.key = format!( "{} #{}", a.key, region );
+        }
+        a
+    } ).collect()
+}
+
+/// Annotations with their ID in front of the key, `-` for those without ID
+fn label_ids( annotations: AnnoContainer ) -> AnnoContainer {
+    annotations.into_iter().map( |a| {
+        let mut a = a;
+        a.key = format!( "{:<width$} {}", a.id.as_ref().map( |id| id.as_str() ).unwrap_or( "-" ), a.key, width = ids::ID_LENGTH );
+        a
+    } ).collect()
+}
+
+/// Whether the command only reads the meta file
+fn is_read_only( args: &Args ) -> bool {
+    args.cmd_list || args.cmd_get_many || args.cmd_history || args.cmd_history_dir || args.cmd_log || args.cmd_stats ||
+    args.cmd_keys || args.cmd_report || args.cmd_search || args.cmd_diff || args.cmd_export_git_notes || args.cmd_export ||
+    args.cmd_dictionary || args.cmd_validate || ( ( args.cmd_template || args.cmd_schema ) && args.cmd_show )
+}
+
+/// The entries that get prints for `key`: the most recent one like query and get-many, or with `all` every one,
+/// oldest first
+fn entries_to_get( annotations: &AnnoContainer, key: &str, matcher: KeyMatcher, all: bool ) -> AnnoContainer {
+    let mut matching: AnnoContainer = annotations.iter().filter( |a| matcher.matches( key, &a.key ) ).cloned().collect();
+    if !all && matching.len() > 1 {
+        matching = matching.split_off( matching.len() - 1 );
+    }
+    matching
+}
+
+/// How the keys given on the command line are compared, see --ignore-case and --fuzzy
+fn key_matcher( args: &Args ) -> KeyMatcher {
+    if args.flag_fuzzy {
+        KeyMatcher::Fuzzy
+    } else if args.flag_ignore_case {
+        KeyMatcher::IgnoreCase
+    } else {
+        KeyMatcher::Exact
+    }
+}
 
-    let widths = determine_column_widths( container, 2 );
-    for annotation in container {
-        display_annotation( annotation, &widths, with_context );
+/// Query for `pattern`, a regular expression with --regex
+fn search_query( args: &Args, pattern: &str ) -> SearchQuery {
+    if args.flag_regex {
+        match SearchQuery::regex( pattern, args.flag_ignore_case ) {
+            Ok( query ) => query,
+            Err( e ) => report_failure( &e.to_string(), &e )
+        }
+    } else {
+        SearchQuery::substring( pattern, args.flag_ignore_case )
     }
 }
 
-fn display_annotation( annotation: &Annotation,
-                       widths: &ColumnWidths,
-                       with_context: bool ) {
+/// The query of --context-match, `None` without it
+fn context_query( args: &Args ) -> Option<SearchQuery> {
+    if args.flag_context_match == "" { None } else { Some( search_query( args, &args.flag_context_match ) ) }
+}
 
-    let dummy_str = String::new();
-    let mut value_lines = annotation.value.lines();
-    let first_line = value_lines.next().unwrap_or( dummy_str.as_str() );
-    print!( "{0:2$}{1:3$}",
-            annotation.key,
-            first_line,
-            widths.key,
-            widths.value );
+/// Abort if a URL target is malformed
+fn check_target( target: &str ) {
+    if looks_like_url( target ) && !is_valid_url( target ) {
+        report_error( &tr( "invalid-url", &[ target ] ) );
+    }
+    //like an invalid --lines, instead of annotating a file whose name ends in the fragment
+    if let Some( fragment ) = Target::malformed_fragment( target ) {
+        exit_with( EXIT_USAGE, &tr( "invalid-target", &[ target, fragment ] ) );
+    }
+}
+
+/// Warn about a meta file that does not match its integrity footer, or refuse it, as configured
+fn check_integrity( meta_file: &str, policy: IntegrityPolicy, force: bool ) {
+    let path = Path::new( meta_file );
+    //a new meta file gets its footer when it is saved
+    if !policy.is_enabled() || force || !path.exists() {
+        return;
+    }
+    match integrity::check_file( path ) {
+        Ok( () ) => {},
+        Err( AnnoError::IntegrityMismatch( _ ) ) if policy == IntegrityPolicy::Warn => report_warning( &tr( "integrity-mismatch", &[ meta_file ] ) ),
+        Err( e @ AnnoError::IntegrityMismatch( _ ) ) => exit_with_error( EXIT_INVALID, &tr( "integrity-mismatch", &[ meta_file ] ), &e ),
+        Err( e ) => report_failure( &e.to_string(), &e )
+    }
+}
 
-    if with_context {
-        println!( "{}", annotation.context );
+/// The region of --lines or --bytes, if one is given
+fn region( args: &Args ) -> Option<Fragment> {
+    let ( range, lines ) = match ( args.flag_lines.as_str(), args.flag_bytes.as_str() ) {
+        ( "", "" ) => return None,
+        ( range, "" ) => ( range, true ),
+        ( "", range ) => ( range, false ),
+        _ => exit_with( EXIT_USAGE, &tr( "lines-and-bytes", &[] ) )
+    };
+    match parse_range( range ) {
+        Some( ( first, last ) ) if lines && first >= 1 => Some( Fragment::Lines( first, last ) ),
+        Some( ( first, last ) ) if !lines => Some( Fragment::Bytes( first, last ) ),
+        _ => exit_with( EXIT_USAGE, &tr( if lines { "invalid-range" } else { "invalid-byte-range" }, &[ range ] ) )
+    }
+}
+
+/// New annotation whose value is checked and normalized according to `value_type`
+fn typed_annotation( key: &str, value: &str, context: &str, value_type: Option<ValueType> ) -> Annotation {
+    match value_type {
+        Some( value_type ) => match Value::parse( value, value_type ) {
+            Ok( parsed ) => Annotation::new( key.to_string(), parsed.to_string(), context.to_string() ).with_type( value_type ),
+            Err( e ) => report_failure( &tr( "invalid-value", &[ &e.to_string() ] ), &e )
+        },
+        None => Annotation::new( key.to_string(), value.to_string(), context.to_string() )
+    }
+}
+
+/// Replace filenames with wildcards by the matching annotated targets and files on disk.
+/// Commands that only change existing metadata pass `annotated_only`.
+fn expand_targets( anno: &Annovate, filenames: &[String], annotated_only: bool ) -> Vec<String> {
+    let mut result = Vec::new();
+    for filename in filenames {
+        if !pattern::is_pattern( filename ) || anno.get_file_annotations( filename ).is_some() {
+            result.push( filename.clone() );
+            continue;
+        }
+        let pattern = match Pattern::new( filename ) {
+            Ok( pattern ) => pattern,
+            Err( e ) => report_failure( &e.to_string(), &e )
+        };
+        let matches: Vec<String> = anno.files_matching( &pattern ).into_iter()
+                                       .filter( |f| !annotated_only || anno.get_file_annotations( f ).is_some() )
+                                       .collect();
+        if matches.is_empty() {
+            report_warning( &tr( "no-match", &[ filename ] ) );
+        }
+        result.extend( matches );
+    }
+    result
+}
+
+/// Ask for a value on stderr and read it from stdin. An empty answer yields `None`.
+fn prompt( question: &str ) -> Option<String> {
+    let mut stderr = stderr();
+    let _ = write!( stderr, "{}: ", question );
+    let _ = stderr.flush();
+    let stdin = stdin();
+    let mut answer = String::new();
+    match stdin.lock().read_line( &mut answer ) {
+        Ok( _ ) if !answer.trim().is_empty() => Some( answer.trim_right_matches( |c| c == '\n' || c == '\r' ).to_string() ),
+        _ => None
+    }
+}
+
+/// Run the `--hook` command with the content that is about to be written to `meta_file`
+fn run_hook( command: &str, meta_file: &str, anno: &Annovate ) -> Result<(), String> {
+    let mut words = command.split_whitespace();
+    let program = words.next().unwrap_or( command );
+    let mut child = match Command::new( program ).args( words ).env( "ANNOVATE_FILE", meta_file ).stdin( Stdio::piped() ).spawn() {
+        Ok( child ) => child,
+        Err( e ) => return Err( tr( "hook-failed", &[ command, &e.to_string() ] ) )
+    };
+    let text = anno.backend().serialize( anno, None );
+    //a hook that does not read its input may already have closed the pipe
+    let _ = child.stdin.take().unwrap().write_all( text.as_bytes() ); //stdin was piped above
+    match child.wait() {
+        Ok( status ) if status.success() => Ok( () ),
+        Ok( status ) => Err( tr( "hook-rejected", &[ command, &status.to_string() ] ) ),
+        Err( e ) => Err( tr( "hook-failed", &[ command, &e.to_string() ] ) )
+    }
+}
+
+/// Let the user edit annotations in the text format until they can be parsed
+fn edit_annotations( annotations: &AnnoContainer ) -> AnnoContainer {
+    let edit_path = env::temp_dir().join( format!( "annovate-edit-{}.txt", std::process::id() ) );
+    let editor = env::var( "VISUAL" ).or( env::var( "EDITOR" ) ).unwrap_or( "vi".to_string() );
+    let mut words = editor.split_whitespace(); //allow editors with arguments like `code --wait`
+    let program = words.next().unwrap_or( "vi" );
+    let editor_args: Vec<&str> = words.collect();
+
+    let mut text = format_annotation_block( annotations );
+    loop {
+        if let Err( e ) = File::create( &edit_path ).and_then( |mut f| f.write_all( text.as_bytes() ) ) {
+            report_error( &tr( "edit-failed", &[ &e.to_string() ] ) );
+        }
+        match Command::new( program ).args( &editor_args ).arg( &edit_path ).status() {
+            Ok( status ) if status.success() => {},
+            _ => {
+                let _ = remove_file( &edit_path );
+                report_error( &tr( "editor-failed", &[ &editor ] ) );
+            }
+        }
+        text.clear();
+        if let Err( e ) = File::open( &edit_path ).and_then( |mut f| f.read_to_string( &mut text ) ) {
+            let _ = remove_file( &edit_path );
+            report_error( &tr( "edit-failed", &[ &e.to_string() ] ) );
+        }
+        match parse_annotation_block( &text ) {
+            Ok( edited ) => {
+                let _ = remove_file( &edit_path );
+                return edited;
+            },
+            Err( e ) => {
+                println!( "{}", e );
+                let again = prompt( &tr( "edit-again", &[] ) ).map( |a| !a.to_lowercase().starts_with( "n" ) ).unwrap_or( true );
+                if !again {
+                    let _ = remove_file( &edit_path );
+                    report_error( &tr( "edit-discarded", &[] ) );
+                }
+            }
+        }
+    }
+}
+
+/// Print which files in `dir` have (=) or lack (-) metadata and which annotated files do not exist (+).
+/// The filenames are prefixed with `prefix`.
+/// With `verify`, annotated files that changed since their checksum was stored are marked with `~` instead of `=`.
+/// With `kind`, only the files of this kind are printed.
+fn print_report( args: &Args, meta_name: &str, result: Result<ReportResult, AnnoError>, kind: Option<FileKind>, format: &dyn OutputFormat ) {
+    let mut result = match result {
+        Ok( result ) => result,
+        Err( e ) => report_failure( &tr( "read-dir-failed", &[ &e.to_string() ] ), &e )
+    };
+    if let Some( kind ) = kind {
+        result = result.with_kind( kind );
+    }
+    //--fail-if-missing looks at every file, also with --orphaned
+    let fail = args.flag_fail_if_missing && result.has_unannotated( meta_name );
+    if args.flag_missing || args.flag_orphaned {
+        let mut statuses = Vec::new();
+        if args.flag_missing {
+            statuses.push( ReportStatus::Unannotated );
+        }
+        if args.flag_orphaned {
+            statuses.push( ReportStatus::Missing );
+        }
+        result = result.with_status( &statuses );
+    }
+    if args.flag_summary {
+        let summary = result.summary();
+        let counts: AnnoContainer = vec![
+            ( "report-annotated", summary.annotated ),
+            ( "report-changed", summary.changed ),
+            ( "report-orphaned", summary.missing ),
+            ( "report-unannotated", summary.unannotated )
+        ].into_iter().map( |( id, count )| Annotation::new( tr( id, &[] ), count.to_string(), String::new() ) ).collect();
+        format.print_annotations( &counts, false );
     } else {
-        println!( "" );
+        for line in &result.lines {
+            format.print_report_line( line.status.marker(), &line.name, line.kind );
+        }
+    }
+    if fail {
+        format.finish();
+        exit_with( EXIT_NOT_FOUND, &tr( "unannotated-files", &[] ) );
+    }
+}
+
+/// Annotate files that appear in the directory of the meta file until the program is interrupted.
+/// The meta file is only locked while the annotations of new files are saved.
+fn watch_directory( meta_file: &str, template_name: &str, context: &str, include_hidden: bool, lock_timeout: Duration ) -> ! {
+    let open = || match Annovate::with_lock_timeout( Path::new( meta_file ), lock_timeout ) {
+        Ok( anno ) => anno,
+        Err( e ) => report_open_error( meta_file, e )
+    };
+    let template = if template_name == "" {
+        None
+    } else {
+        match template::find_template( &open(), template_name ) {
+            Some( t ) => Some( t ),
+            None => report_error( &tr( "unknown-template", &[ template_name ] ) )
+        }
+    };
+    let meta_name = Path::new( meta_file ).file_name().map( |n| n.to_string_lossy().into_owned() ).unwrap_or( String::new() );
+    let dir = meta_directory( meta_file );
+    let mut watcher = match PollingWatcher::new( &dir, Duration::from_secs( 1 ), include_hidden ) {
+        Ok( watcher ) => watcher,
+        Err( e ) => report_failure( &tr( "read-dir-failed", &[ &e.to_string() ] ), &e )
+    };
+    println!( "{}", tr( "watching", &[ &dir.to_string_lossy() ] ) );
+    loop {
+        let new_files = match watcher.next_new_files() {
+            Ok( files ) => files,
+            Err( e ) => report_failure( &tr( "read-dir-failed", &[ &e.to_string() ] ), &e )
+        };
+        for file in new_files.iter().filter( |f| !is_meta_companion( &meta_name, f ) ) {
+            let filename = &Target::file( file ).to_string();
+            println!( "{}", tr( "new-file", &[ filename ] ) );
+            //ask before locking the meta file, answering may take a while
+            let answers: Vec<( String, Option<String> )> = match template {
+                Some( ref t ) => t.blanks().iter().map( |key| ( key.to_string(), prompt( key ) ) ).collect(),
+                None => vec![ ( "description".to_string(), prompt( "description" ) ) ]
+            };
+            let mut anno = open();
+            if anno.get_file_annotations( filename ).is_some() {
+                continue //annotated in the meantime
+            }
+            match template {
+                Some( ref t ) => {
+                    checked( t.apply( &mut anno, &[ filename.clone() ], context, |key| answers.iter().find( |a| a.0 == key ).and_then( |a| a.1.clone() ) ) );
+                },
+                None => if let Some( ref description ) = answers[ 0 ].1 {
+                    checked( anno.add_file_annotation( filename, Annotation::new( "description".to_string(), description.clone(), context.to_string() ).with_created( Timestamp::now() ) ) );
+                }
+            }
+            if anno.save().is_err() {
+                report_error( &tr( "write-failed", &[] ) );
+            }
+        }
+    }
+}
+
+/// Load all meta files below the directory of the meta file
+fn load_tree( meta_file: &str, include_hidden: bool, show_progress: bool ) -> AnnovateTree {
+    let meta_name = Path::new( meta_file ).file_name().map( |n| n.to_string_lossy().into_owned() ).unwrap_or( migrate::meta_name() );
+    let progress: Option<&dyn Fn( &Progress )> = if show_progress { Some( &draw_progress ) } else { None };
+    match AnnovateTree::discover_with_progress( &meta_directory( meta_file ), &meta_name, include_hidden, progress ) {
+        Ok( tree ) => tree,
+        Err( e ) => report_failure( &e.to_string(), &e )
+    }
+}
+
+/// Directory that contains the meta file
+fn meta_directory( meta_file: &str ) -> PathBuf {
+    match Path::new( meta_file ).parent() {
+        Some( dir ) if dir != Path::new( "" ) => dir.to_path_buf(),
+        _ => PathBuf::from( "." )
+    }
+}
+
+/// Diff-style listing of a change set: `+`/`-` lines below the name of each target
+fn print_changes( changes: &ChangeSet, colors: Colors ) {
+    let directory_label = tr( "directory-label", &[] );
+    for target in &changes.targets {
+        println!( "{}", colors.paint( target.target.as_ref().unwrap_or( &directory_label ), Style::Heading ) );
+        for change in &target.changes {
+            let ( marker, style, text ) = match *change {
+                Change::Added( ref a ) => ( "+", Style::Annotated, format!( "{} = {}", a.key, a.value.replace( '\n', "\n      " ) ) ),
+                Change::Removed( ref a ) => ( "-", Style::Missing, format!( "{} = {}", a.key, a.value.replace( '\n', "\n      " ) ) ),
+                Change::TagAdded( ref tag ) => ( "+", Style::Annotated, format!( "+{}", tag ) ),
+                Change::TagRemoved( ref tag ) => ( "-", Style::Missing, format!( "+{}", tag ) )
+            };
+            println!( "  {} {}", colors.paint( marker, style ), text );
+        }
+    }
+}
+
+/// Write the annotations to extended attributes (`push`) or read them back
+#[cfg(feature = "xattr")]
+fn sync_xattr( anno: &mut Annovate, dir: &Path, push: bool, context: &str ) {
+    let result = if push { anno.push_xattrs( dir ) } else { anno.pull_xattrs( dir, context ) };
+    match result {
+        Ok( count ) if push => println!( "{}", tr( "xattr-pushed", &[ &count.to_string() ] ) ),
+        Ok( count ) => println!( "{}", tr( "xattr-pulled", &[ &count.to_string() ] ) ),
+        Err( e ) => report_failure( &tr( "xattr-failed", &[ &e.to_string() ] ), &e )
+    }
+}
+
+#[cfg(not(feature = "xattr"))]
+fn sync_xattr( _anno: &mut Annovate, _dir: &Path, _push: bool, _context: &str ) {
+    report_error( &tr( "xattr-unsupported", &[] ) );
+}
+
+/// Regular files of `dir` except the meta file and its companions, sorted by name
+fn directory_files( dir: &Path, meta_file: &str, include_hidden: bool ) -> Vec<String> {
+    let meta_name = Path::new( meta_file ).file_name().map( |n| n.to_string_lossy().into_owned() ).unwrap_or_default();
+    let entries = match read_dir( dir ) {
+        Ok( entries ) => entries,
+        Err( e ) => report_error( &tr( "read-dir-failed", &[ &e.to_string() ] ) )
+    };
+    let mut files: Vec<String> = entries.filter_map( |e| e.ok() )
+                                        .filter( |e| e.file_type().map( |t| t.is_file() ).unwrap_or( false ) )
+                                        .map( |e| e.file_name().to_string_lossy().into_owned() )
+                                        .filter( |name| ( include_hidden || !name.starts_with( "." ) ) && !is_meta_companion( &meta_name, name ) )
+                                        .map( |name| Target::file( &name ).to_string() )
+                                        .collect();
+    files.sort();
+    files
+}
+
+/// `text` without its final line break
+fn without_final_break( mut text: String ) -> String {
+    if text.ends_with( '\n' ) {
+        text.pop();
+        if text.ends_with( '\r' ) {
+            text.pop();
+        }
+    }
+    text
+}
+
+/// The value of put from `--value-file`, and the value `-` from stdin
+fn read_values( args: &mut Args ) {
+    if args.flag_value_file != "" {
+        if args.arg_key.len() != 1 || !args.arg_value.is_empty() {
+            exit_with( EXIT_USAGE, &tr( "value-file-with-value", &[] ) );
+        }
+        let mut text = String::new();
+        if let Err( e ) = File::open( &args.flag_value_file ).and_then( |mut f| f.read_to_string( &mut text ) ) {
+            report_error( &tr( "read-value-failed", &[ &args.flag_value_file, &e.to_string() ] ) );
+        }
+        args.arg_value.push( without_final_break( text ) );
+    }
+    let from_stdin: Vec<usize> = args.arg_value.iter().enumerate().filter( |&( _, v )| v == "-" ).map( |( i, _ )| i ).collect();
+    if from_stdin.len() > 1 {
+        exit_with( EXIT_USAGE, &tr( "stdin-value-twice", &[] ) );
+    }
+    if let Some( &pos ) = from_stdin.first() {
+        let mut text = String::new();
+        if let Err( e ) = stdin().read_to_string( &mut text ) {
+            report_error( &tr( "read-value-failed", &[ "-", &e.to_string() ] ) );
+        }
+        args.arg_value[ pos ] = without_final_break( text );
+    }
+}
+
+/// The annotated file with the same content as `filename` if `filename` has no annotations itself
+fn find_by_content( filename: &str, meta_file: &str ) -> Option<String> {
+    if Target::parse( filename ).is_sub_file() {
+        return None;
+    }
+    let anno = match Annovate::open( Path::new( meta_file ), OpenMode::ReadOnly ) {
+        Ok( anno ) => anno,
+        Err( err ) => report_open_error( meta_file, err )
+    };
+    if anno.get_file_annotations( filename ).is_some() {
+        return None;
+    }
+    let original = checked( anno.find_by_content( filename ) );
+    if let Some( ref original ) = original {
+        report_warning( &tr( "found-by-content", &[ filename, original ] ) );
+    }
+    original
+}
+
+/// Print annotations of a file or the directory (query, query-dir, get, get-dir) while reading the meta file
+/// as stream, which keeps only the requested targets in memory
+fn stream_lookup( args: &Args, meta_file: &str, identity: IdentityMode, normalization: Normalization, show_context: bool, show_duplicates: bool, output: &dyn OutputFormat ) {
+    let mut target = if args.cmd_query || args.cmd_get { args.arg_filename.get( 0 ).cloned() } else { None }; //getopt ensures that a filename is given
+    //with --inherit, `sub/file.txt` is looked up in `sub/.annovate` if there is one
+    let mut meta_file = meta_file.to_string();
+    if args.flag_inherit && args.flag_m == "" {
+        if let Some( filename ) = target.clone() {
+            let parsed = Target::parse( &filename );
+            let meta_name = Path::new( &meta_file ).file_name().map( |n| n.to_string_lossy().into_owned() ).unwrap_or( migrate::meta_name() );
+            if !parsed.is_remote() {
+                if let Some( owner ) = inherit::find_owner( Path::new( &parsed.file ), &meta_name ) {
+                    target = Some( Target { file: owner.name, fragment: parsed.fragment.clone() }.to_string() );
+                    meta_file = owner.meta_file.to_string_lossy().into_owned();
+                }
+            }
+        }
+    }
+    let meta_file = meta_file.as_str();
+    let path = Path::new( meta_file );
+    if !path.exists() {
+        //like every other command, create the meta file
+        if let Err( e ) = Annovate::new( path ) {
+            report_failure( &e.to_string(), &e );
+        }
+    }
+    if identity == IdentityMode::Hash {
+        target = target.map( |t| find_by_content( &t, meta_file ).unwrap_or( t ) );
+    }
+    let with_sub_targets = args.cmd_query && target.as_ref().map( |t| !Target::parse( t ).is_sub_file() ).unwrap_or( false );
+    //with --lines or --bytes, query only shows the entries and parts of the file that overlap the region
+    let region = if with_sub_targets { region( args ) } else { None };
+    let shown_part = |fragment: &Option<Fragment>| match ( &region, fragment ) {
+        ( &Some( ref region ), &Some( ref fragment ) ) => region.overlaps( fragment ),
+        ( &None, &Some( _ ) ) => true,
+        _ => false
+    };
+    //stored names in another Unicode form are found as well
+    let normalized = |name: &str| normalization.apply_to_target( name );
+    let select = |name: &str| match target {
+        Some( ref target ) => {
+            let name = normalized( name );
+            let parsed = Target::parse( &name );
+            name == *target || ( with_sub_targets && parsed.file == Target::parse( target ).file && shown_part( &parsed.fragment ) )
+        },
+        None => false
+    };
+    let selected = if args.flag_lenient {
+        annovate::reader::read_selected_lenient( path, select ).map( |( dir, targets, warnings )| {
+            report_parse_warnings( meta_file, &warnings );
+            ( dir, targets )
+        } )
+    } else {
+        annovate::reader::read_selected( path, select )
+    };
+    let ( dir, mut targets ) = match selected {
+        Ok( selected ) => selected,
+        Err( err ) => report_open_error( meta_file, err )
+    };
+    let own = match target {
+        Some( ref target ) => targets.iter().position( |t| normalized( &t.0 ) == *target ).map( |pos| targets.remove( pos ).1 ),
+        None => Some( dir.clone() )
+    };
+    //the annotations of the directories above come first, so that those of the file take precedence
+    let inherited = if args.flag_inherit {
+        match inherit::inherited_annotations( path, target.as_ref().map( |t| Target::parse( t ).file ).as_ref().map( |f| f.as_str() ) ) {
+            Ok( inherited ) => inherited,
+            Err( err ) => report_failure( &err.to_string(), &err )
+        }
+    } else {
+        AnnoContainer::new()
+    };
+    let annotations = match own {
+        Some( annotations ) => inherited.into_iter().chain( annotations ).collect(),
+        None if args.cmd_query && ( !targets.is_empty() || !inherited.is_empty() ) => inherited,
+        None => exit_with( EXIT_NOT_FOUND, &tr( if args.cmd_query { "no-annotations" } else { "no-metadata" }, &[] ) )
+    };
+    let mut annotations = match context_query( args ) {
+        Some( query ) => {
+            for target in targets.iter_mut() {
+                target.1.retain_by_context( &query );
+            }
+            annotations.filter_by_context( &query )
+        },
+        None => annotations
+    };
+    if let Some( ref region ) = region {
+        annotations.retain( |a| a.region.as_ref().map( |r| region.overlaps( r ) ).unwrap_or( false ) );
+    }
+    //expired entries are only shown with -a
+    if !show_duplicates {
+        let now = Timestamp::now();
+        expiry::retain_unexpired( &mut annotations, now );
+        for target in targets.iter_mut() {
+            expiry::retain_unexpired( &mut target.1, now );
+        }
+    }
+    //annotations of columns, line ranges, etc. follow the annotations of the whole file
+    targets.sort_by( |a, b| a.0.cmp( &b.0 ) );
+
+    //references like `${dir:project}` in the shown annotations are replaced, `${file:...}` refers to `all`
+    let scope = if target.is_some() { Scope::File } else { Scope::Dir };
+    let resolve = |shown: AnnoContainer, all: &AnnoContainer| -> AnnoContainer {
+        if !args.flag_resolve {
+            return shown;
+        }
+        let resolver = Resolver::new( &dir, if target.is_some() { Some( all ) } else { None } );
+        shown.into_iter().map( |mut a| match resolver.resolve( scope, &a ) {
+            Ok( value ) => {
+                a.value = value;
+                a
+            },
+            Err( e ) => report_error( &tr( "resolve-failed", &[ &a.key, &e.to_string() ] ) )
+        } ).collect()
+    };
+
+    let keyring = Keyring::from_env();
+    let without_passphrase = Cell::new( false );
+    //encrypted values are decrypted if the passphrase is set
+    let reveal = |annotations: AnnoContainer| -> AnnoContainer {
+        annotations.into_iter().map( |mut a| {
+            if a.is_encrypted() {
+                a.value = match keyring.as_ref().map( |k| k.decrypt( &a.value ) ) {
+                    Some( Ok( value ) ) => value,
+                    Some( Err( e ) ) => {
+                        report_warning( &tr( "decrypt-failed", &[ &a.key, &e.to_string() ] ) );
+                        crypt::ENCRYPTED_PLACEHOLDER.to_string()
+                    },
+                    None => {
+                        without_passphrase.set( true );
+                        crypt::ENCRYPTED_PLACEHOLDER.to_string()
+                    }
+                };
+            }
+            a
+        } ).collect()
+    };
+    if args.cmd_get || args.cmd_get_dir {
+        let key = args.arg_key.get( 0 ).unwrap(); //getopt takes care of non-empty vector
+        let matcher = key_matcher( args );
+        if !annotations.iter().any( |a| matcher.matches( key, &a.key ) ) {
+            match closest_key( key, annotations.iter().map( |a| a.key.as_str() ) ) {
+                Some( suggestion ) => exit_with( EXIT_NOT_FOUND, &tr( "no-matching-key-suggestion", &[ key, suggestion ] ) ),
+                None => exit_with( EXIT_NOT_FOUND, &tr( "no-matching-key", &[ key ] ) )
+            }
+        }
+        let matching = entries_to_get( &annotations, key, matcher, show_duplicates );
+        //get is used by scripts, which need the value itself
+        for annotation in matching.iter().filter( |a| a.is_encrypted() ) {
+            match keyring {
+                Some( ref keyring ) => if let Err( e ) = keyring.decrypt( &annotation.value ) {
+                    report_failure( &tr( "decrypt-failed", &[ &annotation.key, &e.to_string() ] ), &e );
+                },
+                None => report_error( &tr( "encrypted-values", &[ crypt::PASSPHRASE_VAR ] ) )
+            }
+        }
+        for annotation in &resolve( reveal( matching ), &annotations ) {
+            if args.flag_print0 {
+                NulOutput.print_record( &[ &annotation.value ] );
+            } else {
+                println!( "{}", annotation.value );
+            }
+        }
+        return;
     }
 
-    while let Some( line ) = value_lines.next() {
-        println!( "{0:1$}{2}", "", widths.key, line );
+    let keys = &args.arg_key;
+    let matcher = key_matcher( args );
+    let select_keys = |annotations: &AnnoContainer| -> AnnoContainer {
+        annotations.iter()
+                   //hidden keys like `.sha256` are only shown with -a or when asked for
+                   .filter( |a| ( keys.len() == 0 && ( show_duplicates || !a.key.starts_with( "." ) ) ) || keys.iter().any( |k| a.key_matches_with( k, matcher ) ) )
+                   .cloned()
+                   .collect()
+    };
+    let entry_order = match args.flag_sort.as_str() {
+        "" => None,
+        name => match AnnotationOrder::from_name( name ) {
+            Some( order ) => Some( order ),
+            None => exit_with( EXIT_USAGE, &tr( "unknown-entry-order", &[ name ] ) )
+        }
+    };
+    let show = |shown: AnnoContainer| {
+        //duplicates are filtered before sorting and paging, so that the pages count the shown entries;
+        //the IDs would also make every key unique
+        let mut shown = if show_duplicates { shown } else { filter_duplicates( &shown ) };
+        if let Some( order ) = entry_order {
+            sort::sort_annotations( &mut shown, order, args.flag_descending );
+        }
+        let shown = sort::paginate( shown, args.flag_offset.unwrap_or( 0 ), args.flag_limit );
+        let shown = label_regions( shown );
+        display_anno_container( &if args.flag_ids { label_ids( shown ) } else { shown }, show_context, true, output );
+    };
+    show( resolve( reveal( select_keys( &annotations ) ), &annotations ) );
+    for &( ref name, ref annos ) in &targets {
+        let fragment = Target::parse( name ).fragment.unwrap(); //only sub-file targets are selected
+        output.print_heading( &format!( "#{}", fragment ) );
+        show( resolve( reveal( select_keys( annos ) ), annos ) );
+    }
+    if without_passphrase.get() {
+        report_warning( &tr( "encrypted-values", &[ crypt::PASSPHRASE_VAR ] ) );
+    }
+    //a key that is not found may be misspelled
+    let all: Vec<&Annotation> = annotations.iter().chain( targets.iter().flat_map( |t| t.1.iter() ) ).collect();
+    for key in keys.iter().filter( |k| !all.iter().any( |a| a.key_matches_with( k, matcher ) ) ) {
+        if let Some( suggestion ) = closest_key( key, all.iter().map( |a| a.key.as_str() ) ) {
+            report_warning( &tr( "no-matching-key-suggestion", &[ key, suggestion ] ) );
+        }
     }
 }
 
 fn main() {
-    let args: Args = Docopt::new( USAGE )
+    //errors in the arguments themselves are already printed as asked for
+    let raw_args: Vec<String> = std::env::args().collect();
+    if raw_args.windows( 2 ).any( |w| w[ 0 ] == "--errors" && w[ 1 ] == "json" ) || raw_args.iter().any( |a| a == "--errors=json" ) {
+        JSON_ERRORS.store( true, Ordering::Relaxed );
+    }
+    let mut args: Args = Docopt::new( USAGE )
         .and_then( |d| d.decode() )
-        .unwrap_or_else( |e| e.exit() );
+        .unwrap_or_else( |e| if e.fatal() { exit_with( EXIT_USAGE, &e.to_string() ) } else { e.exit() } );
+    QUIET.store( args.flag_quiet, Ordering::Relaxed );
+    match args.flag_errors.as_str() {
+        "text" => JSON_ERRORS.store( false, Ordering::Relaxed ),
+        "json" => JSON_ERRORS.store( true, Ordering::Relaxed ),
+        other => exit_with( EXIT_USAGE, &tr( "unknown-error-format", &[ other ] ) )
+    }
+
+    let config = match Config::load_default() {
+        Ok( config ) => config,
+        Err( e ) => report_failure( &tr( "config-failed", &[ &e.to_string() ] ), &e )
+    };
+    //the settings of the directory of -m, or of the current directory
+    let settings = config.settings_for( &if args.flag_m != "" { meta_directory( &args.flag_m ) } else { PathBuf::from( "." ) } );
 
+    messages::set_language( settings.language() );
     if args.cmd_help || args.flag_h || args.flag_help {
-        println!( "{}", USAGE );
+        println!( "{}", messages::localize_help( USAGE, Locale::current() ) );
         return;
     }
 
-    let bad_filename = "<bad-filename>".to_string();
-    let missing_value = "<missing-value>".to_string();
-    let missing_context = "<missing-context>".to_string();
+    //filenames are stored with `/`, whatever separator they were typed with
+    let slashes = args.flag_slashes || cfg!( windows );
+    if slashes {
+        let with_slashes = |name: &str| Target::parse( name ).with_slashes().to_string();
+        args.arg_filename = args.arg_filename.iter().map( |f| with_slashes( f ) ).collect();
+        args.arg_filename2 = with_slashes( &args.arg_filename2 );
+        args.arg_new_filename = with_slashes( &args.arg_new_filename );
+        args.flag_file = with_slashes( &args.flag_file );
+    }
+
+    if args.cmd_put {
+        read_values( &mut args );
+    }
+
+    //filenames are looked up in the Unicode form they are stored in, however the shell or the system spelled them
+    let normalization = settings.normalization();
+    if normalization != Normalization::Keep {
+        let normalized = |name: &str| normalization.apply_to_target( name );
+        args.arg_filename = args.arg_filename.iter().map( |f| normalized( f ) ).collect();
+        args.arg_filename2 = normalized( &args.arg_filename2 );
+        args.arg_new_filename = normalized( &args.arg_new_filename );
+        args.flag_file = normalized( &args.flag_file );
+    }
+
+    let missing_value = tr( "missing-value", &[] );
+    let missing_context = tr( "missing-context", &[] );
 
     //handle flags/options
     let meta_file = if args.flag_m != "" {
-        args.flag_m
+        args.flag_m.clone()
     } else if args.cmd_new {
-        format!( "{}/.annotave", args.arg_dirname ).to_string()
+        format!( "{}/{}", args.arg_dirname, settings.meta_name() )
     } else {
-        ".annovate".to_string()
+        settings.meta_name()
     };
     let meta_outfile = Path::new( if args.flag_M != "" { &args.flag_M } else { &meta_file } );
-    let use_dotfiles = args.flag_d;
+    let use_dotfiles = args.flag_d || settings.dotfiles();
     let show_context = args.flag_c;
     let show_duplicates = args.flag_a;
+    //get-many chooses its format itself, stats prints JSON itself
+    let output = output_format( if args.cmd_get_many || ( args.cmd_stats && args.flag_output == "json" ) { "" } else { &args.flag_output }, args.flag_plain, args.flag_print0, Colors::with_mode( settings.color(), args.flag_no_color ) );
+    let recursive = args.flag_r;
+    //long operations draw a progress bar, unless stderr goes somewhere else
+    let show_progress = ( args.cmd_import || args.cmd_harvest || recursive ) && stderr().is_terminal();
+    let value_type = match args.flag_type.as_str() {
+        "" => None,
+        _ if args.cmd_report => None, //the kind of files, see below
+        name => match ValueType::from_name( name ) {
+            Some( value_type ) => Some( value_type ),
+            None => report_error( &tr( "unknown-type", &[ name ] ) )
+        }
+    };
 
+    let now = Timestamp::now();
+    let expires = match args.flag_expires.as_str() {
+        "" => None,
+        text => match expiry::expiry_time( text, now ) {
+            Some( expires ) => Some( expires ),
+            None => report_error( &tr( "invalid-expiry", &[ text ] ) )
+        }
+    };
     let context = {
         if args.flag_C != "" {
             args.flag_C.clone()
         } else {
-            let now = time::now();
-            format!( "annovate program, {}.{}.{} {:02}:{:02}:{:02}",
-                                   now.tm_mday,
-                                   now.tm_mon + 1,
-                                   now.tm_year + 1900,
-                                   now.tm_hour,
-                                   now.tm_min,
-                                   now.tm_sec )
+            let mut fields = if args.flag_context_fields != "" { args.flag_context_fields.clone() } else { settings.context_fields().to_string() };
+            if args.flag_context_git {
+                for field in &[ "branch", "git" ] {
+                    if !fields.split( ',' ).any( |f| f.trim() == *field ) {
+                        fields = format!( "{},{}", fields, field );
+                    }
                 }
+            }
+            match ContextBuilder::from_fields( "annovate program", &fields, &meta_directory( &meta_file ) ) {
+                Ok( builder ) => builder.build(),
+                Err( e ) => report_failure( &e.to_string(), &e )
+            }
+        }
+    };
+    //metadata added by put, put-batch and put-dir
+    let protect_new = args.flag_protect && args.cmd_put_dir;
+    let put_policy = match ( args.flag_replace, args.flag_if_absent ) {
+        ( true, true ) => exit_with( EXIT_USAGE, &tr( "conflicting-put-policy", &[] ) ),
+        ( true, false ) => PutPolicy::Replace,
+        ( false, true ) => PutPolicy::IfAbsent,
+        ( false, false ) => PutPolicy::Append
+    };
+    let keyring = Keyring::from_env();
+    let encrypt_keys = settings.encrypt_keys();
+    let region = if args.cmd_put { region( &args ) } else { None };
+    let new_annotation = |key: &str, value: &str| -> Annotation {
+        let annotation = typed_annotation( key, value, &context, value_type ).with_created( now ).with_id( ids::generate_id() )
+                                                                            .with_protected( protect_new );
+        let annotation = match expires {
+            Some( expires ) => annotation.with_expires( expires ),
+            None => annotation
+        };
+        let annotation = match region {
+            Some( ref region ) => annotation.with_region( region.clone() ),
+            None => annotation
+        };
+        if !args.flag_encrypt && !encrypt_keys.iter().any( |pattern| annotation.key_matches( pattern ) ) {
+            return annotation;
+        }
+        match keyring {
+            Some( ref keyring ) => match annotation.encrypted( keyring ) {
+                Ok( annotation ) => annotation,
+                Err( e ) => report_failure( &tr( "encrypt-failed", &[ key, &e.to_string() ] ), &e )
+            },
+            None => report_error( &tr( "no-passphrase", &[ key ] ) )
+        }
     };
     
     //handle commands
 
     if args.cmd_new {
         let mut dirbuilder = DirBuilder::new();
-        if dirbuilder.recursive( true ).create( args.arg_dirname ).is_err() {
-            report_error( "Failed to create new directory" );
+        if dirbuilder.recursive( true ).create( &args.arg_dirname ).is_err() {
+            report_error( &tr( "create-dir-failed", &[] ) );
         }
         //the annovate file will be created automatically because it does not exist
     }
 
-    let mut anno = match Annovate::new( Path::new( &meta_file ) ) {
-        Ok( annotations ) => annotations,
-        Err( err ) => { println!( "{}", err ); return; }
-    };
+    //these commands change more than the meta file, which cannot be previewed
+    if args.flag_dry_run && ( args.cmd_new || args.cmd_watch || args.cmd_export_git_notes || args.cmd_install || ( args.cmd_sync_xattr && args.flag_push ) ) {
+        report_error( &tr( "dry-run-unsupported", &[] ) );
+    }
 
-    let mut require_write_to_disk = false;
+    if args.cmd_watch {
+        watch_directory( &meta_file, &args.flag_template, &context, use_dotfiles, Duration::from_secs( args.flag_wait ) );
+    }
 
-    if args.cmd_new {
-        //everything should be done by now
-    } else if args.cmd_query || args.cmd_query_dir {
-        let annotations = if args.cmd_query {
-            let query_file = args.arg_filename.get( 0 ).unwrap(); //getopt ensures that this is not empty
-            match anno.get_file_annotations( &query_file ) {
-                Some( annos ) => annos,
-                None => report_error( "Filename has no annotations" )
-            }
-        } else {
-            anno.get_directory_annotations()
+    if args.cmd_doctor {
+        //the meta file may be broken, so it is examined before parsing it
+        let meta_path = Path::new( &meta_file );
+        let repair = args.flag_repair && !args.flag_dry_run;
+        let result = if repair { doctor::repair( meta_path ) } else { doctor::diagnose( meta_path ) };
+        let problems = match result {
+            Ok( problems ) => problems,
+            Err( e ) => report_failure( &tr( "examine-failed", &[ &meta_file, &e.to_string() ] ), &e )
         };
-
-        if args.arg_key.len() == 0 {
-            display_anno_container( annotations, show_context, show_duplicates );
+        for problem in &problems {
+            println!( "{}", problem );
+        }
+        if problems.is_empty() {
+            println!( "{}", tr( "no-problems", &[] ) );
+        } else if repair {
+            let backup = doctor::backup_path( meta_path );
+            println!( "{}", tr( "repaired", &[ &problems.len().to_string(), &backup.to_string_lossy() ] ) );
         } else {
-            let mut annotations_subset = AnnoContainer::new();
-            for annotation in annotations {
-                if args.arg_key.contains( &annotation.key ) {
-                    annotations_subset.push( annotation.clone() );
-                }
-            }
-            display_anno_container( &annotations_subset, show_context, show_duplicates );
-        }
-    } else if args.cmd_put {
-        let file_with_new_data = args.arg_filename.get( 0 ).unwrap(); //getopt ensures that this is not empty
-        let pairs = args.arg_key.iter().zip( args.arg_value );
-        for ( key, value ) in pairs {
-            anno.add_file_annotation( file_with_new_data,
-                                      Annotation::new( key.clone(),
-                                                       value,
-                                                       context.clone() ) );
-        }
-        require_write_to_disk = true;
-    } else if args.cmd_put_batch {
-        let key = args.arg_key.get( 0 ).unwrap(); //getopt ensures that this is not empty
-        let value = args.arg_value.get( 0 ).unwrap(); //getopt ensures that this is not empty
-        for filename in args.arg_filename {
-            let annotation = Annotation::new( key.clone(),
-                                              value.clone(),
-                                              context.clone() );
-            anno.add_file_annotation( &filename, annotation );
-        }
-        require_write_to_disk = true;
-    } else if args.cmd_put_dir {
-        let pairs = args.arg_key.iter().zip( args.arg_value );
-        for ( key, value ) in pairs {
-            anno.add_directory_annotation( Annotation::new( key.clone(),
-                                                            value,
-                                                            context.clone() ) );
-        }
-        require_write_to_disk = true;
-    } else if args.cmd_list {
-        let default_key = "description".to_string();
-        let key = args.arg_key.get( 0 ).unwrap_or( &default_key );
-        let mut annotations = AnnoContainer::new();
-        for filename in anno.get_files() {
-            if !use_dotfiles && filename.starts_with( "." ) {
-                continue
-            }
-            let mut entry_found = false;
-            for annotation in anno.get_file_annotations( &filename ).unwrap() { //filename exists because it comes from .get_files()
-                if annotation.key == *key {
-                    entry_found = true;
-                    annotations.push( Annotation::new( filename.clone(), //I am cheating here and use the filename as the key so that I do not need to write extra code for printing the file names
-                                                       annotation.value.clone(),
-                                                       annotation.context.clone() ) );
-                }
-            }
-            if !entry_found {
-                annotations.push( Annotation::new( filename.clone(),
-                                                   missing_value.clone(),
-                                                   missing_context.clone() ) );
-            }
-        }
-        //TODO add fancy ANSI codes (underline), also add a flag to disable these things and the headers
-        annotations.push( Annotation::new( "Filename".to_string(), key.clone(), "Context".to_string() ) ); //header line
-        display_anno_container( &annotations, show_context, show_duplicates );
-    } else if args.cmd_get || args.cmd_get_dir {
-        let key = args.arg_key.get( 0 ).unwrap(); //getopt takes care of non-empty vector
+            lock::release_all();
+            std::process::exit( EXIT_INVALID );
+        }
+        return;
+    }
 
-        let annotations = if args.cmd_get {
-            let filename = args.arg_filename.get( 0 ).unwrap(); //unwrap takes care of non-empty vector
-            match anno.get_file_annotations( filename ) {
-                Some( annos ) => annos,
-                None => report_error( "Filename has no metadata" )
+    let meta_dir = meta_directory( &meta_file );
+    let meta_name = Path::new( &meta_file ).file_name().map( |n| n.to_string_lossy().into_owned() ).unwrap_or( migrate::meta_name() );
+    if args.cmd_migrate {
+        if args.flag_dry_run {
+            for legacy in migrate::legacy_files( &meta_dir, &meta_name ) {
+                println!( "{}", tr( "would-migrate", &[ &legacy.to_string_lossy(), &meta_file ] ) );
             }
-        } else {
-            anno.get_directory_annotations()
+            return;
+        }
+        let migrations = match migrate::migrate( &meta_dir, &meta_name ) {
+            Ok( migrations ) => migrations,
+            Err( e ) => report_failure( &tr( "migrate-failed", &[ &e.to_string() ] ), &e )
         };
-
-        for annotation in annotations {
-            if annotation.key == *key {
-                println!( "{}", annotation.value );
-                if !show_duplicates {
-                    break
-                }
+        for migration in &migrations {
+            let id = if migration.merged { "migrated-merged" } else { "migrated" };
+            println!( "{}", tr( id, &[ &migration.from.to_string_lossy(), &migration.to.to_string_lossy() ] ) );
+            if let Some( ref kept ) = migration.kept_journal {
+                println!( "{}", tr( "migrated-journal-kept", &[ &migration.from.to_string_lossy(), &kept.to_string_lossy() ] ) );
             }
         }
-    } else if args.cmd_report {
-        let mut meta_filenames = HashSet::new();
-        for filename in anno.get_files() {
-            meta_filenames.insert( filename ); //I wonder if there is a more elegant way
+        if migrations.is_empty() {
+            println!( "{}", tr( "nothing-to-migrate", &[] ) );
         }
+        return;
+    }
 
-        let entries = match read_dir( Path::new( "." ) ) {
-            Ok( files ) => {
-                files.map( |f| f.unwrap() //TODO find a clean solution to IO error
-                                .file_name()
-                                .into_string()
-                                .unwrap_or( bad_filename.clone() ) )
-            },
-            Err( e ) => {
-                let msg = format!( "Failed to read directory: {}", e );
-                report_error( &msg );
-            }
-        };
+    //a missing meta file is created empty, which hides annotations stored under another name
+    if !args.cmd_new && !Path::new( &meta_file ).exists() {
+        for legacy in migrate::legacy_files( &meta_dir, &meta_name ) {
+            report_warning( &tr( "legacy-meta-file", &[ &meta_file, &legacy.to_string_lossy() ] ) );
+        }
+    }
 
-        let mut real_filenames = HashSet::new();
+    check_integrity( &meta_file, settings.integrity(), args.flag_force );
 
-        for entry in entries {
-            real_filenames.insert( entry );
-        }
+    if args.cmd_query || args.cmd_query_dir || args.cmd_get || args.cmd_get_dir {
+        stream_lookup( &args, &meta_file, settings.identity(), normalization, show_context, show_duplicates, &*output );
+        output.finish();
+        return;
+    }
 
-        for common in real_filenames.intersection( &meta_filenames ) {
-            println!( "= {}", common );
+    //the new annotations are appended as delta records, the meta file is neither read nor rewritten
+    if args.flag_append && ( args.cmd_put || args.cmd_put_batch || args.cmd_put_dir ) {
+        if args.flag_template != "" || args.flag_hook != "" || args.flag_dry_run || args.flag_replace || args.flag_if_absent {
+            report_error( &tr( "append-unsupported", &[] ) );
+        }
+        let annotations: AnnoContainer = args.arg_key.iter().zip( args.arg_value.iter() )
+            .map( |( key, value )| new_annotation( key, value ) )
+            .collect();
+        let targets: Vec<Option<String>> = if args.cmd_put_dir { vec![ None ] } else { args.arg_filename.iter().map( |f| Some( f.clone() ) ).collect() };
+        //protected keys of the directory are checked like for a put-dir that reads the file
+        if args.cmd_put_dir && !args.flag_force && Path::new( &meta_file ).exists() {
+            let dir = match annovate::reader::read_selected( Path::new( &meta_file ), |_| false ) {
+                Ok( ( dir, _ ) ) => dir,
+                Err( err ) => report_open_error( &meta_file, err )
+            };
+            if let Some( key ) = args.arg_key.iter().find( |k| protect::is_protected( &dir, k ) ) {
+                report_error( &tr( "protected-key", &[ key ] ) );
+            }
         }
+        for target in &targets {
+            if let Some( ref target ) = *target {
+                check_target( target );
+            }
+            match delta::append_annotations( Path::new( &meta_file ), target.as_ref().map( |t| t.as_str() ), &annotations, Duration::from_secs( args.flag_wait ) ) {
+                Ok( () ) => {},
+                Err( e @ AnnoError::InvalidInput { .. } ) => exit_with_error( EXIT_USAGE, &e.to_string(), &e ),
+                Err( e ) => report_failure( &tr( "append-failed", &[ &e.to_string() ] ), &e )
+            }
+        }
+        if args.flag_message != "" {
+            let directory = "directory".to_string();
+            let details: Vec<String> = targets.iter().flat_map( |target| annotations.iter().map( move |a| ( target, a ) ) )
+                .map( |( target, a )| format!( "{}: added `{}`", target.as_ref().unwrap_or( &directory ), a.key ) )
+                .collect();
+            let entry = checked( changelog::change_entry( &args.flag_message, &details.join( "\n" ), &context ) );
+            if let Err( e ) = delta::append_annotations( Path::new( &meta_file ), None, &vec![ entry ], Duration::from_secs( args.flag_wait ) ) {
+                report_failure( &tr( "append-failed", &[ &e.to_string() ] ), &e );
+            }
+        }
+        return;
+    }
 
-        for meta_exclusive in meta_filenames.difference( &real_filenames ) {
-            println!( "+ {}", meta_exclusive );
+    if args.cmd_collect {
+        //the meta files of the listed directories have the name of the local one
+        let set = match AnnovateSet::open( &args.arg_dir, &meta_name ) {
+            Ok( set ) => set,
+            Err( e ) => report_failure( &e.to_string(), &e )
+        };
+        //one row per annotation: filename, key and value
+        let mut rows = AnnoContainer::new();
+        for filename in set.get_files() {
+            let annotations = set.get_file_annotations( &filename ).unwrap(); //the file was listed by the set
+            let annotations = if show_duplicates { annotations.clone() } else { filter_duplicates( annotations ) };
+            for annotation in annotations.iter().filter( |a| show_duplicates || !a.key.starts_with( "." ) ) {
+                rows.push( Annotation::new( filename.clone(), annotation.key.clone(), annotation.value.clone() ) );
+            }
         }
+        let header = Annotation::new( tr( "header-filename", &[] ), tr( "header-key", &[] ), tr( "header-value", &[] ) );
+        if output.has_header() {
+            output.print_table( &header, [ "file", "key", "value" ], &rows, true );
+        } else {
+            output.print_annotations( &rows, true );
+        }
+        output.finish();
+        return;
+    }
+
+    let parse_mode = if args.flag_lenient { ParseMode::Lenient } else { ParseMode::Strict };
+    //commands that only read neither take the lock nor wait for it; a missing meta file is still created
+    let mode = if is_read_only( &args ) && Path::new( &meta_file ).exists() { OpenMode::ReadOnly } else { OpenMode::ReadWrite };
+    let opened = if show_progress {
+        Annovate::open_with_progress( Path::new( &meta_file ), mode, parse_mode, Duration::from_secs( args.flag_wait ), Rc::new( draw_progress ) )
+    } else {
+        Annovate::open_with_parse_mode( Path::new( &meta_file ), mode, parse_mode, Duration::from_secs( args.flag_wait ) )
+    };
+    let mut anno = match opened {
+        Ok( annotations ) => annotations,
+        Err( err ) => report_open_error( &meta_file, err )
+    };
+
+    report_parse_warnings( &meta_file, anno.parse_warnings() );
 
-        for real_missing in real_filenames.difference( &meta_filenames ) {
-            println!( "- {}", real_missing );
+    anno.set_override_protection( args.flag_force );
+    anno.set_quota( settings.quota() );
+    let mut options = anno.save_options().clone();
+    options.journal = settings.journal() > 0;
+    options.journal_entries = settings.journal();
+    options.backups = BackupPolicy::keep( settings.backups() );
+    options.integrity = settings.integrity();
+    anno.set_save_options( options );
+
+    if args.flag_hook != "" {
+        let ( hook, outfile ) = ( args.flag_hook.clone(), meta_outfile.to_string_lossy().into_owned() );
+        anno.set_pre_save_hook( Box::new( move |anno| run_hook( &hook, &outfile, anno ) ) );
+    }
+
+    //a dry run shows the changes, a message records them
+    let before = if args.flag_dry_run || args.flag_message != "" { Some( anno.snapshot() ) } else { None };
+    let result = anno.transaction_to( meta_outfile, |anno| {
+        let writable = anno.mode() == OpenMode::ReadWrite;
+        if writable && slashes && checked( anno.normalize_separators() ) > 0 {
+            anno.require_save();
+        }
+        if writable && normalization != Normalization::Keep && checked( anno.normalize_filenames( normalization ) ) > 0 {
+            anno.require_save();
         }
 
-    } else if args.cmd_rm_file_key {
-        let filename = args.arg_filename.get( 0 ).expect( "GetOpt has failed to require the argument <filename>" );
-        for key in args.arg_key {
-            if !anno.remove_file_annotation_entries( filename, &key ) {
-                let msg = format!( "No matching entries found for key `{}`", key  );
-                report_warning( &msg );
+        if args.cmd_new {
+            //everything should be done by now
+        } else if args.cmd_template {
+            if args.cmd_define {
+                let template = Template::parse( &args.arg_name, &args.arg_field.join( "\n" ) );
+                if let Err( e ) = template::define_template( anno, &template, &context ) {
+                    report_failure( &e.to_string(), &e );
+                }
+                anno.require_save();
+            } else if args.cmd_apply {
+                let template = match template::find_template( &anno, &args.arg_name ) {
+                    Some( t ) => t,
+                    None => report_error( &tr( "unknown-template", &[ &args.arg_name ] ) )
+                };
+                checked( template.apply( anno, &args.arg_filename, &context, |key| prompt( key ) ) );
+                anno.require_save();
+            } else if args.cmd_show {
+                for t in template::templates( &anno ) {
+                    if args.arg_name == "" || args.arg_name == t.name {
+                        println!( "{}:", t.name );
+                        for line in t.definition().lines() {
+                            println!( "  {}", line );
+                        }
+                    }
+                }
             }
-        }
-        require_write_to_disk = true;
-    } else if args.cmd_rm_dir_key {
-        for key in args.arg_key {
-            if !anno.remove_directory_annotation_entries( &key ) {
-                let msg = format!( "No matching entries found for key `{}`", key  );
-                report_warning( &msg );
+        } else if args.cmd_schema {
+            if args.cmd_define {
+                let schema = match Schema::parse( &args.arg_field.join( "\n" ) ) {
+                    Ok( schema ) => schema,
+                    Err( e ) => report_failure( &tr( "invalid-schema", &[ &e.to_string() ] ), &e )
+                };
+                checked( schema::define_schema( anno, &schema, &context ) );
+                anno.require_save();
+            } else if args.cmd_show {
+                match schema::find_schema( &anno ) {
+                    Some( Ok( schema ) ) => println!( "{}", schema.definition() ),
+                    Some( Err( e ) ) => report_failure( &tr( "invalid-schema", &[ &e.to_string() ] ), &e ),
+                    None => report_error( &tr( "no-schema", &[] ) )
+                }
+            }
+        } else if args.cmd_validate {
+            let schema = match schema::find_schema( &anno ) {
+                Some( Ok( schema ) ) => schema,
+                Some( Err( e ) ) => report_failure( &tr( "invalid-schema", &[ &e.to_string() ] ), &e ),
+                None => report_error( &tr( "no-schema", &[] ) )
+            };
+            let violations = anno.validate( &schema );
+            for violation in &violations {
+                println!( "{}", violation );
+            }
+            if violations.is_empty() {
+                println!( "{}", tr( "schema-valid", &[] ) );
+            } else {
+                lock::release_all();
+                std::process::exit( EXIT_INVALID );
+            }
+        } else if args.cmd_apply {
+            let substitution = match Substitution::parse( &args.flag_exec ) {
+                Ok( substitution ) => substitution,
+                Err( e ) => exit_with( EXIT_USAGE, &e.to_string() )
+            };
+            //hidden keys like `.sha256` are only changed when asked for
+            let selected = |key: &str| if args.arg_key.is_empty() { show_duplicates || !key.starts_with( "." ) } else { args.arg_key.iter().any( |k| k == key ) };
+            //base64 text of binary values is not changed
+            let binary: HashSet<( Option<String>, String )> = anno.iter_all_annotations()
+                .filter( |&( _, a )| a.value_type == Some( ValueType::Binary ) )
+                .map( |( file, a )| ( file.map( |f| f.to_string() ), a.key.clone() ) )
+                .collect();
+            let changed = checked( anno.map_values( |file, key, value| {
+                if selected( key ) && !crypt::is_encrypted( value ) && !binary.contains( &( file.map( |f| f.to_string() ), key.to_string() ) ) {
+                    substitution.apply( value )
+                } else {
+                    value.to_string()
+                }
+            } ) );
+            println!( "{}", tr( "values-changed", &[ &changed.to_string() ] ) );
+            anno.require_save();
+        } else if args.cmd_put {
+            let filenames = expand_targets( &anno, &args.arg_filename, false );
+            for filename in &filenames {
+                check_target( filename );
             }
+            //pairs that fill blanks of the template are added by the template
+            let mut filled_blanks = vec![];
+            if args.flag_template != "" {
+                let template = match template::find_template( &anno, &args.flag_template ) {
+                    Some( t ) => t,
+                    None => report_error( &tr( "unknown-template", &[ &args.flag_template ] ) )
+                };
+                filled_blanks = template.blanks().into_iter().map( |b| b.to_string() ).filter( |b| args.arg_key.contains( b ) ).collect();
+                let given = |key: &str| args.arg_key.iter().position( |k| k == key ).map( |pos| args.arg_value[ pos ].clone() );
+                checked( template.apply( anno, &filenames, &context, |key| given( key ).or_else( || prompt( key ) ) ) );
+            }
+            for file_with_new_data in &filenames {
+                let pairs = args.arg_key.iter().zip( args.arg_value.iter() ).filter( |&( key, _ )| !filled_blanks.contains( key ) );
+                for ( key, value ) in pairs {
+                    checked( anno.put_file_annotation( file_with_new_data, new_annotation( key, value ), put_policy ) );
+                }
+            }
+            anno.require_save();
+        } else if args.cmd_put_batch {
+            let key = args.arg_key.get( 0 ).unwrap(); //getopt ensures that this is not empty
+            let value = args.arg_value.get( 0 ).unwrap(); //getopt ensures that this is not empty
+            for filename in expand_targets( &anno, &args.arg_filename, false ) {
+                check_target( &filename );
+                let annotation = new_annotation( key, value );
+                checked( anno.put_file_annotation( &filename, annotation, put_policy ) );
+            }
+            anno.require_save();
+        } else if args.cmd_put_dir {
+            let pairs = args.arg_key.iter().zip( args.arg_value.iter() );
+            for ( key, value ) in pairs {
+                checked( anno.put_directory_annotation( new_annotation( key, value ), put_policy ) );
+            }
+            anno.require_save();
+        } else if args.cmd_list {
+            let default_key = settings.list_key().to_string();
+            let key = args.arg_key.get( 0 ).unwrap_or( &default_key );
+            let tree;
+            let ( mut files, tagged ): ( Vec<( String, &AnnoContainer )>, Vec<String> ) = if recursive {
+                tree = load_tree( &meta_file, use_dotfiles, show_progress );
+                ( tree.get_files().into_iter().map( |f| { let annos = tree.get_file_annotations( &f ).unwrap(); ( f, annos ) } ).collect(),
+                  tree.files_with_tag( &args.flag_tag ) )
+            } else {
+                ( anno.get_files().into_iter().map( |f| { let annos = anno.get_file_annotations( &f ).unwrap(); ( f, annos ) } ).collect(),
+                  anno.files_with_tag( &args.flag_tag ) )
+            };
+            let sort_order = match args.flag_sort.as_str() {
+                "" => None,
+                name => match SortOrder::from_name( name ) {
+                    Some( order ) => Some( order ),
+                    None => report_error( &tr( "unknown-sort-order", &[ name ] ) )
+                }
+            };
+            if let Some( order ) = sort_order {
+                sort::sort_files( &mut files, order, key, args.flag_descending );
+            }
+            let position: HashMap<String, usize> = files.iter().enumerate().map( |( i, f )| ( f.0.clone(), i ) ).collect();
+            let context_query = context_query( &args );
+            let mut annotations = AnnoContainer::new();
+            for ( filename, file_annotations ) in files {
+                if args.flag_tag != "" && !tagged.contains( &filename ) {
+                    continue
+                }
+                let basename = Path::new( &filename ).file_name().map( |n| n.to_string_lossy().into_owned() ).unwrap_or( filename.clone() );
+                if !use_dotfiles && basename.starts_with( "." ) {
+                    continue
+                }
+                if Target::parse( &filename ).is_sub_file() {
+                    continue //parts of files are shown by query
+                }
+                let mut entry_found = false;
+                let shown = |a: &&Annotation| ( show_duplicates || !a.is_expired( now ) ) && context_query.as_ref().map( |q| q.is_match( &a.context ) ).unwrap_or( true );
+                for annotation in file_annotations.iter().filter( shown ) {
+                    if annotation.key == *key {
+                        entry_found = true;
+                        //I am cheating here and use the filename as the key so that I do not need to write extra code for printing the file names
+                        let mut annotation = annotation.clone();
+                        annotation.key = filename.clone();
+                        annotations.push( annotation );
+                    }
+                }
+                if !entry_found {
+                    annotations.push( Annotation::new( filename.clone(),
+                                                       missing_value.clone(),
+                                                       missing_context.clone() ) );
+                }
+            }
+            let mut annotations = if show_duplicates { annotations } else { filter_duplicates( &annotations ) };
+            if sort_order.is_some() {
+                //filter_duplicates reverses the rows, entries of the same file keep their order
+                annotations.sort_by_key( |a| position.get( &a.key ).cloned() );
+            }
+            if output.has_header() {
+                let header = Annotation::new( tr( "header-filename", &[] ), key.clone(), tr( "header-context", &[] ) );
+                output.print_table( &header, [ "file", "value", "context" ], &annotations, show_context );
+            } else {
+                display_anno_container( &annotations, show_context, true, &*output );
+            }
+        } else if args.cmd_tag {
+            let filename = args.arg_filename.get( 0 ).unwrap(); //getopt ensures that this is not empty
+            check_target( filename );
+            for change in &args.arg_tag_change {
+                let ( add, tag ) = if change.starts_with( "-" ) {
+                    ( false, &change[ 1.. ] )
+                } else if change.starts_with( "+" ) {
+                    ( true, &change[ 1.. ] )
+                } else {
+                    ( true, change.as_str() )
+                };
+                if !is_valid_tag( tag ) {
+                    report_error( &tr( "invalid-tag", &[ change ] ) );
+                }
+                if add {
+                    checked( anno.add_tag( filename, tag ) );
+                } else {
+                    checked( anno.remove_tag( filename, tag ) );
+                }
+                anno.require_save();
+            }
+            if args.arg_tag_change.is_empty() {
+                for tag in anno.get_tags( filename ).into_iter().flat_map( |t| t.iter() ) {
+                    println!( "{}", tag );
+                }
+            }
+        } else if args.cmd_history || args.cmd_history_dir {
+            let key = args.arg_key.get( 0 ).unwrap(); //getopt ensures that this is not empty
+            let history = match args.arg_filename.get( 0 ) {
+                Some( filename ) => anno.annotation_history( filename, key ),
+                None => anno.directory_annotation_history( key )
+            };
+            if history.is_empty() {
+                exit_with( EXIT_NOT_FOUND, &tr( "no-matching-entry", &[ key ] ) );
+            }
+            let unknown_time = tr( "unknown-time", &[] );
+            let rows: AnnoContainer = history.iter().map( |a| {
+                let created = a.created.map( |c| c.to_string() ).unwrap_or( unknown_time.clone() );
+                Annotation::new( created, a.value.clone(), a.context.clone() )
+            } ).collect();
+            if output.has_header() {
+                let header = Annotation::new( tr( "header-created", &[] ), tr( "header-value", &[] ), tr( "header-context", &[] ) );
+                output.print_table( &header, [ "created", "value", "context" ], &rows, true );
+            } else {
+                output.print_annotations( &rows, true );
+            }
+        } else if args.cmd_log {
+            let log = anno.change_log();
+            if log.is_empty() {
+                exit_with( EXIT_NOT_FOUND, &tr( "no-matching-entry", &[ CHANGELOG_KEY ] ) );
+            }
+            let unknown_time = tr( "unknown-time", &[] );
+            let rows: AnnoContainer = log.iter().rev().map( |record| {
+                let created = record.created.map( |c| c.to_string() ).unwrap_or( unknown_time.clone() );
+                let message = if record.details.is_empty() { record.summary.clone() } else { format!( "{}\n{}", record.summary, record.details ) };
+                Annotation::new( created, message, record.context.clone() )
+            } ).collect();
+            if output.has_header() {
+                let header = Annotation::new( tr( "header-created", &[] ), tr( "header-message", &[] ), tr( "header-context", &[] ) );
+                output.print_table( &header, [ "created", "message", "context" ], &rows, true );
+            } else {
+                output.print_annotations( &rows, true );
+            }
+        } else if args.cmd_keys {
+            let file = if args.flag_file != "" { Some( args.flag_file.as_str() ) } else { None };
+            let key_counts = match anno.key_counts( file, show_duplicates ) {
+                Some( key_counts ) => key_counts,
+                None => exit_with( EXIT_NOT_FOUND, &tr( "file-not-annotated", &[ &args.flag_file ] ) )
+            };
+            let keys: AnnoContainer = key_counts.into_iter().map( |( key, count )| Annotation::new( key, count.to_string(), String::new() ) ).collect();
+            if output.has_header() {
+                output.print_table( &Annotation::new( tr( "header-key", &[] ), tr( "header-count", &[] ), String::new() ), [ "key", "count", "" ], &keys, false );
+            } else {
+                output.print_annotations( &keys, false );
+            }
+        } else if args.cmd_stats {
+            let stats = checked( anno.statistics() );
+            if args.flag_output == "json" {
+                let mut object = BTreeMap::new();
+                object.insert( "annotated_files".to_string(), Json::U64( stats.annotated_files as u64 ) );
+                object.insert( "annotations".to_string(), Json::U64( stats.annotations as u64 ) );
+                object.insert( "directory_annotations".to_string(), Json::U64( stats.directory_annotations as u64 ) );
+                object.insert( "average_per_file".to_string(), Json::F64( stats.average_per_file() ) );
+                object.insert( "keys".to_string(), Json::Array( stats.key_counts.iter().map( |&( ref key, count )| {
+                    let mut row = BTreeMap::new();
+                    row.insert( "key".to_string(), Json::String( key.clone() ) );
+                    row.insert( "files".to_string(), Json::U64( count as u64 ) );
+                    Json::Object( row )
+                } ).collect() ) );
+                object.insert( "unannotated_files".to_string(), Json::Array( stats.unannotated_files.iter().map( |f| Json::String( f.clone() ) ).collect() ) );
+                println!( "{}", Json::Object( object ).pretty() );
+            } else {
+                let summary: AnnoContainer = vec![
+                    ( "stats-annotated-files", stats.annotated_files.to_string() ),
+                    ( "stats-annotations", stats.annotations.to_string() ),
+                    ( "stats-directory-annotations", stats.directory_annotations.to_string() ),
+                    ( "stats-average", format!( "{:.2}", stats.average_per_file() ) ),
+                    ( "stats-unannotated", stats.unannotated_files.len().to_string() )
+                ].into_iter().map( |( id, value )| Annotation::new( tr( id, &[] ), value, String::new() ) ).collect();
+                output.print_annotations( &summary, false );
+                //the ten most common keys
+                let keys: AnnoContainer = stats.key_counts.iter().take( 10 )
+                                               .map( |&( ref key, count )| Annotation::new( key.clone(), count.to_string(), String::new() ) )
+                                               .collect();
+                if !keys.is_empty() {
+                    output.print_heading( &tr( "stats-common-keys", &[] ) );
+                    if output.has_header() {
+                        output.print_table( &Annotation::new( tr( "header-key", &[] ), tr( "header-files", &[] ), String::new() ), [ "key", "files", "" ], &keys, false );
+                    } else {
+                        output.print_annotations( &keys, false );
+                    }
+                }
+                if !stats.unannotated_files.is_empty() {
+                    output.print_heading( &tr( "stats-unannotated", &[] ) );
+                    for file in &stats.unannotated_files {
+                        output.print_report_line( "-", file, None );
+                    }
+                }
+            }
+        } else if args.cmd_get_many {
+            let key = args.arg_key.get( 0 ).unwrap(); //getopt ensures that this is not empty
+            let filenames = expand_targets( &anno, &args.arg_filename, true );
+            let values = if show_duplicates { anno.get_values( key ) } else { anno.get_unexpired_values( key, now ) };
+            let values: Vec<( String, &str )> = values.into_iter()
+                                                    .filter( |&( ref f, _ )| filenames.is_empty() || filenames.contains( f ) )
+                                                    .collect();
+            match args.flag_output.as_str() {
+                _ if args.flag_print0 => {
+                    for &( ref file, value ) in &values {
+                        NulOutput.print_record( &[ file, value ] );
+                    }
+                },
+                "json" => {
+                    let rows = values.iter().map( |&( ref file, value )| {
+                        let mut row = BTreeMap::new();
+                        row.insert( "file".to_string(), Json::String( file.clone() ) );
+                        row.insert( "value".to_string(), Json::String( value.to_string() ) );
+                        Json::Object( row )
+                    } ).collect();
+                    println!( "{}", Json::Array( rows ).pretty() );
+                },
+                "" | "tsv" | "csv" => {
+                    let format = DelimitedOutput { delimiter: if args.flag_output == "csv" { ',' } else { '\t' } };
+                    for &( ref file, value ) in &values {
+                        format.print_row( &[ file, value ] );
+                    }
+                },
+                other => report_error( &tr( "unknown-output", &[ other ] ) )
+            }
+        } else if args.cmd_report {
+            let kind = match args.flag_type.as_str() {
+                "" => None,
+                name => match FileKind::from_name( name ) {
+                    Some( kind ) => Some( kind ),
+                    None => report_error( &tr( "unknown-file-kind", &[ name ] ) )
+                }
+            };
+            let options = ReportOptions { verify: args.flag_verify, follow_links: args.flag_follow_links, normalization: settings.normalization() };
+            if recursive {
+                let root = meta_directory( &meta_file );
+                let dir = if args.flag_dir != "" { PathBuf::from( &args.flag_dir ) } else { root.clone() };
+                let jobs = args.flag_jobs.unwrap_or_else( || std::thread::available_parallelism().map( |n| n.get() ).unwrap_or( 1 ) );
+                let result = if show_progress {
+                    report::report_tree_with_progress( &root, &dir, &meta_name, use_dotfiles, options, jobs, &draw_progress )
+                } else {
+                    report::report_tree( &root, &dir, &meta_name, use_dotfiles, options, jobs )
+                };
+                print_report( &args, &meta_name, result, kind, &*output );
+            } else {
+                let dir = if args.flag_dir != "" { PathBuf::from( &args.flag_dir ) } else { anno.base_dir() };
+                print_report( &args, &meta_name, report::report_directory( &anno, &dir, Path::new( "" ), options ), kind, &*output );
+            }
+        } else if args.cmd_snapshot {
+            let dir = if args.flag_dir != "" { PathBuf::from( &args.flag_dir ) } else { anno.base_dir() };
+            match anno.record_checksums( &dir, &context ) {
+                Ok( count ) => println!( "{}", tr( "checksums-stored", &[ &count.to_string() ] ) ),
+                Err( e ) => report_failure( &e.to_string(), &e )
+            }
+            anno.require_save();
+        } else if args.cmd_relink {
+            let dir = if args.flag_dir != "" { PathBuf::from( &args.flag_dir ) } else { anno.base_dir() };
+            let moved = checked( anno.relink( &dir ) );
+            for &( ref old, ref new ) in &moved {
+                println!( "{} -> {}", old, new );
+            }
+            println!( "{}", tr( "relinked", &[ &moved.len().to_string() ] ) );
+            anno.require_save();
+        } else if args.cmd_rm_file_key {
+            let filenames = expand_targets( &anno, &args.arg_filename, true );
+            let matcher = key_matcher( &args );
+            for key in &args.arg_key {
+                let mut removed = false;
+                for filename in &filenames {
+                    removed = checked( anno.remove_file_annotation_entries_matching( filename, key, matcher ) ) || removed;
+                }
+                if !removed {
+                    let msg = tr( "no-matching-key", &[ key ] );
+                    report_warning( &msg );
+                }
+            }
+            anno.require_save();
+        } else if args.cmd_rm_entry {
+            let filename = args.arg_filename.get( 0 ).unwrap(); //getopt ensures that this is not empty
+            let key = args.arg_key.get( 0 ).unwrap();
+            let removed = match ( args.flag_index, args.arg_value.get( 0 ) ) {
+                ( Some( n ), _ ) => n > 0 && checked( anno.remove_nth_file_annotation( filename, key, n - 1 ) ),
+                ( None, Some( value ) ) => checked( anno.remove_file_annotation( filename, key, value ) ) > 0,
+                ( None, None ) => false //getopt requires a value or an index
+            };
+            if !removed {
+                exit_with( EXIT_NOT_FOUND, &tr( "no-matching-entry", &[ key ] ) );
+            }
+            anno.require_save();
+        } else if args.cmd_rm_id {
+            if !checked( anno.remove_id( &args.arg_id ) ) {
+                exit_with( EXIT_NOT_FOUND, &tr( "unknown-id", &[ &args.arg_id ] ) );
+            }
+            anno.require_save();
+        } else if args.cmd_amend {
+            //the new value is checked against the type of the entry
+            let value = match anno.find_id( &args.arg_id ) {
+                Some( ( _, annotation ) ) => typed_annotation( &annotation.key, &args.arg_new_value, "", annotation.value_type ).value,
+                None => exit_with( EXIT_NOT_FOUND, &tr( "unknown-id", &[ &args.arg_id ] ) )
+            };
+            checked( anno.amend_id( &args.arg_id, value ) );
+            anno.require_save();
+        } else if args.cmd_rm_dir_key {
+            let matcher = key_matcher( &args );
+            for key in &args.arg_key {
+                if !checked( anno.remove_directory_annotation_entries_matching( key, matcher ) ) {
+                    let msg = tr( "no-matching-key", &[ key ] );
+                    report_warning( &msg );
+                }
+            }
+            anno.require_save();
+        } else if args.cmd_rm_dir_entry {
+            let key = args.arg_key.get( 0 ).unwrap(); //getopt ensures that this is not empty
+            let removed = match ( args.flag_index, args.arg_value.get( 0 ) ) {
+                ( Some( n ), _ ) => n > 0 && checked( anno.remove_nth_directory_annotation( key, n - 1 ) ),
+                ( None, Some( value ) ) => checked( anno.remove_directory_annotation( key, value ) ) > 0,
+                ( None, None ) => false //getopt requires a value or an index
+            };
+            if !removed {
+                exit_with( EXIT_NOT_FOUND, &tr( "no-matching-entry", &[ key ] ) );
+            }
+            anno.require_save();
+        } else if args.cmd_drop_dir {
+            if !checked( anno.drop_directory_annotations() ) {
+                report_warning( &tr( "directory-not-annotated", &[] ) );
+            }
+            anno.require_save();
+        } else if args.cmd_drop_file {
+            for file in expand_targets( &anno, &args.arg_filename, true ) {
+                if !checked( anno.drop_file_annotations( &file ) ) {
+                    let msg = tr( "file-not-annotated", &[ &file ] );
+                    report_warning( &msg );
+                }
+            }
+            anno.require_save();
+        } else if args.cmd_search {
+            let query = search_query( &args, &args.arg_pattern );
+            //show the hits as table of filename, key and value
+            let directory_label = tr( "directory-label", &[] );
+            let rows: AnnoContainer = anno.search( &query ).iter().map( |hit| {
+                Annotation::new( hit.file.clone().unwrap_or( directory_label.clone() ),
+                                 hit.annotation.key.clone(),
+                                 hit.annotation.value.lines().next().unwrap_or( "" ).to_string() )
+            } ).collect();
+            output.print_columns( [ "file", "key", "value" ], &rows, true );
+        } else if args.cmd_edit {
+            let filename = args.arg_filename.get( 0 ).unwrap(); //getopt ensures that this is not empty
+            check_target( filename );
+            let original = anno.get_file_annotations( filename ).cloned().unwrap_or( AnnoContainer::new() );
+            let edited = edit_annotations( &original );
+            if edited != original {
+                checked( anno.set_file_annotations( filename, edited ) );
+                anno.require_save();
+            }
+        } else if args.cmd_copy {
+            let src = args.arg_filename.get( 0 ).unwrap(); //getopt ensures that this is not empty
+            //with --keys, the arguments after the source are destinations instead of keys
+            let ( destinations, keys ) = if args.flag_keys != "" {
+                let mut destinations = vec![ args.arg_filename2.clone() ];
+                destinations.extend( args.arg_key.iter().cloned() );
+                ( destinations, args.flag_keys.split( ',' ).map( |k| k.trim().to_string() ).filter( |k| !k.is_empty() ).collect() )
+            } else {
+                ( vec![ args.arg_filename2.clone() ], args.arg_key.clone() )
+            };
+            let map = match args.flag_map.as_str() {
+                "" => vec![],
+                text => match CopySpec::parse_map( text ) {
+                    Some( map ) => map,
+                    None => exit_with( EXIT_USAGE, &tr( "invalid-map", &[ text ] ) )
+                }
+            };
+            for dst in &destinations {
+                check_target( dst );
+            }
+            if anno.get_file_annotations( src ).is_none() {
+                exit_with( EXIT_NOT_FOUND, &tr( "file-not-annotated", &[ src ] ) );
+            }
+            let spec = CopySpec::new( src, &destinations ).with_keys( &keys ).with_map( map ).with_context( &context );
+            if checked( anno.copy( &spec ) ) == 0 {
+                report_warning( &tr( "nothing-copied", &[] ) );
+            } else {
+                anno.require_save();
+            }
+        } else if args.cmd_diff {
+            let other_path = Path::new( &args.arg_other_file );
+            let other = match Annovate::open( other_path, OpenMode::ReadOnly ) {
+                Ok( other ) => other,
+                Err( e ) => report_failure( &tr( "diff-failed", &[ &args.arg_other_file, &e.to_string() ] ), &e )
+            };
+            let report = anno.diff( &other );
+            if report.is_empty() {
+                output.print_note( &tr( "no-differences", &[] ) );
+            } else {
+                output.print_diff( &report, &meta_file, &args.arg_other_file );
+                let ( added, removed, changed ) = report.counts();
+                output.print_note( &tr( "diff-summary", &[ &added.to_string(), &removed.to_string(), &changed.to_string() ] ) );
+            }
+        } else if args.cmd_merge {
+            let strategy = match MergeStrategy::from_name( &args.flag_strategy ) {
+                Some( strategy ) => strategy,
+                None => report_error( &tr( "unknown-strategy", &[ &args.flag_strategy ] ) )
+            };
+            let other_path = Path::new( &args.arg_other_file );
+            if !other_path.is_file() {
+                report_error( &tr( "merge-failed", &[ &args.arg_other_file, &tr( "no-such-file", &[] ) ] ) );
+            }
+            let other = match Annovate::with_lock_timeout( other_path, Duration::from_secs( args.flag_wait ) ) {
+                Ok( other ) => other,
+                Err( e ) => report_failure( &tr( "merge-failed", &[ &args.arg_other_file, &e.to_string() ] ), &e )
+            };
+            let report = checked( anno.merge( &other, strategy ) );
+            let directory_label = tr( "directory-label", &[] );
+            for conflict in &report.conflicts {
+                let file = conflict.file.clone().unwrap_or( directory_label.clone() );
+                let kept = if conflict.took_theirs { &conflict.theirs } else { &conflict.ours };
+                println!( "{}", tr( "merge-conflict", &[ &file, &conflict.key, &conflict.ours, &conflict.theirs, kept ] ) );
+            }
+            println!( "{}", tr( "merged", &[ &report.added.to_string(), &report.conflicts.len().to_string() ] ) );
+            anno.require_save();
+        } else if args.cmd_undo {
+            let count = if args.arg_count == "" { Ok( 1 ) } else { args.arg_count.parse::<usize>() };
+            let count = match count {
+                Ok( count ) => count,
+                Err( _ ) => report_error( &tr( "invalid-count", &[ &args.arg_count ] ) )
+            };
+            match anno.undo( count ) {
+                Ok( 0 ) => println!( "{}", tr( "nothing-to-undo", &[] ) ),
+                Ok( undone ) => {
+                    println!( "{}", tr( "undone", &[ &undone.to_string() ] ) );
+                    anno.require_save();
+                },
+                Err( e ) => report_failure( &tr( "undo-failed", &[ &e.to_string() ] ), &e )
+            }
+        } else if args.cmd_restore_backup {
+            let number = if args.arg_number == "" { Ok( 1 ) } else { args.arg_number.parse::<usize>() };
+            let number = match number {
+                Ok( number ) if number > 0 => number,
+                _ => report_error( &tr( "invalid-backup", &[ &args.arg_number ] ) )
+            };
+            match anno.restore_backup( number ) {
+                Ok( true ) => {
+                    println!( "{}", tr( "restored-backup", &[ &backup::backup_path( Path::new( &meta_file ), number ).to_string_lossy() ] ) );
+                    anno.require_save();
+                },
+                Ok( false ) => exit_with( EXIT_NOT_FOUND, &tr( "no-backup", &[ &number.to_string() ] ) ),
+                Err( e ) => report_failure( &tr( "restore-failed", &[ &e.to_string() ] ), &e )
+            }
+        } else if args.cmd_compact {
+            if args.flag_keep == 0 {
+                report_error( &tr( "invalid-keep", &[] ) );
+            }
+            let folded = anno.delta_records();
+            let removed = checked( anno.compact( args.flag_keep ) );
+            if folded > 0 {
+                println!( "{}", tr( "folded-deltas", &[ &folded.to_string() ] ) );
+            }
+            println!( "{}", tr( "compacted", &[ &removed.to_string() ] ) );
+            anno.require_save();
+        } else if args.cmd_dedup {
+            let removed = checked( anno.dedup_exact() );
+            println!( "{}", tr( "deduplicated", &[ &removed.to_string() ] ) );
+            anno.require_save();
+        } else if args.cmd_prune_expired {
+            let removed = checked( anno.prune_expired( now ) );
+            println!( "{}", tr( "pruned-expired", &[ &removed.to_string() ] ) );
+            anno.require_save();
+        } else if args.cmd_rename {
+            let old = args.arg_filename.get( 0 ).unwrap(); //getopt ensures that this is not empty
+            if args.flag_fs && !args.flag_dry_run {
+                if let Err( e ) = rename( anno.resolve( old ), anno.resolve( &args.arg_new_filename ) ) {
+                    report_error( &tr( "rename-failed", &[ old, &e.to_string() ] ) );
+                }
+            }
+            if !checked( anno.rename_file( old, &args.arg_new_filename ) ) {
+                report_warning( &tr( "file-not-annotated", &[ old ] ) );
+            }
+            anno.require_save();
+        } else if args.cmd_move_file {
+            let filename = args.arg_filename.get( 0 ).unwrap(); //getopt ensures that this is not empty
+            let other_path = Path::new( &args.flag_to );
+            if other_path.canonicalize().ok() == Path::new( &meta_file ).canonicalize().ok() && other_path.exists() {
+                report_error( &tr( "move-to-same-file", &[] ) );
+            }
+            let mut other = match Annovate::with_lock_timeout( other_path, Duration::from_secs( args.flag_wait ) ) {
+                Ok( other ) => other,
+                Err( ref e @ AnnoError::Locked( _ ) ) => exit_with_error( EXIT_LOCKED, &tr( "file-locked", &[ &args.flag_to, &e.path().unwrap_or( Path::new( "" ) ).to_string_lossy() ] ), e ),
+                Err( e ) => report_failure( &e.to_string(), &e )
+            };
+            let mut targets = vec![ filename.clone() ];
+            targets.extend( anno.get_sub_targets( filename ) );
+            let mut moved = false;
+            for target in &targets {
+                let tags: Vec<String> = anno.get_tags( target ).map( |tags| tags.iter().cloned().collect() ).unwrap_or_default();
+                if let Some( annotations ) = checked( anno.extract_file( target ) ) {
+                    checked( other.absorb_file( target, annotations ) );
+                    moved = true;
+                }
+                for tag in &tags {
+                    checked( other.add_tag( target, tag ) );
+                }
+            }
+            if !moved {
+                exit_with( EXIT_NOT_FOUND, &tr( "file-not-annotated", &[ filename ] ) );
+            }
+            //the annotations are saved in their new place before they are removed from this file
+            if !args.flag_dry_run {
+                if let Err( e ) = other.save() {
+                    report_failure( &tr( "move-failed", &[ &args.flag_to, &e.to_string() ] ), &e );
+                }
+            }
+            anno.require_save();
+        } else if args.cmd_rename_key {
+            if args.arg_new_key.is_empty() {
+                report_error( &tr( "empty-key", &[] ) );
+            }
+            let scope = if args.flag_file != "" { KeyScope::File( args.flag_file.clone() ) } else { KeyScope::All };
+            let record = if args.flag_record { Some( context.as_str() ) } else { None };
+            if checked( anno.rename_key( &args.arg_old_key, &args.arg_new_key, scope, record ) ) == 0 {
+                report_warning( &tr( "no-matching-key", &[ &args.arg_old_key ] ) );
+            }
+            anno.require_save();
+        } else if args.cmd_harvest {
+            let dir = anno.base_dir();
+            let filenames = if args.arg_filename.is_empty() {
+                directory_files( &dir, &meta_file, use_dotfiles )
+            } else {
+                args.arg_filename.clone()
+            };
+            let harvester = Harvester::default();
+            let mut count = 0;
+            for ( done, filename ) in filenames.iter().enumerate() {
+                match anno.harvest( &dir, filename, &harvester, &context ) {
+                    Ok( added ) => count += added,
+                    Err( e ) => report_failure( &tr( "harvest-failed", &[ &e.to_string() ] ), &e )
+                }
+                if show_progress {
+                    draw_progress( &Progress { stage: ProgressStage::Entries, done: done as u64 + 1, total: Some( filenames.len() as u64 ) } );
+                }
+            }
+            println!( "{}", tr( "harvested", &[ &count.to_string() ] ) );
+            anno.require_save();
+        } else if args.cmd_sync_xattr {
+            let dir = anno.base_dir();
+            sync_xattr( anno, &dir, args.flag_push, &context );
+            if args.flag_pull {
+                anno.require_save();
+            }
+        } else if args.cmd_import_git {
+            let dir = anno.base_dir();
+            match annovate::git::import_history( anno, &dir, args.flag_with_message, &context ) {
+                Ok( count ) => println!( "{}", tr( "git-imported", &[ &count.to_string() ] ) ),
+                Err( e ) => report_failure( &tr( "git-import-failed", &[ &e.to_string() ] ), &e )
+            }
+            anno.require_save();
+        } else if args.cmd_export_git_notes {
+            match annovate::git::export_notes( &anno, &anno.base_dir(), &args.arg_filename ) {
+                Ok( count ) => println!( "{}", tr( "notes-written", &[ &count.to_string() ] ) ),
+                Err( e ) => report_failure( &tr( "notes-export-failed", &[ &e.to_string() ] ), &e )
+            }
+        } else if args.cmd_import_git_notes {
+            let dir = anno.base_dir();
+            match annovate::git::import_notes( anno, &dir ) {
+                Ok( count ) => println!( "{}", tr( "notes-read", &[ &count.to_string() ] ) ),
+                Err( e ) => report_failure( &tr( "notes-import-failed", &[ &e.to_string() ] ), &e )
+            }
+            anno.require_save();
+        } else if args.cmd_git_hook {
+            let dir = anno.base_dir();
+            if args.cmd_install {
+                let meta_path = Path::new( &meta_file ).canonicalize().unwrap_or( PathBuf::from( &meta_file ) );
+                let program = env::current_exe().map( |p| p.to_string_lossy().into_owned() ).unwrap_or( "anno".to_string() );
+                let meta_path = meta_path.to_string_lossy();
+                match annovate::git::install_hook( &dir, "post-commit", &[ &program, "-m", &meta_path, "--context-git", "git-hook", "run" ] ) {
+                    Ok( hook ) => println!( "{}", tr( "hook-installed", &[ &hook.to_string_lossy() ] ) ),
+                    Err( e ) => report_failure( &tr( "hook-install-failed", &[ &e.to_string() ] ), &e )
+                }
+            } else if args.cmd_run {
+                if let Err( e ) = annovate::git::record_snapshot( anno, &dir, &context ) {
+                    report_failure( &tr( "snapshot-failed", &[ &e.to_string() ] ), &e );
+                }
+                anno.require_save();
+            }
+        } else if args.cmd_export {
+            match args.flag_format.as_str() {
+                "" | "json" => println!( "{}", anno.to_json() ),
+                other => match backend_by_name( other ) {
+                    Some( backend ) => print!( "{}", backend.serialize( &anno, None ) ),
+                    None => report_error( &tr( "unknown-format", &[ other ] ) )
+                }
+            }
+        } else if args.cmd_import {
+            let progress: Option<&dyn Fn( &Progress )> = if show_progress { Some( &draw_progress ) } else { None };
+            let read_result = if args.arg_input == "" || args.arg_input == "-" {
+                progress::read_with_progress( stdin(), None, progress )
+            } else {
+                File::open( &args.arg_input ).and_then( |f| {
+                    let size = f.metadata().ok().map( |m| m.len() );
+                    progress::read_with_progress( f, size, progress )
+                } )
+            };
+            let text = match read_result.map( String::from_utf8 ) {
+                Ok( Ok( text ) ) => text,
+                Ok( Err( e ) ) => report_error( &tr( "import-failed", &[ &e.to_string() ] ) ),
+                Err( e ) => report_error( &tr( "import-failed", &[ &e.to_string() ] ) )
+            };
+            let imported = match args.flag_format.as_str() {
+                "" | "json" => match Annovate::from_json( &text, Path::new( &meta_file ) ) {
+                    Ok( imported ) => imported,
+                    Err( e ) => report_failure( &tr( "import-failed", &[ &e.to_string() ] ), &e )
+                },
+                other => match backend_by_name( other ).map( |backend| backend.parse( &text ) ) {
+                    Some( Ok( data ) ) => Annovate::from_data( data, Path::new( &meta_file ) ),
+                    Some( Err( e ) ) => report_failure( &tr( "import-failed", &[ &e.to_string() ] ), &e ),
+                    None => report_error( &tr( "unknown-format", &[ other ] ) )
+                }
+            };
+            checked( anno.replace_annotations( imported ) );
+            anno.require_save();
+        } else if args.cmd_dictionary {
+            let filename = args.arg_filename.get( 0 ).unwrap(); //getopt ensures that this is not empty
+            let dictionary = match DataDictionary::new( &anno, &anno.base_dir(), filename ) {
+                Ok( d ) => d,
+                Err( e ) => report_failure( &tr( "dictionary-failed", &[ &e.to_string() ] ), &e )
+            };
+            match args.flag_format.as_str() {
+                "" | "markdown" => print!( "{}", dictionary.to_markdown() ),
+                "csv" => print!( "{}", dictionary.to_csv() ),
+                other => report_error( &tr( "unknown-format", &[ other ] ) )
+            }
+        } else {
+            assert!( false ); //docopt should have caught any other case
         }
-        require_write_to_disk = true;
-    } else if args.cmd_drop_file {
-        for file in args.arg_filename {
-            if !anno.drop_file_annotations( &file ) {
-                let msg = format!( "File is not in annotations: {}", file );
-                report_warning( &msg );
+        output.finish();
+        report_quota_warnings( anno.quota_warnings() );
+        //files identified by content need a checksum from their first annotation on
+        if settings.identity() == IdentityMode::Hash && anno.is_modified() {
+            let dir = anno.base_dir();
+            checked( anno.record_missing_checksums( &dir, &context ) );
+        }
+        if let Some( ref before ) = before {
+            if args.flag_message != "" {
+                let changes = ChangeSet::between( before, anno );
+                if changes.is_empty() {
+                    report_warning( &tr( "message-unused", &[] ) );
+                } else {
+                    checked( anno.record_change_in_context( &args.flag_message, &changelog::describe_changes( &changes ), &context ) );
+                }
+            }
+            //the changes of a dry run are shown instead of saving them
+            if args.flag_dry_run {
+                if anno.is_modified() || anno.is_save_required() {
+                    let changes = ChangeSet::between( before, anno );
+                    print_changes( &changes, output.colors() );
+                    println!( "{}", tr( "dry-run", &[ &changes.len().to_string() ] ) );
+                }
+                anno.discard();
             }
         }
-        require_write_to_disk = true;
-    } else {
-        assert!( false ); //docopt should have caught any other case
+        Ok( () )
+    } );
+    match result {
+        Ok( () ) => {},
+        Err( ref e ) => match *e {
+            AnnoError::Rejected( ref reason ) => report_failure( &tr( "save-rejected", &[ reason ] ), e ),
+            AnnoError::Locked( ref lock ) => exit_with_error( EXIT_LOCKED, &tr( "file-locked", &[ &meta_outfile.to_string_lossy(), &lock.to_string_lossy() ] ), e ),
+            _ => report_failure( &tr( "write-failed", &[] ), e )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use docopt::Docopt;
+
+    use annovate::{AnnoContainer, Annotation, Fragment, KeyMatcher};
+    use super::{USAGE, entries_to_get, filter_duplicates, label_regions};
+
+    #[test]
+    fn usage_is_valid() {
+        //docopt takes every line that starts with `-` for the definition of an option
+        if let Err( e ) = Docopt::new( USAGE ) {
+            panic!( "{}", e );
+        }
     }
 
-    if require_write_to_disk {
-        if anno.save_as( meta_outfile ).is_err() {
-            stderr().write( b"[FATAL] Failed to write annovate file to disk\n" ).unwrap();
+    #[test]
+    fn configured_language() {
+        use std::env;
+        use messages::{self, Locale};
+        if env::var( "ANNOVATE_LANG" ).map( |l| !l.is_empty() ).unwrap_or( false ) {
+            return; //it takes precedence over the configuration
         }
+        messages::set_language( Some( "de" ) );
+        assert_eq!( Locale::current(), Locale::German );
+        messages::set_language( Some( "en" ) );
+        assert_eq!( Locale::current(), Locale::English );
+        messages::set_language( None );
+    }
+
+    #[test]
+    fn get_most_recent() {
+        let annotation = |key: &str, value: &str| Annotation::new( key.to_string(), value.to_string(), "c".to_string() );
+        let annotations: AnnoContainer = vec![ annotation( "k", "v1" ), annotation( "other", "x" ), annotation( "k", "v2" ) ];
+        let values = |all: bool| entries_to_get( &annotations, "k", KeyMatcher::Exact, all ).into_iter().map( |a| a.value ).collect::<Vec<String>>();
+        assert_eq!( values( false ), vec![ "v2" ] );
+        assert_eq!( values( true ), vec![ "v1", "v2" ] );
+        assert!( entries_to_get( &annotations, "missing", KeyMatcher::Exact, false ).is_empty() );
+    }
+
+    #[test]
+    fn shown_regions() {
+        let annotation = |value: &str, lines: Option<( u64, u64 )>| {
+            let annotation = Annotation::new( "anomaly".to_string(), value.to_string(), "c".to_string() );
+            match lines {
+                Some( ( first, last ) ) => annotation.with_region( Fragment::Lines( first, last ) ),
+                None => annotation
+            }
+        };
+        let annotations: AnnoContainer = vec![ annotation( "a", Some( ( 10, 20 ) ) ), annotation( "b", Some( ( 30, 30 ) ) ), annotation( "c", Some( ( 10, 20 ) ) ) ];
+        let shown = label_regions( filter_duplicates( &annotations ) );
+        let shown: Vec<( &str, &str )> = shown.iter().map( |a| ( a.key.as_str(), a.value.as_str() ) ).collect();
+        assert_eq!( shown, vec![ ( "anomaly #L10-20", "c" ), ( "anomaly #L30", "b" ) ] );
+        assert_eq!( label_regions( vec![ annotation( "d", None ) ] )[ 0 ].key, "anomaly" );
     }
 }
@@ -0,0 +1,131 @@
+use time;
+
+use {Annovate, Annotation, AnnoContainer, AnnoError, Timestamp};
+
+/// How to resolve a key whose most recent value differs between two annovate files
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MergeStrategy {
+    /// Keep our value
+    Ours,
+    /// Take their value
+    Theirs,
+    /// Keep the history of both files: all of their entries that we do not have are added
+    Union,
+    /// Take the value that was set last, judged by the creation time or the date in the context
+    NewestByContext
+}
+
+impl MergeStrategy {
+    /// Parse the name of a strategy as used on the command line
+    pub fn from_name( name: &str ) -> Option<MergeStrategy> {
+        match name {
+            "ours" => Some( MergeStrategy::Ours ),
+            "theirs" => Some( MergeStrategy::Theirs ),
+            "union" => Some( MergeStrategy::Union ),
+            "newest-by-context" => Some( MergeStrategy::NewestByContext ),
+            _ => None
+        }
+    }
+}
+
+/// A key that had different values in both files
+#[derive(Clone, PartialEq, Debug)]
+pub struct MergeConflict {
+    /// `None` for directory annotations
+    pub file: Option<String>,
+    pub key: String,
+    pub ours: String,
+    pub theirs: String,
+    pub took_theirs: bool
+}
+
+/// What `Annovate::merge` changed
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct MergeReport {
+    /// Number of annotations that were added
+    pub added: usize,
+    pub conflicts: Vec<MergeConflict>
+}
+
+/// Time at which an annotation was made: its creation time or the date at the end of a context
+/// like `annovate program, 1.3.2016 12:30:00` (compared as is, without time zone)
+pub fn annotation_time( annotation: &Annotation ) -> Option<Timestamp> {
+    if annotation.created.is_some() {
+        return annotation.created;
+    }
+    let date = annotation.context.rsplit( ", " ).next().unwrap_or( "" );
+    time::strptime( date.trim(), "%d.%m.%Y %H:%M:%S" ).ok().map( |tm| Timestamp::from_seconds( tm.to_timespec().sec ) )
+}
+
+fn merge_container( ours: &mut AnnoContainer, theirs: &AnnoContainer, file: Option<&str>, strategy: MergeStrategy, report: &mut MergeReport ) {
+    let mut keys: Vec<&str> = Vec::new();
+    for annotation in theirs {
+        if !keys.contains( &annotation.key.as_str() ) {
+            keys.push( &annotation.key );
+        }
+    }
+    for key in keys {
+        let their_entries: Vec<&Annotation> = theirs.iter().filter( |a| a.key == key ).collect();
+        let their_latest = their_entries[ their_entries.len() - 1 ]; //the key comes from their entries
+        let our_latest = ours.iter().rev().find( |a| a.key == key ).cloned();
+        let our_latest = match our_latest {
+            Some( annotation ) => annotation,
+            None => {
+                ours.extend( their_entries.iter().map( |a| ( *a ).clone() ) );
+                report.added += their_entries.len();
+                continue;
+            }
+        };
+        if strategy == MergeStrategy::Union {
+            //the history of both files is kept and the last entry of theirs wins if the values differ
+            let missing: Vec<Annotation> = their_entries.iter().filter( |a| !ours.contains( a ) ).map( |a| ( *a ).clone() ).collect();
+            report.added += missing.len();
+            ours.extend( missing );
+        }
+        if our_latest.value == their_latest.value {
+            continue;
+        }
+        let take_theirs = match strategy {
+            MergeStrategy::Ours => false,
+            MergeStrategy::Theirs => true,
+            MergeStrategy::Union => ours.iter().rev().find( |a| a.key == key ) == Some( their_latest ),
+            MergeStrategy::NewestByContext => annotation_time( their_latest ) > annotation_time( &our_latest )
+        };
+        if take_theirs && strategy != MergeStrategy::Union {
+            ours.push( their_latest.clone() ); //the most recent entry of a key wins
+            report.added += 1;
+        }
+        report.conflicts.push( MergeConflict { file: file.map( |f| f.to_string() ), key: key.to_string(),
+                                               ours: our_latest.value.clone(), theirs: their_latest.value.clone(), took_theirs: take_theirs } );
+    }
+}
+
+impl Annovate {
+    /// Add the annotations and tags of `other`. For keys whose most recent values differ, `strategy` decides
+    /// which value is the most recent after merging; earlier entries are kept like after `add`.
+    pub fn merge( &mut self, other: &Annovate, strategy: MergeStrategy ) -> Result<MergeReport, AnnoError> {
+        try!( self.check_writable() );
+        let entries_before = self.count_entries();
+        let mut report = MergeReport::default();
+        merge_container( &mut self.dir, &other.dir, None, strategy, &mut report );
+        for file in other.get_files() {
+            if !self.files.contains_key( &file ) {
+                self.file_order.push( file.clone() );
+            }
+            let ours = self.files.entry( file.clone() ).or_insert( AnnoContainer::new() );
+            merge_container( ours, &other.files[ &file ], Some( &file ), strategy, &mut report );
+        }
+        self.dir_tags.extend( other.dir_tags.iter().cloned() );
+        for ( file, tags ) in &other.tags {
+            self.tags.entry( file.clone() ).or_insert( Default::default() ).extend( tags.iter().cloned() );
+        }
+        //merging only adds annotations and tags
+        let changed = self.count_entries() != entries_before;
+        Ok( self.mark_modified( changed, report ) )
+    }
+
+    /// Number of annotations and tags
+    fn count_entries( &self ) -> usize {
+        self.dir.len() + self.dir_tags.len() + self.files.values().map( |a| a.len() ).sum::<usize>() + self.tags.values().map( |t| t.len() ).sum::<usize>()
+    }
+}
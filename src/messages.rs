@@ -0,0 +1,459 @@
+//Message catalog for the command line interface.
+//Messages are looked up by id and may contain positional placeholders (`{0}`, `{1}`, ...).
+//Messages that are missing in a locale fall back to English.
+
+use std::env;
+use std::sync::Mutex;
+
+/// The `language` of the configuration, see `set_language`
+static CONFIGURED: Mutex<Option<Locale>> = Mutex::new( None );
+
+/// Use the locale named `name` (e.g. `de`) unless ANNOVATE_LANG is set, `None` to go by the environment again
+pub fn set_language( name: Option<&str> ) {
+    if let Ok( mut configured ) = CONFIGURED.lock() {
+        *configured = name.filter( |n| !n.is_empty() ).map( Locale::from_name );
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Locale {
+    English,
+    German
+}
+
+impl Locale {
+    /// Determine the locale from ANNOVATE_LANG, the configured language, LC_ALL, LC_MESSAGES or LANG (in that order)
+    pub fn current() -> Locale {
+        let from_env = |var: &str| env::var( var ).ok().filter( |value| !value.is_empty() ).map( |value| Locale::from_name( &value ) );
+        if let Some( locale ) = from_env( "ANNOVATE_LANG" ) {
+            return locale;
+        }
+        if let Some( locale ) = CONFIGURED.lock().ok().and_then( |configured| *configured ) {
+            return locale;
+        }
+        [ "LC_ALL", "LC_MESSAGES", "LANG" ].iter().filter_map( |var| from_env( var ) ).next().unwrap_or( Locale::English )
+    }
+
+    /// Map a locale name like `de_DE.UTF-8` to a supported locale
+    pub fn from_name( name: &str ) -> Locale {
+        if name.starts_with( "de" ) {
+            Locale::German
+        } else {
+            Locale::English
+        }
+    }
+
+    fn catalog( &self ) -> &'static [( &'static str, &'static str )] {
+        match *self {
+            Locale::English => ENGLISH,
+            Locale::German => GERMAN
+        }
+    }
+}
+
+const ENGLISH: &'static [( &'static str, &'static str )] = &[
+    ( "explanation-heading", "Explanation of subcommands:" ),
+    ( "header-filename", "Filename" ),
+    ( "header-key", "Key" ),
+    ( "header-value", "Value" ),
+    ( "header-context", "Context" ),
+    ( "header-created", "Created" ),
+    ( "header-message", "Message" ),
+    ( "header-files", "Files" ),
+    ( "header-count", "Count" ),
+    ( "stats-annotated-files", "Annotated files" ),
+    ( "stats-annotations", "Annotations of files" ),
+    ( "stats-directory-annotations", "Annotations of the directory" ),
+    ( "stats-average", "Annotations per file" ),
+    ( "stats-unannotated", "Files without annotations" ),
+    ( "stats-common-keys", "Most common keys" ),
+    ( "unknown-time", "<unknown-time>" ),
+    ( "missing-value", "<missing-value>" ),
+    ( "missing-context", "<missing-context>" ),
+    ( "create-dir-failed", "Failed to create new directory" ),
+    ( "examine-failed", "Failed to examine {0}: {1}" ),
+    ( "no-problems", "No problems found" ),
+    ( "migrated", "Renamed {0} to {1}" ),
+    ( "migrated-merged", "Merged {0} into {1}" ),
+    ( "migrated-journal-kept", "The changes of {0} cannot be undone any more, its journal was kept as {1}" ),
+    ( "would-migrate", "Would move the annotations of {0} to {1}" ),
+    ( "nothing-to-migrate", "No meta files to migrate" ),
+    ( "migrate-failed", "Migration failed: {0}" ),
+    ( "legacy-meta-file", "{0} does not exist, but {1} does; run `anno migrate` to use its annotations" ),
+    ( "repaired", "Repaired {0} problems, the original file was saved as {1}" ),
+    ( "run-doctor", "{0} (run `anno doctor` for details, or use --lenient)" ),
+    ( "parse-warning", "Skipped in {0}: {1}" ),
+    ( "append-unsupported", "--append cannot be combined with --template, --hook, --dry-run, --replace or --if-absent" ),
+    ( "conflicting-put-policy", "--replace and --if-absent cannot be combined" ),
+    ( "append-failed", "Appending failed: {0}" ),
+    ( "resolve-failed", "The value of `{0}` cannot be resolved: {1}" ),
+    ( "no-annotations", "Filename has no annotations" ),
+    ( "no-metadata", "Filename has no metadata" ),
+    ( "invalid-url", "Invalid URL: {0}" ),
+    ( "invalid-target", "Invalid part `{1}` of `{0}`, expected e.g. #column:age, #L10-20 (lines start at 1) or #B0-511; write ## for a # of the filename" ),
+    ( "invalid-range", "Invalid range `{0}`, expected e.g. 10-20 (lines start at 1)" ),
+    ( "invalid-byte-range", "Invalid range `{0}`, expected e.g. 0-511 (bytes are counted from 0)" ),
+    ( "lines-and-bytes", "--lines and --bytes cannot be combined" ),
+    ( "read-dir-failed", "Failed to read directory: {0}" ),
+    ( "report-annotated", "Annotated (=)" ),
+    ( "report-changed", "Changed (~)" ),
+    ( "report-orphaned", "Metadata without file (+)" ),
+    ( "report-unannotated", "Without metadata (-)" ),
+    ( "unannotated-files", "Some files have no metadata" ),
+    ( "no-matching-key", "No matching entries found for key `{0}`" ),
+    ( "no-matching-key-suggestion", "No matching entries found for key `{0}`, did you mean `{1}`?" ),
+    ( "empty-key", "The new key must not be empty" ),
+    ( "move-to-same-file", "The annotations cannot be moved to the meta file they are in" ),
+    ( "move-failed", "Failed to save {0}, nothing was moved: {1}" ),
+    ( "no-matching-entry", "No matching entry found for key `{0}`" ),
+    ( "message-unused", "Nothing changed, the message was not recorded" ),
+    ( "unknown-id", "No entry has the ID `{0}`" ),
+    ( "file-not-annotated", "File is not in annotations: {0}" ),
+    ( "directory-not-annotated", "The directory has no annotations" ),
+    ( "harvested", "Harvested {0} annotations" ),
+    ( "progress-read", "Reading" ),
+    ( "progress-save", "Saving" ),
+    ( "progress-entries", "Processing" ),
+    ( "checksums-stored", "Stored {0} checksums" ),
+    ( "relinked", "Moved the annotations of {0} files" ),
+    ( "found-by-content", "{0} has no annotations, showing those of {1} with the same content (`anno relink` moves them)" ),
+    ( "harvest-failed", "Failed to harvest annotations: {0}" ),
+    ( "xattr-pushed", "Wrote the extended attributes of {0} files" ),
+    ( "xattr-pulled", "Read {0} annotations from extended attributes" ),
+    ( "xattr-failed", "Failed to synchronize extended attributes: {0}" ),
+    ( "xattr-unsupported", "This anno was built without support for extended attributes (cargo feature `xattr`)" ),
+    ( "git-imported", "Imported git history for {0} files" ),
+    ( "git-import-failed", "Failed to import git history: {0}" ),
+    ( "notes-written", "Wrote {0} git notes" ),
+    ( "notes-export-failed", "Failed to export git notes: {0}" ),
+    ( "notes-read", "Read {0} git notes" ),
+    ( "hook-installed", "Installed the git hook {0}" ),
+    ( "hook-install-failed", "Failed to install the git hook: {0}" ),
+    ( "snapshot-failed", "Failed to record the commit: {0}" ),
+    ( "notes-import-failed", "Failed to import git notes: {0}" ),
+    ( "dictionary-failed", "Failed to build data dictionary: {0}" ),
+    ( "unknown-format", "Unknown format `{0}`" ),
+    ( "unknown-output", "Unknown output format `{0}` (use table, plain, csv or tsv)" ),
+    ( "config-failed", "Failed to read the configuration: {0}" ),
+    ( "dry-run", "Dry run: {0} changes were not saved" ),
+    ( "dry-run-unsupported", "--dry-run is not supported by this command" ),
+    ( "unknown-type", "Unknown type `{0}` (use text, int, bool, date, list or binary)" ),
+    ( "unknown-file-kind", "Unknown kind of file `{0}` (use file, dir, symlink, broken-link or other)" ),
+    ( "invalid-expiry", "Invalid expiry time `{0}` (use a duration like 30m, 12h, 7d or 2w, or a time like 2026-12-31T00:00:00Z)" ),
+    ( "invalid-value", "Invalid value: {0}" ),
+    ( "nothing-copied", "None of the keys were found, nothing was copied" ),
+    ( "invalid-map", "Invalid key mapping `{0}`, expected e.g. owner=original_owner,*=upstream.*" ),
+    ( "no-match", "No file matches `{0}`" ),
+    ( "watching", "Waiting for new files in {0} (stop with Ctrl-C)" ),
+    ( "new-file", "New file: {0}" ),
+    ( "write-failed", "Failed to write annovate file to disk" ),
+    ( "unknown-template", "There is no template named `{0}`" ),
+    ( "no-schema", "No schema is defined (see `anno schema define`)" ),
+    ( "invalid-schema", "The schema cannot be used: {0}" ),
+    ( "schema-valid", "All files match the schema" ),
+    ( "rename-failed", "Failed to rename {0}: {1}" ),
+    ( "import-failed", "Failed to import annotations: {0}" ),
+    ( "read-value-failed", "Failed to read the value from {0}: {1}" ),
+    ( "no-passphrase", "The value of `{0}` is to be encrypted, but ANNOVATE_PASSPHRASE is not set" ),
+    ( "encrypt-failed", "Failed to encrypt the value of `{0}`: {1}" ),
+    ( "decrypt-failed", "Failed to decrypt the value of `{0}`: {1}" ),
+    ( "encrypted-values", "Some values are encrypted, set {0} to decrypt them" ),
+    ( "stdin-value-twice", "Only one value can be read from stdin (`-`)" ),
+    ( "value-file-with-value", "--value-file gives the value of a single key, which needs no other value" ),
+    ( "directory-label", "<directory>" ),
+    ( "file-locked", "{0} is in use by another anno process (remove {1} if that process is gone)" ),
+    ( "edit-failed", "Failed to exchange the annotations with the editor: {0}" ),
+    ( "editor-failed", "The editor `{0}` did not finish successfully, the annotations are unchanged" ),
+    ( "edit-again", "The annotations cannot be read. Edit them again? [Y/n]" ),
+    ( "edit-discarded", "The changes were discarded" ),
+    ( "invalid-tag", "Invalid tag `{0}`: tags are single words like +urgent or -draft" ),
+    ( "invalid-count", "`{0}` is not a number of changes" ),
+    ( "nothing-to-undo", "There are no changes to undo" ),
+    ( "undone", "Reverted {0} changes" ),
+    ( "compacted", "Removed {0} older entries" ),
+    ( "folded-deltas", "Folded {0} appended records into the meta file" ),
+    ( "pruned-expired", "Removed {0} expired entries" ),
+    ( "deduplicated", "Removed {0} duplicate entries" ),
+    ( "values-changed", "Changed {0} values" ),
+    ( "quota-target", "{0} has {1} annotations, more than the limit of {2} (max_per_file)" ),
+    ( "quota-key", "{0} has {1} entries of `{2}`, more than the limit of {3} (max_per_key)" ),
+    ( "invalid-keep", "--keep must be at least 1" ),
+    ( "undo-failed", "Failed to undo: {0}" ),
+    ( "protected-key", "`{0}` is protected, use --force to change or remove it" ),
+    ( "integrity-mismatch", "{0} was changed outside of annovate or truncated: it does not match its integrity footer or has none (--force uses it anyway, the next change writes a new footer)" ),
+    ( "invalid-backup", "`{0}` is not the number of a backup (1 is the most recent)" ),
+    ( "no-backup", "There is no backup {0} of the meta file" ),
+    ( "restored-backup", "Restored {0}, the current content becomes the most recent backup if backups are kept" ),
+    ( "restore-failed", "Failed to restore the backup: {0}" ),
+    ( "unknown-strategy", "Unknown merge strategy `{0}` (use ours, theirs, union or newest-by-context)" ),
+    ( "unknown-sort-order", "Unknown sort order `{0}` (use name, value, time or key-count)" ),
+    ( "unknown-entry-order", "Unknown sort order `{0}` (use key, time or context)" ),
+    ( "unknown-error-format", "Unknown error format `{0}` (use text or json)" ),
+    ( "no-such-file", "the file does not exist" ),
+    ( "merge-failed", "Failed to merge {0}: {1}" ),
+    ( "merge-conflict", "{0}: `{1}` is `{2}` here and `{3}` there, keeping `{4}`" ),
+    ( "merged", "Added {0} annotations, {1} keys had different values" ),
+    ( "diff-failed", "Failed to compare with {0}: {1}" ),
+    ( "hook-failed", "the hook `{0}` could not be run: {1}" ),
+    ( "hook-rejected", "the hook `{0}` failed ({1})" ),
+    ( "save-rejected", "The changes were not saved: {0}" ),
+    ( "no-differences", "The current values of both files are the same" ),
+    ( "diff-summary", "{0} added, {1} removed, {2} changed" ),
+];
+
+const GERMAN: &'static [( &'static str, &'static str )] = &[
+    ( "explanation-heading", "Erklärung der Unterbefehle:" ),
+    ( "header-filename", "Dateiname" ),
+    ( "header-key", "Schlüssel" ),
+    ( "header-value", "Wert" ),
+    ( "header-context", "Kontext" ),
+    ( "header-created", "Erstellt" ),
+    ( "header-message", "Nachricht" ),
+    ( "header-files", "Dateien" ),
+    ( "header-count", "Anzahl" ),
+    ( "stats-annotated-files", "Annotierte Dateien" ),
+    ( "stats-annotations", "Annotationen von Dateien" ),
+    ( "stats-directory-annotations", "Annotationen des Verzeichnisses" ),
+    ( "stats-average", "Annotationen pro Datei" ),
+    ( "stats-unannotated", "Dateien ohne Annotationen" ),
+    ( "stats-common-keys", "Häufigste Schlüssel" ),
+    ( "unknown-time", "<unbekannte-zeit>" ),
+    ( "missing-value", "<fehlender-wert>" ),
+    ( "missing-context", "<fehlender-kontext>" ),
+    ( "create-dir-failed", "Das neue Verzeichnis konnte nicht angelegt werden" ),
+    ( "examine-failed", "{0} konnte nicht untersucht werden: {1}" ),
+    ( "no-problems", "Keine Probleme gefunden" ),
+    ( "migrated", "{0} in {1} umbenannt" ),
+    ( "migrated-merged", "{0} mit {1} zusammengeführt" ),
+    ( "migrated-journal-kept", "Die Änderungen von {0} lassen sich nicht mehr rückgängig machen, sein Journal wurde als {1} behalten" ),
+    ( "would-migrate", "Die Annotationen von {0} würden nach {1} verschoben" ),
+    ( "nothing-to-migrate", "Keine Metadateien zu migrieren" ),
+    ( "migrate-failed", "Migration fehlgeschlagen: {0}" ),
+    ( "legacy-meta-file", "{0} existiert nicht, aber {1}; `anno migrate` ausführen, um dessen Annotationen zu verwenden" ),
+    ( "repaired", "{0} Probleme repariert, die ursprüngliche Datei wurde als {1} gesichert" ),
+    ( "run-doctor", "{0} (`anno doctor` liefert Details, --lenient überspringt Fehler)" ),
+    ( "parse-warning", "In {0} übersprungen: {1}" ),
+    ( "append-unsupported", "--append kann nicht mit --template, --hook, --dry-run, --replace oder --if-absent kombiniert werden" ),
+    ( "conflicting-put-policy", "--replace und --if-absent können nicht kombiniert werden" ),
+    ( "append-failed", "Anhängen fehlgeschlagen: {0}" ),
+    ( "resolve-failed", "Der Wert von `{0}` kann nicht aufgelöst werden: {1}" ),
+    ( "no-annotations", "Zu dieser Datei gibt es keine Annotationen" ),
+    ( "no-metadata", "Zu dieser Datei gibt es keine Metadaten" ),
+    ( "invalid-url", "Ungültige URL: {0}" ),
+    ( "invalid-target", "Ungültiger Teil `{1}` von `{0}`, erwartet z.B. #column:age, #L10-20 (Zeilen beginnen bei 1) oder #B0-511; ## für ein # des Dateinamens" ),
+    ( "invalid-range", "Ungültiger Bereich `{0}`, erwartet z.B. 10-20 (Zeilen beginnen bei 1)" ),
+    ( "invalid-byte-range", "Ungültiger Bereich `{0}`, erwartet z.B. 0-511 (Bytes werden ab 0 gezählt)" ),
+    ( "lines-and-bytes", "--lines und --bytes können nicht kombiniert werden" ),
+    ( "read-dir-failed", "Das Verzeichnis konnte nicht gelesen werden: {0}" ),
+    ( "report-annotated", "Annotiert (=)" ),
+    ( "report-changed", "Geändert (~)" ),
+    ( "report-orphaned", "Metadaten ohne Datei (+)" ),
+    ( "report-unannotated", "Ohne Metadaten (-)" ),
+    ( "unannotated-files", "Einige Dateien haben keine Metadaten" ),
+    ( "no-matching-key", "Keine Einträge für den Schlüssel `{0}` gefunden" ),
+    ( "no-matching-key-suggestion", "Keine Einträge für den Schlüssel `{0}` gefunden, war `{1}` gemeint?" ),
+    ( "empty-key", "Der neue Schlüssel darf nicht leer sein" ),
+    ( "move-to-same-file", "Die Annotationen können nicht in die Metadatei verschoben werden, in der sie stehen" ),
+    ( "move-failed", "{0} konnte nicht gespeichert werden, nichts wurde verschoben: {1}" ),
+    ( "no-matching-entry", "Kein passender Eintrag für den Schlüssel `{0}` gefunden" ),
+    ( "message-unused", "Nichts wurde geändert, die Nachricht wurde nicht aufgezeichnet" ),
+    ( "unknown-id", "Kein Eintrag hat die ID `{0}`" ),
+    ( "file-not-annotated", "Die Datei hat keine Annotationen: {0}" ),
+    ( "directory-not-annotated", "Das Verzeichnis hat keine Annotationen" ),
+    ( "harvested", "{0} Annotationen eingesammelt" ),
+    ( "progress-read", "Lesen" ),
+    ( "progress-save", "Speichern" ),
+    ( "progress-entries", "Verarbeiten" ),
+    ( "checksums-stored", "{0} Prüfsummen gespeichert" ),
+    ( "relinked", "Die Annotationen von {0} Dateien wurden verschoben" ),
+    ( "found-by-content", "{0} hat keine Annotationen, angezeigt werden die von {1} mit demselben Inhalt (`anno relink` verschiebt sie)" ),
+    ( "harvest-failed", "Die Annotationen konnten nicht eingesammelt werden: {0}" ),
+    ( "xattr-pushed", "Die erweiterten Attribute von {0} Dateien wurden geschrieben" ),
+    ( "xattr-pulled", "{0} Annotationen aus erweiterten Attributen gelesen" ),
+    ( "xattr-failed", "Die erweiterten Attribute konnten nicht abgeglichen werden: {0}" ),
+    ( "xattr-unsupported", "Dieses anno wurde ohne Unterstützung für erweiterte Attribute gebaut (Cargo-Feature `xattr`)" ),
+    ( "git-imported", "Git-Historie für {0} Dateien importiert" ),
+    ( "git-import-failed", "Die Git-Historie konnte nicht importiert werden: {0}" ),
+    ( "notes-written", "{0} Git-Notizen geschrieben" ),
+    ( "notes-export-failed", "Die Git-Notizen konnten nicht exportiert werden: {0}" ),
+    ( "notes-read", "{0} Git-Notizen gelesen" ),
+    ( "hook-installed", "Git-Hook {0} installiert" ),
+    ( "hook-install-failed", "Der Git-Hook konnte nicht installiert werden: {0}" ),
+    ( "snapshot-failed", "Der Commit konnte nicht festgehalten werden: {0}" ),
+    ( "notes-import-failed", "Die Git-Notizen konnten nicht importiert werden: {0}" ),
+    ( "dictionary-failed", "Das Datenwörterbuch konnte nicht erstellt werden: {0}" ),
+    ( "unknown-format", "Unbekanntes Format `{0}`" ),
+    ( "unknown-output", "Unbekanntes Ausgabeformat `{0}` (möglich sind table, plain, csv oder tsv)" ),
+    ( "config-failed", "Die Konfiguration konnte nicht gelesen werden: {0}" ),
+    ( "dry-run", "Probelauf: {0} Änderungen wurden nicht gespeichert" ),
+    ( "dry-run-unsupported", "--dry-run wird von diesem Befehl nicht unterstützt" ),
+    ( "unknown-type", "Unbekannter Typ `{0}` (möglich sind text, int, bool, date, list oder binary)" ),
+    ( "unknown-file-kind", "Unbekannte Art von Datei `{0}` (möglich sind file, dir, symlink, broken-link oder other)" ),
+    ( "invalid-expiry", "Ungültige Ablaufzeit `{0}` (möglich ist eine Dauer wie 30m, 12h, 7d oder 2w oder ein Zeitpunkt wie 2026-12-31T00:00:00Z)" ),
+    ( "invalid-value", "Ungültiger Wert: {0}" ),
+    ( "nothing-copied", "Keiner der Schlüssel wurde gefunden, es wurde nichts kopiert" ),
+    ( "invalid-map", "Ungültige Zuordnung von Schlüsseln `{0}`, erwartet z.B. owner=original_owner,*=upstream.*" ),
+    ( "no-match", "Keine Datei passt zu `{0}`" ),
+    ( "watching", "Warte auf neue Dateien in {0} (Beenden mit Strg-C)" ),
+    ( "new-file", "Neue Datei: {0}" ),
+    ( "write-failed", "Die annovate-Datei konnte nicht geschrieben werden" ),
+    ( "unknown-template", "Es gibt keine Vorlage namens `{0}`" ),
+    ( "no-schema", "Es ist kein Schema definiert (siehe `anno schema define`)" ),
+    ( "invalid-schema", "Das Schema kann nicht verwendet werden: {0}" ),
+    ( "schema-valid", "Alle Dateien entsprechen dem Schema" ),
+    ( "rename-failed", "{0} konnte nicht umbenannt werden: {1}" ),
+    ( "import-failed", "Die Annotationen konnten nicht importiert werden: {0}" ),
+    ( "read-value-failed", "Der Wert konnte nicht aus {0} gelesen werden: {1}" ),
+    ( "no-passphrase", "Der Wert von `{0}` soll verschlüsselt werden, aber ANNOVATE_PASSPHRASE ist nicht gesetzt" ),
+    ( "encrypt-failed", "Der Wert von `{0}` konnte nicht verschlüsselt werden: {1}" ),
+    ( "decrypt-failed", "Der Wert von `{0}` konnte nicht entschlüsselt werden: {1}" ),
+    ( "encrypted-values", "Einige Werte sind verschlüsselt, {0} muss zum Entschlüsseln gesetzt sein" ),
+    ( "stdin-value-twice", "Nur ein Wert kann von der Standardeingabe gelesen werden (`-`)" ),
+    ( "value-file-with-value", "--value-file gibt den Wert eines einzelnen Schlüssels an, der keinen weiteren Wert braucht" ),
+    ( "directory-label", "<Verzeichnis>" ),
+    ( "file-locked", "{0} wird von einem anderen anno-Prozess verwendet ({1} löschen, falls dieser nicht mehr läuft)" ),
+    ( "edit-failed", "Die Annotationen konnten nicht mit dem Editor ausgetauscht werden: {0}" ),
+    ( "editor-failed", "Der Editor `{0}` wurde nicht erfolgreich beendet, die Annotationen sind unverändert" ),
+    ( "edit-again", "Die Annotationen können nicht gelesen werden. Erneut bearbeiten? [J/n]" ),
+    ( "edit-discarded", "Die Änderungen wurden verworfen" ),
+    ( "invalid-tag", "Ungültiges Schlagwort `{0}`: Schlagwörter sind einzelne Wörter wie +urgent oder -draft" ),
+    ( "invalid-count", "`{0}` ist keine Anzahl von Änderungen" ),
+    ( "nothing-to-undo", "Es gibt keine Änderungen, die rückgängig gemacht werden können" ),
+    ( "undone", "{0} Änderungen rückgängig gemacht" ),
+    ( "compacted", "{0} ältere Einträge entfernt" ),
+    ( "folded-deltas", "{0} angehängte Einträge in die Metadatei übernommen" ),
+    ( "pruned-expired", "{0} abgelaufene Einträge entfernt" ),
+    ( "deduplicated", "{0} doppelte Einträge entfernt" ),
+    ( "values-changed", "{0} Werte geändert" ),
+    ( "quota-target", "{0} hat {1} Annotationen, mehr als die Grenze von {2} (max_per_file)" ),
+    ( "quota-key", "{0} hat {1} Einträge von `{2}`, mehr als die Grenze von {3} (max_per_key)" ),
+    ( "invalid-keep", "--keep muss mindestens 1 sein" ),
+    ( "undo-failed", "Rückgängig machen fehlgeschlagen: {0}" ),
+    ( "protected-key", "`{0}` ist geschützt, mit --force kann es trotzdem geändert oder entfernt werden" ),
+    ( "integrity-mismatch", "{0} wurde außerhalb von annovate geändert oder abgeschnitten: die Datei passt nicht zu ihrer Prüfsumme am Ende oder hat keine (mit --force wird sie trotzdem verwendet, die nächste Änderung schreibt eine neue Prüfsumme)" ),
+    ( "invalid-backup", "`{0}` ist keine Nummer einer Sicherung (1 ist die neueste)" ),
+    ( "no-backup", "Es gibt keine Sicherung {0} der Metadatei" ),
+    ( "restored-backup", "{0} wiederhergestellt, der bisherige Inhalt wird zur neuesten Sicherung, falls Sicherungen aufbewahrt werden" ),
+    ( "restore-failed", "Die Sicherung konnte nicht wiederhergestellt werden: {0}" ),
+    ( "unknown-strategy", "Unbekannte Strategie `{0}` (möglich sind ours, theirs, union oder newest-by-context)" ),
+    ( "unknown-sort-order", "Unbekannte Sortierung `{0}` (möglich sind name, value, time oder key-count)" ),
+    ( "unknown-entry-order", "Unbekannte Sortierung `{0}` (möglich sind key, time oder context)" ),
+    ( "unknown-error-format", "Unbekanntes Fehlerformat `{0}` (möglich sind text oder json)" ),
+    ( "no-such-file", "die Datei existiert nicht" ),
+    ( "merge-failed", "{0} konnte nicht zusammengeführt werden: {1}" ),
+    ( "merge-conflict", "{0}: `{1}` ist hier `{2}` und dort `{3}`, `{4}` wird behalten" ),
+    ( "merged", "{0} Annotationen hinzugefügt, {1} Schlüssel hatten unterschiedliche Werte" ),
+    ( "diff-failed", "Vergleich mit {0} fehlgeschlagen: {1}" ),
+    ( "hook-failed", "der Hook `{0}` konnte nicht ausgeführt werden: {1}" ),
+    ( "hook-rejected", "der Hook `{0}` ist fehlgeschlagen ({1})" ),
+    ( "save-rejected", "Die Änderungen wurden nicht gespeichert: {0}" ),
+    ( "no-differences", "Die aktuellen Werte beider Dateien sind gleich" ),
+    ( "diff-summary", "{0} hinzugefügt, {1} entfernt, {2} geändert" ),
+    ( "help-help", "Diese Hilfe anzeigen" ),
+    ( "help-new", "Ein neues Verzeichnis mit einer annovate-Datei anlegen" ),
+    ( "help-query", "(Bestimmte oder alle) Metadaten einer Datei auflisten" ),
+    ( "help-query-dir", "(Bestimmte oder alle) Metadaten des Verzeichnisses auflisten" ),
+    ( "help-list", "Den Wert eines Schlüssels für alle Dateien anzeigen (Standard: description oder list_key der Konfiguration)" ),
+    ( "help-tag", "Schlagwörter einer Datei hinzufügen (+tag) oder entfernen (-tag) oder anzeigen. Vor dem ersten -tag muss `--` stehen" ),
+    ( "help-get", "Nur den jüngsten Wert eines Schlüssels einer Datei ausgeben; mit -a alle Werte, die ältesten zuerst. Bis Version 0.1.1 gab get den ältesten Wert aus" ),
+    ( "help-history", "Jeden Wert, den ein Schlüssel einer Datei hatte, mit Kontext und Zeit anzeigen, den ältesten zuerst" ),
+    ( "help-history-dir", "Jeden Wert, den ein Schlüssel des Verzeichnisses hatte, mit Kontext und Zeit anzeigen, den ältesten zuerst" ),
+    ( "help-log", "Das Änderungsprotokoll, das --message aufzeichnet, mit Kontext und Zeit anzeigen, das neueste zuerst" ),
+    ( "help-keys", "Die verwendeten Schlüssel mit der Anzahl ihrer Einträge auflisten, z.B. für die Vervollständigung; versteckte Schlüssel wie .sha256 mit -a" ),
+    ( "help-stats", "Annotierte Dateien, Annotationen und die häufigsten Schlüssel zählen und Dateien ohne Annotationen auflisten; `--output json` für JSON" ),
+    ( "help-get-many", "`Dateiname<TAB>Wert` für alle (oder die angegebenen) Dateien mit dem Schlüssel ausgeben; `--output json` oder `csv` für andere Formate" ),
+    ( "help-get-dir", "Nur den Wert eines Schlüssels des Verzeichnisses ausgeben" ),
+    ( "help-drop-file", "Die Metadaten bestimmter Dateien vollständig entfernen" ),
+    ( "help-drop-dir", "Die Metadaten und Tags des Verzeichnisses vollständig entfernen; geschützte Metadaten nur mit --force" ),
+    ( "help-add", "Schlüssel-Wert-Paare für eine einzelne Datei hinzufügen. Mit --template werden die Felder der Vorlage hinzugefügt und ihre Lücken abgefragt (oder den angegebenen Paaren entnommen). Ein Wert `-` wird von der Standardeingabe gelesen" ),
+    ( "help-add-batch", "Ein gemeinsames Schlüssel-Wert-Paar für mehrere Dateien hinzufügen" ),
+    ( "help-add-dir", "Schlüssel-Wert-Paare für das Verzeichnis der Metadatei hinzufügen" ),
+    ( "help-copy", "Schlüssel-Wert-Paare einer Datei auf eine oder mehrere andere kopieren. Der Kontext ist `copied from filename`" ),
+    ( "help-rm-file", "Alle Annotationen einer Datei mit bestimmten Schlüsseln entfernen" ),
+    ( "help-rm-entry", "Nur die Annotationen einer Datei mit diesem Schlüssel und Wert entfernen, oder die n-te mit dem Schlüssel" ),
+    ( "help-rm-id", "Den Eintrag mit dieser ID entfernen (angezeigt von query --ids)" ),
+    ( "help-amend", "Den Wert des Eintrags mit dieser ID ersetzen; Schlüssel, Kontext und Zeit bleiben erhalten" ),
+    ( "help-rm-dir", "Alle Annotationen des Verzeichnisses mit bestimmten Schlüsseln entfernen" ),
+    ( "help-rm-dir-entry", "Nur die Annotationen des Verzeichnisses mit diesem Schlüssel und Wert entfernen, oder die n-te mit dem Schlüssel" ),
+    ( "help-import-git", "Alle von git verwalteten Dateien mit ihrem ersten und letzten Commit-Datum und dem letzten Autor annotieren" ),
+    ( "help-export-git-notes", "Annotationen als Git-Notizen (refs/notes/annovate) an die Blobs der Dateien und die Verzeichnis-Annotationen an HEAD hängen; Teile von Dateien, URLs, Unterverzeichnisse und fehlende Dateien werden übersprungen" ),
+    ( "help-harvest", "In Kommentaren von Dateien (oder aller Dateien des Verzeichnisses) eingebettete Annotationen wie `// anno: key = value` übernehmen" ),
+    ( "help-sync-xattr", "Die Annotationen in den erweiterten Attributen `user.annovate.<key>` der Dateien spiegeln (benötigt das Feature `xattr`)" ),
+    ( "help-import-git-notes", "Annotationen aus Git-Notizen zurücklesen; die des Verzeichnisses vom jüngsten Commit mit einer Notiz" ),
+    ( "help-git-hook install", "Einen post-commit-Hook im Git-Repository installieren, der jeden Commit als Verzeichnis-Annotation `snapshot` festhält" ),
+    ( "help-git-hook run", "Den Commit von HEAD als Verzeichnis-Annotation `snapshot` festhalten (was der Hook tut)" ),
+    ( "help-doctor", "Die Metadatei auf strukturelle Probleme prüfen und diese erklären" ),
+    ( "help-migrate", "Metadateien des Verzeichnisses mit altem Namen (.annotave) oder dem Standardnamen in den eingestellten umbenennen und sie zusammenführen, falls dieser existiert; ein Journal, das nicht übernommen werden kann, wird als <name>.log.migrated behalten" ),
+    ( "help-dictionary", "Ein Datenwörterbuch für eine CSV/TSV-Datei aus ihren Annotationen und denen ihrer Spalten (<filename>#column:<name>) erzeugen" ),
+    ( "help-template define", "Eine Vorlage definieren; jedes Feld ist `key=value` oder nur `key` für eine Lücke, nach der beim Anwenden gefragt wird" ),
+    ( "help-template apply", "Die Felder einer Vorlage zu Dateien hinzufügen (`{file}` in Werten wird durch den Dateinamen ersetzt, `{now}` durch die Uhrzeit)" ),
+    ( "help-template show", "Die Definition einer oder aller Vorlagen anzeigen" ),
+    ( "help-schema define", "Das Schema definieren; jedes Feld ist `key` für einen Pflichtschlüssel oder `key=regex` für einen Pflichtschlüssel, dessen Wert passen muss" ),
+    ( "help-schema show", "Die Definition des Schemas anzeigen" ),
+    ( "help-validate", "Prüfen, ob jede Datei die vom Schema verlangten Schlüssel hat und deren Werte zu seinen Mustern passen" ),
+    ( "help-export", "Alle Annotationen in einem maschinenlesbaren Format ausgeben (Standard: json)" ),
+    ( "help-import", "Alle Annotationen durch den Inhalt einer von export geschriebenen Datei (oder stdin) ersetzen" ),
+    ( "help-search", "Annotationen finden, deren Schlüssel, Wert oder Kontext ein Muster enthält" ),
+    ( "help-watch", "Auf neue Dateien im Verzeichnis warten und nach ihrer Beschreibung fragen (oder eine Vorlage anwenden)" ),
+    ( "help-merge", "Die Annotationen einer anderen Metadatei übernehmen, z.B. einer abweichenden Kopie von einem anderen Rechner" ),
+    ( "help-diff", "Anzeigen, welche Schlüssel eine andere Metadatei hinzugefügt (+), entfernt (-) oder geändert hat, z.B. vor dem Zusammenführen" ),
+    ( "help-undo", "Die letzte (oder die letzten <count>) Änderungen der Metadatei rückgängig machen; das Journal behält die letzten 50 Speicherungen oder `journal = <count>` der Konfiguration (0 für keines)" ),
+    ( "help-restore-backup", "Zur neuesten (oder der angegebenen) Sicherung der Metadatei zurückkehren; mit `backups = <count>` in der Konfiguration werden Sicherungen als <meta-file>.bak.1, .bak.2, ... aufbewahrt" ),
+    ( "help-compact", "Bis auf den neuesten (oder die --keep neuesten) alle Einträge jedes Schlüssels des Verzeichnisses und der Dateien entfernen und mit --append angehängte Einträge in die Datei übernehmen" ),
+    ( "help-prune-expired", "Die Einträge entfernen, deren mit --expires angegebene Zeit abgelaufen ist" ),
+    ( "help-dedup", "Die Einträge des Verzeichnisses und der Dateien entfernen, die denselben Schlüssel und Wert wie ein späterer Eintrag haben" ),
+    ( "help-edit", "Die Annotationen einer Datei in $VISUAL oder $EDITOR (Standard: vi) bearbeiten" ),
+    ( "help-rename", "Alle Annotationen einer Datei auf einen neuen Dateinamen übertragen" ),
+    ( "help-move-file", "Die Annotationen und Tags einer Datei (samt Spalten, Zeilenbereichen usw.) in eine andere Metadatei verschieben, z.B. nachdem die Datei in ein anderes Verzeichnis verschoben wurde" ),
+    ( "help-apply", "Die Werte des Verzeichnisses und aller Dateien (oder nur die der angegebenen Schlüssel) mit der Ersetzung von --exec ändern, Kontexte und Zeiten bleiben erhalten; verschlüsselte Werte und die Schlüssel binärer Werte bleiben unverändert" ),
+    ( "help-rename-key", "Einen Schlüssel des Verzeichnisses und aller Dateien (oder mit --file einer Datei) ändern, Werte und Verlauf bleiben erhalten" ),
+    ( "help-report", "Übersicht, welche Dateien Metadaten haben (=), welche keine haben (-) und welche nicht existieren (+). URLs werden nicht geprüft. Verzeichnisse enden auf /, symbolische Links auf @, defekte Links auf ! und andere besondere Dateien auf |" ),
+    ( "help-collect", "Die Annotationen der Metadateien mehrerer Verzeichnisse als eine Tabelle ausgeben, die Dateinamen mit ihrem Verzeichnis davor" ),
+    ( "help-snapshot", "Die SHA-256-Prüfsumme jeder annotierten Datei als versteckte Annotation `.sha256` speichern; `report --verify` markiert seitdem geänderte Dateien mit ~" ),
+    ( "help-relink", "Die Annotationen nicht mehr vorhandener Dateien auf die nicht annotierte Datei mit derselben Prüfsumme verschieben, z.B. nachdem Dateien ohne anno umbenannt wurden" ),
+];
+
+/// Look up a message for the given locale, falling back to English and finally to the id itself
+pub fn lookup( locale: Locale, id: &str ) -> &str {
+    for catalog in &[ locale.catalog(), ENGLISH ] {
+        if let Some( &( _, text ) ) = catalog.iter().find( |entry| entry.0 == id ) {
+            return text;
+        }
+    }
+    id
+}
+
+/// Replace the placeholders `{0}`, `{1}`, ... in a message
+pub fn fill( template: &str, args: &[&str] ) -> String {
+    let mut result = template.to_string();
+    for ( i, arg ) in args.iter().enumerate() {
+        result = result.replace( &format!( "{{{}}}", i ), arg );
+    }
+    result
+}
+
+/// Localized message for the current locale
+pub fn tr( id: &str, args: &[&str] ) -> String {
+    fill( lookup( Locale::current(), id ), args )
+}
+
+/// Localize the help text. The usage and option sections are kept as they are (docopt parses them);
+/// the explanation of each subcommand is replaced if the locale has a `help-<command>` message.
+pub fn localize_help( usage: &str, locale: Locale ) -> String {
+    const HEADING: &'static str = "Explanation of subcommands:";
+    let split = match usage.find( HEADING ) {
+        Some( pos ) => pos,
+        None => return usage.to_string()
+    };
+    let mut result = usage[ ..split ].to_string();
+    result.push_str( lookup( locale, "explanation-heading" ) );
+    for line in usage[ split + HEADING.len().. ].lines() {
+        let translated = line.find( ": " ).and_then( |pos| {
+            let id = format!( "help-{}", line[ ..pos ].trim() );
+            locale.catalog().iter().find( |entry| entry.0 == id ).map( |entry| format!( "{}: {}", &line[ ..pos ], entry.1 ) )
+        } );
+        result.push_str( &translated.unwrap_or( line.to_string() ) );
+        result.push( '\n' );
+    }
+    result
+}
@@ -0,0 +1,92 @@
+//Meta files that were created under another name: `anno new` used to write `.annotave`, and a directory keeps
+//its `.annovate` when another meta-file name is configured. Migrating renames them to the configured name.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use {Annovate, AnnoError, backend_for_path, write_atomically};
+use journal;
+use lock::FileLock;
+use merge::MergeStrategy;
+
+/// Name of the meta file of a directory unless configured otherwise
+pub const META_NAME: &'static str = ".annovate";
+
+/// Names under which meta files were created by mistake
+pub const LEGACY_NAMES: &'static [&'static str] = &[ ".annotave" ];
+
+/// Environment variable with the name of the meta file
+pub const META_NAME_VAR: &'static str = "ANNOVATE_META_NAME";
+
+/// The configured name of meta files: `ANNOVATE_META_NAME` or `.annovate`
+pub fn meta_name() -> String {
+    match env::var( META_NAME_VAR ) {
+        Ok( ref name ) if !name.trim().is_empty() => name.trim().to_string(),
+        _ => META_NAME.to_string()
+    }
+}
+
+/// Meta files in `dir` with a legacy name or the default name, except `meta_name` itself
+pub fn legacy_files( dir: &Path, meta_name: &str ) -> Vec<PathBuf> {
+    LEGACY_NAMES.iter().chain( Some( &META_NAME ) )
+        .filter( |name| **name != meta_name )
+        .map( |name| dir.join( name ) )
+        .filter( |path| path.is_file() )
+        .collect()
+}
+
+/// A meta file that was migrated
+#[derive(Clone, PartialEq, Debug)]
+pub struct Migration {
+    pub from: PathBuf,
+    pub to: PathBuf,
+    /// Whether `to` already existed and the annotations were merged into it
+    pub merged: bool,
+    /// Where the journal of `from` was kept if `to` could not take it over
+    pub kept_journal: Option<PathBuf>
+}
+
+/// Name under which the journal of a migrated meta file is kept: `<name>.log.migrated` next to it
+pub fn kept_journal_path( path: &Path ) -> PathBuf {
+    let journal = journal::journal_path( path );
+    let mut name = journal.file_name().map( |n| n.to_os_string() ).unwrap_or_default();
+    name.push( ".migrated" );
+    journal.with_file_name( name )
+}
+
+/// Move the annotations of every legacy meta file in `dir` to `meta_name` and remove the legacy file.
+/// A new meta file is written in normalized form and takes over the journal, an existing one gets the
+/// annotations merged like `merge --strategy union`. A journal that cannot be taken over is kept, see
+/// `kept_journal_path`.
+pub fn migrate( dir: &Path, meta_name: &str ) -> Result<Vec<Migration>, AnnoError> {
+    let target = dir.join( meta_name );
+    let mut migrations = Vec::new();
+    for legacy in legacy_files( dir, meta_name ) {
+        let merged = target.exists();
+        {
+            let old = try!( Annovate::new( &legacy ) );
+            if merged {
+                let mut anno = try!( Annovate::new( &target ) );
+                try!( anno.merge( &old, MergeStrategy::Union ) );
+                try!( anno.save() );
+            } else {
+                let _lock = try!( FileLock::acquire( &target, old.lock_timeout ) );
+                try!( write_atomically( &target, backend_for_path( &target ).serialize( &old, None ).as_bytes() ) );
+            }
+        }
+        let ( old_journal, new_journal ) = ( journal::journal_path( &legacy ), journal::journal_path( &target ) );
+        let mut kept_journal = None;
+        if old_journal.exists() {
+            //the history of a file that was merged into another one cannot be undone there
+            let destination = if merged || new_journal.exists() { kept_journal_path( &legacy ) } else { new_journal };
+            try!( fs::rename( &old_journal, &destination ).map_err( |e| AnnoError::io( &old_journal, e ) ) );
+            if destination != journal::journal_path( &target ) {
+                kept_journal = Some( destination );
+            }
+        }
+        try!( fs::remove_file( &legacy ).map_err( |e| AnnoError::io( &legacy, e ) ) );
+        migrations.push( Migration { from: legacy, to: target.clone(), merged: merged, kept_journal: kept_journal } );
+    }
+    Ok( migrations )
+}
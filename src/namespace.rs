@@ -0,0 +1,165 @@
+//Keys like `quality.reviewed_by` and `quality.score` belong to the namespace `quality`. The key pattern
+//`quality.*` selects every key of the namespace, also those of nested namespaces like `quality.review.date`;
+//any other pattern selects the key with exactly that name, or with `KeyMatcher::IgnoreCase` or `KeyMatcher::Fuzzy`
+//a key that is spelled a little differently.
+
+use {Annovate, Annotation};
+
+/// Separator of the parts of a key
+pub const SEPARATOR: char = '.';
+
+/// Suffix that turns a namespace into a pattern for all of its keys
+pub const WILDCARD: &'static str = ".*";
+
+/// The namespace of `key`, everything before the last separator. Keys without a separator and hidden keys
+/// like `.sha256` have none.
+pub fn namespace( key: &str ) -> Option<&str> {
+    key.rfind( SEPARATOR ).filter( |&pos| pos > 0 ).map( |pos| &key[ ..pos ] )
+}
+
+/// The part of `key` after its namespace
+pub fn local_name( key: &str ) -> &str {
+    match namespace( key ) {
+        Some( namespace ) => &key[ namespace.len() + 1.. ],
+        None => key
+    }
+}
+
+/// Whether `key` belongs to `namespace` or to a namespace nested in it
+pub fn in_namespace( key: &str, namespace: &str ) -> bool {
+    key.len() > namespace.len() + 1 && key.starts_with( namespace ) && key[ namespace.len().. ].starts_with( SEPARATOR )
+}
+
+/// Whether `pattern` selects a namespace like `quality.*`
+pub fn is_namespace_pattern( pattern: &str ) -> bool {
+    pattern.len() > WILDCARD.len() && pattern.ends_with( WILDCARD )
+}
+
+/// Whether `key` is selected by `pattern`: the key itself or, for `<namespace>.*`, any key of the namespace
+pub fn key_matches( pattern: &str, key: &str ) -> bool {
+    if is_namespace_pattern( pattern ) {
+        in_namespace( key, &pattern[ ..pattern.len() - WILDCARD.len() ] )
+    } else {
+        key == pattern
+    }
+}
+
+/// Minimum length of a normalized key for which `KeyMatcher::Fuzzy` accepts a typo
+const FUZZY_MIN_LENGTH: usize = 4;
+
+/// How a key given by the user is compared with the keys of annotations
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum KeyMatcher {
+    Exact,
+    /// `Description` is the same key as `description`
+    IgnoreCase,
+    /// Like `IgnoreCase`, and spaces, `-` and `_` are ignored, so that `last-modified` is `LastModified`.
+    /// Keys of at least four such characters may also differ in one inserted, removed or replaced character.
+    Fuzzy
+}
+
+impl Default for KeyMatcher {
+    fn default() -> KeyMatcher {
+        KeyMatcher::Exact
+    }
+}
+
+/// `key` in lower case without spaces, `-` and `_`
+fn normalize( key: &str ) -> Vec<char> {
+    key.chars().filter( |&c| c != ' ' && c != '-' && c != '_' ).flat_map( |c| c.to_lowercase() ).collect()
+}
+
+/// Whether `a` becomes `b` by inserting, removing or replacing at most one character
+fn within_one_edit( a: &[char], b: &[char] ) -> bool {
+    if a.len() > b.len() {
+        return within_one_edit( b, a );
+    }
+    if b.len() - a.len() > 1 {
+        return false;
+    }
+    let prefix = a.iter().zip( b ).take_while( |&( x, y )| x == y ).count();
+    if prefix == a.len() {
+        true
+    } else if a.len() == b.len() {
+        a[ prefix + 1.. ] == b[ prefix + 1.. ]
+    } else {
+        a[ prefix.. ] == b[ prefix + 1.. ]
+    }
+}
+
+/// Number of characters that have to be inserted, removed or replaced to turn `a` into `b`
+pub fn edit_distance( a: &str, b: &str ) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = ( 0..b.len() + 1 ).collect();
+    for ( i, x ) in a.chars().enumerate() {
+        let mut current = vec![ i + 1 ];
+        for ( j, &y ) in b.iter().enumerate() {
+            let replaced = previous[ j ] + if x == y { 0 } else { 1 };
+            current.push( replaced.min( previous[ j + 1 ] + 1 ).min( current[ j ] + 1 ) );
+        }
+        previous = current;
+    }
+    previous[ b.len() ]
+}
+
+/// The key of `keys` that the user most likely meant with the unknown key `name`, e.g. `description` for
+/// `desciption`: the closest one that differs in at most a third of the characters (see `KeyMatcher::Fuzzy`
+/// for what is ignored). Namespace patterns get no suggestion.
+pub fn closest_key<'a, I>( name: &str, keys: I ) -> Option<&'a str> where I: IntoIterator<Item = &'a str> {
+    if is_namespace_pattern( name ) {
+        return None;
+    }
+    let normalized: String = normalize( name ).into_iter().collect();
+    let limit = ::std::cmp::max( 1, normalized.chars().count() / 3 );
+    keys.into_iter()
+        .filter( |&key| key != name )
+        .map( |key| ( edit_distance( &normalized, &normalize( key ).into_iter().collect::<String>() ), key ) )
+        .filter( |&( distance, _ )| distance <= limit )
+        .min_by_key( |&( distance, _ )| distance )
+        .map( |( _, key )| key )
+}
+
+impl KeyMatcher {
+    /// Whether `key` is the key the user meant with `name`. Namespace patterns are not expanded here, see
+    /// `Annotation::key_matches_with`.
+    pub fn matches( &self, name: &str, key: &str ) -> bool {
+        match *self {
+            KeyMatcher::Exact => name == key,
+            KeyMatcher::IgnoreCase => name.to_lowercase() == key.to_lowercase(),
+            KeyMatcher::Fuzzy => {
+                let ( name, key ) = ( normalize( name ), normalize( key ) );
+                name == key || ( name.len() >= FUZZY_MIN_LENGTH && within_one_edit( &name, &key ) )
+            }
+        }
+    }
+}
+
+impl Annotation {
+    /// Whether the key of the annotation is selected by `pattern`, see `key_matches`
+    pub fn key_matches( &self, pattern: &str ) -> bool {
+        key_matches( pattern, &self.key )
+    }
+
+    /// Like `key_matches`, with keys compared by `matcher`. Other than exact matching ignores the case of a
+    /// namespace pattern.
+    pub fn key_matches_with( &self, pattern: &str, matcher: KeyMatcher ) -> bool {
+        match matcher {
+            KeyMatcher::Exact => self.key_matches( pattern ),
+            _ if is_namespace_pattern( pattern ) => key_matches( &pattern.to_lowercase(), &self.key.to_lowercase() ),
+            _ => matcher.matches( pattern, &self.key )
+        }
+    }
+}
+
+impl Annovate {
+    /// The distinct keys of the directory and all files that belong to `namespace`, sorted
+    pub fn keys_in_namespace( &self, namespace: &str ) -> Vec<String> {
+        let mut keys: Vec<String> = self.dir.iter().chain( self.files.values().flat_map( |annos| annos.iter() ) )
+            .filter( |a| in_namespace( &a.key, namespace ) )
+            .map( |a| a.key.clone() )
+            .collect();
+        keys.sort();
+        keys.dedup();
+        keys
+    }
+}
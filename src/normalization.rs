@@ -0,0 +1,79 @@
+//A filename with accents like `café.txt` can be written with a composed `é` (NFC) or with `e` and a combining accent
+//(NFD). macOS hands out names in NFD while most other systems and editors use NFC, so a name that was annotated on
+//one system may not be found on another. With a normalization form, the filenames of a meta file and the names that
+//are looked up are brought to the same form:
+//
+//    anno.normalize_filenames( Normalization::Nfc );
+//    anno.get_file_annotations( &Normalization::Nfc.apply( name_from_disk ) );
+//
+//`doctor` reports names of a meta file that differ only in their normalization.
+
+use std::collections::BTreeSet;
+
+use unicode_normalization::UnicodeNormalization;
+
+use {Annovate, AnnoError, Target};
+
+/// Unicode normalization form of filenames
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Normalization {
+    /// Names are stored and looked up as they are
+    Keep,
+    /// Composed characters, as on most systems
+    Nfc,
+    /// Decomposed characters, as macOS returns them
+    Nfd
+}
+
+/// Names from macOS are decomposed, so there they are composed to match those of other systems
+impl Default for Normalization {
+    fn default() -> Normalization {
+        if cfg!( target_os = "macos" ) { Normalization::Nfc } else { Normalization::Keep }
+    }
+}
+
+impl Normalization {
+    /// `none`, `nfc` or `nfd`
+    pub fn from_name( name: &str ) -> Option<Normalization> {
+        match name {
+            "none" => Some( Normalization::Keep ),
+            "nfc" => Some( Normalization::Nfc ),
+            "nfd" => Some( Normalization::Nfd ),
+            _ => None
+        }
+    }
+
+    /// `text` in this form
+    pub fn apply( &self, text: &str ) -> String {
+        match *self {
+            Normalization::Keep => text.to_string(),
+            Normalization::Nfc => text.nfc().collect(),
+            Normalization::Nfd => text.nfd().collect()
+        }
+    }
+
+    /// The name of a target in this form. URLs are kept.
+    pub fn apply_to_target( &self, name: &str ) -> String {
+        if Target::parse( name ).is_remote() { name.to_string() } else { self.apply( name ) }
+    }
+}
+
+/// Whether two different names are the same after normalization
+pub fn differ_only_in_normalization( a: &str, b: &str ) -> bool {
+    a != b && Normalization::Nfc.apply( a ) == Normalization::Nfc.apply( b )
+}
+
+impl Annovate {
+    /// Bring all filenames to `form`. Files whose names then coincide are merged like by `rename_file`.
+    /// Returns the number of renamed files.
+    pub fn normalize_filenames( &mut self, form: Normalization ) -> Result<usize, AnnoError> {
+        try!( self.check_writable() );
+        let names: BTreeSet<String> = self.files.keys().map( |k| Target::parse( k ) )
+                                                       .filter( |t| !t.is_remote() && form.apply( &t.file ) != t.file )
+                                                       .map( |t| t.file ).collect();
+        for name in &names {
+            try!( self.rename_file( name, &form.apply( name ) ) );
+        }
+        Ok( names.len() )
+    }
+}
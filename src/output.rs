@@ -0,0 +1,435 @@
+//Output formats of the commands that print annotations, report lines or differences. `--output` selects one
+//for query, list, search, history, collect, report, stats and diff:
+//
+//    table  aligned columns with a header row (default)
+//    plain  unaligned `key: value` lines
+//    csv    comma separated values
+//    tsv    tab separated values
+//    json   one array of objects, printed when the command is finished
+//
+//`--print0` terminates every field with NUL instead.
+
+use std::cell::RefCell;
+use std::cmp::max;
+use std::collections::BTreeMap;
+
+use rustc_serialize::json::Json;
+
+use annovate::{Annotation, AnnoContainer};
+use annovate::diff::{DiffEntry, DiffReport};
+use annovate::report::FileKind;
+use annovate::subdir;
+
+use display::{Colors, Style};
+use messages::tr;
+use report_error;
+
+struct ColumnWidths {
+    key: usize,
+    value: usize,
+    context: usize
+}
+
+fn determine_column_widths( container: &AnnoContainer,
+                            padding: usize ) -> ColumnWidths {
+
+    let mut result = ColumnWidths{ key: 0, value: 0, context: 0 };
+    fn num_chars( string: &str ) -> usize {
+        string.chars().count()
+    }
+
+    for annotation in container {
+        result.key = max( num_chars( annotation.key.as_str() ),
+                          result.key );
+        result.context = max( num_chars( annotation.context.as_str() ),
+                              result.context );
+
+        for line in annotation.value.lines() {
+            result.value = max( num_chars( line ), result.value );
+        }
+    }
+    result.key += padding;
+    result.value += padding;
+    result.context += padding;
+    result
+}
+
+/// How tables of annotations, the lines of `report` and differences are printed
+pub trait OutputFormat {
+    /// Print key, value and (optionally) context of every annotation
+    fn print_annotations( &self, container: &AnnoContainer, with_context: bool );
+
+    /// Print rows of three fields without header row. `columns` names the fields for formats that repeat the
+    /// names in every record.
+    fn print_columns( &self, _columns: [&str; 3], container: &AnnoContainer, with_context: bool ) {
+        self.print_annotations( container, with_context );
+    }
+
+    /// Print a header row followed by the annotations, `columns` as for `print_columns`
+    fn print_table( &self, header: &Annotation, _columns: [&str; 3], container: &AnnoContainer, with_context: bool ) {
+        let mut rows = vec![ header.clone() ];
+        rows.extend( container.iter().cloned() );
+        self.print_annotations( &rows, with_context );
+    }
+
+    /// Print a line of `report`: a status symbol and a filename with the marker of its kind
+    fn print_report_line( &self, status: &str, filename: &str, kind: Option<FileKind> ) {
+        let status = match Style::of_marker( status ) {
+            Some( style ) => self.colors().paint( status, style ),
+            None => status.to_string()
+        };
+        //annotated subdirectories already end in `/`
+        let marker = match kind {
+            Some( FileKind::Dir ) if subdir::is_subdirectory_entry( filename ) => "",
+            kind => kind.map( |k| k.marker() ).unwrap_or( "" )
+        };
+        println!( "{} {}{}", status, filename, marker );
+    }
+
+    /// Print the heading of a part of the output, e.g. a column of the queried file
+    fn print_heading( &self, heading: &str ) {
+        println!( "" );
+        println!( "{}", self.colors().paint( heading, Style::Heading ) );
+    }
+
+    /// Print a diff like `diff -u`: the differing keys of every target, changed values as a removed and an added line
+    fn print_diff( &self, report: &DiffReport, ours: &str, theirs: &str ) {
+        let colors = self.colors();
+        let directory_label = tr( "directory-label", &[] );
+        println!( "{}", colors.paint( &format!( "--- {}", ours ), Style::Missing ) );
+        println!( "{}", colors.paint( &format!( "+++ {}", theirs ), Style::Annotated ) );
+        let line = |marker: &str, a: &Annotation| {
+            let style = if marker == "+" { Style::Annotated } else { Style::Missing };
+            println!( "  {} {} = {}", colors.paint( marker, style ), a.key, a.value.replace( '\n', "\n      " ) );
+        };
+        for target in &report.targets {
+            println!( "{}", colors.paint( &format!( "@ {}", target.target.as_ref().unwrap_or( &directory_label ) ), Style::Heading ) );
+            for entry in &target.entries {
+                match *entry {
+                    DiffEntry::Added( ref a ) => line( "+", a ),
+                    DiffEntry::Removed( ref a ) => line( "-", a ),
+                    DiffEntry::Changed( ref ours, ref theirs ) => {
+                        line( "-", ours );
+                        line( "+", theirs );
+                    }
+                }
+            }
+        }
+    }
+
+    /// Print a message for the reader like a summary; machine-readable formats leave it out
+    fn print_note( &self, text: &str ) {
+        println!( "{}", text );
+    }
+
+    /// Called once the command printed everything, for formats that write the output as a whole
+    fn finish( &self ) {
+    }
+
+    /// Colors of the terminal output; machine-readable formats are never colored
+    fn colors( &self ) -> Colors {
+        Colors::disabled()
+    }
+
+    /// Whether tables start with a header row
+    fn has_header( &self ) -> bool {
+        true
+    }
+}
+
+/// The rows of a diff for formats with one record per line: marker, target (empty for the directory), key, value
+fn diff_rows( report: &DiffReport ) -> Vec<[String; 4]> {
+    let mut rows = Vec::new();
+    for target in &report.targets {
+        let name = target.target.clone().unwrap_or_default();
+        let row = |marker: &str, a: &Annotation| [ marker.to_string(), name.clone(), a.key.clone(), a.value.clone() ];
+        for entry in &target.entries {
+            match *entry {
+                DiffEntry::Added( ref a ) => rows.push( row( "+", a ) ),
+                DiffEntry::Removed( ref a ) => rows.push( row( "-", a ) ),
+                DiffEntry::Changed( ref ours, ref theirs ) => {
+                    rows.push( row( "-", ours ) );
+                    rows.push( row( "+", theirs ) );
+                }
+            }
+        }
+    }
+    rows
+}
+
+/// Aligned columns
+pub struct TableOutput {
+    colors: Colors
+}
+
+impl OutputFormat for TableOutput {
+    fn print_annotations( &self, container: &AnnoContainer, with_context: bool ) {
+        let widths = determine_column_widths( container, 2 );
+        for annotation in container {
+            display_annotation( annotation, &widths, with_context, Style::Key, &self.colors );
+        }
+    }
+
+    fn print_table( &self, header: &Annotation, _columns: [&str; 3], container: &AnnoContainer, with_context: bool ) {
+        let mut rows = vec![ header.clone() ];
+        rows.extend( container.iter().cloned() );
+        let widths = determine_column_widths( &rows, 2 ); //the header is aligned with the rows
+        display_annotation( header, &widths, with_context, Style::Heading, &self.colors );
+        for annotation in container {
+            display_annotation( annotation, &widths, with_context, Style::Key, &self.colors );
+        }
+    }
+
+    fn colors( &self ) -> Colors {
+        self.colors
+    }
+}
+
+/// Unaligned `key: value` lines
+pub struct PlainOutput {
+    colors: Colors
+}
+
+impl OutputFormat for PlainOutput {
+    fn print_annotations( &self, container: &AnnoContainer, with_context: bool ) {
+        for annotation in container {
+            display_plain_annotation( annotation, with_context, &self.colors );
+        }
+    }
+
+    fn has_header( &self ) -> bool {
+        false
+    }
+
+    fn colors( &self ) -> Colors {
+        self.colors
+    }
+}
+
+/// Comma (CSV, RFC 4180 quoting) or tab (TSV, backslash escapes) separated values
+pub struct DelimitedOutput {
+    pub delimiter: char
+}
+
+impl DelimitedOutput {
+    fn field( &self, text: &str ) -> String {
+        if self.delimiter == '\t' {
+            text.replace( '\\', "\\\\" ).replace( '\t', "\\t" ).replace( '\n', "\\n" ).replace( '\r', "\\r" )
+        } else if text.contains( |c| c == self.delimiter || c == '"' || c == '\n' || c == '\r' ) {
+            format!( "\"{}\"", text.replace( '"', "\"\"" ) )
+        } else {
+            text.to_string()
+        }
+    }
+
+    pub fn print_row( &self, fields: &[&str] ) {
+        let fields: Vec<String> = fields.iter().map( |f| self.field( f ) ).collect();
+        println!( "{}", fields.join( &self.delimiter.to_string() ) );
+    }
+}
+
+impl OutputFormat for DelimitedOutput {
+    fn print_annotations( &self, container: &AnnoContainer, with_context: bool ) {
+        for annotation in container {
+            if with_context {
+                self.print_row( &[ &annotation.key, &annotation.value, &annotation.context ] );
+            } else {
+                self.print_row( &[ &annotation.key, &annotation.value ] );
+            }
+        }
+    }
+
+    fn print_report_line( &self, status: &str, filename: &str, kind: Option<FileKind> ) {
+        self.print_row( &[ status, filename, kind.map( |k| k.name() ).unwrap_or( "" ) ] );
+    }
+
+    fn print_heading( &self, heading: &str ) {
+        self.print_row( &[ heading ] );
+    }
+
+    fn print_diff( &self, report: &DiffReport, _ours: &str, _theirs: &str ) {
+        for row in diff_rows( report ) {
+            self.print_row( &[ &row[ 0 ], &row[ 1 ], &row[ 2 ], &row[ 3 ] ] );
+        }
+    }
+
+    fn print_note( &self, _text: &str ) {
+    }
+}
+
+/// Fields terminated by NUL, so that any filename or value can be read back (`xargs -0`).
+/// Every record of a command has the same fields; there are no header rows or headings.
+pub struct NulOutput;
+
+impl NulOutput {
+    pub fn print_record( &self, fields: &[&str] ) {
+        for field in fields {
+            print!( "{}\0", field );
+        }
+    }
+}
+
+impl OutputFormat for NulOutput {
+    fn print_annotations( &self, container: &AnnoContainer, with_context: bool ) {
+        for annotation in container {
+            if with_context {
+                self.print_record( &[ &annotation.key, &annotation.value, &annotation.context ] );
+            } else {
+                self.print_record( &[ &annotation.key, &annotation.value ] );
+            }
+        }
+    }
+
+    fn print_report_line( &self, status: &str, filename: &str, _kind: Option<FileKind> ) {
+        self.print_record( &[ status, filename ] );
+    }
+
+    fn print_heading( &self, _heading: &str ) {
+    }
+
+    fn print_diff( &self, report: &DiffReport, _ours: &str, _theirs: &str ) {
+        for row in diff_rows( report ) {
+            self.print_record( &[ &row[ 0 ], &row[ 1 ], &row[ 2 ], &row[ 3 ] ] );
+        }
+    }
+
+    fn print_note( &self, _text: &str ) {
+    }
+
+    fn has_header( &self ) -> bool {
+        false
+    }
+}
+
+/// One JSON array with an object per row, report line or difference. The records are collected and printed by
+/// `finish`; after a heading they get the heading as `section`.
+pub struct JsonOutput {
+    records: RefCell<Vec<Json>>,
+    section: RefCell<Option<String>>
+}
+
+impl JsonOutput {
+    fn push( &self, fields: Vec<( &str, Json )> ) {
+        let mut object: BTreeMap<String, Json> = fields.into_iter().map( |( name, value )| ( name.to_string(), value ) ).collect();
+        if let Some( ref section ) = *self.section.borrow() {
+            object.insert( "section".to_string(), Json::String( section.clone() ) );
+        }
+        self.records.borrow_mut().push( Json::Object( object ) );
+    }
+
+    fn push_rows( &self, columns: [&str; 3], container: &AnnoContainer, with_context: bool ) {
+        for annotation in container {
+            let mut fields = vec![ ( columns[ 0 ], Json::String( annotation.key.clone() ) ),
+                                   ( columns[ 1 ], Json::String( annotation.value.clone() ) ) ];
+            if with_context {
+                fields.push( ( columns[ 2 ], Json::String( annotation.context.clone() ) ) );
+            }
+            self.push( fields );
+        }
+    }
+}
+
+impl OutputFormat for JsonOutput {
+    fn print_annotations( &self, container: &AnnoContainer, with_context: bool ) {
+        self.push_rows( [ "key", "value", "context" ], container, with_context );
+    }
+
+    fn print_columns( &self, columns: [&str; 3], container: &AnnoContainer, with_context: bool ) {
+        self.push_rows( columns, container, with_context );
+    }
+
+    fn print_table( &self, _header: &Annotation, columns: [&str; 3], container: &AnnoContainer, with_context: bool ) {
+        self.push_rows( columns, container, with_context );
+    }
+
+    fn print_report_line( &self, status: &str, filename: &str, kind: Option<FileKind> ) {
+        self.push( vec![ ( "status", Json::String( status.to_string() ) ),
+                         ( "file", Json::String( filename.to_string() ) ),
+                         ( "kind", kind.map( |k| Json::String( k.name().to_string() ) ).unwrap_or( Json::Null ) ) ] );
+    }
+
+    fn print_heading( &self, heading: &str ) {
+        *self.section.borrow_mut() = Some( heading.to_string() );
+    }
+
+    fn print_diff( &self, report: &DiffReport, _ours: &str, _theirs: &str ) {
+        for target in &report.targets {
+            let name = target.target.clone().map( Json::String ).unwrap_or( Json::Null );
+            for entry in &target.entries {
+                let ( change, key, value, old_value ) = match *entry {
+                    DiffEntry::Added( ref a ) => ( "added", &a.key, &a.value, None ),
+                    DiffEntry::Removed( ref a ) => ( "removed", &a.key, &a.value, None ),
+                    DiffEntry::Changed( ref ours, ref theirs ) => ( "changed", &theirs.key, &theirs.value, Some( &ours.value ) )
+                };
+                let mut fields = vec![ ( "change", Json::String( change.to_string() ) ),
+                                       ( "target", name.clone() ),
+                                       ( "key", Json::String( key.clone() ) ),
+                                       ( "value", Json::String( value.clone() ) ) ];
+                if let Some( old_value ) = old_value {
+                    fields.push( ( "old_value", Json::String( old_value.clone() ) ) );
+                }
+                self.push( fields );
+            }
+        }
+    }
+
+    fn print_note( &self, _text: &str ) {
+    }
+
+    fn finish( &self ) {
+        println!( "{}", Json::Array( self.records.borrow().clone() ).pretty() );
+    }
+}
+
+/// The output format given by `--output`; `--plain` is a shorthand for `--output plain`, `--print0` wins over both
+pub fn output_format( name: &str, plain: bool, print0: bool, colors: Colors ) -> Box<dyn OutputFormat> {
+    match name {
+        _ if print0 => Box::new( NulOutput ),
+        "" if plain => Box::new( PlainOutput { colors: colors } ),
+        "" | "table" => Box::new( TableOutput { colors: colors } ),
+        "plain" => Box::new( PlainOutput { colors: colors } ),
+        "csv" => Box::new( DelimitedOutput { delimiter: ',' } ),
+        "tsv" => Box::new( DelimitedOutput { delimiter: '\t' } ),
+        "json" => Box::new( JsonOutput { records: RefCell::new( Vec::new() ), section: RefCell::new( None ) } ),
+        other => report_error( &tr( "unknown-output", &[ other ] ) )
+    }
+}
+
+/// Unaligned `key: value` output, continuation lines of the value are indented by two spaces
+fn display_plain_annotation( annotation: &Annotation, with_context: bool, colors: &Colors ) {
+    let mut value_lines = annotation.value.lines();
+    print!( "{}: {}", colors.paint( &annotation.key, Style::Key ), value_lines.next().unwrap_or( "" ) );
+    if with_context {
+        print!( " ({})", annotation.context );
+    }
+    println!( "" );
+    for line in value_lines {
+        println!( "  {}", line );
+    }
+}
+
+/// Header rows are printed completely in `Style::Heading`, other rows only have a styled key
+fn display_annotation( annotation: &Annotation,
+                       widths: &ColumnWidths,
+                       with_context: bool,
+                       key_style: Style,
+                       colors: &Colors ) {
+
+    let dummy_str = String::new();
+    let mut value_lines = annotation.value.lines();
+    let first_line = value_lines.next().unwrap_or( dummy_str.as_str() );
+    let other_style = if key_style == Style::Heading { Some( Style::Heading ) } else { None };
+    print!( "{}{}",
+            colors.pad( &annotation.key, widths.key, Some( key_style ) ),
+            colors.pad( first_line, widths.value, other_style ) );
+
+    if with_context {
+        println!( "{}", colors.pad( &annotation.context, 0, other_style ) );
+    } else {
+        println!( "" );
+    }
+
+    while let Some( line ) = value_lines.next() {
+        println!( "{0:1$}{2}", "", widths.key, line );
+    }
+}
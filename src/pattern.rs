@@ -0,0 +1,151 @@
+use std::collections::BTreeSet;
+use std::fs::read_dir;
+
+use {Annovate, AnnoError};
+
+#[derive(Clone, PartialEq, Debug)]
+enum Token {
+    Literal( char ),
+    /// `?`
+    AnyChar,
+    /// `*`
+    AnySequence,
+    /// `[a-z]` or `[!a-z]`
+    Class { negated: bool, ranges: Vec<( char, char )> }
+}
+
+/// Shell-like filename pattern with `*`, `?` and `[...]`. Wildcards do not match `/` and
+/// do not match a leading `.` of a name, like in the shell. `\` makes the next character literal.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Pattern {
+    text: String,
+    tokens: Vec<Token>,
+    /// Whether wildcards match a leading `.` as well
+    hidden: bool
+}
+
+/// Whether `text` contains wildcards and should be expanded
+pub fn is_pattern( text: &str ) -> bool {
+    text.contains( |c| c == '*' || c == '?' || c == '[' )
+}
+
+impl Pattern {
+    pub fn new( text: &str ) -> Result<Pattern, AnnoError> {
+        let mut tokens = Vec::new();
+        let mut chars = text.chars();
+        while let Some( c ) = chars.next() {
+            let token = match c {
+                '*' => Token::AnySequence,
+                '?' => Token::AnyChar,
+                '\\' => match chars.next() {
+                    Some( escaped ) => Token::Literal( escaped ),
+                    None => return Err( AnnoError::FormatError( format!( "pattern `{}` ends with `\\`", text ) ) )
+                },
+                '[' => {
+                    let mut negated = false;
+                    let mut ranges = Vec::new();
+                    let mut closed = false;
+                    let mut first = true;
+                    while let Some( c ) = chars.next() {
+                        if ( c == '!' || c == '^' ) && first && !negated {
+                            negated = true;
+                            continue;
+                        }
+                        if c == ']' && !( first && ranges.is_empty() ) {
+                            closed = true;
+                            break;
+                        }
+                        first = false;
+                        //`a-z` is a range, a `-` before `]` is literal
+                        let mut lookahead = chars.clone();
+                        match ( lookahead.next(), lookahead.next() ) {
+                            ( Some( '-' ), Some( end ) ) if end != ']' => {
+                                ranges.push( ( c, end ) );
+                                chars = lookahead;
+                            },
+                            _ => ranges.push( ( c, c ) )
+                        }
+                    }
+                    if !closed {
+                        return Err( AnnoError::FormatError( format!( "pattern `{}` has an unclosed `[`", text ) ) );
+                    }
+                    Token::Class { negated: negated, ranges: ranges }
+                },
+                c => Token::Literal( c )
+            };
+            tokens.push( token );
+        }
+        Ok( Pattern { text: text.to_string(), tokens: tokens, hidden: false } )
+    }
+
+    pub fn as_str( &self ) -> &str {
+        &self.text
+    }
+
+    /// Let wildcards match a leading `.` of a name, like in `.gitignore` files
+    pub fn matching_hidden( mut self ) -> Pattern {
+        self.hidden = true;
+        self
+    }
+
+    pub fn matches( &self, name: &str ) -> bool {
+        let chars: Vec<char> = name.chars().collect();
+        matches_from( &self.tokens, &chars, 0, self.hidden )
+    }
+
+    /// Directory part of the pattern if it has no wildcards, e.g. `data` for `data/*.csv`
+    fn literal_dir( &self ) -> Option<&str> {
+        match self.text.rfind( '/' ) {
+            Some( pos ) if !is_pattern( &self.text[ ..pos ] ) && !self.text[ ..pos ].contains( '\\' ) => Some( &self.text[ ..pos ] ),
+            _ => None
+        }
+    }
+}
+
+fn matches_from( tokens: &[Token], name: &[char], pos: usize, hidden: bool ) -> bool {
+    let at_name_start = pos == 0 || name[ pos - 1 ] == '/';
+    let wildcard_allowed = pos < name.len() && name[ pos ] != '/' && !( at_name_start && name[ pos ] == '.' && !hidden );
+    match tokens.first() {
+        None => pos == name.len(),
+        Some( &Token::Literal( c ) ) => pos < name.len() && name[ pos ] == c && matches_from( &tokens[ 1.. ], name, pos + 1, hidden ),
+        Some( &Token::AnyChar ) => wildcard_allowed && matches_from( &tokens[ 1.. ], name, pos + 1, hidden ),
+        Some( &Token::Class { negated, ref ranges } ) => {
+            wildcard_allowed && ranges.iter().any( |&( from, to )| from <= name[ pos ] && name[ pos ] <= to ) != negated
+                && matches_from( &tokens[ 1.. ], name, pos + 1, hidden )
+        },
+        Some( &Token::AnySequence ) => {
+            //match the empty sequence or consume one more character
+            matches_from( &tokens[ 1.. ], name, pos, hidden ) || ( wildcard_allowed && matches_from_sequence( tokens, name, pos + 1, hidden ) )
+        }
+    }
+}
+
+/// Like `matches_from` in the middle of a `*`, where a leading dot does not matter anymore
+fn matches_from_sequence( tokens: &[Token], name: &[char], pos: usize, hidden: bool ) -> bool {
+    matches_from( &tokens[ 1.. ], name, pos, hidden ) || ( pos < name.len() && name[ pos ] != '/' && matches_from_sequence( tokens, name, pos + 1, hidden ) )
+}
+
+impl Annovate {
+    /// Annotated targets and files on disk (next to the meta file) whose names match `pattern`, sorted by name.
+    /// Files on disk that the ignore file lists are left out.
+    pub fn files_matching( &self, pattern: &Pattern ) -> Vec<String> {
+        let mut result: BTreeSet<String> = self.iter_files().filter( |f| pattern.matches( f ) ).map( |f| f.to_string() ).collect();
+        //an unreadable ignore file ignores nothing here, reports fail on it
+        let ignore = self.ignore_rules().unwrap_or_default();
+        let base = self.base_dir();
+        let ( dir, prefix ) = match pattern.literal_dir() {
+            Some( dir ) => ( base.join( dir ), format!( "{}/", dir ) ),
+            None => ( base, String::new() )
+        };
+        //a directory that cannot be read has no files to match
+        if let Ok( entries ) = read_dir( &dir ) {
+            for entry in entries.filter_map( |e| e.ok() ) {
+                let name = format!( "{}{}", prefix, entry.file_name().to_string_lossy() );
+                if pattern.matches( &name ) && !ignore.is_ignored( &name, entry.path().is_dir() ) {
+                    result.insert( name );
+                }
+            }
+        }
+        result.into_iter().collect()
+    }
+}
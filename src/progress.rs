@@ -0,0 +1,96 @@
+//Progress of long operations for embedders that want to show it:
+//
+//    anno.set_progress_handler( Rc::new( |p: &Progress| eprint!( "\r{} of {:?} bytes", p.done, p.total ) ) );
+//
+//Reading and saving meta files is counted in bytes, going through files or meta files in entries. A handler that is
+//given to `Annovate::open_with_progress` also sees the file being read. The last report of an operation has
+//`done == total`, also when the total was not known before.
+
+use std::io::{self, Read, Write};
+use std::rc::Rc;
+
+/// Bytes that are read or written between two reports
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// What the progress counts
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ProgressStage {
+    /// Bytes of a meta file or an import that were read
+    Read,
+    /// Bytes of a meta file that were written
+    Save,
+    /// Files or meta files that were processed, e.g. by harvesting or loading a tree
+    Entries
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Progress {
+    pub stage: ProgressStage,
+    pub done: u64,
+    /// `None` while the total is not known yet
+    pub total: Option<u64>
+}
+
+impl Progress {
+    /// How much is done, between 0 and 1. `None` if the total is not known.
+    pub fn fraction( &self ) -> Option<f64> {
+        match self.total {
+            Some( 0 ) => Some( 1.0 ),
+            Some( total ) => Some( ( self.done as f64 / total as f64 ).min( 1.0 ) ),
+            None => None
+        }
+    }
+
+    pub fn is_finished( &self ) -> bool {
+        self.total == Some( self.done )
+    }
+}
+
+/// Called with the progress of long operations
+pub type ProgressHandler = Rc<dyn Fn( &Progress )>;
+
+/// Read everything from `reader`, reporting every chunk to `progress`
+pub fn read_with_progress<R: Read>( mut reader: R, total: Option<u64>, progress: Option<&dyn Fn( &Progress )> ) -> io::Result<Vec<u8>> {
+    let mut content = Vec::new();
+    let progress = match progress {
+        Some( progress ) => progress,
+        None => {
+            try!( reader.read_to_end( &mut content ) );
+            return Ok( content );
+        }
+    };
+    let mut chunk = vec![ 0; CHUNK_SIZE ];
+    loop {
+        match reader.read( &mut chunk ) {
+            Ok( 0 ) => break,
+            Ok( n ) => {
+                content.extend_from_slice( &chunk[ ..n ] );
+                //a file that grew while it is read must not report more than its total
+                let total = total.map( |t| t.max( content.len() as u64 + 1 ) );
+                progress( &Progress { stage: ProgressStage::Read, done: content.len() as u64, total: total } );
+            },
+            Err( ref e ) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err( e ) => return Err( e )
+        }
+    }
+    progress( &Progress { stage: ProgressStage::Read, done: content.len() as u64, total: Some( content.len() as u64 ) } );
+    Ok( content )
+}
+
+/// Write all of `content` to `writer`, reporting every chunk to `progress`
+pub fn write_with_progress<W: Write>( writer: &mut W, content: &[u8], progress: Option<&dyn Fn( &Progress )> ) -> io::Result<()> {
+    let progress = match progress {
+        Some( progress ) => progress,
+        None => return writer.write_all( content )
+    };
+    let mut done = 0;
+    for chunk in content.chunks( CHUNK_SIZE ) {
+        try!( writer.write_all( chunk ) );
+        done += chunk.len();
+        progress( &Progress { stage: ProgressStage::Save, done: done as u64, total: Some( content.len() as u64 ) } );
+    }
+    if content.is_empty() {
+        progress( &Progress { stage: ProgressStage::Save, done: 0, total: Some( 0 ) } );
+    }
+    Ok( () )
+}
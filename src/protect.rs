@@ -0,0 +1,29 @@
+//Directory annotations like `creation time` can be protected, so that they are not changed by accident:
+//
+//    >creation time
+//    =2016-03-01
+//    <annovate program, 1.3.2016 12:30:00
+//    !2016-03-01T12:30:00Z
+//    ^protected
+//
+//The `^` line follows the creation and expiry time and precedes the ID. Removing a key of the directory whose
+//most recent entry is protected, adding a new value for it or changing the entry by its ID fails with
+//`AnnoError::Protected` unless `Annovate::set_override_protection` allows it.
+//Protected entries of a file can likewise not be removed, on their own or by dropping the file.
+
+use {Annovate, AnnoContainer};
+
+/// Flag of the `^` line of a protected annotation
+pub const PROTECTED_FLAG: &'static str = "protected";
+
+/// Whether the most recent annotation with `key` is protected
+pub fn is_protected( annotations: &AnnoContainer, key: &str ) -> bool {
+    annotations.iter().rev().find( |a| a.key == key ).map( |a| a.protected ).unwrap_or( false )
+}
+
+impl Annovate {
+    /// Let the mutation methods change and remove protected annotations, e.g. for `--force`
+    pub fn set_override_protection( &mut self, allow: bool ) {
+        self.override_protection = allow;
+    }
+}
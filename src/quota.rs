@@ -0,0 +1,145 @@
+//Pipelines that annotate the same files again and again can pile up entries. Limits on the number of annotations
+//of a target (a file or the directory) and on the entries of one key catch that:
+//
+//    anno.set_quota( Quota { max_per_target: Some( 1000 ), max_per_key: Some( 100 ), action: QuotaAction::Dedup } );
+//
+//When an added annotation exceeds a limit, `QuotaAction::Dedup` first removes the entries of the target that have
+//the same key and value as a later entry. A limit that is still exceeded, or any exceeded limit with
+//`QuotaAction::Warn`, is recorded in `Annovate::quota_warnings`. The annotation is added in either case.
+//`Annovate::dedup_exact` removes such duplicates of all targets at once.
+
+use std::collections::HashSet;
+use std::fmt;
+
+use {Annovate, AnnoContainer, AnnoError};
+
+/// What happens when an added annotation exceeds a limit of the quota
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum QuotaAction {
+    /// Record a warning
+    Warn,
+    /// Remove exact duplicates of the target, then warn if the limit is still exceeded
+    Dedup
+}
+
+impl Default for QuotaAction {
+    fn default() -> QuotaAction {
+        QuotaAction::Warn
+    }
+}
+
+impl QuotaAction {
+    pub fn from_name( name: &str ) -> Option<QuotaAction> {
+        match name {
+            "warn" => Some( QuotaAction::Warn ),
+            "dedup" => Some( QuotaAction::Dedup ),
+            _ => None
+        }
+    }
+}
+
+/// Limits on the number of annotations, `None` for no limit
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct Quota {
+    /// Annotations of one file or the directory
+    pub max_per_target: Option<usize>,
+    /// Entries of one key of a file or the directory
+    pub max_per_key: Option<usize>,
+    pub action: QuotaAction
+}
+
+impl Quota {
+    /// Whether any limit is set
+    pub fn is_limited( &self ) -> bool {
+        self.max_per_target.is_some() || self.max_per_key.is_some()
+    }
+
+    /// The limits that `annotations` exceed for `key`, as warnings for `target`
+    fn exceeded( &self, target: Option<&str>, annotations: &AnnoContainer, key: &str ) -> Vec<QuotaWarning> {
+        let mut warnings = Vec::new();
+        if let Some( limit ) = self.max_per_target {
+            if annotations.len() > limit {
+                warnings.push( QuotaWarning { target: target.map( |t| t.to_string() ), key: None, count: annotations.len(), limit: limit } );
+            }
+        }
+        if let Some( limit ) = self.max_per_key {
+            let count = annotations.iter().filter( |a| a.key == key ).count();
+            if count > limit {
+                warnings.push( QuotaWarning { target: target.map( |t| t.to_string() ), key: Some( key.to_string() ), count: count, limit: limit } );
+            }
+        }
+        warnings
+    }
+}
+
+/// A limit of the quota that a target exceeds
+#[derive(Clone, PartialEq, Debug)]
+pub struct QuotaWarning {
+    /// The file, `None` for the directory
+    pub target: Option<String>,
+    /// The key for a limit of entries per key, `None` for the limit of the whole target
+    pub key: Option<String>,
+    pub count: usize,
+    pub limit: usize
+}
+
+impl fmt::Display for QuotaWarning {
+    fn fmt( &self, f: &mut fmt::Formatter ) -> fmt::Result {
+        let target = self.target.as_ref().map( |t| t.as_str() ).unwrap_or( "the directory" );
+        match self.key {
+            Some( ref key ) => write!( f, "{} has {} entries of `{}` (limit {})", target, self.count, key, self.limit ),
+            None => write!( f, "{} has {} annotations (limit {})", target, self.count, self.limit )
+        }
+    }
+}
+
+/// Apply `quota` after an annotation with `key` was added to `annotations` of `target` (`None` for the
+/// directory). Returns the limits that are still exceeded.
+pub fn apply_quota( quota: Quota, target: Option<&str>, annotations: &mut AnnoContainer, key: &str ) -> Vec<QuotaWarning> {
+    if !quota.is_limited() {
+        return Vec::new();
+    }
+    if quota.action == QuotaAction::Dedup && !quota.exceeded( target, annotations, key ).is_empty() {
+        dedup_annotations( annotations );
+    }
+    quota.exceeded( target, annotations, key )
+}
+
+/// Remove annotations with the same key and value as a later one, so that the most recent entry of every
+/// key stays the same. Returns how many were removed.
+pub fn dedup_annotations( annotations: &mut AnnoContainer ) -> usize {
+    let old_length = annotations.len();
+    let mut seen: HashSet<( String, String )> = HashSet::new();
+    //walk from the most recent entry backwards
+    let mut kept: AnnoContainer = annotations.drain( .. ).rev().filter( |a| seen.insert( ( a.key.clone(), a.value.clone() ) ) ).collect();
+    kept.reverse();
+    *annotations = kept;
+    old_length - annotations.len()
+}
+
+impl Annovate {
+    /// Limits that adding annotations checks, see `quota`
+    pub fn set_quota( &mut self, quota: Quota ) {
+        self.quota = quota;
+    }
+
+    pub fn quota( &self ) -> Quota {
+        self.quota
+    }
+
+    /// Limits that added annotations exceeded since the file was read
+    pub fn quota_warnings( &self ) -> &[QuotaWarning] {
+        &self.quota_warnings
+    }
+
+    /// Remove the annotations of the directory and the files that have the same key and value as a later
+    /// annotation of their target. Returns the number of removed annotations.
+    pub fn dedup_exact( &mut self ) -> Result<usize, AnnoError> {
+        try!( self.check_writable() );
+        let mut removed = 0;
+        for annotations in Some( &mut self.dir ).into_iter().chain( self.files.values_mut() ) {
+            removed += dedup_annotations( annotations );
+        }
+        Ok( self.mark_modified( removed > 0, removed ) )
+    }
+}
@@ -0,0 +1,331 @@
+//Event based parser of the annovate format. `Annovate::new` collects the events of a whole file,
+//read-only commands can keep only what they need.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+use {Annovate, Annotation, AnnoContainer, AnnoError, DEFAULT_LOCK_TIMEOUT, Fragment, OpenMode, ParseMode, Target, Timestamp, ValueType};
+use doctor::Problem;
+use backend::{Backend, LineBackend, backend_for_path};
+use {expected_leaders, extract_line_parts, invalid_line, is_valid_tag, test_leader, unescape_value_line};
+use ids;
+use subdir;
+use integrity;
+use protect;
+use value;
+
+/// Something read from an annovate file. Events before the first `FileStart` belong to the directory.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Event {
+    /// An `@file` or `#fragment` line: the following tags and annotations belong to this target
+    FileStart( String ),
+    Tag( String ),
+    /// A complete annotation, including its creation time
+    Annotation( Annotation ),
+    /// An `&` line of a delta record (see `delta`): the following tags and annotations are added to this target,
+    /// `None` for the directory
+    Delta( Option<String> )
+}
+
+/// Reads an annovate file line by line and yields its events. After an error, the iteration ends.
+pub struct AnnovateReader<R> {
+    lines: io::Lines<R>,
+    path: Option<PathBuf>,
+    allow_sections: bool,
+    in_file_section: bool,
+    /// Delta records started, no more sections may follow
+    in_deltas: bool,
+    current_base_file: String,
+    current_key: String,
+    current_type: Option<ValueType>,
+    current_value: String,
+    value_lines: usize,
+    /// The value started with `=b64:`
+    binary: bool,
+    last_leader: char,
+    line_no: u64,
+    /// Annotation whose `<` line was read, it is complete unless a `!`, `~`, `*`, `^` or `%` line follows
+    pending: Option<Annotation>,
+    queued: Option<Event>,
+    finished: bool
+}
+
+impl AnnovateReader<BufReader<File>> {
+    /// Reader of a meta file. It does not wait for the lock, files are always replaced as a whole when saved.
+    pub fn open( path: &Path ) -> Result<AnnovateReader<BufReader<File>>, AnnoError> {
+        let file = try!( File::open( path ).map_err( |e| AnnoError::io( path, e ) ) );
+        let mut reader = AnnovateReader::new( BufReader::new( file ) );
+        reader.path = Some( path.to_path_buf() );
+        Ok( reader )
+    }
+}
+
+impl<R: BufRead> AnnovateReader<R> {
+    pub fn new( reader: R ) -> AnnovateReader<R> {
+        AnnovateReader {
+            lines: reader.lines(),
+            path: None,
+            allow_sections: true,
+            in_file_section: false,
+            in_deltas: false,
+            current_base_file: String::new(),
+            current_key: String::new(),
+            current_type: None,
+            current_value: String::new(),
+            value_lines: 0,
+            binary: false,
+            last_leader: ' ', //dummy value
+            line_no: 1,
+            pending: None,
+            queued: None,
+            finished: false
+        }
+    }
+
+    /// Reader of a single block of annotations, e.g. of a template, where `@` and `#` lines are errors
+    pub fn without_sections( reader: R ) -> AnnovateReader<R> {
+        AnnovateReader { allow_sections: false, ..AnnovateReader::new( reader ) }
+    }
+
+    fn fail( &mut self, error: AnnoError ) -> Option<Result<Event, AnnoError>> {
+        self.finished = true;
+        self.pending = None;
+        Some( Err( match self.path {
+            Some( ref path ) => error.at( path ),
+            None => error
+        } ) )
+    }
+
+    /// Check a line and update the state. Returns the event that the line completes, if any.
+    fn parse_line( &mut self, line: &str ) -> Result<Option<Event>, AnnoError> {
+        let ( leader, rest ) = extract_line_parts( line );
+        let ( last_leader, line_no ) = ( self.last_leader, self.line_no );
+        let ( allow_sections, in_file_section, in_deltas ) = ( self.allow_sections, self.in_file_section, self.in_deltas );
+        let expected = || expected_leaders( last_leader, allow_sections, in_file_section, in_deltas );
+        let event = if leader == '@' && allow_sections && !in_deltas {
+            try!( test_leader( last_leader, "@<#!~*^%+& ", leader, line_no, expected ) );
+            self.current_base_file = rest.to_string();
+            self.in_file_section = true;
+            Some( Event::FileStart( rest.to_string() ) )
+        } else if leader == '#' && allow_sections && in_file_section && !in_deltas {
+            //fragment section of the file from the last `@` line
+            try!( test_leader( last_leader, "@<#!~^%+", leader, line_no, expected ) );
+            //a subdirectory has no parts
+            let fragment = match Fragment::parse( rest ) {
+                Some( ref fragment ) if subdir::is_subdirectory_entry( &self.current_base_file ) => return Err( invalid_line( line_no, leader ) ),
+                Some( fragment ) => fragment,
+                None => return Err( invalid_line( line_no, leader ) )
+            };
+            Some( Event::FileStart( Target { file: self.current_base_file.clone(), fragment: Some( fragment ) }.to_string() ) )
+        } else if leader == '&' && allow_sections {
+            try!( test_leader( last_leader, "@<#!~*^%+& ", leader, line_no, expected ) );
+            self.in_deltas = true;
+            self.in_file_section = false;
+            Some( Event::Delta( if rest.is_empty() { None } else { Some( rest.to_string() ) } ) )
+        } else if leader == '>' {
+            try!( test_leader( last_leader, "@<#!~*^%+& ", leader, line_no, expected ) );
+            let ( key, value_type ) = value::split_typed_key( rest );
+            self.current_key = key;
+            self.current_type = value_type;
+            self.current_value = String::new();
+            self.value_lines = 0;
+            self.binary = false;
+            None
+        } else if leader == '=' || leader == '\\' {
+            try!( test_leader( last_leader, ">=\\", leader, line_no, expected ) );
+            if leader == '=' && self.value_lines == 0 && rest.starts_with( "b64:" ) {
+                self.binary = true;
+                self.current_value.push_str( &rest[ 4.. ] );
+            } else if self.binary {
+                //base64 continues without line breaks
+                if leader != '=' {
+                    return Err( invalid_line( line_no, leader ) );
+                }
+                self.current_value.push_str( rest );
+            } else {
+                if self.value_lines > 0 {
+                    self.current_value.push_str( "\n" ); //separate lines with newline
+                }
+                if leader == '=' {
+                    self.current_value.push_str( rest );
+                } else {
+                    //not trimmed, trailing whitespace other than spaces and tabs is not escaped
+                    match unescape_value_line( &line[ 1.. ] ) {
+                        Some( line ) => self.current_value.push_str( &line ),
+                        None => return Err( invalid_line( line_no, leader ) )
+                    }
+                }
+            }
+            self.value_lines += 1;
+            None
+        } else if leader == '<' {
+            try!( test_leader( last_leader, "=>\\", leader, line_no, expected ) );
+            let mut anno = Annotation::new( self.current_key.clone(), self.current_value.clone(), rest.to_string() );
+            anno.value_type = self.current_type;
+            if self.binary {
+                anno.value_type = Some( ValueType::Binary );
+                if anno.typed_value().is_err() {
+                    return Err( invalid_line( line_no - 1, '=' ) ); //the base64 text is broken
+                }
+            }
+            self.pending = Some( anno );
+            None
+        } else if leader == '+' {
+            try!( test_leader( last_leader, "@<#!~*^%+& ", leader, line_no, expected ) );
+            if !is_valid_tag( rest ) {
+                return Err( invalid_line( line_no, leader ) );
+            }
+            Some( Event::Tag( rest.to_string() ) )
+        } else if leader == '!' {
+            //creation time of the annotation that was just closed
+            try!( test_leader( last_leader, "<", leader, line_no, expected ) );
+            let created = match Timestamp::parse( rest ) {
+                Some( created ) => created,
+                None => return Err( invalid_line( line_no, leader ) )
+            };
+            self.pending.as_mut().unwrap().created = Some( created ); //the `<` line started an annotation
+            None
+        } else if leader == '~' {
+            //expiry time of the annotation that was just closed
+            try!( test_leader( last_leader, "<!", leader, line_no, expected ) );
+            let expires = match Timestamp::parse( rest ) {
+                Some( expires ) => expires,
+                None => return Err( invalid_line( line_no, leader ) )
+            };
+            self.pending.as_mut().unwrap().expires = Some( expires );
+            None
+        } else if leader == '*' {
+            //region of the annotation that was just closed
+            try!( test_leader( last_leader, "<!~", leader, line_no, expected ) );
+            let region = match Fragment::parse_region( rest ) {
+                Some( region ) => region,
+                None => return Err( invalid_line( line_no, leader ) )
+            };
+            self.pending.as_mut().unwrap().region = Some( region );
+            None
+        } else if leader == '^' {
+            //flag of the annotation that was just closed
+            try!( test_leader( last_leader, "<!~*", leader, line_no, expected ) );
+            if rest != protect::PROTECTED_FLAG {
+                return Err( invalid_line( line_no, leader ) );
+            }
+            self.pending.as_mut().unwrap().protected = true;
+            None
+        } else if leader == '%' {
+            //ID of the annotation that was just closed
+            try!( test_leader( last_leader, "<!~*^", leader, line_no, expected ) );
+            if !ids::is_valid_id( rest ) {
+                return Err( invalid_line( line_no, leader ) );
+            }
+            self.pending.as_mut().unwrap().id = Some( rest.to_string() );
+            None
+        } else {
+            return Err( AnnoError::ParseError { path: None, line: line_no, found: leader, expected: Some( expected() ) } );
+        };
+        self.last_leader = leader;
+        self.line_no += 1;
+        Ok( event )
+    }
+}
+
+impl<R: BufRead> Iterator for AnnovateReader<R> {
+    type Item = Result<Event, AnnoError>;
+
+    fn next( &mut self ) -> Option<Self::Item> {
+        if let Some( event ) = self.queued.take() {
+            return Some( Ok( event ) );
+        }
+        if self.finished {
+            return None;
+        }
+        loop {
+            let line = match self.lines.next() {
+                //values never contain a raw carriage return, it belongs to a CRLF line ending
+                Some( Ok( ref line ) ) if line.ends_with( '\r' ) => line[ ..line.len() - 1 ].to_string(),
+                Some( Ok( line ) ) => line,
+                Some( Err( e ) ) => return self.fail( AnnoError::from( e ) ),
+                None => {
+                    if self.last_leader == '<' || self.last_leader == '!' || self.last_leader == '~' || self.last_leader == '*' || self.last_leader == '^' || self.last_leader == '%' || self.last_leader == '+' {
+                        self.finished = true;
+                        return self.pending.take().map( |anno| Ok( Event::Annotation( anno ) ) );
+                    }
+                    let expected = expected_leaders( self.last_leader, self.allow_sections, self.in_file_section, self.in_deltas );
+                    return self.fail( AnnoError::ParseError { path: None, line: self.line_no, found: ' ', expected: Some( expected ) } );
+                }
+            };
+            //the integrity footer is not part of the annotations
+            if line.starts_with( integrity::FOOTER_PREFIX ) && integrity::footer_hash( &line ).is_some() {
+                self.line_no += 1;
+                continue;
+            }
+            //every line except `!`, `~`, `*`, `^` and `%` completes the previous annotation
+            let leader = extract_line_parts( &line ).0;
+            let completed = if leader != '!' && leader != '~' && leader != '*' && leader != '^' && leader != '%' { self.pending.take() } else { None };
+            let event = match self.parse_line( &line ) {
+                Ok( event ) => event,
+                Err( e ) => return self.fail( e )
+            };
+            match ( completed, event ) {
+                ( Some( anno ), Some( event ) ) => {
+                    self.queued = Some( event );
+                    return Some( Ok( Event::Annotation( anno ) ) );
+                },
+                ( Some( anno ), None ) => return Some( Ok( Event::Annotation( anno ) ) ),
+                ( None, Some( event ) ) => return Some( Ok( event ) ),
+                ( None, None ) => continue
+            }
+        }
+    }
+}
+
+fn select_targets<F: FnMut( &str ) -> bool>( anno: &Annovate, mut select: F ) -> ( AnnoContainer, Vec<( String, AnnoContainer )> ) {
+    let targets = anno.iter_files().filter( |f| select( f ) ).map( |f| ( f.to_string(), anno.files[ f ].clone() ) ).collect();
+    ( anno.dir.clone(), targets )
+}
+
+/// Directory annotations and the annotations of the targets for which `select` is true (in the order of the file)
+/// read from a meta file. The annotations of other targets are not kept in memory unless the file is not in the line format.
+pub fn read_selected<F: FnMut( &str ) -> bool>( path: &Path, mut select: F ) -> Result<( AnnoContainer, Vec<( String, AnnoContainer )> ), AnnoError> {
+    if backend_for_path( path ).name() != LineBackend.name() {
+        let anno = try!( Annovate::open( path, OpenMode::ReadOnly ) );
+        return Ok( select_targets( &anno, select ) );
+    }
+    let mut dir = AnnoContainer::new();
+    let mut targets: Vec<( String, AnnoContainer )> = Vec::new();
+    //`None` while the directory annotations are read, then the index in `targets` if the target is selected
+    let mut current: Option<Option<usize>> = None;
+    for event in try!( AnnovateReader::open( path ) ) {
+        match try!( event ) {
+            Event::Delta( None ) => current = None,
+            Event::FileStart( name ) | Event::Delta( Some( name ) ) => {
+                current = Some( if select( &name ) {
+                    //duplicate sections are merged
+                    match targets.iter().position( |t| t.0 == name ) {
+                        Some( pos ) => Some( pos ),
+                        None => {
+                            targets.push( ( name, AnnoContainer::new() ) );
+                            Some( targets.len() - 1 )
+                        }
+                    }
+                } else {
+                    None
+                } );
+            },
+            Event::Annotation( anno ) => match current {
+                None => dir.push( anno ),
+                Some( Some( pos ) ) => targets[ pos ].1.push( anno ),
+                Some( None ) => {}
+            },
+            Event::Tag( _ ) => {}
+        }
+    }
+    Ok( ( dir, targets ) )
+}
+
+/// Like `read_selected`, but the file is read with `ParseMode::Lenient`; the skipped problems are returned as well
+pub fn read_selected_lenient<F: FnMut( &str ) -> bool>( path: &Path, select: F ) -> Result<( AnnoContainer, Vec<( String, AnnoContainer )>, Vec<Problem> ), AnnoError> {
+    let anno = try!( Annovate::open_with_parse_mode( path, OpenMode::ReadOnly, ParseMode::Lenient, DEFAULT_LOCK_TIMEOUT ) );
+    let ( dir, targets ) = select_targets( &anno, select );
+    Ok( ( dir, targets, anno.parse_warnings().to_vec() ) )
+}
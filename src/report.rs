@@ -0,0 +1,294 @@
+//Comparison of the annotated files with the files on disk (`anno report`). Annotated subdirectories (`data/`) are
+//reported under that name and only match directories. Recursive reports parse the meta files of the subdirectories
+//and read their directories on several threads.
+
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, FileType, read_dir};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use {Annovate, AnnoError, OpenMode, Target, is_meta_companion};
+use ignore::IgnoreRules;
+use normalization::Normalization;
+use progress::{Progress, ProgressStage};
+use subdir;
+use tree::AnnovateTree;
+
+/// How an annotated file or a file on disk compares
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum ReportStatus {
+    /// The file exists and has metadata
+    Annotated,
+    /// The file has metadata, but its content changed since the last snapshot
+    Changed,
+    /// Only the metadata exists
+    Missing,
+    /// The file has no metadata
+    Unannotated
+}
+
+impl ReportStatus {
+    /// Marker of the status in the output: `=`, `~`, `+` or `-`
+    pub fn marker( &self ) -> &'static str {
+        match *self {
+            ReportStatus::Annotated => "=",
+            ReportStatus::Changed => "~",
+            ReportStatus::Missing => "+",
+            ReportStatus::Unannotated => "-"
+        }
+    }
+}
+
+/// What a name in a directory stands for
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FileKind {
+    File,
+    Dir,
+    /// A symbolic link to an existing file or directory
+    Symlink,
+    /// A symbolic link whose target does not exist
+    BrokenLink,
+    /// Sockets, named pipes and devices
+    Other
+}
+
+impl FileKind {
+    /// Kind of `path`. With `follow_links`, a link to an existing file or directory counts as what it points to.
+    pub fn of( path: &Path, follow_links: bool ) -> io::Result<FileKind> {
+        let metadata = try!( fs::symlink_metadata( path ) );
+        if !metadata.file_type().is_symlink() {
+            return Ok( FileKind::of_type( metadata.file_type() ) );
+        }
+        match fs::metadata( path ) {
+            Ok( target ) if follow_links => Ok( FileKind::of_type( target.file_type() ) ),
+            Ok( _ ) => Ok( FileKind::Symlink ),
+            Err( _ ) => Ok( FileKind::BrokenLink )
+        }
+    }
+
+    fn of_type( file_type: FileType ) -> FileKind {
+        if file_type.is_file() {
+            FileKind::File
+        } else if file_type.is_dir() {
+            FileKind::Dir
+        } else {
+            FileKind::Other
+        }
+    }
+
+    /// Name for `--type`: `file`, `dir`, `symlink`, `broken-link` or `other`
+    pub fn name( &self ) -> &'static str {
+        match *self {
+            FileKind::File => "file",
+            FileKind::Dir => "dir",
+            FileKind::Symlink => "symlink",
+            FileKind::BrokenLink => "broken-link",
+            FileKind::Other => "other"
+        }
+    }
+
+    pub fn from_name( name: &str ) -> Option<FileKind> {
+        [ FileKind::File, FileKind::Dir, FileKind::Symlink, FileKind::BrokenLink, FileKind::Other ].iter().cloned().find( |k| k.name() == name )
+    }
+
+    /// Suffix of the name in the output like that of `ls -F`: `/`, `@`, `!` or `|`, nothing for regular files
+    pub fn marker( &self ) -> &'static str {
+        match *self {
+            FileKind::File => "",
+            FileKind::Dir => "/",
+            FileKind::Symlink => "@",
+            FileKind::BrokenLink => "!",
+            FileKind::Other => "|"
+        }
+    }
+}
+
+/// A file of a report with its path relative to the root of the report
+#[derive(Clone, PartialEq, Debug)]
+pub struct ReportLine {
+    pub status: ReportStatus,
+    /// Ends with `/` for an annotated subdirectory
+    pub name: String,
+    /// What the name stands for on disk, `None` if only the metadata exists
+    pub kind: Option<FileKind>
+}
+
+impl ReportLine {
+    /// Whether the line is about the entry of a subdirectory like `data/`, not about a file
+    pub fn is_subdirectory_entry( &self ) -> bool {
+        subdir::is_subdirectory_entry( &self.name )
+    }
+}
+
+/// Number of lines of a report with each status
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct ReportSummary {
+    pub annotated: usize,
+    pub changed: usize,
+    /// Metadata of files that do not exist
+    pub missing: usize,
+    pub unannotated: usize
+}
+
+impl ReportSummary {
+    pub fn total( &self ) -> usize {
+        self.annotated + self.changed + self.missing + self.unannotated
+    }
+}
+
+/// Result of `report_directory` or `report_tree`
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct ReportResult {
+    /// Sorted by name
+    pub lines: Vec<ReportLine>
+}
+
+impl ReportResult {
+    pub fn summary( &self ) -> ReportSummary {
+        let mut summary = ReportSummary::default();
+        for line in &self.lines {
+            *match line.status {
+                ReportStatus::Annotated => &mut summary.annotated,
+                ReportStatus::Changed => &mut summary.changed,
+                ReportStatus::Missing => &mut summary.missing,
+                ReportStatus::Unannotated => &mut summary.unannotated
+            } += 1;
+        }
+        summary
+    }
+
+    /// Only the lines with one of `statuses`
+    pub fn with_status( self, statuses: &[ReportStatus] ) -> ReportResult {
+        ReportResult { lines: self.lines.into_iter().filter( |l| statuses.contains( &l.status ) ).collect() }
+    }
+
+    /// Only the lines of files of this kind. Metadata of files that do not exist is left out.
+    pub fn with_kind( self, kind: FileKind ) -> ReportResult {
+        ReportResult { lines: self.lines.into_iter().filter( |l| l.kind == Some( kind ) ).collect() }
+    }
+
+    /// Whether a file on disk has no metadata. Meta files called `meta_name` with their journal, lock and backups
+    /// do not count.
+    pub fn has_unannotated( &self, meta_name: &str ) -> bool {
+        self.lines.iter().any( |l| {
+            let filename = Path::new( &l.name ).file_name().map( |n| n.to_string_lossy().into_owned() ).unwrap_or_default();
+            l.status == ReportStatus::Unannotated && !is_meta_companion( meta_name, &filename )
+        } )
+    }
+}
+
+/// What a report checks besides the existence of the files
+#[derive(Clone, Copy, Default, Debug)]
+pub struct ReportOptions {
+    /// Check the content of files with a stored checksum
+    pub verify: bool,
+    /// Report symbolic links to existing files and directories as what they point to
+    pub follow_links: bool,
+    /// Form that the names of the files on disk are brought to before they are compared with the annotated ones
+    pub normalization: Normalization
+}
+
+/// Compare the annotated files of `anno` with the files in `dir`, with `prefix` in front of the names.
+/// Files without metadata that the ignore file next to the meta file lists are left out. The lines are sorted by name.
+pub fn report_directory( anno: &Annovate, dir: &Path, prefix: &Path, options: ReportOptions ) -> Result<ReportResult, AnnoError> {
+    let meta_filenames: HashSet<String> = anno.iter_files()
+        .map( Target::parse )
+        .filter( |target| !target.is_remote() ) //URLs cannot be checked for existence
+        .map( |target| target.file ) //sub-file targets count for their file
+        .collect();
+    let ignore = try!( anno.ignore_rules() );
+    let meta_name = anno.filename.file_name().map( |n| n.to_string_lossy().into_owned() ).unwrap_or_default();
+    let mut real_files = HashMap::new();
+    for entry in try!( read_dir( dir ).map_err( |e| AnnoError::io( dir, e ) ) ) {
+        let entry = try!( entry );
+        let path = entry.path();
+        let kind = try!( FileKind::of( &path, options.follow_links ).map_err( |e| AnnoError::io( &path, e ) ) );
+        let name = options.normalization.apply( &entry.file_name().to_string_lossy() );
+        if is_meta_companion( &meta_name, &name ) && !meta_filenames.contains( &name ) {
+            continue;
+        }
+        let entry_name = subdir::subdirectory_entry( &name );
+        let has_entry = kind == FileKind::Dir && meta_filenames.contains( &entry_name );
+        if !meta_filenames.contains( &name ) && !has_entry && ignore.is_ignored( &name, kind == FileKind::Dir ) {
+            continue;
+        }
+        //a directory that is annotated as `data/` is only listed as such, unless it is annotated as `data` as well
+        if has_entry {
+            real_files.insert( entry_name, kind );
+            if !meta_filenames.contains( &name ) {
+                continue;
+            }
+        }
+        real_files.insert( name, kind );
+    }
+    let real_filenames: HashSet<String> = real_files.keys().cloned().collect();
+    let changed: HashSet<String> = if options.verify {
+        try!( anno.changed_files( dir ) ).into_iter().map( |f| Target::parse( &f ).file ).collect()
+    } else {
+        HashSet::new()
+    };
+
+    let mut lines = Vec::new();
+    let mut add = |status, name: &String| lines.push( ReportLine { status: status, name: AnnovateTree::relative_name( prefix, name ), kind: real_files.get( name ).cloned() } );
+    for common in real_filenames.intersection( &meta_filenames ) {
+        add( if changed.contains( common ) { ReportStatus::Changed } else { ReportStatus::Annotated }, common );
+    }
+    for meta_exclusive in meta_filenames.difference( &real_filenames ) {
+        add( ReportStatus::Missing, meta_exclusive );
+    }
+    for real_missing in real_filenames.difference( &meta_filenames ) {
+        add( ReportStatus::Unannotated, real_missing );
+    }
+    lines.sort_by( |a, b| a.name.cmp( &b.name ) );
+    Ok( ReportResult { lines: lines } )
+}
+
+/// Report of every directory below `root` with a file called `meta_name`, compared with the same directory
+/// below `dir`. Up to `jobs` threads parse the meta files and read the directories; the combined lines are
+/// sorted by name. Hidden directories are skipped unless `include_hidden` is set. The ignore file of `root`
+/// applies to the whole tree, that of each directory to its own files.
+pub fn report_tree( root: &Path, dir: &Path, meta_name: &str, include_hidden: bool, options: ReportOptions, jobs: usize ) -> Result<ReportResult, AnnoError> {
+    report_tree_with_progress( root, dir, meta_name, include_hidden, options, jobs, &|_| () )
+}
+
+/// Like `report_tree`, reporting every reported directory to `progress`, which any of the threads may call
+pub fn report_tree_with_progress( root: &Path, dir: &Path, meta_name: &str, include_hidden: bool, options: ReportOptions, jobs: usize,
+                                  progress: &( dyn Fn( &Progress ) + Sync ) ) -> Result<ReportResult, AnnoError> {
+    let members = try!( AnnovateTree::find( root, meta_name, include_hidden ) );
+    let ignore = try!( IgnoreRules::load( root ) );
+    let next = AtomicUsize::new( 0 );
+    let results: Mutex<Vec<Result<ReportResult, AnnoError>>> = Mutex::new( Vec::new() );
+    //annovate files cannot be shared between threads, so each one is parsed by the thread that reports it
+    let report_member = |relative: &PathBuf| -> Result<ReportResult, AnnoError> {
+        let anno = try!( Annovate::open( &root.join( relative ).join( meta_name ), OpenMode::ReadOnly ) );
+        report_directory( &anno, &dir.join( relative ), relative, options )
+    };
+    thread::scope( |scope| {
+        for _ in 0..jobs.max( 1 ).min( members.len() ) {
+            scope.spawn( || {
+                loop {
+                    let index = next.fetch_add( 1, Ordering::SeqCst );
+                    match members.get( index ) {
+                        Some( relative ) => {
+                            let result = report_member( relative );
+                            let mut results = results.lock().unwrap();
+                            results.push( result );
+                            //reported while the lock is held, so that the count only goes up
+                            progress( &Progress { stage: ProgressStage::Entries, done: results.len() as u64, total: Some( members.len() as u64 ) } );
+                        },
+                        None => return
+                    }
+                }
+            } );
+        }
+    } );
+    let mut lines = Vec::new();
+    for result in results.into_inner().unwrap() {
+        lines.extend( try!( result ).lines.into_iter().filter( |line| line.status != ReportStatus::Unannotated || !ignore.is_ignored( &line.name, line.kind == Some( FileKind::Dir ) ) ) );
+    }
+    lines.sort_by( |a, b| a.name.cmp( &b.name ) );
+    Ok( ReportResult { lines: lines } )
+}
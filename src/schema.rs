@@ -0,0 +1,104 @@
+use std::fmt;
+
+use regex::Regex;
+
+use {Annovate, Annotation, AnnoError, Target};
+
+/// Key of the directory annotation in which the schema is stored
+pub const SCHEMA_KEY: &'static str = "schema";
+
+/// A key every file must have, optionally with a pattern its value has to match
+#[derive(Clone, Debug)]
+pub struct Rule {
+    pub key: String,
+    pub pattern: Option<Regex>
+}
+
+/// Requirements for the annotations of every file
+#[derive(Clone, Debug)]
+pub struct Schema {
+    pub rules: Vec<Rule>
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum ViolationKind {
+    /// The file has no value for the key
+    Missing,
+    /// The most recent value does not match the pattern
+    Mismatch( String )
+}
+
+/// A file that does not satisfy a rule of the schema
+#[derive(Clone, PartialEq, Debug)]
+pub struct Violation {
+    pub file: String,
+    pub key: String,
+    pub kind: ViolationKind
+}
+
+impl fmt::Display for Violation {
+    fn fmt( &self, f: &mut fmt::Formatter ) -> fmt::Result {
+        match self.kind {
+            ViolationKind::Missing => write!( f, "{}: `{}` is missing", self.file, self.key ),
+            ViolationKind::Mismatch( ref value ) => write!( f, "{}: `{}` has the invalid value `{}`", self.file, self.key, value )
+        }
+    }
+}
+
+impl Schema {
+    /// Parse a schema definition: one rule per line, either `key` or `key=regex`
+    pub fn parse( definition: &str ) -> Result<Schema, AnnoError> {
+        let mut rules = Vec::new();
+        for line in definition.lines().filter( |l| !l.trim().is_empty() ) {
+            let rule = match line.find( '=' ) {
+                Some( pos ) => match Regex::new( &line[ pos + 1.. ] ) {
+                    Ok( re ) => Rule { key: line[ ..pos ].trim().to_string(), pattern: Some( re ) },
+                    Err( e ) => return Err( AnnoError::FormatError( format!( "invalid pattern for `{}`: {}", line[ ..pos ].trim(), e ) ) )
+                },
+                None => Rule { key: line.trim().to_string(), pattern: None }
+            };
+            rules.push( rule );
+        }
+        Ok( Schema { rules: rules } )
+    }
+
+    /// Inverse of `parse`
+    pub fn definition( &self ) -> String {
+        let lines: Vec<String> = self.rules.iter().map( |rule| match rule.pattern {
+            Some( ref re ) => format!( "{}={}", rule.key, re.as_str() ),
+            None => rule.key.clone()
+        } ).collect();
+        lines.join( "\n" )
+    }
+}
+
+/// The schema stored in the directory annotations, if there is one
+pub fn find_schema( anno: &Annovate ) -> Option<Result<Schema, AnnoError>> {
+    anno.get_directory_annotations().iter().rev().find( |a| a.key == SCHEMA_KEY ).map( |a| Schema::parse( &a.value ) )
+}
+
+/// Store a schema as directory annotation
+pub fn define_schema( anno: &mut Annovate, schema: &Schema, context: &str ) -> Result<(), AnnoError> {
+    anno.add_directory_annotation( Annotation::new( SCHEMA_KEY.to_string(), schema.definition(), context.to_string() ) )
+}
+
+impl Annovate {
+    /// Check every annotated file against the schema. Parts of files are not checked.
+    pub fn validate( &self, schema: &Schema ) -> Vec<Violation> {
+        let mut violations = Vec::new();
+        for file in self.iter_files().filter( |f| !Target::parse( f ).is_sub_file() ) {
+            let annotations = &self.files[ file ];
+            for rule in &schema.rules {
+                let kind = match ( annotations.iter().rev().find( |a| a.key == rule.key ), rule.pattern.as_ref() ) {
+                    ( None, _ ) => Some( ViolationKind::Missing ),
+                    ( Some( a ), Some( re ) ) if !re.is_match( &a.value ) => Some( ViolationKind::Mismatch( a.value.clone() ) ),
+                    _ => None
+                };
+                if let Some( kind ) = kind {
+                    violations.push( Violation { file: file.to_string(), key: rule.key.clone(), kind: kind } );
+                }
+            }
+        }
+        violations
+    }
+}
@@ -0,0 +1,96 @@
+use regex::Regex;
+
+use {Annovate, Annotation, AnnoContainer, AnnoError};
+
+/// Part of an annotation that matched a search
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Field {
+    Key,
+    Value,
+    Context
+}
+
+enum Matcher {
+    Substring( String ),
+    Regex( Regex )
+}
+
+/// What to search for and where
+pub struct SearchQuery {
+    matcher: Matcher,
+    ignore_case: bool,
+    pub fields: Vec<Field>
+}
+
+impl SearchQuery {
+    /// Search for a plain substring in keys, values and contexts
+    pub fn substring( pattern: &str, ignore_case: bool ) -> SearchQuery {
+        let pattern = if ignore_case { pattern.to_lowercase() } else { pattern.to_string() };
+        SearchQuery { matcher: Matcher::Substring( pattern ), ignore_case: ignore_case, fields: vec![ Field::Key, Field::Value, Field::Context ] }
+    }
+
+    /// Search for a regular expression in keys, values and contexts
+    pub fn regex( pattern: &str, ignore_case: bool ) -> Result<SearchQuery, AnnoError> {
+        let pattern = if ignore_case { format!( "(?i){}", pattern ) } else { pattern.to_string() };
+        match Regex::new( &pattern ) {
+            Ok( re ) => Ok( SearchQuery { matcher: Matcher::Regex( re ), ignore_case: ignore_case, fields: vec![ Field::Key, Field::Value, Field::Context ] } ),
+            Err( e ) => Err( AnnoError::FormatError( format!( "invalid regular expression: {}", e ) ) )
+        }
+    }
+
+    pub fn is_match( &self, text: &str ) -> bool {
+        match self.matcher {
+            Matcher::Substring( ref pattern ) if self.ignore_case => text.to_lowercase().contains( pattern.as_str() ),
+            Matcher::Substring( ref pattern ) => text.contains( pattern.as_str() ),
+            Matcher::Regex( ref re ) => re.is_match( text )
+        }
+    }
+
+    fn matching_fields( &self, annotation: &Annotation ) -> Vec<Field> {
+        self.fields.iter().cloned().filter( |field| match *field {
+            Field::Key => self.is_match( &annotation.key ),
+            Field::Value => self.is_match( &annotation.value ),
+            Field::Context => self.is_match( &annotation.context )
+        } ).collect()
+    }
+}
+
+/// An annotation that matched a search. `file` is `None` for directory annotations.
+pub struct SearchHit<'a> {
+    pub file: Option<String>,
+    pub annotation: &'a Annotation,
+    pub fields: Vec<Field>
+}
+
+impl Annovate {
+    /// Find all annotations of the directory and of all files that match the query
+    pub fn search<'a>( &'a self, query: &SearchQuery ) -> Vec<SearchHit<'a>> {
+        let mut hits = Vec::new();
+        for ( file, annotation ) in self {
+            let fields = query.matching_fields( annotation );
+            if !fields.is_empty() {
+                hits.push( SearchHit { file: file.map( |f| f.to_string() ), annotation: annotation, fields: fields } );
+            }
+        }
+        hits
+    }
+}
+
+/// Selection of annotations by their context, e.g. only those recorded by a certain user or tool
+pub trait ContextFilter {
+    /// The annotations whose context matches `query`, in their order
+    fn filter_by_context( &self, query: &SearchQuery ) -> AnnoContainer;
+
+    /// Remove the annotations whose context does not match `query`
+    fn retain_by_context( &mut self, query: &SearchQuery );
+}
+
+impl ContextFilter for AnnoContainer {
+    fn filter_by_context( &self, query: &SearchQuery ) -> AnnoContainer {
+        self.iter().filter( |a| query.is_match( &a.context ) ).cloned().collect()
+    }
+
+    fn retain_by_context( &mut self, query: &SearchQuery ) {
+        self.retain( |a| query.is_match( &a.context ) );
+    }
+}
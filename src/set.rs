@@ -0,0 +1,56 @@
+use std::path::{Path, PathBuf};
+
+use {Annovate, AnnoContainer, AnnoError, OpenMode};
+use tree::AnnovateTree;
+
+/// Annovate files of several unrelated directories. Filenames are prefixed with the directory
+/// they were loaded from, e.g. `data/raw.csv`.
+pub struct AnnovateSet {
+    members: Vec<( PathBuf, Annovate )>
+}
+
+impl AnnovateSet {
+    pub fn new() -> AnnovateSet {
+        AnnovateSet { members: Vec::new() }
+    }
+
+    /// Load the file called `meta_name` of every directory, read-only
+    pub fn open<P: AsRef<Path>>( dirs: &[P], meta_name: &str ) -> Result<AnnovateSet, AnnoError> {
+        let mut set = AnnovateSet::new();
+        for dir in dirs {
+            let dir = dir.as_ref();
+            set.add( dir, try!( Annovate::open( &dir.join( meta_name ), OpenMode::ReadOnly ) ) );
+        }
+        Ok( set )
+    }
+
+    pub fn add( &mut self, dir: &Path, anno: Annovate ) {
+        self.members.push( ( dir.to_path_buf(), anno ) );
+    }
+
+    /// The annovate files with the directory they belong to
+    pub fn members( &self ) -> &[( PathBuf, Annovate )] {
+        &self.members
+    }
+
+    /// Prefixed filenames of all members, in the order of the directories
+    pub fn get_files( &self ) -> Vec<String> {
+        self.members.iter().flat_map( |&( ref dir, ref anno )| {
+            anno.iter_files().map( move |f| AnnovateTree::relative_name( dir, f ) )
+        } ).collect()
+    }
+
+    /// Annotations of a prefixed filename
+    pub fn get_file_annotations( &self, path: &str ) -> Option<&AnnoContainer> {
+        self.members.iter().filter_map( |&( ref dir, ref anno )| {
+            Path::new( path ).strip_prefix( dir ).ok().and_then( |rest| anno.get_file_annotations( &rest.to_string_lossy() ) )
+        } ).next()
+    }
+
+    /// Most recent value of `key` of every file of every directory that has it
+    pub fn get_values( &self, key: &str ) -> Vec<( String, &str )> {
+        self.members.iter().flat_map( |&( ref dir, ref anno )| {
+            anno.get_values( key ).into_iter().map( move |( f, v )| ( AnnovateTree::relative_name( dir, &f ), v ) )
+        } ).collect()
+    }
+}
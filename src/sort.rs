@@ -0,0 +1,118 @@
+use std::cmp::Ordering;
+use std::collections::HashSet;
+
+use {Annovate, Annotation, AnnoContainer, Timestamp, Value};
+
+/// What files are sorted by
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SortOrder {
+    /// The filename
+    Name,
+    /// The most recent value of a key, numbers and dates by their meaning if they have a type
+    Value,
+    /// The time the file was last annotated
+    Time,
+    /// The number of different keys
+    KeyCount
+}
+
+impl SortOrder {
+    /// Parse the name of an order as used on the command line
+    pub fn from_name( name: &str ) -> Option<SortOrder> {
+        match name {
+            "name" => Some( SortOrder::Name ),
+            "value" => Some( SortOrder::Value ),
+            "time" => Some( SortOrder::Time ),
+            "key-count" => Some( SortOrder::KeyCount ),
+            _ => None
+        }
+    }
+}
+
+/// What the entries of a file or the directory are sorted by
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum AnnotationOrder {
+    Key,
+    /// The creation time, entries without one come first
+    Time,
+    Context
+}
+
+impl AnnotationOrder {
+    /// Parse the name of an order as used on the command line
+    pub fn from_name( name: &str ) -> Option<AnnotationOrder> {
+        match name {
+            "key" => Some( AnnotationOrder::Key ),
+            "time" => Some( AnnotationOrder::Time ),
+            "context" => Some( AnnotationOrder::Context ),
+            _ => None
+        }
+    }
+}
+
+/// Compare two annotations by `order` alone
+pub fn compare_annotations( a: &Annotation, b: &Annotation, order: AnnotationOrder ) -> Ordering {
+    match order {
+        AnnotationOrder::Key => a.key.cmp( &b.key ),
+        AnnotationOrder::Time => a.created.cmp( &b.created ),
+        AnnotationOrder::Context => a.context.cmp( &b.context )
+    }
+}
+
+/// Sort annotations, e.g. for `query`. Entries that are equal by `order` keep their order, also when descending.
+pub fn sort_annotations( annotations: &mut AnnoContainer, order: AnnotationOrder, descending: bool ) {
+    annotations.sort_by( |a, b| {
+        let ordering = compare_annotations( a, b, order );
+        if descending { ordering.reverse() } else { ordering }
+    } );
+}
+
+/// The `limit` annotations (all for `None`) that follow the first `offset` ones
+pub fn paginate( annotations: AnnoContainer, offset: usize, limit: Option<usize> ) -> AnnoContainer {
+    annotations.into_iter().skip( offset ).take( limit.unwrap_or( usize::MAX ) ).collect()
+}
+
+/// Value of an annotation for sorting. Values that do not match their type are sorted as text.
+pub fn sort_value( annotation: &Annotation ) -> Value {
+    annotation.typed_value().unwrap_or_else( |_| Value::Text( annotation.value.clone() ) )
+}
+
+/// Most recent creation time of the annotations
+pub fn last_annotated( annotations: &AnnoContainer ) -> Option<Timestamp> {
+    annotations.iter().filter_map( |a| a.created ).max()
+}
+
+/// Number of different keys of the annotations
+pub fn key_count( annotations: &AnnoContainer ) -> usize {
+    annotations.iter().map( |a| a.key.as_str() ).collect::<HashSet<_>>().len()
+}
+
+/// Compare the annotations of two files. `key` is the key whose values are compared for `SortOrder::Value`,
+/// files without it come first. Ties are broken by the filename.
+pub fn compare_files( a: ( &str, &AnnoContainer ), b: ( &str, &AnnoContainer ), order: SortOrder, key: &str ) -> Ordering {
+    let latest = |annos: &AnnoContainer| annos.iter().rev().find( |anno| anno.key == key ).map( sort_value );
+    let ordering = match order {
+        SortOrder::Name => Ordering::Equal,
+        SortOrder::Value => latest( a.1 ).cmp( &latest( b.1 ) ),
+        SortOrder::Time => last_annotated( a.1 ).cmp( &last_annotated( b.1 ) ),
+        SortOrder::KeyCount => key_count( a.1 ).cmp( &key_count( b.1 ) )
+    };
+    ordering.then_with( || a.0.cmp( b.0 ) )
+}
+
+/// Sort files with their annotations, e.g. for `list`
+pub fn sort_files<S: AsRef<str>>( files: &mut [( S, &AnnoContainer )], order: SortOrder, key: &str, descending: bool ) {
+    files.sort_by( |a, b| {
+        let ordering = compare_files( ( a.0.as_ref(), a.1 ), ( b.0.as_ref(), b.1 ), order, key );
+        if descending { ordering.reverse() } else { ordering }
+    } );
+}
+
+impl Annovate {
+    /// Annotated files in the given order
+    pub fn sorted_files( &self, order: SortOrder, key: &str, descending: bool ) -> Vec<String> {
+        let mut files: Vec<( &str, &AnnoContainer )> = self.iter_files().map( |f| ( f, &self.files[ f ] ) ).collect();
+        sort_files( &mut files, order, key, descending );
+        files.into_iter().map( |( f, _ )| f.to_string() ).collect()
+    }
+}
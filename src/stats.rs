@@ -0,0 +1,87 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::read_dir;
+
+use {Annovate, AnnoContainer, AnnoError, Target, is_meta_companion};
+
+/// Numbers about the annotations of a meta file, see `Annovate::statistics`
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Stats {
+    /// Targets with at least one annotation
+    pub annotated_files: usize,
+    /// Entries of the files, including overwritten ones
+    pub annotations: usize,
+    /// Entries of the directory, including overwritten ones
+    pub directory_annotations: usize,
+    /// Keys with the number of files that have them, most common first. Hidden keys like `.sha256` are left out.
+    pub key_counts: Vec<( String, usize )>,
+    /// Files in the directory of the meta file without annotations, sorted. Dotfiles, the meta file and ignored
+    /// files are left out.
+    pub unannotated_files: Vec<String>
+}
+
+impl Stats {
+    /// Annotations per annotated file
+    pub fn average_per_file( &self ) -> f64 {
+        if self.annotated_files == 0 { 0.0 } else { self.annotations as f64 / self.annotated_files as f64 }
+    }
+}
+
+impl Annovate {
+    /// The distinct keys of the directory and all files, or only of `file`, with the number of their entries
+    /// (including overwritten ones), sorted by key. Hidden keys like `.sha256` are only included with `include_hidden`.
+    /// `None` if `file` has no annotations.
+    pub fn key_counts( &self, file: Option<&str>, include_hidden: bool ) -> Option<Vec<( String, usize )>> {
+        let annotations: Vec<&AnnoContainer> = match file {
+            Some( file ) => match self.files.get( file ) {
+                Some( annotations ) => vec![ annotations ],
+                None => return None
+            },
+            None => Some( &self.dir ).into_iter().chain( self.files.values() ).collect()
+        };
+        let mut key_counts: BTreeMap<&str, usize> = BTreeMap::new();
+        for annotation in annotations.into_iter().flat_map( |annos| annos.iter() ) {
+            if include_hidden || !annotation.key.starts_with( "." ) {
+                *key_counts.entry( &annotation.key ).or_insert( 0 ) += 1;
+            }
+        }
+        Some( key_counts.into_iter().map( |( key, count )| ( key.to_string(), count ) ).collect() )
+    }
+
+    /// Count the annotated files, annotations and keys, and look for files without annotations on disk
+    pub fn statistics( &self ) -> Result<Stats, AnnoError> {
+        let mut stats = Stats::default();
+        let mut key_counts: HashMap<&str, usize> = HashMap::new();
+        stats.directory_annotations = self.dir.len();
+        for file in self.iter_files() {
+            let annotations = &self.files[ file ];
+            if annotations.is_empty() {
+                continue;
+            }
+            stats.annotated_files += 1;
+            stats.annotations += annotations.len();
+            let mut keys: Vec<&str> = annotations.iter().map( |a| a.key.as_str() ).filter( |k| !k.starts_with( "." ) ).collect();
+            keys.sort();
+            keys.dedup();
+            for key in keys {
+                *key_counts.entry( key ).or_insert( 0 ) += 1;
+            }
+        }
+        stats.key_counts = key_counts.into_iter().map( |( key, count )| ( key.to_string(), count ) ).collect();
+        stats.key_counts.sort_by( |a, b| b.1.cmp( &a.1 ).then_with( || a.0.cmp( &b.0 ) ) );
+
+        let dir = self.base_dir();
+        //the meta file, its journal and its lock
+        let meta_name = self.filename.file_name().map( |n| n.to_string_lossy().into_owned() ).unwrap_or_default();
+        let ignore = try!( self.ignore_rules() );
+        for entry in try!( read_dir( &dir ).map_err( |e| AnnoError::io( &dir, e ) ) ) {
+            let entry = try!( entry );
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if !name.starts_with( "." ) && !is_meta_companion( &meta_name, &name ) && try!( entry.file_type() ).is_file() && self.files.get( &Target::file( &name ).to_string() ).map( |a| a.is_empty() ).unwrap_or( true )
+                && !ignore.is_ignored( &name, false ) {
+                stats.unannotated_files.push( name );
+            }
+        }
+        stats.unannotated_files.sort();
+        Ok( stats )
+    }
+}
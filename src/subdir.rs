@@ -0,0 +1,67 @@
+//Subdirectories are annotated in the meta file of their parent like files, with a `/` after the name:
+//
+//    @data/
+//    >source
+//    =measurements of 2026
+//    <alice
+//
+//Such a directory entry has the same annotations and tags as a file, but no columns, lines or bytes. `report`
+//matches it only with a directory on disk. The annotations of the directory of the meta file itself stay those
+//before the first `@` line.
+
+use {Annovate, AnnoContainer, Annotation, AnnoError, PutPolicy, looks_like_url};
+
+/// Marks the name of a directory entry
+pub const SUBDIR_MARKER: char = '/';
+
+/// Whether `name` from the meta file is that of a subdirectory, like `data/`
+pub fn is_subdirectory_entry( name: &str ) -> bool {
+    name.ends_with( SUBDIR_MARKER ) && !looks_like_url( name )
+}
+
+/// The name of the entry of the subdirectory `dir` (`data` or `data/`) in the meta file
+pub fn subdirectory_entry( dir: &str ) -> String {
+    format!( "{}{}", dir.trim_right_matches( SUBDIR_MARKER ), SUBDIR_MARKER )
+}
+
+impl Annovate {
+    /// Names of the annotated subdirectories without the trailing `/`, in the order of `get_files`
+    pub fn get_subdirectories( &self ) -> Vec<String> {
+        self.iter_files().filter( |f| is_subdirectory_entry( f ) ).map( |f| f[ ..f.len() - 1 ].to_string() ).collect()
+    }
+
+    /// Like `get_file_annotations` for the subdirectory `dir`
+    pub fn get_subdirectory_annotations( &self, dir: &str ) -> Option<&AnnoContainer> {
+        self.get_file_annotations( &subdirectory_entry( dir ) )
+    }
+
+    /// Like `get_file_annotation` for the subdirectory `dir`
+    pub fn get_subdirectory_annotation( &self, dir: &str, key: &str ) -> Option<&Annotation> {
+        self.get_file_annotation( &subdirectory_entry( dir ), key )
+    }
+
+    /// Like `add_file_annotation` for the subdirectory `dir`
+    pub fn add_subdirectory_annotation( &mut self, dir: &str, anno: Annotation ) -> Result<(), AnnoError> {
+        self.add_file_annotation( &subdirectory_entry( dir ), anno )
+    }
+
+    /// Like `put_file_annotation` for the subdirectory `dir`
+    pub fn put_subdirectory_annotation( &mut self, dir: &str, anno: Annotation, policy: PutPolicy ) -> Result<bool, AnnoError> {
+        self.put_file_annotation( &subdirectory_entry( dir ), anno, policy )
+    }
+
+    /// Like `remove_file_annotation_entries` for the subdirectory `dir`
+    pub fn remove_subdirectory_annotation_entries( &mut self, dir: &str, key: &str ) -> Result<bool, AnnoError> {
+        self.remove_file_annotation_entries( &subdirectory_entry( dir ), key )
+    }
+
+    /// Like `drop_file_annotations` for the subdirectory `dir`
+    pub fn drop_subdirectory_annotations( &mut self, dir: &str ) -> Result<bool, AnnoError> {
+        self.drop_file_annotations( &subdirectory_entry( dir ) )
+    }
+
+    /// Like `rename_file` for the subdirectory `old`
+    pub fn rename_subdirectory( &mut self, old: &str, new: &str ) -> Result<bool, AnnoError> {
+        self.rename_file( &subdirectory_entry( old ), &subdirectory_entry( new ) )
+    }
+}
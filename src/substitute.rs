@@ -0,0 +1,143 @@
+//Sed-like substitutions that `anno apply --exec` runs on values:
+//
+//    s/draft/final/          the first match
+//    s/ +$//g                every match
+//    s/.*/\L&/               the whole value in lower case (`\U` upper case, `\E` ends the conversion)
+//    s|^/old/|/new/|i        another delimiter, ignoring case
+//
+//In the replacement, `&` is the match and `\1` to `\9` are its groups; `\&`, `\\`, `\n` and the escaped
+//delimiter are literal.
+
+use regex::{Captures, Regex};
+
+use AnnoError;
+
+/// Upper or lower case conversion of the replacement text that follows
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Case {
+    Keep,
+    Upper,
+    Lower
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum Part {
+    Literal( String ),
+    Group( usize ),
+    Case( Case )
+}
+
+/// A parsed `s/pattern/replacement/flags` expression
+pub struct Substitution {
+    regex: Regex,
+    replacement: Vec<Part>,
+    global: bool
+}
+
+fn invalid( expr: &str, reason: &str ) -> AnnoError {
+    AnnoError::FormatError( format!( "invalid expression `{}`: {}", expr, reason ) )
+}
+
+/// Split `text` at unescaped `delimiter`s. Escaped delimiters lose their backslash, other escapes are kept.
+fn split_unescaped( text: &str, delimiter: char ) -> Vec<String> {
+    let mut parts = vec![ String::new() ];
+    let mut chars = text.chars();
+    while let Some( c ) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some( next ) if next == delimiter => parts.last_mut().unwrap().push( next ),
+                Some( next ) => {
+                    parts.last_mut().unwrap().push( '\\' );
+                    parts.last_mut().unwrap().push( next );
+                },
+                None => parts.last_mut().unwrap().push( '\\' )
+            },
+            c if c == delimiter => parts.push( String::new() ),
+            c => parts.last_mut().unwrap().push( c ) //parts is never empty
+        }
+    }
+    parts
+}
+
+fn parse_replacement( text: &str ) -> Vec<Part> {
+    let mut parts = Vec::new();
+    let mut literal = String::new();
+    let mut chars = text.chars();
+    while let Some( c ) = chars.next() {
+        let part = match c {
+            '&' => Part::Group( 0 ),
+            '\\' => match chars.next() {
+                Some( digit @ '1' ..= '9' ) => Part::Group( digit as usize - '0' as usize ),
+                Some( 'U' ) => Part::Case( Case::Upper ),
+                Some( 'L' ) => Part::Case( Case::Lower ),
+                Some( 'E' ) => Part::Case( Case::Keep ),
+                Some( 'n' ) => { literal.push( '\n' ); continue },
+                Some( other ) => { literal.push( other ); continue },
+                None => { literal.push( '\\' ); continue }
+            },
+            c => { literal.push( c ); continue }
+        };
+        if !literal.is_empty() {
+            parts.push( Part::Literal( literal.clone() ) );
+            literal.clear();
+        }
+        parts.push( part );
+    }
+    if !literal.is_empty() {
+        parts.push( Part::Literal( literal ) );
+    }
+    parts
+}
+
+impl Substitution {
+    /// Parse an expression like `s/pattern/replacement/g`. The flags are `g` (every match) and `i` (ignore case).
+    pub fn parse( expr: &str ) -> Result<Substitution, AnnoError> {
+        let mut chars = expr.chars();
+        if chars.next() != Some( 's' ) {
+            return Err( invalid( expr, "only substitutions `s/pattern/replacement/` are supported" ) );
+        }
+        let delimiter = match chars.next() {
+            Some( c ) if !c.is_alphanumeric() && !c.is_whitespace() && c != '\\' => c,
+            _ => return Err( invalid( expr, "`s` must be followed by a delimiter like `/`" ) )
+        };
+        let parts = split_unescaped( chars.as_str(), delimiter );
+        if parts.len() != 3 {
+            return Err( invalid( expr, "expected a pattern, a replacement and flags" ) );
+        }
+        let ( mut global, mut ignore_case ) = ( false, false );
+        for flag in parts[ 2 ].chars() {
+            match flag {
+                'g' => global = true,
+                'i' => ignore_case = true,
+                other => return Err( invalid( expr, &format!( "unknown flag `{}`", other ) ) )
+            }
+        }
+        let pattern = if ignore_case { format!( "(?i){}", parts[ 0 ] ) } else { parts[ 0 ].clone() };
+        let regex = try!( Regex::new( &pattern ).map_err( |e| invalid( expr, &e.to_string() ) ) );
+        Ok( Substitution { regex: regex, replacement: parse_replacement( &parts[ 1 ] ), global: global } )
+    }
+
+    fn expand( &self, captures: &Captures ) -> String {
+        let mut result = String::new();
+        let mut case = Case::Keep;
+        for part in &self.replacement {
+            let text = match *part {
+                Part::Literal( ref text ) => text.as_str(),
+                Part::Group( group ) => captures.at( group ).unwrap_or( "" ),
+                Part::Case( new_case ) => { case = new_case; continue }
+            };
+            match case {
+                Case::Keep => result.push_str( text ),
+                Case::Upper => result.push_str( &text.to_uppercase() ),
+                Case::Lower => result.push_str( &text.to_lowercase() )
+            }
+        }
+        result
+    }
+
+    /// `text` with the first (or with `g` every) match replaced
+    pub fn apply( &self, text: &str ) -> String {
+        let replace = |captures: &Captures| self.expand( captures );
+        if self.global { self.regex.replace_all( text, replace ) } else { self.regex.replace( text, replace ) }
+    }
+}
@@ -0,0 +1,66 @@
+use {Annovate, AnnoContainer, AnnoError, TagSet};
+use validate;
+
+/// Tags are single words: not empty and without whitespace
+pub fn is_valid_tag( tag: &str ) -> bool {
+    !tag.is_empty() && !tag.contains( char::is_whitespace )
+}
+
+impl Annovate {
+    pub fn get_directory_tags( &self ) -> &TagSet {
+        &self.dir_tags
+    }
+
+    pub fn get_tags( &self, filename: &str ) -> Option<&TagSet> {
+        self.tags.get( filename )
+    }
+
+    /// Returns false if the directory already had the tag or the tag is invalid
+    pub fn add_directory_tag( &mut self, tag: &str ) -> Result<bool, AnnoError> {
+        try!( self.check_writable() );
+        let added = is_valid_tag( tag ) && self.dir_tags.insert( tag.to_string() );
+        Ok( self.mark_modified( added, added ) )
+    }
+
+    pub fn remove_directory_tag( &mut self, tag: &str ) -> Result<bool, AnnoError> {
+        try!( self.check_writable() );
+        let removed = self.dir_tags.remove( tag );
+        Ok( self.mark_modified( removed, removed ) )
+    }
+
+    /// Returns false if the file already had the tag or the tag is invalid
+    pub fn add_tag( &mut self, filename: &str, tag: &str ) -> Result<bool, AnnoError> {
+        try!( self.check_writable() );
+        try!( validate::check_filename( filename ) );
+        if !is_valid_tag( tag ) {
+            return Ok( false );
+        }
+        if !self.files.contains_key( filename ) {
+            self.file_order.push( filename.to_string() );
+            self.files.insert( filename.to_string(), AnnoContainer::new() );
+        }
+        let added = self.tags.entry( filename.to_string() ).or_insert( TagSet::new() ).insert( tag.to_string() );
+        Ok( self.mark_modified( added, added ) )
+    }
+
+    /// Returns false if the file did not have the tag. A file without tags and annotations is dropped.
+    pub fn remove_tag( &mut self, filename: &str, tag: &str ) -> Result<bool, AnnoError> {
+        try!( self.check_writable() );
+        let ( removed, now_empty ) = match self.tags.get_mut( filename ) {
+            Some( tags ) => ( tags.remove( tag ), tags.is_empty() ),
+            None => return Ok( false )
+        };
+        if now_empty {
+            self.tags.remove( filename );
+            if self.files.get( filename ).map( |a| a.is_empty() ).unwrap_or( false ) {
+                try!( self.drop_file_annotations( filename ) );
+            }
+        }
+        Ok( self.mark_modified( removed, removed ) )
+    }
+
+    /// Files with the given tag in the order of `get_files`
+    pub fn files_with_tag( &self, tag: &str ) -> Vec<String> {
+        self.get_files().into_iter().filter( |f| self.tags.get( f ).map( |t| t.contains( tag ) ).unwrap_or( false ) ).collect()
+    }
+}
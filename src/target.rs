@@ -0,0 +1,207 @@
+use std::fmt;
+
+/// Part of a file that can carry its own annotations
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Fragment {
+    /// A column of a tabular file, written as `column:<name>`
+    Column( String ),
+    /// An inclusive range of lines, written as `L<first>-<last>` or `L<line>`
+    Lines( u64, u64 ),
+    /// An inclusive range of bytes counted from 0, written as `B<first>-<last>` or `B<byte>`
+    Bytes( u64, u64 )
+}
+
+/// Parse an inclusive range like `10-20`, or `7` for a single position
+pub fn parse_range( text: &str ) -> Option<( u64, u64 )> {
+    let mut bounds = text.splitn( 2, '-' );
+    let first = match bounds.next().and_then( |b| b.parse::<u64>().ok() ) {
+        Some( first ) => first,
+        None => return None
+    };
+    let last = match bounds.next() {
+        Some( b ) => match b.parse::<u64>() {
+            Ok( last ) => last,
+            Err( _ ) => return None
+        },
+        None => first
+    };
+    if first <= last { Some( ( first, last ) ) } else { None }
+}
+
+impl Fragment {
+    /// Parse the part after `#`. Returns `None` if it is not a valid fragment.
+    pub fn parse( text: &str ) -> Option<Fragment> {
+        if text.starts_with( "column:" ) && text.len() > "column:".len() {
+            Some( Fragment::Column( text[ "column:".len().. ].to_string() ) )
+        } else if text.starts_with( "L" ) {
+            match parse_range( &text[ 1.. ] ) {
+                Some( ( first, last ) ) if first >= 1 => Some( Fragment::Lines( first, last ) ),
+                _ => None
+            }
+        } else if text.starts_with( "B" ) {
+            parse_range( &text[ 1.. ] ).map( |( first, last )| Fragment::Bytes( first, last ) )
+        } else {
+            None
+        }
+    }
+
+    /// Parse the region of an annotation (see `Annotation::region`): lines or bytes, but no column
+    pub fn parse_region( text: &str ) -> Option<Fragment> {
+        match Fragment::parse( text ) {
+            Some( Fragment::Column( _ ) ) => None,
+            region => region
+        }
+    }
+
+    /// True if both fragments refer to a common part of the file: the same column, or overlapping lines or bytes
+    pub fn overlaps( &self, other: &Fragment ) -> bool {
+        match ( self, other ) {
+            ( &Fragment::Column( ref a ), &Fragment::Column( ref b ) ) => a == b,
+            ( &Fragment::Lines( first, last ), &Fragment::Lines( other_first, other_last ) ) |
+            ( &Fragment::Bytes( first, last ), &Fragment::Bytes( other_first, other_last ) ) => first <= other_last && other_first <= last,
+            _ => false
+        }
+    }
+}
+
+impl fmt::Display for Fragment {
+    fn fmt( &self, f: &mut fmt::Formatter ) -> fmt::Result {
+        match *self {
+            Fragment::Column( ref name ) => write!( f, "column:{}", name ),
+            Fragment::Lines( first, last ) if first == last => write!( f, "L{}", first ),
+            Fragment::Lines( first, last ) => write!( f, "L{}-{}", first, last ),
+            Fragment::Bytes( first, last ) if first == last => write!( f, "B{}", first ),
+            Fragment::Bytes( first, last ) => write!( f, "B{}-{}", first, last ),
+        }
+    }
+}
+
+/// True if the text has the shape of a URL (`<scheme>://...`) and should therefore be treated as a remote resource
+pub fn looks_like_url( text: &str ) -> bool {
+    text.contains( "://" )
+}
+
+/// Check that a URL has a valid scheme, a host part and contains no whitespace
+pub fn is_valid_url( text: &str ) -> bool {
+    let ( scheme, rest ) = match text.find( "://" ) {
+        Some( pos ) => ( &text[ ..pos ], &text[ pos + 3.. ] ),
+        None => return false
+    };
+    let valid_scheme = scheme.chars().next().map( |c| c.is_ascii_alphabetic() ).unwrap_or( false )
+                       && scheme.chars().all( |c| c.is_ascii_alphanumeric() || c == '+' || c == '-' || c == '.' );
+    let host = rest.split( |c| c == '/' || c == '?' || c == '#' ).next().unwrap_or( "" );
+    valid_scheme && !host.is_empty() && !text.chars().any( |c| c.is_whitespace() )
+}
+
+/// Whether `text` after a `#` has the shape of a fragment, valid or not, like `column:age`, `L10-20` or `L20-10`
+fn looks_like_fragment( text: &str ) -> bool {
+    text.starts_with( "column:" ) ||
+        ( ( text.starts_with( 'L' ) || text.starts_with( 'B' ) ) && text[ 1.. ].chars().all( |c| c.is_ascii_digit() || c == '-' ) )
+}
+
+/// Positions of the `#` in `text` that are not part of a doubled `##`
+fn single_hashes( text: &str ) -> Vec<usize> {
+    let bytes = text.as_bytes();
+    let mut result = Vec::new();
+    let mut pos = 0;
+    while pos < bytes.len() {
+        if bytes[ pos ] == b'#' {
+            if bytes.get( pos + 1 ) == Some( &b'#' ) {
+                pos += 2;
+                continue;
+            }
+            result.push( pos );
+        }
+        pos += 1;
+    }
+    result
+}
+
+/// Something that can be annotated: a whole file or a fragment of it (`data.csv#column:age`, `notes.txt#L10-20`,
+/// `data.bin#B0-511`) or a URL. In the text of a target, a `#` of the filename that would start a fragment or
+/// precedes another `#` is doubled: `notes##L5` is the file `notes#L5`, and `notes##L5#L1` its first line.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Target {
+    pub file: String,
+    pub fragment: Option<Fragment>
+}
+
+impl Target {
+    /// Split a target string at the first single `#` that is followed by a valid fragment, and turn `##` of the
+    /// filename into `#`. Anything else is treated as a plain filename. URLs are never split.
+    pub fn parse( text: &str ) -> Target {
+        if looks_like_url( text ) {
+            return Target::file( text );
+        }
+        for pos in single_hashes( text ) {
+            if let Some( fragment ) = Fragment::parse( &text[ pos + 1.. ] ) {
+                return Target { file: text[ ..pos ].replace( "##", "#" ), fragment: Some( fragment ) };
+            }
+        }
+        Target { file: text.replace( "##", "#" ), fragment: None }
+    }
+
+    /// The part of `text` from a `#` that has the shape of a fragment but is not valid, like `#column:` or
+    /// `#L20-10`. `None` if the fragment is valid or `text` has none; a name like `issue#12.txt` has none.
+    pub fn malformed_fragment( text: &str ) -> Option<&str> {
+        if looks_like_url( text ) || Target::parse( text ).fragment.is_some() {
+            return None;
+        }
+        single_hashes( text ).into_iter().find( |&pos| looks_like_fragment( &text[ pos + 1.. ] ) ).map( |pos| &text[ pos.. ] )
+    }
+
+    pub fn file( filename: &str ) -> Target {
+        Target { file: filename.to_string(), fragment: None }
+    }
+
+    pub fn column( filename: &str, column: &str ) -> Target {
+        Target { file: filename.to_string(), fragment: Some( Fragment::Column( column.to_string() ) ) }
+    }
+
+    pub fn lines( filename: &str, first: u64, last: u64 ) -> Target {
+        Target { file: filename.to_string(), fragment: Some( Fragment::Lines( first, last ) ) }
+    }
+
+    pub fn bytes( filename: &str, first: u64, last: u64 ) -> Target {
+        Target { file: filename.to_string(), fragment: Some( Fragment::Bytes( first, last ) ) }
+    }
+
+    /// True if the target is a URL of a remote resource instead of a local file
+    pub fn is_remote( &self ) -> bool {
+        looks_like_url( &self.file )
+    }
+
+    /// The target with `/` instead of `\` as path separator. URLs and fragments are kept.
+    pub fn with_slashes( &self ) -> Target {
+        if self.is_remote() {
+            return self.clone();
+        }
+        Target { file: self.file.replace( '\\', "/" ), fragment: self.fragment.clone() }
+    }
+
+    /// True if the target refers to a part of a file rather than the whole file
+    pub fn is_sub_file( &self ) -> bool {
+        self.fragment.is_some()
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt( &self, f: &mut fmt::Formatter ) -> fmt::Result {
+        let suffix = self.fragment.as_ref().map( |fragment| format!( "#{}", fragment ) ).unwrap_or_default();
+        if self.is_remote() {
+            return write!( f, "{}{}", self.file, suffix );
+        }
+        let mut file = String::new();
+        for ( pos, c ) in self.file.char_indices() {
+            file.push( c );
+            if c == '#' {
+                let rest = format!( "{}{}", &self.file[ pos + 1.. ], suffix );
+                if rest.starts_with( '#' ) || looks_like_fragment( &rest ) {
+                    file.push( '#' );
+                }
+            }
+        }
+        write!( f, "{}{}", file, suffix )
+    }
+}
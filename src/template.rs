@@ -0,0 +1,100 @@
+use {Annovate, Annotation, AnnoError, Timestamp};
+
+/// Prefix of the directory annotation keys under which templates are stored
+pub const TEMPLATE_KEY_PREFIX: &'static str = "template:";
+
+/// Placeholder in template values that is replaced by the name of the annotated file
+pub const FILE_PLACEHOLDER: &'static str = "{file}";
+
+/// Placeholder in template values that is replaced by the time at which the template is applied
+pub const NOW_PLACEHOLDER: &'static str = "{now}";
+
+/// A reusable set of keys. Fields without a value are blanks that have to be filled when the template is applied.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Template {
+    pub name: String,
+    pub fields: Vec<( String, Option<String> )>
+}
+
+impl Template {
+    /// Parse the definition of a template: one field per line, either `key=value` or just `key` for a blank
+    pub fn parse( name: &str, definition: &str ) -> Template {
+        let fields = definition.lines()
+                               .filter( |l| !l.trim().is_empty() )
+                               .map( |l| match l.find( '=' ) {
+                                   Some( pos ) => ( l[ ..pos ].trim().to_string(), Some( l[ pos + 1.. ].to_string() ) ),
+                                   None => ( l.trim().to_string(), None )
+                               } )
+                               .collect();
+        Template { name: name.to_string(), fields: fields }
+    }
+
+    /// Inverse of `parse`
+    pub fn definition( &self ) -> String {
+        let lines: Vec<String> = self.fields.iter().map( |&( ref key, ref value )| match *value {
+            Some( ref v ) => format!( "{}={}", key, v ),
+            None => key.clone()
+        } ).collect();
+        lines.join( "\n" )
+    }
+
+    /// Keys that have no value in the template
+    pub fn blanks( &self ) -> Vec<&str> {
+        self.fields.iter().filter( |f| f.1.is_none() ).map( |f| f.0.as_str() ).collect()
+    }
+
+    /// Add the fields of the template to every file. `fill` is asked once for the value of each blank;
+    /// blanks for which it returns `None` are skipped. `{file}` in values is replaced by the filename
+    /// and `{now}` by the current time.
+    pub fn apply<F>( &self, anno: &mut Annovate, filenames: &[String], context: &str, mut fill: F ) -> Result<usize, AnnoError>
+        where F: FnMut( &str ) -> Option<String> {
+        let mut values = Vec::new();
+        for &( ref key, ref value ) in &self.fields {
+            let value = match *value {
+                Some( ref v ) => Some( v.clone() ),
+                None => fill( key )
+            };
+            if let Some( v ) = value {
+                values.push( ( key.clone(), v ) );
+            }
+        }
+
+        let now = Timestamp::now();
+        let mut count = 0;
+        for filename in filenames {
+            for &( ref key, ref value ) in &values {
+                let value = value.replace( FILE_PLACEHOLDER, filename ).replace( NOW_PLACEHOLDER, &now.to_string() );
+                try!( anno.add_file_annotation( filename, Annotation::new( key.clone(), value, context.to_string() ) ) );
+                count += 1;
+            }
+        }
+        Ok( count )
+    }
+}
+
+/// All templates defined in the directory annotations. Later definitions replace earlier ones.
+pub fn templates( anno: &Annovate ) -> Vec<Template> {
+    let mut result: Vec<Template> = Vec::new();
+    for annotation in anno.get_directory_annotations() {
+        if annotation.key.starts_with( TEMPLATE_KEY_PREFIX ) {
+            let template = Template::parse( &annotation.key[ TEMPLATE_KEY_PREFIX.len().. ], &annotation.value );
+            result.retain( |t| t.name != template.name );
+            result.push( template );
+        }
+    }
+    result
+}
+
+/// Look up a template by name
+pub fn find_template( anno: &Annovate, name: &str ) -> Option<Template> {
+    templates( anno ).into_iter().find( |t| t.name == name )
+}
+
+/// Store a template as directory annotation
+pub fn define_template( anno: &mut Annovate, template: &Template, context: &str ) -> Result<(), AnnoError> {
+    if template.fields.is_empty() {
+        return Err( AnnoError::TemplateError( format!( "template `{}` has no fields", template.name ) ) );
+    }
+    let key = format!( "{}{}", TEMPLATE_KEY_PREFIX, template.name );
+    anno.add_directory_annotation( Annotation::new( key, template.definition(), context.to_string() ) )
+}
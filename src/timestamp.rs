@@ -0,0 +1,80 @@
+use std::fmt;
+
+use time::{self, Timespec};
+
+use Annotation;
+
+/// Format of timestamps in the annovate file, always in UTC
+pub const TIMESTAMP_FORMAT: &'static str = "%Y-%m-%dT%H:%M:%SZ";
+
+/// A point in time with second precision
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct Timestamp {
+    seconds: i64
+}
+
+impl Timestamp {
+    pub fn now() -> Timestamp {
+        Timestamp { seconds: time::get_time().sec }
+    }
+
+    /// Timestamp from the number of seconds since 1970-01-01 00:00:00 UTC
+    pub fn from_seconds( seconds: i64 ) -> Timestamp {
+        Timestamp { seconds: seconds }
+    }
+
+    /// Seconds since 1970-01-01 00:00:00 UTC
+    pub fn seconds( &self ) -> i64 {
+        self.seconds
+    }
+
+    /// Parse a timestamp like `2016-03-01T12:30:00Z`
+    pub fn parse( text: &str ) -> Option<Timestamp> {
+        match time::strptime( text.trim(), TIMESTAMP_FORMAT ) {
+            Ok( tm ) => Some( Timestamp { seconds: tm.to_timespec().sec } ),
+            Err( _ ) => None
+        }
+    }
+}
+
+impl fmt::Display for Timestamp {
+    fn fmt( &self, f: &mut fmt::Formatter ) -> fmt::Result {
+        let tm = time::at_utc( Timespec::new( self.seconds, 0 ) );
+        match tm.strftime( TIMESTAMP_FORMAT ) {
+            Ok( text ) => write!( f, "{}", text ),
+            Err( _ ) => Err( fmt::Error )
+        }
+    }
+}
+
+/// Serialized as text in the format of the `!` lines
+#[cfg(feature = "serde")]
+impl ::serde::Serialize for Timestamp {
+    fn serialize<S: ::serde::Serializer>( &self, serializer: S ) -> Result<S::Ok, S::Error> {
+        serializer.collect_str( self )
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> ::serde::Deserialize<'de> for Timestamp {
+    fn deserialize<D: ::serde::Deserializer<'de>>( deserializer: D ) -> Result<Timestamp, D::Error> {
+        let text: String = try!( ::serde::Deserialize::deserialize( deserializer ) );
+        Timestamp::parse( &text ).ok_or_else( || ::serde::de::Error::custom( format!( "invalid timestamp `{}`", text ) ) )
+    }
+}
+
+/// Sort annotations by creation time, oldest first. Annotations without a timestamp come first
+/// and keep their relative order.
+pub fn sort_by_created( annotations: &mut [Annotation] ) {
+    annotations.sort_by_key( |anno| anno.created );
+}
+
+/// Annotations created at or after `since` and before `until`. Either bound may be left open;
+/// annotations without a timestamp never match a bound.
+pub fn created_between<'a>( annotations: &'a [Annotation], since: Option<Timestamp>, until: Option<Timestamp> ) -> Vec<&'a Annotation> {
+    annotations.iter().filter( |anno| {
+        let after_since = match since { Some( s ) => anno.created.map( |c| c >= s ).unwrap_or( false ), None => true };
+        let before_until = match until { Some( u ) => anno.created.map( |c| c < u ).unwrap_or( false ), None => true };
+        after_since && before_until
+    } ).collect()
+}
@@ -0,0 +1,283 @@
+//Reader of the subset of TOML that annovate needs for TOML meta files and the configuration: comments, tables,
+//arrays of tables, strings in all four notations, booleans, date-times and arrays of strings.
+
+use AnnoError;
+
+/// Value of a key: a string (also a date-time), a boolean or an array of strings
+#[derive(Clone, PartialEq, Debug)]
+pub enum TomlValue {
+    Text( String ),
+    List( Vec<String> ),
+    Bool( bool )
+}
+
+/// Part of a document
+#[derive(Clone, PartialEq, Debug)]
+pub enum Item {
+    /// Header of a table, `[a."b"]`, or of an array of tables, `[[a.b]]`, with the parts of its name
+    Table { name: Vec<String>, array: bool },
+    /// `key = value` in the current table
+    Pair( String, TomlValue )
+}
+
+/// Error at a line of a document
+pub fn error<T>( line: u64, msg: &str ) -> Result<T, AnnoError> {
+    Err( AnnoError::FormatError( format!( "line {}: {}", line, msg ) ) )
+}
+
+/// Headers and key-value pairs of a document with the line they start at, in the order of the document.
+/// Keys that appear twice in one table are rejected.
+pub fn parse( text: &str ) -> Result<Vec<( u64, Item )>, AnnoError> {
+    TomlParser { chars: text.chars().collect(), pos: 0, line: 1 }.items()
+}
+
+/// Number of the last line of a document, where errors at its end are reported
+pub fn last_line( text: &str ) -> u64 {
+    text.matches( '\n' ).count() as u64 + 1
+}
+
+struct TomlParser {
+    chars: Vec<char>,
+    pos: usize,
+    line: u64
+}
+
+impl TomlParser {
+    fn error<T>( &self, msg: &str ) -> Result<T, AnnoError> {
+        error( self.line, msg )
+    }
+
+    fn peek( &self ) -> Option<char> {
+        self.chars.get( self.pos ).cloned()
+    }
+
+    fn looking_at( &self, text: &str ) -> bool {
+        text.chars().enumerate().all( |( i, c )| self.chars.get( self.pos + i ) == Some( &c ) )
+    }
+
+    fn advance( &mut self ) -> Option<char> {
+        let c = self.peek();
+        if c == Some( '\n' ) {
+            self.line += 1;
+        }
+        self.pos += 1;
+        c
+    }
+
+    /// Skip spaces and tabs, and with `newlines` also line breaks and comments
+    fn skip_space( &mut self, newlines: bool ) {
+        while let Some( c ) = self.peek() {
+            match c {
+                ' ' | '\t' => {},
+                '\r' | '\n' if newlines => {},
+                '#' if newlines => {
+                    while self.peek().map( |c| c != '\n' ).unwrap_or( false ) {
+                        self.pos += 1;
+                    }
+                    continue;
+                },
+                _ => return
+            }
+            self.advance();
+        }
+    }
+
+    /// Only a comment may follow a value on its line
+    fn end_of_line( &mut self ) -> Result<(), AnnoError> {
+        self.skip_space( false );
+        if self.peek() == Some( '#' ) {
+            while self.peek().map( |c| c != '\n' ).unwrap_or( false ) {
+                self.pos += 1;
+            }
+        }
+        if self.looking_at( "\r\n" ) {
+            self.pos += 1;
+        }
+        match self.advance() {
+            Some( '\n' ) | None => Ok( () ),
+            Some( _ ) => self.error( "expected the end of the line" )
+        }
+    }
+
+    fn bare_word( &mut self ) -> String {
+        let start = self.pos;
+        while self.peek().map( |c| c.is_alphanumeric() || "_-.:+".contains( c ) ).unwrap_or( false ) {
+            self.pos += 1;
+        }
+        self.chars[ start..self.pos ].iter().collect()
+    }
+
+    fn key( &mut self ) -> Result<String, AnnoError> {
+        match self.peek() {
+            Some( '"' ) | Some( '\'' ) => self.string(),
+            _ => {
+                let key = self.bare_word();
+                if key.is_empty() { self.error( "expected a key" ) } else { Ok( key ) }
+            }
+        }
+    }
+
+    fn string( &mut self ) -> Result<String, AnnoError> {
+        let ( quote, multiline ) = match self.peek() {
+            Some( '"' ) => ( '"', self.looking_at( "\"\"\"" ) ),
+            _ => ( '\'', self.looking_at( "'''" ) )
+        };
+        self.pos += if multiline { 3 } else { 1 };
+        if multiline {
+            //a line break right after the opening quotes is not part of the string
+            if self.looking_at( "\r\n" ) {
+                self.pos += 1;
+            }
+            if self.peek() == Some( '\n' ) {
+                self.advance();
+            }
+        }
+        let closing: String = ::std::iter::repeat( quote ).take( if multiline { 3 } else { 1 } ).collect();
+        let mut result = String::new();
+        loop {
+            if self.looking_at( &closing ) {
+                self.pos += closing.len();
+                return Ok( result );
+            }
+            match self.advance() {
+                None => return self.error( "unterminated string" ),
+                Some( '\n' ) if !multiline => return self.error( "unterminated string" ),
+                Some( '\\' ) if quote == '"' => try!( self.escape( &mut result, multiline ) ),
+                Some( c ) => result.push( c )
+            }
+        }
+    }
+
+    fn escape( &mut self, result: &mut String, multiline: bool ) -> Result<(), AnnoError> {
+        if multiline {
+            //a backslash at the end of a line removes the line break and the indentation of the next line
+            let rest = self.chars[ self.pos.. ].iter().skip_while( |c| **c == ' ' || **c == '\t' || **c == '\r' ).next().cloned();
+            if rest == Some( '\n' ) {
+                while self.peek().map( char::is_whitespace ).unwrap_or( false ) {
+                    self.advance();
+                }
+                return Ok( () );
+            }
+        }
+        let c = self.advance();
+        match c {
+            Some( 'n' ) => result.push( '\n' ),
+            Some( 't' ) => result.push( '\t' ),
+            Some( 'r' ) => result.push( '\r' ),
+            Some( 'b' ) => result.push( '\u{8}' ),
+            Some( 'f' ) => result.push( '\u{c}' ),
+            Some( '"' ) => result.push( '"' ),
+            Some( '\\' ) => result.push( '\\' ),
+            Some( 'u' ) | Some( 'U' ) => {
+                let digits = if c == Some( 'u' ) { 4 } else { 8 };
+                let hex: String = self.chars.iter().skip( self.pos ).take( digits ).collect();
+                self.pos += digits;
+                match u32::from_str_radix( &hex, 16 ).ok().and_then( ::std::char::from_u32 ) {
+                    Some( c ) if hex.len() == digits => result.push( c ),
+                    _ => return self.error( "invalid unicode escape" )
+                }
+            },
+            _ => return self.error( "invalid escape sequence" )
+        }
+        Ok( () )
+    }
+
+    fn value( &mut self ) -> Result<TomlValue, AnnoError> {
+        match self.peek() {
+            Some( '"' ) | Some( '\'' ) => self.string().map( TomlValue::Text ),
+            Some( '[' ) => {
+                self.pos += 1;
+                let mut items = Vec::new();
+                loop {
+                    self.skip_space( true );
+                    if self.peek() == Some( ']' ) {
+                        self.pos += 1;
+                        return Ok( TomlValue::List( items ) );
+                    }
+                    match self.peek() {
+                        Some( '"' ) | Some( '\'' ) => items.push( try!( self.string() ) ),
+                        _ => return self.error( "arrays may only contain strings" )
+                    }
+                    self.skip_space( true );
+                    match self.peek() {
+                        Some( ',' ) => self.pos += 1,
+                        Some( ']' ) => {},
+                        _ => return self.error( "expected `,` or `]`" )
+                    }
+                }
+            },
+            //unquoted date-times like those of `created`
+            Some( c ) if c.is_digit( 10 ) => Ok( TomlValue::Text( self.bare_word() ) ),
+            Some( 't' ) | Some( 'f' ) => match self.bare_word().as_str() {
+                "true" => Ok( TomlValue::Bool( true ) ),
+                "false" => Ok( TomlValue::Bool( false ) ),
+                _ => self.error( "expected a string, a boolean or an array of strings" )
+            },
+            _ => self.error( "expected a string, a boolean or an array of strings" )
+        }
+    }
+
+    /// Parts of a table name, separated by dots
+    fn dotted_key( &mut self ) -> Result<Vec<String>, AnnoError> {
+        let mut parts = Vec::new();
+        loop {
+            self.skip_space( false );
+            match self.peek() {
+                Some( '"' ) | Some( '\'' ) => parts.push( try!( self.string() ) ),
+                _ => {
+                    //bare words take the dots between the parts with them
+                    let word = try!( self.key() );
+                    parts.extend( word.split( '.' ).filter( |p| !p.is_empty() ).map( |p| p.to_string() ) );
+                    if word.ends_with( '.' ) {
+                        continue;
+                    }
+                }
+            }
+            self.skip_space( false );
+            if self.peek() != Some( '.' ) {
+                return Ok( parts );
+            }
+            self.pos += 1;
+        }
+    }
+
+    fn items( mut self ) -> Result<Vec<( u64, Item )>, AnnoError> {
+        let mut items = Vec::new();
+        let mut seen_keys: Vec<String> = Vec::new();
+        loop {
+            self.skip_space( true );
+            let line = self.line;
+            match self.peek() {
+                None => return Ok( items ),
+                Some( '[' ) => {
+                    let array = self.looking_at( "[[" );
+                    self.pos += if array { 2 } else { 1 };
+                    let name = try!( self.dotted_key() );
+                    let closing = if array { "]]" } else { "]" };
+                    if !self.looking_at( closing ) {
+                        return self.error( &format!( "expected `{}`", closing ) );
+                    }
+                    self.pos += closing.len();
+                    try!( self.end_of_line() );
+                    seen_keys.clear();
+                    items.push( ( line, Item::Table { name: name, array: array } ) );
+                },
+                Some( _ ) => {
+                    let key = try!( self.key() );
+                    self.skip_space( false );
+                    if self.advance() != Some( '=' ) {
+                        return self.error( "expected `=`" );
+                    }
+                    self.skip_space( false );
+                    let value = try!( self.value() );
+                    try!( self.end_of_line() );
+                    if seen_keys.contains( &key ) {
+                        return self.error( &format!( "duplicate key `{}`", key ) );
+                    }
+                    seen_keys.push( key.clone() );
+                    items.push( ( line, Item::Pair( key, value ) ) );
+                }
+            }
+        }
+    }
+}
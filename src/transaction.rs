@@ -0,0 +1,97 @@
+//A transaction groups changes of the metadata, so that they are saved together or not at all:
+//
+//    try!( anno.transaction( |tx| {
+//        try!( tx.add_file_annotation( "a.txt", annotation ) );
+//        try!( tx.remove_file_annotation_entries( "b.txt", &keys ) );
+//        Ok( () )
+//    } ) );
+//
+//The closure changes the annotations through the `Transaction`, which dereferences to the `Annovate`. If it returns
+//an error, the annotations and tags are reset to their state before the transaction and nothing is saved. Otherwise
+//the file is saved once at the end, if anything changed. A failed save resets them as well.
+
+use std::ops::{Deref, DerefMut};
+use std::path::Path;
+
+use {Annovate, AnnoError};
+
+/// Access to an `Annovate` while a transaction is running, see `Annovate::transaction`
+pub struct Transaction<'a> {
+    anno: &'a mut Annovate,
+    save_required: bool,
+    discarded: bool
+}
+
+impl<'a> Transaction<'a> {
+    /// Save at the end even if nothing changed, so that `transaction_to` writes its outfile in any case
+    pub fn require_save( &mut self ) {
+        self.save_required = true;
+    }
+
+    pub fn is_save_required( &self ) -> bool {
+        self.save_required
+    }
+
+    /// Reset the changes at the end instead of saving them, e.g. for a dry run
+    pub fn discard( &mut self ) {
+        self.discarded = true;
+    }
+}
+
+impl<'a> Deref for Transaction<'a> {
+    type Target = Annovate;
+
+    fn deref( &self ) -> &Annovate {
+        self.anno
+    }
+}
+
+impl<'a> DerefMut for Transaction<'a> {
+    fn deref_mut( &mut self ) -> &mut Annovate {
+        self.anno
+    }
+}
+
+impl Annovate {
+    /// Run `f` and save its changes once it succeeded. If it fails, its changes are reset and its error is returned.
+    pub fn transaction<T, F>( &mut self, f: F ) -> Result<T, AnnoError>
+        where F: FnOnce( &mut Transaction ) -> Result<T, AnnoError> {
+        let outfile = self.filename.clone();
+        self.transaction_to( &outfile, f )
+    }
+
+    /// Like `transaction`, but the changes are saved to `outfile` (see `save_as`)
+    pub fn transaction_to<T, F>( &mut self, outfile: &Path, f: F ) -> Result<T, AnnoError>
+        where F: FnOnce( &mut Transaction ) -> Result<T, AnnoError> {
+        let before = self.snapshot();
+        let modified = self.modified.get();
+        let ( result, save_required, discarded ) = {
+            let mut tx = Transaction { anno: self, save_required: false, discarded: false };
+            let result = f( &mut tx );
+            ( result, tx.save_required, tx.discarded )
+        };
+        if result.is_ok() && !discarded {
+            let save = self.is_modified() || ( save_required && outfile != self.filename.as_path() );
+            match if save { self.save_as( outfile ) } else { Ok( () ) } {
+                Ok( () ) => return result,
+                Err( e ) => {
+                    self.reset_to( before, modified );
+                    return Err( e );
+                }
+            }
+        }
+        self.reset_to( before, modified );
+        result
+    }
+
+    /// Go back to the annotations and tags of `before`, which was taken by `snapshot`
+    fn reset_to( &mut self, before: Annovate, modified: bool ) {
+        self.dir = before.dir;
+        self.files = before.files;
+        self.file_order = before.file_order;
+        self.dir_tags = before.dir_tags;
+        self.tags = before.tags;
+        self.undone.set( before.undone.get() );
+        self.modified.set( modified );
+    }
+}
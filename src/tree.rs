@@ -0,0 +1,121 @@
+use std::fs::read_dir;
+use std::path::{Path, PathBuf};
+
+use {Annovate, AnnoContainer, AnnoError, OpenMode};
+use ignore::IgnoreRules;
+use progress::{Progress, ProgressStage};
+
+/// All annovate files below a root directory, combined into one view with paths relative to the root
+pub struct AnnovateTree {
+    root: PathBuf,
+    members: Vec<( PathBuf, Annovate )>
+}
+
+fn collect_meta_files( root: &Path, relative: &Path, meta_name: &str, include_hidden: bool, ignore: &IgnoreRules, found: &mut Vec<PathBuf> ) -> Result<(), AnnoError> {
+    let dir = root.join( relative );
+    if dir.join( meta_name ).is_file() {
+        found.push( relative.to_path_buf() );
+    }
+    let mut subdirs = Vec::new();
+    for entry in try!( read_dir( &dir ).map_err( |e| AnnoError::io( &dir, e ) ) ) {
+        let entry = try!( entry );
+        let name = entry.file_name();
+        if !include_hidden && name.to_string_lossy().starts_with( "." ) {
+            continue;
+        }
+        let subdir = relative.join( name );
+        if try!( entry.file_type() ).is_dir() && !ignore.is_ignored( &subdir.to_string_lossy(), true ) { //symlinks are not followed to avoid cycles
+            subdirs.push( subdir );
+        }
+    }
+    subdirs.sort();
+    for subdir in subdirs {
+        try!( collect_meta_files( root, &subdir, meta_name, include_hidden, ignore, found ) );
+    }
+    Ok( () )
+}
+
+impl AnnovateTree {
+    /// Find and parse every file called `meta_name` in `root` and its subdirectories, read-only.
+    /// Hidden directories are skipped unless `include_hidden` is set.
+    pub fn discover( root: &Path, meta_name: &str, include_hidden: bool ) -> Result<AnnovateTree, AnnoError> {
+        AnnovateTree::discover_with_progress( root, meta_name, include_hidden, None )
+    }
+
+    /// Like `discover`, reporting every parsed meta file to `progress`
+    pub fn discover_with_progress( root: &Path, meta_name: &str, include_hidden: bool, progress: Option<&dyn Fn( &Progress )> ) -> Result<AnnovateTree, AnnoError> {
+        let found = try!( AnnovateTree::find( root, meta_name, include_hidden ) );
+        let total = found.len() as u64;
+        let mut members = Vec::new();
+        for relative in found {
+            let anno = try!( Annovate::open( &root.join( &relative ).join( meta_name ), OpenMode::ReadOnly ) );
+            members.push( ( relative, anno ) );
+            if let Some( progress ) = progress {
+                progress( &Progress { stage: ProgressStage::Entries, done: members.len() as u64, total: Some( total ) } );
+            }
+        }
+        Ok( AnnovateTree { root: root.to_path_buf(), members: members } )
+    }
+
+    /// Directories below `root` (relative to it, sorted) that contain a file called `meta_name`, without parsing them.
+    /// Directories that the ignore file of `root` lists are skipped.
+    pub fn find( root: &Path, meta_name: &str, include_hidden: bool ) -> Result<Vec<PathBuf>, AnnoError> {
+        let ignore = try!( IgnoreRules::load( root ) );
+        let mut relative_dirs = Vec::new();
+        try!( collect_meta_files( root, Path::new( "" ), meta_name, include_hidden, &ignore, &mut relative_dirs ) );
+        Ok( relative_dirs )
+    }
+
+    pub fn root( &self ) -> &Path {
+        &self.root
+    }
+
+    /// The annovate files with their directory relative to the root (empty for the root itself)
+    pub fn members( &self ) -> &[( PathBuf, Annovate )] {
+        &self.members
+    }
+
+    /// Path of a file relative to the root
+    pub fn relative_name( dir: &Path, filename: &str ) -> String {
+        dir.join( filename ).to_string_lossy().into_owned()
+    }
+
+    /// Filenames of all annovate files, relative to the root
+    pub fn get_files( &self ) -> Vec<String> {
+        let mut result = Vec::new();
+        for &( ref dir, ref anno ) in &self.members {
+            for file in anno.get_files() {
+                result.push( AnnovateTree::relative_name( dir, &file ) );
+            }
+        }
+        result
+    }
+
+    /// Files with the given tag, relative to the root
+    pub fn files_with_tag( &self, tag: &str ) -> Vec<String> {
+        let mut result = Vec::new();
+        for &( ref dir, ref anno ) in &self.members {
+            for file in anno.files_with_tag( tag ) {
+                result.push( AnnovateTree::relative_name( dir, &file ) );
+            }
+        }
+        result
+    }
+
+    /// Annotations of a file given relative to the root. The deepest directory with an annovate file wins.
+    pub fn get_file_annotations( &self, path: &str ) -> Option<&AnnoContainer> {
+        let path = Path::new( path );
+        let mut best: Option<( usize, &AnnoContainer )> = None;
+        for &( ref dir, ref anno ) in &self.members {
+            if let Ok( rest ) = path.strip_prefix( dir ) {
+                let depth = dir.components().count();
+                if let Some( annotations ) = anno.get_file_annotations( &rest.to_string_lossy() ) {
+                    if best.map( |b| depth >= b.0 ).unwrap_or( true ) {
+                        best = Some( ( depth, annotations ) );
+                    }
+                }
+            }
+        }
+        best.map( |b| b.1 )
+    }
+}
@@ -0,0 +1,96 @@
+//Keys, contexts and filenames take one line of the meta file each, and trailing whitespace of a line is not kept.
+//A key with a line break would therefore end its line early and make the next save unreadable. Every change of the
+//annotations checks what it adds and fails with `AnnoError::InvalidInput` instead:
+//
+//    keys         not empty, at most `MAX_KEY_LENGTH` bytes, no control characters, no trailing whitespace
+//    contexts     at most `MAX_CONTEXT_LENGTH` bytes, no control characters, no trailing whitespace
+//    filenames    not empty, no control characters, no trailing whitespace, no malformed parts like `#column:`;
+//                 subdirectories (`data/`) without parts
+//    values       at most `MAX_VALUE_LENGTH` bytes
+//
+//Values span several lines and are escaped where needed, so they keep any characters.
+
+use {Annotation, AnnoError, Target};
+use subdir;
+
+pub const MAX_KEY_LENGTH: usize = 256;
+pub const MAX_CONTEXT_LENGTH: usize = 4096;
+pub const MAX_VALUE_LENGTH: usize = 16 * 1024 * 1024;
+
+/// Input that is shown in an error is cut to this many characters
+const SHOWN_LENGTH: usize = 40;
+
+fn invalid( what: &'static str, input: &str, reason: String ) -> AnnoError {
+    let mut shown: String = input.chars().take( SHOWN_LENGTH ).collect();
+    if shown.len() < input.len() {
+        shown.push_str( "..." );
+    }
+    AnnoError::InvalidInput { what: what, input: shown, reason: reason }
+}
+
+/// The first control character of `text`, with a description
+fn control_character( text: &str ) -> Option<String> {
+    text.chars().find( |c| c.is_control() ).map( |c| match c {
+        '\n' | '\r' => "contains a line break".to_string(),
+        c => format!( "contains the control character U+{:04X}", c as u32 )
+    } )
+}
+
+/// Checks of a text that is written on a single line
+fn check_line( what: &'static str, text: &str, max_length: Option<usize> ) -> Result<(), AnnoError> {
+    if let Some( reason ) = control_character( text ) {
+        return Err( invalid( what, text, reason ) );
+    }
+    if text.ends_with( char::is_whitespace ) {
+        return Err( invalid( what, text, "ends with whitespace".to_string() ) );
+    }
+    match max_length {
+        Some( max ) if text.len() > max => Err( invalid( what, text, format!( "is longer than {} bytes", max ) ) ),
+        _ => Ok( () )
+    }
+}
+
+pub fn check_key( key: &str ) -> Result<(), AnnoError> {
+    if key.is_empty() {
+        return Err( invalid( "key", key, "is empty".to_string() ) );
+    }
+    check_line( "key", key, Some( MAX_KEY_LENGTH ) )
+}
+
+pub fn check_context( context: &str ) -> Result<(), AnnoError> {
+    check_line( "context", context, Some( MAX_CONTEXT_LENGTH ) )
+}
+
+/// A filename or another target like `a.csv#L1-2` or a URL
+pub fn check_filename( filename: &str ) -> Result<(), AnnoError> {
+    if filename.is_empty() {
+        return Err( invalid( "filename", filename, "is empty".to_string() ) );
+    }
+    if let Some( fragment ) = Target::malformed_fragment( filename ) {
+        return Err( invalid( "filename", filename, format!( "has the malformed part `{}` (e.g. #column:age, #L10-20 or #B0-511; ## for a # of the filename)", fragment ) ) );
+    }
+    let target = Target::parse( filename );
+    if subdir::is_subdirectory_entry( &target.file ) {
+        if target.file.trim_right_matches( subdir::SUBDIR_MARKER ).is_empty() {
+            return Err( invalid( "filename", filename, "names no directory".to_string() ) );
+        }
+        if target.fragment.is_some() {
+            return Err( invalid( "filename", filename, "addresses a part of a directory".to_string() ) );
+        }
+    }
+    check_line( "filename", filename, None )
+}
+
+pub fn check_value( value: &str ) -> Result<(), AnnoError> {
+    if value.len() > MAX_VALUE_LENGTH {
+        return Err( invalid( "value", value, format!( "is longer than {} bytes", MAX_VALUE_LENGTH ) ) );
+    }
+    Ok( () )
+}
+
+/// Key, value and context of `annotation`
+pub fn check_annotation( annotation: &Annotation ) -> Result<(), AnnoError> {
+    try!( check_key( &annotation.key ) );
+    try!( check_value( &annotation.value ) );
+    check_context( &annotation.context )
+}
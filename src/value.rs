@@ -0,0 +1,152 @@
+use std::fmt;
+
+use rustc_serialize::base64::{FromBase64, ToBase64, STANDARD};
+use time;
+
+use {Annotation, AnnoError};
+
+/// Type of a value, written after the key: `>size:int`
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize), serde(rename_all = "lowercase"))]
+pub enum ValueType {
+    /// Plain text, the default. Only written for keys that end in `:<type name>` themselves.
+    Text,
+    Int,
+    Bool,
+    /// `YYYY-MM-DD`
+    Date,
+    /// Comma separated items
+    List,
+    /// Base64 encoded bytes. Not written after the key but as value lines starting with `=b64:`.
+    Binary
+}
+
+impl ValueType {
+    pub fn name( &self ) -> &'static str {
+        match *self {
+            ValueType::Text => "text",
+            ValueType::Int => "int",
+            ValueType::Bool => "bool",
+            ValueType::Date => "date",
+            ValueType::List => "list",
+            ValueType::Binary => "binary"
+        }
+    }
+
+    pub fn from_name( name: &str ) -> Option<ValueType> {
+        match name {
+            "text" => Some( ValueType::Text ),
+            "int" => Some( ValueType::Int ),
+            "bool" => Some( ValueType::Bool ),
+            "date" => Some( ValueType::Date ),
+            "list" => Some( ValueType::List ),
+            "binary" => Some( ValueType::Binary ),
+            _ => None
+        }
+    }
+}
+
+/// A parsed value. Values of different types are ordered by type, text last.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Value {
+    Int( i64 ),
+    Bool( bool ),
+    /// Year, month and day
+    Date( i32, u32, u32 ),
+    List( Vec<String> ),
+    Bytes( Vec<u8> ),
+    Text( String )
+}
+
+impl Value {
+    pub fn parse( text: &str, value_type: ValueType ) -> Result<Value, AnnoError> {
+        let invalid = || AnnoError::FormatError( format!( "`{}` is not a valid {}", text, value_type.name() ) );
+        let trimmed = text.trim();
+        match value_type {
+            ValueType::Text => Ok( Value::Text( text.to_string() ) ),
+            ValueType::Int => trimmed.parse::<i64>().map( Value::Int ).map_err( |_| invalid() ),
+            ValueType::Bool => match trimmed.to_lowercase().as_str() {
+                "true" | "yes" | "1" => Ok( Value::Bool( true ) ),
+                "false" | "no" | "0" => Ok( Value::Bool( false ) ),
+                _ => Err( invalid() )
+            },
+            ValueType::Date => match time::strptime( trimmed, "%Y-%m-%d" ) {
+                Ok( tm ) => Ok( Value::Date( tm.tm_year + 1900, tm.tm_mon as u32 + 1, tm.tm_mday as u32 ) ),
+                Err( _ ) => Err( invalid() )
+            },
+            ValueType::List => Ok( Value::List( trimmed.split( ',' ).map( |item| item.trim().to_string() ).filter( |item| !item.is_empty() ).collect() ) ),
+            ValueType::Binary => trimmed.from_base64().map( Value::Bytes ).map_err( |_| invalid() )
+        }
+    }
+
+    pub fn value_type( &self ) -> ValueType {
+        match *self {
+            Value::Int( _ ) => ValueType::Int,
+            Value::Bool( _ ) => ValueType::Bool,
+            Value::Date( .. ) => ValueType::Date,
+            Value::List( _ ) => ValueType::List,
+            Value::Bytes( _ ) => ValueType::Binary,
+            Value::Text( _ ) => ValueType::Text
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    /// The normalized text that `parse` accepts again
+    fn fmt( &self, f: &mut fmt::Formatter ) -> fmt::Result {
+        match *self {
+            Value::Int( i ) => write!( f, "{}", i ),
+            Value::Bool( b ) => write!( f, "{}", b ),
+            Value::Date( year, month, day ) => write!( f, "{:04}-{:02}-{:02}", year, month, day ),
+            Value::List( ref items ) => write!( f, "{}", items.join( ", " ) ),
+            Value::Bytes( ref bytes ) => write!( f, "{}", bytes.to_base64( STANDARD ) ),
+            Value::Text( ref text ) => write!( f, "{}", text )
+        }
+    }
+}
+
+/// Split a key line into key and type. A suffix that is no type name (or `binary`) belongs to the key.
+pub fn split_typed_key( line: &str ) -> ( String, Option<ValueType> ) {
+    if let Some( pos ) = line.rfind( ':' ) {
+        if let Some( value_type ) = ValueType::from_name( &line[ pos + 1.. ] ).filter( |&t| t != ValueType::Binary ) {
+            let value_type = if value_type == ValueType::Text { None } else { Some( value_type ) };
+            return ( line[ ..pos ].to_string(), value_type );
+        }
+    }
+    ( line.to_string(), None )
+}
+
+/// Inverse of `split_typed_key`. Untyped keys that look typed (`template:int`) get the suffix `:text`.
+pub fn format_typed_key( key: &str, value_type: Option<ValueType> ) -> String {
+    match value_type {
+        Some( value_type ) if value_type != ValueType::Text && value_type != ValueType::Binary => format!( "{}:{}", key, value_type.name() ),
+        _ if split_typed_key( key ).0 != key => format!( "{}:text", key ),
+        _ => key.to_string()
+    }
+}
+
+impl Annotation {
+    /// The same annotation with a value type (`Text` removes the type)
+    pub fn with_type( self, value_type: ValueType ) -> Annotation {
+        let value_type = if value_type == ValueType::Text { None } else { Some( value_type ) };
+        Annotation { value_type: value_type, ..self }
+    }
+
+    /// Annotation with binary data as value, see `value_bytes`
+    pub fn new_binary( key: String, bytes: &[u8], context: String ) -> Annotation {
+        Annotation::new( key, bytes.to_base64( STANDARD ), context ).with_type( ValueType::Binary )
+    }
+
+    /// Bytes of the value: decoded for binary values, the UTF-8 text otherwise
+    pub fn value_bytes( &self ) -> Vec<u8> {
+        match self.typed_value() {
+            Ok( Value::Bytes( bytes ) ) => bytes,
+            _ => self.value.as_bytes().to_vec()
+        }
+    }
+
+    /// The value parsed according to the type of the annotation
+    pub fn typed_value( &self ) -> Result<Value, AnnoError> {
+        Value::parse( &self.value, self.value_type.unwrap_or( ValueType::Text ) )
+    }
+}
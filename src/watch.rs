@@ -0,0 +1,58 @@
+use std::collections::HashSet;
+use std::fs::read_dir;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use AnnoError;
+
+/// Source of files that appear in a directory. Implementations may poll or use notifications
+/// of the operating system (inotify, FSEvents).
+pub trait Watcher {
+    /// Block until new files appeared and return their names
+    fn next_new_files( &mut self ) -> Result<Vec<String>, AnnoError>;
+}
+
+/// Regular files of a directory
+fn list_files( dir: &Path, include_hidden: bool ) -> Result<HashSet<String>, AnnoError> {
+    let mut result = HashSet::new();
+    for entry in try!( read_dir( dir ).map_err( |e| AnnoError::io( dir, e ) ) ) {
+        let entry = try!( entry );
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if ( include_hidden || !name.starts_with( "." ) ) && try!( entry.file_type() ).is_file() {
+            result.insert( name );
+        }
+    }
+    Ok( result )
+}
+
+/// Watcher that compares the content of the directory in fixed intervals
+pub struct PollingWatcher {
+    dir: PathBuf,
+    interval: Duration,
+    include_hidden: bool,
+    known: HashSet<String>
+}
+
+impl PollingWatcher {
+    /// Watch `dir`; files that exist already are not reported
+    pub fn new( dir: &Path, interval: Duration, include_hidden: bool ) -> Result<PollingWatcher, AnnoError> {
+        let known = try!( list_files( dir, include_hidden ) );
+        Ok( PollingWatcher { dir: dir.to_path_buf(), interval: interval, include_hidden: include_hidden, known: known } )
+    }
+}
+
+impl Watcher for PollingWatcher {
+    fn next_new_files( &mut self ) -> Result<Vec<String>, AnnoError> {
+        loop {
+            let current = try!( list_files( &self.dir, self.include_hidden ) );
+            let mut new_files: Vec<String> = current.difference( &self.known ).cloned().collect();
+            self.known = current; //files that were deleted are reported again if they come back
+            if !new_files.is_empty() {
+                new_files.sort();
+                return Ok( new_files );
+            }
+            thread::sleep( self.interval );
+        }
+    }
+}
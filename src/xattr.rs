@@ -0,0 +1,185 @@
+//Mirror of the annotations in extended file attributes (Linux and macOS), so that other tools can read them.
+//The most recent value of each key of a file is stored in the attribute `user.annovate.<key>`.
+
+use std::ffi::CString;
+use std::fs::read_dir;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::ptr;
+
+use libc::{self, c_char, c_void, size_t};
+
+use {Annovate, Annotation, AnnoError, Target, Timestamp};
+
+/// Prefix of the attribute names
+pub const PREFIX: &'static str = "user.annovate.";
+
+#[cfg(target_os = "macos")]
+const NO_ATTRIBUTE: i32 = libc::ENOATTR;
+#[cfg(not(target_os = "macos"))]
+const NO_ATTRIBUTE: i32 = libc::ENODATA;
+
+#[cfg(target_os = "macos")]
+unsafe fn sys_get( path: *const c_char, name: *const c_char, value: *mut c_void, size: size_t ) -> isize {
+    libc::getxattr( path, name, value, size, 0, 0 )
+}
+#[cfg(not(target_os = "macos"))]
+unsafe fn sys_get( path: *const c_char, name: *const c_char, value: *mut c_void, size: size_t ) -> isize {
+    libc::getxattr( path, name, value, size )
+}
+
+#[cfg(target_os = "macos")]
+unsafe fn sys_set( path: *const c_char, name: *const c_char, value: *const c_void, size: size_t ) -> i32 {
+    libc::setxattr( path, name, value, size, 0, 0 )
+}
+#[cfg(not(target_os = "macos"))]
+unsafe fn sys_set( path: *const c_char, name: *const c_char, value: *const c_void, size: size_t ) -> i32 {
+    libc::setxattr( path, name, value, size, 0 )
+}
+
+#[cfg(target_os = "macos")]
+unsafe fn sys_list( path: *const c_char, list: *mut c_char, size: size_t ) -> isize {
+    libc::listxattr( path, list, size, 0 )
+}
+#[cfg(not(target_os = "macos"))]
+unsafe fn sys_list( path: *const c_char, list: *mut c_char, size: size_t ) -> isize {
+    libc::listxattr( path, list, size )
+}
+
+#[cfg(target_os = "macos")]
+unsafe fn sys_remove( path: *const c_char, name: *const c_char ) -> i32 {
+    libc::removexattr( path, name, 0 )
+}
+#[cfg(not(target_os = "macos"))]
+unsafe fn sys_remove( path: *const c_char, name: *const c_char ) -> i32 {
+    libc::removexattr( path, name )
+}
+
+fn c_string( bytes: &[u8] ) -> Result<CString, AnnoError> {
+    CString::new( bytes ).map_err( |_| AnnoError::FormatError( "names must not contain NUL characters".to_string() ) )
+}
+
+fn last_error( path: &Path ) -> AnnoError {
+    AnnoError::io( path, io::Error::last_os_error() )
+}
+
+/// Names of all extended attributes of a file
+pub fn list_attributes( path: &Path ) -> Result<Vec<String>, AnnoError> {
+    let c_path = try!( c_string( path.as_os_str().as_bytes() ) );
+    let size = unsafe { sys_list( c_path.as_ptr(), ptr::null_mut(), 0 ) };
+    if size < 0 {
+        return Err( last_error( path ) );
+    }
+    let mut buffer = vec![ 0u8; size as usize ];
+    let size = unsafe { sys_list( c_path.as_ptr(), buffer.as_mut_ptr() as *mut c_char, buffer.len() ) };
+    if size < 0 {
+        return Err( last_error( path ) );
+    }
+    buffer.truncate( size as usize );
+    Ok( buffer.split( |&b| b == 0 ).filter( |n| !n.is_empty() ).map( |n| String::from_utf8_lossy( n ).into_owned() ).collect() )
+}
+
+/// Value of an attribute, `None` if the file does not have it
+pub fn get_attribute( path: &Path, name: &str ) -> Result<Option<String>, AnnoError> {
+    let ( c_path, c_name ) = ( try!( c_string( path.as_os_str().as_bytes() ) ), try!( c_string( name.as_bytes() ) ) );
+    loop {
+        let size = unsafe { sys_get( c_path.as_ptr(), c_name.as_ptr(), ptr::null_mut(), 0 ) };
+        if size < 0 {
+            let err = io::Error::last_os_error();
+            return if err.raw_os_error() == Some( NO_ATTRIBUTE ) { Ok( None ) } else { Err( AnnoError::io( path, err ) ) };
+        }
+        let mut buffer = vec![ 0u8; size as usize ];
+        let read = unsafe { sys_get( c_path.as_ptr(), c_name.as_ptr(), buffer.as_mut_ptr() as *mut c_void, buffer.len() ) };
+        if read < 0 && io::Error::last_os_error().raw_os_error() == Some( libc::ERANGE ) {
+            continue; //the value grew in between
+        }
+        if read < 0 {
+            return Err( last_error( path ) );
+        }
+        buffer.truncate( read as usize );
+        return Ok( Some( String::from_utf8_lossy( &buffer ).into_owned() ) );
+    }
+}
+
+pub fn set_attribute( path: &Path, name: &str, value: &str ) -> Result<(), AnnoError> {
+    let ( c_path, c_name ) = ( try!( c_string( path.as_os_str().as_bytes() ) ), try!( c_string( name.as_bytes() ) ) );
+    let result = unsafe { sys_set( c_path.as_ptr(), c_name.as_ptr(), value.as_ptr() as *const c_void, value.len() ) };
+    if result < 0 { Err( last_error( path ) ) } else { Ok( () ) }
+}
+
+pub fn remove_attribute( path: &Path, name: &str ) -> Result<(), AnnoError> {
+    let ( c_path, c_name ) = ( try!( c_string( path.as_os_str().as_bytes() ) ), try!( c_string( name.as_bytes() ) ) );
+    let result = unsafe { sys_remove( c_path.as_ptr(), c_name.as_ptr() ) };
+    if result < 0 { Err( last_error( path ) ) } else { Ok( () ) }
+}
+
+/// Annotations stored in the attributes of a file as key and value
+pub fn read_annotations( path: &Path ) -> Result<Vec<( String, String )>, AnnoError> {
+    let mut result = Vec::new();
+    for name in try!( list_attributes( path ) ).into_iter().filter( |n| n.starts_with( PREFIX ) ) {
+        if let Some( value ) = try!( get_attribute( path, &name ) ) {
+            result.push( ( name[ PREFIX.len().. ].to_string(), value ) );
+        }
+    }
+    result.sort();
+    Ok( result )
+}
+
+impl Annovate {
+    /// Write the most recent value of each key of the annotated files in `dir` to their attributes and remove
+    /// attributes of keys the files do not have anymore. Parts of files, URLs and missing files are skipped.
+    /// Returns the number of files whose attributes were written.
+    pub fn push_xattrs( &self, dir: &Path ) -> Result<usize, AnnoError> {
+        let mut count = 0;
+        for file in self.iter_files() {
+            let target = Target::parse( file );
+            let path = dir.join( &target.file );
+            if target.is_sub_file() || target.is_remote() || !path.exists() {
+                continue;
+            }
+            let annotations = &self.files[ file ];
+            let mut keys: Vec<&str> = Vec::new();
+            for annotation in annotations.iter().rev() {
+                if !keys.contains( &annotation.key.as_str() ) {
+                    keys.push( &annotation.key );
+                    try!( set_attribute( &path, &format!( "{}{}", PREFIX, annotation.key ), &annotation.value ) );
+                }
+            }
+            for ( key, _ ) in try!( read_annotations( &path ) ) {
+                if !keys.contains( &key.as_str() ) {
+                    try!( remove_attribute( &path, &format!( "{}{}", PREFIX, key ) ) );
+                }
+            }
+            count += 1;
+        }
+        Ok( count )
+    }
+
+    /// Add the attribute values of the files in `dir` that differ from the most recent value of their key,
+    /// with the context `<context>, read from extended attributes`. Returns the number of added annotations.
+    pub fn pull_xattrs( &mut self, dir: &Path, context: &str ) -> Result<usize, AnnoError> {
+        let mut names = Vec::new();
+        for entry in try!( read_dir( dir ).map_err( |e| AnnoError::io( dir, e ) ) ) {
+            let entry = try!( entry );
+            if try!( entry.file_type() ).is_file() {
+                names.push( entry.file_name().to_string_lossy().into_owned() );
+            }
+        }
+        names.sort();
+        let now = Timestamp::now();
+        let mut added = 0;
+        for file in names {
+            let name = Target::file( &file ).to_string();
+            for ( key, value ) in try!( read_annotations( &dir.join( &file ) ) ) {
+                let current = self.get_file_annotations( &name ).and_then( |annos| annos.iter().rev().find( |a| a.key == key ) ).map( |a| a.value.clone() );
+                if current.as_ref() != Some( &value ) {
+                    let pull_context = format!( "{}, read from extended attributes", context );
+                    try!( self.add_file_annotation( &name, Annotation::new( key, value, pull_context ).with_created( now ) ) );
+                    added += 1;
+                }
+            }
+        }
+        Ok( added )
+    }
+}